@@ -5,56 +5,55 @@
 //! which loads the whole region from the backing memory file
 //! when a page fault occurs.
 
-use std::os::unix::io::AsRawFd;
-
-use daemon::serve_mem_regions::{create_pf_handler, MemPageState};
+use daemon::encrypted_source::{read_key, EncryptedSource};
+use daemon::fault_stats::register_dump_signal;
+use daemon::page_source::{PageSource, PmemSlotSource, DAX_DEVICE_PATH, SNAPSHOT_INDEX_PATH};
+use daemon::serve_mem_regions::create_pf_handler;
 // use daemon::pm_check;
 use daemon::mem_manager;
 
-fn main() {
-    let mut uffd_handler = create_pf_handler();
+/// Environment variable holding the path of the key of encrypted snapshot memory.
+const MEM_KEY_PATH_ENV: &str = "SNAPSTART_MEM_KEY_PATH";
 
-    let mut pollfd = libc::pollfd {
-        fd: uffd_handler.uffd.as_raw_fd(),
-        events: libc::POLLIN,
-        revents: 0,
+fn main() {
+    let uffd_sock_path = std::env::args().nth(1).expect("No socket path given");
+    let mem4fun = std::env::args().nth(2).expect("No snapshot memory given"); //e.g., "recognition"
+
+    // Where to write the fault statistics of the session, printed to stdout if not given.
+    let stats_path = std::env::args().nth(3);
+
+    // Map the PMem slot holding the snapshot memory of the function.
+    let source = PmemSlotSource::from_index(DAX_DEVICE_PATH, SNAPSHOT_INDEX_PATH, &mem4fun)
+        .expect("Cannot map the snapshot memory slot");
+    // deliberately trigger page faults by accessing all virtual pages.
+    // establish the actual MMU page mappings
+    source.prefault();
+
+    // Snapshot memory encrypted by Firecracker is decrypted with the key found at this path.
+    let source: Box<dyn PageSource> = match std::env::var_os(MEM_KEY_PATH_ENV) {
+        Some(key_path) => {
+            let key = read_key(key_path).expect("Cannot read the snapshot memory key");
+            Box::new(
+                EncryptedSource::new(source, &key)
+                    .expect("Cannot decrypt the snapshot memory slot"),
+            )
+        }
+        None => Box::new(source),
     };
+
+    let mut uffd_handler =
+        create_pf_handler(&uffd_sock_path, source).expect("Cannot create uffd handler");
+    if let Some(stats_path) = &stats_path {
+        uffd_handler.set_stats_path(stats_path);
+        // `kill -USR1` dumps the statistics gathered so far.
+        register_dump_signal(libc::SIGUSR1).expect("Cannot register SIGUSR1 handler");
+    }
+
     // Loop, handling incoming events on the userfaultfd file descriptor.
     // It will be triggered only once during the SnapStart of one VM!
-    loop {
-        // See what poll() tells us about the userfaultfd.
-        let nready = unsafe { libc::poll(&mut pollfd, 1, -1) };
+    uffd_handler.run().expect("Failed to serve page faults");
 
-        if nready == -1 {
-            panic!("Could not poll for events!")
-        }
-
-        let revents = pollfd.revents;
-
-        println!(
-            "poll() returns: nready = {}; POLLIN = {}; POLLERR = {}",
-            nready,
-            revents & libc::POLLIN,
-            revents & libc::POLLERR,
-        );
-
-        // Read an event from the userfaultfd.
-        let event = uffd_handler
-            .uffd
-            .read_event()
-            .expect("Failed to read uffd_msg")
-            .expect("uffd_msg not ready");
-
-        // We expect to receive either a Page Fault or Removed
-        // event (if the balloon device is enabled).
-        match event {
-            userfaultfd::Event::Pagefault { addr, .. } => uffd_handler.serve_pf(addr as *mut u8),
-            userfaultfd::Event::Remove { start, end } => uffd_handler.update_mem_state_mappings(
-                start as *mut u8 as u64,
-                end as *mut u8 as u64,
-                &MemPageState::Removed,
-            ),
-            _ => panic!("Unexpected event on userfaultfd"),
-        }
+    if stats_path.is_none() {
+        println!("{}", uffd_handler.stats().to_json());
     }
 }
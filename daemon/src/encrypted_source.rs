@@ -0,0 +1,322 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serves guest memory from snapshot memory files encrypted by Firecracker.
+//!
+//! The files start with a 40 byte header (magic `FCSNAPE1`, whose last byte is the format
+//! version, chunk size, reserved zero word, plaintext length and salt) followed by AES-256-GCM
+//! sealed chunks of the plaintext, each one followed by its tag. The chunk key is derived from
+//! the user key and the salt with HKDF-SHA256, the nonce of a chunk is its index and the header
+//! is the associated data. This matches `vmm::snapshot_crypto`, which the daemon cannot depend
+//! on.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use aws_lc_rs::hkdf::{Salt, HKDF_SHA256};
+
+use crate::page_source::PageSource;
+
+/// Length of the key, in bytes.
+pub const KEY_LEN: usize = 32;
+
+const MAGIC: &[u8; 8] = b"FCSNAPE1";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = 40;
+const TAG_LEN: usize = 16;
+const KEY_INFO: &[&[u8]] = &[b"firecracker snapshot file"];
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a raw 32 byte key from `path`.
+pub fn read_key<P: AsRef<Path>>(path: P) -> io::Result<[u8; KEY_LEN]> {
+    let key = fs::read(path)?;
+    key.as_slice()
+        .try_into()
+        .map_err(|_| invalid_data(format!("Key must be {} bytes, got {}", KEY_LEN, key.len())))
+}
+
+/// Fields of the header of an encrypted file, as `vmm::snapshot_crypto` writes them.
+struct Header {
+    chunk_size: usize,
+    plaintext_len: usize,
+    salt: [u8; SALT_LEN],
+}
+
+impl Header {
+    fn from_bytes(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+        if bytes[0..7] != MAGIC[0..7] {
+            return Err(invalid_data(
+                "Not an encrypted snapshot memory file".to_string(),
+            ));
+        }
+        if bytes[7] != MAGIC[7] {
+            return Err(invalid_data(format!(
+                "Unsupported encrypted snapshot version {:?}",
+                char::from(bytes[7])
+            )));
+        }
+        let bad_header = || invalid_data("Invalid encrypted snapshot header".to_string());
+        // The unwraps are safe because the slices have the right length.
+        let chunk_size = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        if chunk_size == 0 || bytes[12..16] != [0; 4] {
+            return Err(bad_header());
+        }
+        let plaintext_len = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        Ok(Header {
+            chunk_size,
+            plaintext_len: usize::try_from(plaintext_len).map_err(|_| bad_header())?,
+            salt: bytes[24..40].try_into().unwrap(),
+        })
+    }
+}
+
+/// The last chunk decrypted, kept because consecutive faults usually hit the same chunk.
+struct CachedChunk {
+    index: u64,
+    data: Vec<u8>,
+}
+
+/// Guest memory decrypted on demand from an encrypted snapshot memory file held by another
+/// page source (e.g. a PMem slot the file was copied to).
+pub struct EncryptedSource<S: PageSource> {
+    inner: S,
+    key: LessSafeKey,
+    header: [u8; HEADER_LEN],
+    chunk_size: usize,
+    plaintext_len: usize,
+    cache: RefCell<Option<CachedChunk>>,
+}
+
+impl<S: PageSource> EncryptedSource<S> {
+    /// Checks the header found at the start of `inner` and derives the chunk key.
+    pub fn new(inner: S, key: &[u8; KEY_LEN]) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        inner.read_at(0, &mut header)?;
+        let Header {
+            chunk_size,
+            plaintext_len,
+            salt,
+        } = Header::from_bytes(&header)?;
+        let chunks = plaintext_len / chunk_size + usize::from(plaintext_len % chunk_size != 0);
+        // The source may be larger than the file (e.g. a whole PMem slot), never smaller.
+        let encrypted_len = HEADER_LEN
+            .saturating_add(plaintext_len)
+            .saturating_add(chunks.saturating_mul(TAG_LEN));
+        if inner.len() < encrypted_len {
+            return Err(invalid_data(format!(
+                "Encrypted snapshot memory file truncated: {:#x} bytes available",
+                inner.len()
+            )));
+        }
+
+        let okm = Salt::new(HKDF_SHA256, &salt)
+            .extract(key)
+            .expand(KEY_INFO, &AES_256_GCM)
+            .map_err(|_| invalid_data("Cannot derive the chunk key".to_string()))?;
+
+        Ok(EncryptedSource {
+            inner,
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            header,
+            chunk_size,
+            plaintext_len,
+            cache: RefCell::new(None),
+        })
+    }
+
+    fn decrypt_chunk(&self, index: u64) -> io::Result<Vec<u8>> {
+        let start = index as usize * self.chunk_size;
+        let len = (self.plaintext_len - start).min(self.chunk_size);
+        let mut data = vec![0u8; len + TAG_LEN];
+        self.inner.read_at(
+            (HEADER_LEN + index as usize * (self.chunk_size + TAG_LEN)) as u64,
+            &mut data,
+        )?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[NONCE_LEN - 8..].copy_from_slice(&index.to_be_bytes());
+        self.key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&self.header),
+                &mut data,
+            )
+            .map_err(|_| {
+                invalid_data(format!(
+                    "Authentication failed for chunk {}: the file was modified or the key is \
+                     wrong",
+                    index
+                ))
+            })?;
+        data.truncate(len);
+        Ok(data)
+    }
+}
+
+impl<S: PageSource> PageSource for EncryptedSource<S> {
+    fn len(&self) -> usize {
+        self.plaintext_len
+    }
+
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()> {
+        let offset =
+            usize::try_from(offset).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        match offset.checked_add(dst.len()) {
+            Some(end) if end <= self.plaintext_len => (),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "Range {:#x}+{:#x} is outside of the page source ({:#x} bytes)",
+                        offset,
+                        dst.len(),
+                        self.plaintext_len
+                    ),
+                ))
+            }
+        }
+
+        let mut cache = self.cache.borrow_mut();
+        let mut done = 0;
+        while done < dst.len() {
+            let pos = offset + done;
+            let index = (pos / self.chunk_size) as u64;
+            if cache.as_ref().map(|chunk| chunk.index) != Some(index) {
+                *cache = Some(CachedChunk {
+                    index,
+                    data: self.decrypt_chunk(index)?,
+                });
+            }
+            // Safe to unwrap because the cache was just filled.
+            let chunk = &cache.as_ref().unwrap().data;
+            let start = pos % self.chunk_size;
+            let count = (chunk.len() - start).min(dst.len() - done);
+            dst[done..done + count].copy_from_slice(&chunk[start..start + count]);
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use aws_lc_rs::rand;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::page_source::BufferSource;
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    // Seals `plaintext` the way Firecracker does.
+    fn encrypt(plaintext: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&(chunk_size as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&(plaintext.len() as u64).to_le_bytes());
+        rand::fill(&mut header[24..40]).unwrap();
+        let okm = Salt::new(HKDF_SHA256, &header[24..40])
+            .extract(&KEY)
+            .expand(KEY_INFO, &AES_256_GCM)
+            .unwrap();
+        let key = LessSafeKey::new(UnboundKey::from(okm));
+
+        let mut out = header.to_vec();
+        for (index, chunk) in plaintext.chunks(chunk_size).enumerate() {
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce[NONCE_LEN - 8..].copy_from_slice(&(index as u64).to_be_bytes());
+            let mut data = chunk.to_vec();
+            let tag = key
+                .seal_in_place_separate_tag(
+                    Nonce::assume_unique_for_key(nonce),
+                    Aad::from(&header),
+                    &mut data,
+                )
+                .unwrap();
+            out.extend_from_slice(&data);
+            out.extend_from_slice(tag.as_ref());
+        }
+        out
+    }
+
+    #[test]
+    fn test_encrypted_source() {
+        let plaintext: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut encrypted = encrypt(&plaintext, 4096);
+        // Sources such as PMem slots are larger than the file.
+        encrypted.extend(vec![0u8; 4096]);
+
+        let source = EncryptedSource::new(BufferSource::new(encrypted.clone()), &KEY).unwrap();
+        assert_eq!(source.len(), plaintext.len());
+        assert!(source.as_ptr().is_none());
+
+        let mut dst = vec![0u8; 6000];
+        source.read_at(4000, &mut dst).unwrap();
+        assert_eq!(dst, plaintext[4000..]);
+        let mut dst = vec![0u8; 100];
+        source.read_at(100, &mut dst).unwrap();
+        assert_eq!(dst, plaintext[100..200]);
+        source.read_at(9950, &mut dst).unwrap_err();
+
+        // Wrong key.
+        let source = EncryptedSource::new(BufferSource::new(encrypted.clone()), &[8; KEY_LEN]);
+        source.unwrap().read_at(0, &mut dst).unwrap_err();
+
+        // Tampered chunk.
+        encrypted[HEADER_LEN + 4096 + TAG_LEN] ^= 1;
+        let source = EncryptedSource::new(BufferSource::new(encrypted.clone()), &KEY).unwrap();
+        source.read_at(0, &mut dst).unwrap();
+        let err = source.read_at(4096, &mut dst).unwrap_err();
+        assert!(err.to_string().contains("chunk 1"));
+
+        // Truncated file.
+        encrypted.truncate(HEADER_LEN + 4096);
+        assert!(EncryptedSource::new(BufferSource::new(encrypted), &KEY).is_err());
+
+        // Plaintext memory file.
+        assert!(EncryptedSource::new(BufferSource::new(plaintext), &KEY).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let encrypted = encrypt(&[1u8; 100], 4096);
+        let header: [u8; HEADER_LEN] = encrypted[..HEADER_LEN].try_into().unwrap();
+        let parsed = Header::from_bytes(&header).unwrap();
+        assert_eq!(parsed.chunk_size, 4096);
+        assert_eq!(parsed.plaintext_len, 100);
+        assert_eq!(parsed.salt, header[24..40]);
+
+        let mut bad = header;
+        bad[7] = b'2';
+        let err = Header::from_bytes(&bad).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("Unsupported encrypted snapshot version"));
+
+        // The reserved word is written as zero.
+        let mut bad = header;
+        bad[13] = 1;
+        assert!(Header::from_bytes(&bad).is_err());
+
+        let mut bad = header;
+        bad[8..12].copy_from_slice(&[0; 4]);
+        assert!(Header::from_bytes(&bad).is_err());
+    }
+
+    #[test]
+    fn test_read_key() {
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&KEY).unwrap();
+        assert_eq!(read_key(file.as_path()).unwrap(), KEY);
+        file.as_file().write_all(&[0]).unwrap();
+        read_key(file.as_path()).unwrap_err();
+    }
+}
@@ -0,0 +1,313 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-VM statistics collected while serving guest page faults.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use utils::signal::register_signal_handler;
+
+/// Number of buckets in a [`LatencyHistogram`]. The last one holds everything above 2^30 us.
+const HISTOGRAM_BUCKETS: usize = 32;
+
+fn as_us(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Microseconds since the UNIX epoch, the clock used for Zipkin span timestamps.
+fn epoch_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(as_us)
+        .unwrap_or(0)
+}
+
+/// One bucket of a [`LatencyHistogram`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HistogramBucket {
+    /// Inclusive upper bound of the bucket, in microseconds.
+    pub le_us: u64,
+    /// Number of samples in the bucket.
+    pub count: u64,
+}
+
+/// Latency histogram with power-of-two microsecond buckets.
+#[derive(Clone, Debug, Serialize)]
+pub struct LatencyHistogram {
+    /// Number of samples.
+    pub count: u64,
+    /// Sum of all samples, in microseconds.
+    pub sum_us: u64,
+    /// Smallest sample, in microseconds.
+    pub min_us: u64,
+    /// Largest sample, in microseconds.
+    pub max_us: u64,
+    #[serde(serialize_with = "serialize_buckets")]
+    buckets: [u64; HISTOGRAM_BUCKETS],
+}
+
+fn non_empty_buckets(
+    buckets: &[u64; HISTOGRAM_BUCKETS],
+) -> impl Iterator<Item = HistogramBucket> + '_ {
+    buckets
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(idx, count)| HistogramBucket {
+            le_us: LatencyHistogram::upper_bound_us(idx),
+            count: *count,
+        })
+}
+
+fn serialize_buckets<S: serde::Serializer>(
+    buckets: &[u64; HISTOGRAM_BUCKETS],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(non_empty_buckets(buckets))
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            count: 0,
+            sum_us: 0,
+            min_us: 0,
+            max_us: 0,
+            buckets: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn bucket(us: u64) -> usize {
+        // Bucket 0 holds samples up to 1us, bucket `i` those in (2^(i-1), 2^i].
+        let idx = match us {
+            0 | 1 => 0,
+            us => (64 - (us - 1).leading_zeros()) as usize,
+        };
+        idx.min(HISTOGRAM_BUCKETS - 1)
+    }
+
+    fn upper_bound_us(idx: usize) -> u64 {
+        if idx == HISTOGRAM_BUCKETS - 1 {
+            u64::MAX
+        } else {
+            1 << idx
+        }
+    }
+
+    /// Records one sample.
+    pub fn record(&mut self, latency: Duration) {
+        let us = as_us(latency);
+        if self.count == 0 || us < self.min_us {
+            self.min_us = us;
+        }
+        self.max_us = self.max_us.max(us);
+        self.count += 1;
+        self.sum_us = self.sum_us.saturating_add(us);
+        self.buckets[Self::bucket(us)] += 1;
+    }
+
+    /// Returns the non-empty buckets.
+    pub fn buckets(&self) -> Vec<HistogramBucket> {
+        non_empty_buckets(&self.buckets).collect()
+    }
+}
+
+/// Counters describing how the guest memory of one microVM was served.
+#[derive(Clone, Debug, Serialize)]
+pub struct FaultStats {
+    /// PID of the Firecracker process owning the guest memory.
+    pub firecracker_pid: u32,
+    /// When the handler received the guest memory, in microseconds since the UNIX epoch.
+    pub session_start_us: u64,
+    /// When the session ended, in microseconds since the UNIX epoch.
+    pub session_end_us: Option<u64>,
+    /// Time between the handshake and the first page fault, in microseconds.
+    pub time_to_first_fault_us: Option<u64>,
+    /// Page faults served.
+    pub faults: u64,
+    /// Pages populated from the page source.
+    pub pages_copied: u64,
+    /// Pages populated with zeroes.
+    pub zero_pages: u64,
+    /// Remove events received (e.g. on balloon inflation).
+    pub remove_events: u64,
+    /// Bytes copied from the page source into the guest.
+    pub bytes_copied: u64,
+    /// Time spent serving each page fault.
+    pub fault_latency: LatencyHistogram,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl FaultStats {
+    /// Starts a session for the Firecracker process `firecracker_pid`.
+    pub fn new(firecracker_pid: u32) -> Self {
+        FaultStats {
+            firecracker_pid,
+            session_start_us: epoch_us(),
+            session_end_us: None,
+            time_to_first_fault_us: None,
+            faults: 0,
+            pages_copied: 0,
+            zero_pages: 0,
+            remove_events: 0,
+            bytes_copied: 0,
+            fault_latency: LatencyHistogram::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records a page fault that arrived at `received` and took `latency` to serve.
+    pub fn record_fault(&mut self, received: Instant, latency: Duration) {
+        if self.time_to_first_fault_us.is_none() {
+            self.time_to_first_fault_us = Some(as_us(received.duration_since(self.started)));
+        }
+        self.faults += 1;
+        self.fault_latency.record(latency);
+    }
+
+    /// Records `bytes` copied from the page source, in pages of `page_size` bytes.
+    pub fn record_copy(&mut self, bytes: usize, page_size: usize) {
+        self.bytes_copied += bytes as u64;
+        self.pages_copied += (bytes / page_size) as u64;
+    }
+
+    /// Records one page populated with zeroes.
+    pub fn record_zero_page(&mut self) {
+        self.zero_pages += 1;
+    }
+
+    /// Records a remove event.
+    pub fn record_remove(&mut self) {
+        self.remove_events += 1;
+    }
+
+    /// Marks the end of the session.
+    pub fn end_session(&mut self) {
+        self.session_end_us = Some(epoch_us());
+    }
+
+    /// Serializes the statistics as JSON.
+    pub fn to_json(&self) -> String {
+        // Safe to unwrap: the structure only holds numbers.
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Writes the statistics as JSON to `path`.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+    }
+}
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_dump(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    DUMP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes signal `signum` (e.g. `SIGUSR1`) request a dump of the statistics of the running
+/// sessions.
+pub fn register_dump_signal(signum: libc::c_int) -> Result<(), utils::errno::Error> {
+    register_signal_handler(signum, request_dump)
+}
+
+/// Returns `true` if a dump was requested since the last call.
+pub fn dump_requested() -> bool {
+    DUMP_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert!(histogram.buckets().is_empty());
+
+        for us in [0, 1, 2, 3, 4, 5, 1000] {
+            histogram.record(Duration::from_micros(us));
+        }
+        histogram.record(Duration::from_secs(1 << 40));
+
+        assert_eq!(histogram.count, 8);
+        assert_eq!(histogram.min_us, 0);
+        assert_eq!(histogram.max_us, (1 << 40) * 1_000_000);
+        assert_eq!(
+            histogram.buckets(),
+            vec![
+                HistogramBucket { le_us: 1, count: 2 },
+                HistogramBucket { le_us: 2, count: 1 },
+                HistogramBucket { le_us: 4, count: 2 },
+                HistogramBucket { le_us: 8, count: 1 },
+                HistogramBucket {
+                    le_us: 1024,
+                    count: 1
+                },
+                HistogramBucket {
+                    le_us: u64::MAX,
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fault_stats() {
+        let mut stats = FaultStats::new(42);
+        assert!(stats.time_to_first_fault_us.is_none());
+
+        stats.record_copy(3 * 4096, 4096);
+        stats.record_zero_page();
+        stats.record_remove();
+        stats.record_fault(Instant::now(), Duration::from_micros(10));
+        stats.record_fault(Instant::now(), Duration::from_micros(20));
+        stats.end_session();
+
+        assert!(stats.time_to_first_fault_us.is_some());
+        assert!(stats.session_end_us.unwrap() >= stats.session_start_us);
+
+        let file = TempFile::new().unwrap();
+        stats.write_to(file.as_path()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_reader(File::open(file.as_path()).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::from_str::<serde_json::Value>(&stats.to_json()).unwrap()
+        );
+
+        assert_eq!(json["firecracker_pid"], 42);
+        assert_eq!(json["faults"], 2);
+        assert_eq!(json["pages_copied"], 3);
+        assert_eq!(json["bytes_copied"], 3 * 4096);
+        assert_eq!(json["zero_pages"], 1);
+        assert_eq!(json["remove_events"], 1);
+        assert_eq!(json["fault_latency"]["count"], 2);
+        assert_eq!(json["fault_latency"]["sum_us"], 30);
+        assert_eq!(
+            json["fault_latency"]["buckets"],
+            serde_json::json!([{"le_us": 16, "count": 1}, {"le_us": 32, "count": 1}])
+        );
+        assert!(json.get("started").is_none());
+    }
+
+    #[test]
+    fn test_dump_signal() {
+        assert!(!dump_requested());
+        register_dump_signal(libc::SIGUSR1).unwrap();
+        // SAFETY: Safe because the handler only stores to an atomic.
+        assert_eq!(unsafe { libc::raise(libc::SIGUSR1) }, 0);
+        assert!(dump_requested());
+        assert!(!dump_requested());
+    }
+}
@@ -1,5 +1,9 @@
 // 
 
+pub mod encrypted_source;
+pub mod fault_stats;
+pub mod page_source;
+pub mod page_state;
 pub mod serve_mem_regions;
 // pub mod pm_check;
 pub mod mem_manager;
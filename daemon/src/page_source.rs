@@ -0,0 +1,341 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backends from which the page fault handler fetches guest memory contents.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+/// Default DAX device holding the snapshot memory slots.
+pub const DAX_DEVICE_PATH: &str = "/dev/dax1.0";
+/// Default location of the index mapping function names to PMem slots.
+pub const SNAPSHOT_INDEX_PATH: &str = "/dev/shm/snapshot_index.json";
+/// Size of a PMem slot holding the guest memory of one snapshot.
+pub const PMEM_SLOT_SIZE: usize = 1 << 30;
+
+/// A source of guest memory contents, addressed by byte offset.
+pub trait PageSource: Send {
+    /// Number of bytes available in the source.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the source holds no data.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `dst` with the contents found at `offset` bytes from the start of the source.
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()>;
+
+    /// Returns a pointer to the contents of the source if they are mapped in this process,
+    /// allowing the handler to copy them into the guest without a bounce buffer.
+    fn as_ptr(&self) -> Option<*const u8> {
+        None
+    }
+}
+
+fn check_range(len: usize, offset: u64, count: usize) -> io::Result<usize> {
+    let start =
+        usize::try_from(offset).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    match start.checked_add(count) {
+        Some(end) if end <= len => Ok(start),
+        _ => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Range {:#x}+{:#x} is outside of the page source ({:#x} bytes)",
+                offset, count, len
+            ),
+        )),
+    }
+}
+
+/// A shared or private mapping of (part of) a file.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: The mapping is owned by this object and only read through shared references.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, offset: u64, len: usize, prot: i32, flags: i32) -> io::Result<Self> {
+        let offset = libc::off_t::try_from(offset)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // SAFETY: Safe because we check the return value and the fd is valid for the
+        // duration of the call.
+        let addr =
+            unsafe { libc::mmap(ptr::null_mut(), len, prot, flags, file.as_raw_fd(), offset) };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr.cast(),
+            len,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: `addr` points to a live mapping of `len` bytes.
+        unsafe { std::slice::from_raw_parts(self.addr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `addr` and `len` describe a mapping we own.
+        unsafe {
+            libc::munmap(self.addr.cast(), self.len);
+        }
+    }
+}
+
+/// Guest memory served from a privately mapped snapshot memory file.
+pub struct MmapFileSource {
+    mapping: Mapping,
+}
+
+impl MmapFileSource {
+    /// Maps the whole memory file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mapping = Mapping::new(&file, 0, len, libc::PROT_READ, libc::MAP_PRIVATE)?;
+        Ok(MmapFileSource { mapping })
+    }
+}
+
+impl PageSource for MmapFileSource {
+    fn len(&self) -> usize {
+        self.mapping.len
+    }
+
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()> {
+        let start = check_range(self.mapping.len, offset, dst.len())?;
+        dst.copy_from_slice(&self.mapping.as_slice()[start..start + dst.len()]);
+        Ok(())
+    }
+
+    fn as_ptr(&self) -> Option<*const u8> {
+        Some(self.mapping.addr)
+    }
+}
+
+/// Guest memory served from a slot of a byte-addressable PMem (DAX) device.
+pub struct PmemSlotSource {
+    mapping: Mapping,
+}
+
+impl PmemSlotSource {
+    /// Maps `len` bytes of the DAX device at `path`, starting at `offset`.
+    pub fn open<P: AsRef<Path>>(path: P, offset: u64, len: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mapping = Mapping::new(
+            &file,
+            offset,
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+        )?;
+        Ok(PmemSlotSource { mapping })
+    }
+
+    /// Maps the slot assigned to function `name` in the snapshot index at `index_path`.
+    pub fn from_index<P: AsRef<Path>, Q: AsRef<Path>>(
+        dax_path: P,
+        index_path: Q,
+        name: &str,
+    ) -> io::Result<Self> {
+        let offset = snapshot_slot_offset(index_path, name)?;
+        Self::open(dax_path, offset, PMEM_SLOT_SIZE)
+    }
+
+    /// Touches every page of the slot so that the page table entries mapping it are in
+    /// place before the first guest page fault is served.
+    pub fn prefault(&self) {
+        let page_size = utils::get_page_size().unwrap();
+        let data = self.mapping.as_slice();
+        for offset in (0..data.len()).step_by(page_size) {
+            // SAFETY: `offset` is within the mapping.
+            unsafe { ptr::read_volatile(data.as_ptr().add(offset)) };
+        }
+    }
+}
+
+impl PageSource for PmemSlotSource {
+    fn len(&self) -> usize {
+        self.mapping.len
+    }
+
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()> {
+        let start = check_range(self.mapping.len, offset, dst.len())?;
+        dst.copy_from_slice(&self.mapping.as_slice()[start..start + dst.len()]);
+        Ok(())
+    }
+
+    fn as_ptr(&self) -> Option<*const u8> {
+        Some(self.mapping.addr)
+    }
+}
+
+/// Guest memory held in a buffer owned by this process.
+///
+/// Used to exercise the handler without any backing file or PMem device.
+pub struct BufferSource {
+    data: Vec<u8>,
+}
+
+impl BufferSource {
+    /// Creates a source serving `data`.
+    pub fn new(data: Vec<u8>) -> Self {
+        BufferSource { data }
+    }
+}
+
+impl PageSource for BufferSource {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()> {
+        let start = check_range(self.data.len(), offset, dst.len())?;
+        dst.copy_from_slice(&self.data[start..start + dst.len()]);
+        Ok(())
+    }
+}
+
+/// Guest memory served by positioned reads from a file, for sources that should not be mapped.
+pub struct FileSource {
+    file: File,
+    len: usize,
+}
+
+impl FileSource {
+    /// Serves the contents `file` holds when the source is created.
+    pub fn new(file: File) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(FileSource { file, len })
+    }
+
+    /// Opens the memory file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl PageSource for FileSource {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&self, offset: u64, dst: &mut [u8]) -> io::Result<()> {
+        check_range(self.len, offset, dst.len())?;
+        self.file.read_exact_at(dst, offset)
+    }
+}
+
+/// Returns the byte offset on the DAX device of the slot holding the snapshot memory of
+/// function `name`, as recorded in the index at `index_path`.
+pub fn snapshot_slot_offset<P: AsRef<Path>>(index_path: P, name: &str) -> io::Result<u64> {
+    let file = File::open(index_path)?;
+    let index: serde_json::Value = serde_json::from_reader(file)?;
+    let slot = index
+        .get(name)
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No snapshot memory slot for {}", name),
+            )
+        })?;
+    Ok(slot << 30)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_buffer_source() {
+        let source = BufferSource::new((0..16).collect());
+        assert_eq!(source.len(), 16);
+        assert!(source.as_ptr().is_none());
+
+        let mut dst = [0u8; 4];
+        source.read_at(12, &mut dst).unwrap();
+        assert_eq!(dst, [12, 13, 14, 15]);
+        source.read_at(13, &mut dst).unwrap_err();
+        source.read_at(u64::MAX, &mut dst).unwrap_err();
+    }
+
+    #[test]
+    fn test_mmap_file_source() {
+        let data: Vec<u8> = (0..8192u32).map(|i| (i % 251) as u8).collect();
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&data).unwrap();
+
+        let source = MmapFileSource::open(file.as_path()).unwrap();
+        assert_eq!(source.len(), data.len());
+
+        let mut dst = vec![0u8; 4096];
+        source.read_at(4096, &mut dst).unwrap();
+        assert_eq!(dst, data[4096..]);
+        // SAFETY: The pointer covers the whole mapped file.
+        let mapped = unsafe { std::slice::from_raw_parts(source.as_ptr().unwrap(), data.len()) };
+        assert_eq!(mapped, &data[..]);
+
+        // Positioned reads serve the same contents.
+        let source = FileSource::open(file.as_path()).unwrap();
+        assert_eq!(source.len(), data.len());
+        let mut dst_file = vec![0u8; 4096];
+        source.read_at(4096, &mut dst_file).unwrap();
+        assert_eq!(dst_file, dst);
+        source.read_at(4097, &mut dst_file).unwrap_err();
+    }
+
+    #[test]
+    fn test_pmem_slot_source() {
+        // A regular file stands in for the DAX device.
+        let page_size = utils::get_page_size().unwrap();
+        let data: Vec<u8> = (0..3 * page_size).map(|i| (i % 7) as u8).collect();
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&data).unwrap();
+
+        let source = PmemSlotSource::open(file.as_path(), page_size as u64, page_size).unwrap();
+        source.prefault();
+        let mut dst = vec![0u8; page_size];
+        source.read_at(0, &mut dst).unwrap();
+        assert_eq!(dst, data[page_size..2 * page_size]);
+        source.read_at(1, &mut dst).unwrap_err();
+    }
+
+    #[test]
+    fn test_snapshot_slot_offset() {
+        let index = TempFile::new().unwrap();
+        index
+            .as_file()
+            .write_all(br#"{"image": 0, "recognition": 3}"#)
+            .unwrap();
+
+        assert_eq!(snapshot_slot_offset(index.as_path(), "image").unwrap(), 0);
+        assert_eq!(
+            snapshot_slot_offset(index.as_path(), "recognition").unwrap(),
+            3 << 30
+        );
+        assert_eq!(
+            snapshot_slot_offset(index.as_path(), "matmul")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
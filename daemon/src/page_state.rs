@@ -0,0 +1,160 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compact tracking of the state of guest memory pages.
+
+use std::ops::Range;
+
+/// Number of bits used to store the state of one page.
+const BITS_PER_PAGE: usize = 2;
+/// Number of pages whose state fits in one word.
+const PAGES_PER_WORD: usize = u64::BITS as usize / BITS_PER_PAGE;
+const STATE_MASK: u64 = (1 << BITS_PER_PAGE) - 1;
+/// Repeating this pattern `state` times over a word stores `state` for all of its pages.
+const WORD_PATTERN: u64 = 0x5555_5555_5555_5555;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MemPageState {
+    Uninitialized = 0,
+    FromFile = 1,
+    Removed = 2,
+    Anonymous = 3,
+}
+
+impl MemPageState {
+    fn from_bits(bits: u64) -> Self {
+        match bits & STATE_MASK {
+            0 => MemPageState::Uninitialized,
+            1 => MemPageState::FromFile,
+            2 => MemPageState::Removed,
+            _ => MemPageState::Anonymous,
+        }
+    }
+}
+
+/// State of every page of a guest memory region, packed in 2 bits per page.
+///
+/// A 1 GiB region of 4 KiB pages takes 64 KiB.
+#[derive(Clone, Debug)]
+pub struct PageStateMap {
+    pages: usize,
+    words: Vec<u64>,
+}
+
+impl PageStateMap {
+    /// Creates a map of `pages` pages, all of them `Uninitialized`.
+    pub fn new(pages: usize) -> Self {
+        PageStateMap {
+            pages,
+            words: vec![0; (pages + PAGES_PER_WORD - 1) / PAGES_PER_WORD],
+        }
+    }
+
+    /// Number of pages tracked.
+    pub fn len(&self) -> usize {
+        self.pages
+    }
+
+    /// Returns `true` if no pages are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.pages == 0
+    }
+
+    /// Returns the state of page `page`, or `None` if it is out of range.
+    pub fn get(&self, page: usize) -> Option<MemPageState> {
+        if page >= self.pages {
+            return None;
+        }
+        let shift = (page % PAGES_PER_WORD) * BITS_PER_PAGE;
+        Some(MemPageState::from_bits(
+            self.words[page / PAGES_PER_WORD] >> shift,
+        ))
+    }
+
+    fn set_in_word(&mut self, page: usize, state: MemPageState) {
+        let shift = (page % PAGES_PER_WORD) * BITS_PER_PAGE;
+        let word = &mut self.words[page / PAGES_PER_WORD];
+        *word = (*word & !(STATE_MASK << shift)) | ((state as u64) << shift);
+    }
+
+    /// Sets the state of the pages in `pages`, clamped to the tracked range.
+    pub fn set_range(&mut self, pages: Range<usize>, state: MemPageState) {
+        let end = pages.end.min(self.pages);
+        let mut page = pages.start;
+
+        // Pages up to the first word boundary.
+        while page < end && page % PAGES_PER_WORD != 0 {
+            self.set_in_word(page, state);
+            page += 1;
+        }
+        // Whole words.
+        let fill = WORD_PATTERN * state as u64;
+        while page + PAGES_PER_WORD <= end {
+            self.words[page / PAGES_PER_WORD] = fill;
+            page += PAGES_PER_WORD;
+        }
+        // Remaining pages.
+        while page < end {
+            self.set_in_word(page, state);
+            page += 1;
+        }
+    }
+
+    /// Number of pages in state `state`.
+    pub fn count(&self, state: MemPageState) -> usize {
+        (0..self.pages)
+            .filter(|page| self.get(*page) == Some(state))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let map = PageStateMap::new(100);
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.words.len(), 4);
+        assert_eq!(map.get(0), Some(MemPageState::Uninitialized));
+        assert_eq!(map.get(99), Some(MemPageState::Uninitialized));
+        assert_eq!(map.get(100), None);
+        assert_eq!(map.count(MemPageState::Uninitialized), 100);
+
+        assert!(PageStateMap::new(0).is_empty());
+    }
+
+    #[test]
+    fn test_set_range() {
+        let mut map = PageStateMap::new(100);
+
+        // Spans a partial word, two whole words and another partial word.
+        map.set_range(5..80, MemPageState::FromFile);
+        assert_eq!(map.get(4), Some(MemPageState::Uninitialized));
+        assert_eq!(map.get(5), Some(MemPageState::FromFile));
+        assert_eq!(map.get(79), Some(MemPageState::FromFile));
+        assert_eq!(map.get(80), Some(MemPageState::Uninitialized));
+        assert_eq!(map.count(MemPageState::FromFile), 75);
+
+        // Single pages only touch their own bits.
+        map.set_range(40..41, MemPageState::Removed);
+        assert_eq!(map.get(39), Some(MemPageState::FromFile));
+        assert_eq!(map.get(40), Some(MemPageState::Removed));
+        assert_eq!(map.get(41), Some(MemPageState::FromFile));
+
+        map.set_range(40..41, MemPageState::Anonymous);
+        assert_eq!(map.get(40), Some(MemPageState::Anonymous));
+
+        // Ranges past the end are clamped.
+        map.set_range(90..1000, MemPageState::Removed);
+        assert_eq!(map.count(MemPageState::Removed), 10);
+        map.set_range(200..300, MemPageState::Removed);
+        assert_eq!(map.count(MemPageState::Removed), 10);
+
+        // Whole map.
+        map.set_range(0..100, MemPageState::Uninitialized);
+        assert_eq!(map.count(MemPageState::Uninitialized), 100);
+    }
+}
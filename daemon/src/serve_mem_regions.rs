@@ -1,17 +1,28 @@
 // Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
-use std::ffi::CString;
-use std::collections::HashMap;
-use std::fs::File;
-use serde_json::{json, Value};
+
+//! Userspace page fault handling for guest memory restored from a snapshot.
+//!
+//! The handler receives the userfaultfd and the guest region mappings from Firecracker over
+//! a Unix domain socket and serves faults from a [`PageSource`].
+
+use std::io;
+use std::mem;
+use std::ops::Range;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::{mem, ptr};
-use std::slice;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
 use serde::Deserialize;
-use userfaultfd::Uffd;
+use userfaultfd::{Event, Uffd};
 use utils::get_page_size;
 use utils::sock_ctrl_msg::ScmSocket;
+
+use crate::fault_stats::{self, FaultStats};
+use crate::page_source::PageSource;
+pub use crate::page_state::MemPageState;
+use crate::page_state::PageStateMap;
 // ------------rust-pmem------------
 // extern crate pmem;
 // extern crate rand;
@@ -23,6 +34,59 @@ use utils::sock_ctrl_msg::ScmSocket;
 // use pmem::pmap::PersistentMap;
 // use rand::{Rng, thread_rng};
 
+/// Amount of guest memory copied at once when the page source is not mapped.
+const BOUNCE_BUFFER_SIZE: usize = 2 << 20;
+
+/// Errors associated with serving guest page faults.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to bind or accept on the handler socket.
+    #[error("Failed to accept a connection on the handler socket: {0}")]
+    Accept(io::Error),
+    /// Failed to receive the handshake message.
+    #[error("Failed to receive the memory mappings and uffd: {0}")]
+    Handshake(utils::errno::Error),
+    /// Failed to deserialize the guest region mappings.
+    #[error("Cannot deserialize memory mappings: {0}")]
+    Mappings(serde_json::Error),
+    /// The handshake message carried no file descriptor.
+    #[error("Uffd not passed through UDS")]
+    MissingUffd,
+    /// Failed to obtain the credentials of the Firecracker process.
+    #[error("Failed to get peer process credentials: {0}")]
+    PeerCredentials(io::Error),
+    /// Failed to poll the userfaultfd.
+    #[error("Could not poll for events: {0}")]
+    Poll(io::Error),
+    /// A guest region lies (partially) outside of the page source.
+    #[error("Guest region {offset:#x}+{size:#x} is outside of the page source ({len:#x} bytes)")]
+    SourceTooSmall {
+        /// Offset of the region in the page source.
+        offset: u64,
+        /// Size of the region.
+        size: usize,
+        /// Size of the page source.
+        len: usize,
+    },
+    /// Failed to read from the page source.
+    #[error("Failed to read from the page source: {0}")]
+    Source(io::Error),
+    /// A userfaultfd operation failed.
+    #[error("Uffd operation failed: {0}")]
+    Uffd(userfaultfd::Error),
+    /// An event other than a page fault or a remove was received.
+    #[error("Unexpected event on userfaultfd: {0}")]
+    UnexpectedEvent(String),
+    /// The faulting address is not covered by any guest region.
+    #[error("Could not find addr {0:#x} within guest region mappings")]
+    UnknownAddress(u64),
+    /// Failed to write the fault statistics.
+    #[error("Failed to write fault statistics: {0}")]
+    WriteStats(io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
 // This is the same with the one used in src/vmm.
 /// This describes the mapping between Firecracker base virtual address and offset in the
 /// buffer or file backend for a guest memory region. It is used to tell an external
@@ -43,143 +107,296 @@ pub struct GuestRegionUffdMapping {
 
 struct MemRegion {
     mapping: GuestRegionUffdMapping,
-    page_states: HashMap<u64, MemPageState>,
+    page_states: PageStateMap,
+}
+
+impl MemRegion {
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.mapping.base_host_virt_addr
+            && addr - self.mapping.base_host_virt_addr < self.mapping.size as u64
+    }
+
+    /// Indices of the pages of this region overlapping `[start, end)`.
+    fn page_range(&self, start: u64, end: u64, page_size: usize) -> Range<usize> {
+        let base = self.mapping.base_host_virt_addr;
+        let start = start.max(base) - base;
+        let end = end.min(base + self.mapping.size as u64).max(base) - base;
+        let page_size = page_size as u64;
+        (start / page_size) as usize..((end + page_size - 1) / page_size) as usize
+    }
 }
 
 pub struct UffdPfHandler {
     mem_regions: Vec<MemRegion>,
-    backing_buffer: *const u8,
+    source: Box<dyn PageSource>,
+    bounce_buffer: Vec<u8>,
+    page_size: usize,
+    stats: FaultStats,
+    stats_path: Option<PathBuf>,
     pub uffd: Uffd,
     // Not currently used but included to demonstrate how a page fault handler can
     // fetch Firecracker's PID in order to make it aware of any crashes/exits.
     _firecracker_pid: u32,
 }
 
-#[derive(Clone)]
-pub enum MemPageState {
-    Uninitialized,
-    FromFile,
-    Removed,
-    Anonymous,
-}
-
 impl UffdPfHandler {
-    pub fn from_unix_stream(stream: UnixStream, data: *const u8, size: usize) -> Self {
+    /// Creates a handler serving faults on `uffd` for the regions described by `mappings`,
+    /// with their contents fetched from `source`.
+    pub fn new(
+        uffd: Uffd,
+        mappings: &[GuestRegionUffdMapping],
+        source: Box<dyn PageSource>,
+        firecracker_pid: u32,
+    ) -> Result<Self> {
+        // Make sure every region is backed by the page source.
+        for mapping in mappings {
+            let in_bounds = usize::try_from(mapping.offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(mapping.size))
+                .map_or(false, |end| end <= source.len());
+            if !in_bounds {
+                return Err(Error::SourceTooSmall {
+                    offset: mapping.offset,
+                    size: mapping.size,
+                    len: source.len(),
+                });
+            }
+        }
+
+        let bounce_buffer = if source.as_ptr().is_some() {
+            Vec::new()
+        } else {
+            vec![0u8; BOUNCE_BUFFER_SIZE]
+        };
+
+        Ok(Self {
+            mem_regions: create_mem_regions(mappings),
+            source,
+            bounce_buffer,
+            page_size: get_page_size().unwrap(),
+            stats: FaultStats::new(firecracker_pid),
+            stats_path: None,
+            uffd,
+            _firecracker_pid: firecracker_pid,
+        })
+    }
+
+    /// Receives the uffd and the guest region mappings sent by Firecracker on `stream`.
+    pub fn from_unix_stream(stream: UnixStream, source: Box<dyn PageSource>) -> Result<Self> {
         let mut message_buf = vec![0u8; 1024];
         let (bytes_read, file) = stream
             .recv_with_fd(&mut message_buf[..])
-            .expect("Cannot recv_with_fd");
+            .map_err(Error::Handshake)?;
         message_buf.resize(bytes_read, 0);
 
-        let body = String::from_utf8(message_buf).unwrap();
-        let file = file.expect("Uffd not passed through UDS!");
+        let file = file.ok_or(Error::MissingUffd)?;
+        let mappings = serde_json::from_slice::<Vec<GuestRegionUffdMapping>>(&message_buf)
+            .map_err(Error::Mappings)?;
 
-        let mappings = serde_json::from_str::<Vec<GuestRegionUffdMapping>>(&body)
-            .expect("Cannot deserialize memory mappings.");
-        let memsize: usize = mappings.iter().map(|r| r.size).sum();
+        // SAFETY: The fd was just received and is owned by nobody else.
+        let uffd = unsafe { Uffd::from_raw_fd(file.into_raw_fd()) };
 
-        // Make sure memory size matches backing data size.
-        assert_eq!(memsize, size);
+        let creds: libc::ucred = get_peer_process_credentials(&stream)?;
 
-        let uffd = unsafe { Uffd::from_raw_fd(file.into_raw_fd()) };
+        Self::new(uffd, &mappings, source, creds.pid as u32)
+    }
 
-        let creds: libc::ucred = get_peer_process_credentials(stream);
+    /// Statistics of the session served by this handler.
+    pub fn stats(&self) -> &FaultStats {
+        &self.stats
+    }
 
-        let mem_regions = create_mem_regions(&mappings);
+    /// Writes the statistics to `path` when a dump is requested and when the session ends.
+    pub fn set_stats_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.stats_path = Some(path.into());
+    }
 
-        Self {
-            mem_regions,
-            backing_buffer: data,
-            uffd,
-            _firecracker_pid: creds.pid as u32,
+    fn write_stats(&self) -> Result<()> {
+        match &self.stats_path {
+            Some(path) => self.stats.write_to(path).map_err(Error::WriteStats),
+            None => Ok(()),
         }
     }
 
     pub fn update_mem_state_mappings(&mut self, start: u64, end: u64, state: &MemPageState) {
         for region in self.mem_regions.iter_mut() {
-            for (key, value) in region.page_states.iter_mut() {
-                if key >= &start && key < &end {
-                    *value = state.clone();
-                }
-            }
+            let pages = region.page_range(start, end, self.page_size);
+            region.page_states.set_range(pages, *state);
         }
     }
 
-    fn populate_from_file(&self, region: &MemRegion) -> (u64, u64) {
-        let src = self.backing_buffer as u64 + region.mapping.offset;
-        let start_addr = region.mapping.base_host_virt_addr;
-        let len = region.mapping.size;
-        // Populate whole region from backing mem-file.
+    fn populate_from_source(&mut self, region_idx: usize) -> Result<(u64, u64)> {
+        let mapping = &self.mem_regions[region_idx].mapping;
+        let start_addr = mapping.base_host_virt_addr;
+        let len = mapping.size;
+        // Populate whole region from the page source.
         // This offers an example of how memory can be loaded in RAM,
         // however this can be adjusted to accommodate use case needs.
-        let ret = unsafe {
-            self.uffd
-                .copy(src as *const _, start_addr as *mut _, len, true)
-                .expect("Uffd copy failed")
-        };
-
-        // Make sure the UFFD copied some bytes.
-        assert!(ret > 0);
+        if let Some(base) = self.source.as_ptr() {
+            // SAFETY: The region was checked against the size of the source when the
+            // handler was created.
+            let src = unsafe { base.add(mapping.offset as usize) };
+            // SAFETY: `src` is valid for `len` bytes and the destination range is
+            // registered with the uffd.
+            let ret = unsafe {
+                self.uffd
+                    .copy(src.cast(), start_addr as *mut _, len, true)
+                    .map_err(Error::Uffd)?
+            };
+            // Make sure the UFFD copied some bytes.
+            assert!(ret > 0);
+            self.stats.record_copy(len, self.page_size);
+        } else {
+            let mut done = 0;
+            while done < len {
+                let count = std::cmp::min(len - done, self.bounce_buffer.len());
+                let buf = &mut self.bounce_buffer[..count];
+                self.source
+                    .read_at(mapping.offset + done as u64, buf)
+                    .map_err(Error::Source)?;
+                // SAFETY: `buf` is valid for `count` bytes and the destination range is
+                // registered with the uffd.
+                let ret = unsafe {
+                    self.uffd
+                        .copy(
+                            buf.as_ptr().cast(),
+                            (start_addr + done as u64) as *mut _,
+                            count,
+                            true,
+                        )
+                        .map_err(Error::Uffd)?
+                };
+                assert!(ret > 0);
+                self.stats.record_copy(count, self.page_size);
+                done += count;
+            }
+        }
 
-        return (start_addr, start_addr + len as u64);
+        Ok((start_addr, start_addr + len as u64))
     }
 
-    fn zero_out(&mut self, addr: u64) -> (u64, u64) {
-        let page_size = get_page_size().unwrap();
-
+    fn zero_out(&mut self, addr: u64) -> Result<(u64, u64)> {
+        // SAFETY: The page is part of a range registered with the uffd.
         let ret = unsafe {
             self.uffd
-                .zeropage(addr as *mut _, page_size, true)
-                .expect("Uffd zeropage failed")
+                .zeropage(addr as *mut _, self.page_size, true)
+                .map_err(Error::Uffd)?
         };
         // Make sure the UFFD zeroed out some bytes.
         assert!(ret > 0);
+        self.stats.record_zero_page();
 
-        return (addr, addr + page_size as u64);
+        Ok((addr, addr + self.page_size as u64))
     }
 
-    pub fn serve_pf(&mut self, addr: *mut u8) {
-        let page_size = get_page_size().unwrap();
-
+    pub fn serve_pf(&mut self, addr: *mut u8) -> Result<()> {
         // Find the start of the page that the current faulting address belongs to.
-        let dst = (addr as usize & !(page_size as usize - 1)) as *mut libc::c_void;
-        let fault_page_addr = dst as u64;
+        let fault_page_addr = addr as u64 & !(self.page_size as u64 - 1);
 
         // Get the state of the current faulting page.
-        for region in self.mem_regions.iter() {
-            match region.page_states.get(&fault_page_addr) {
-                // Our simple PF handler has a simple strategy:
-                // There exist 4 states in which a memory page can be in:
-                // 1. Uninitialized - page was never touched
-                // 2. FromFile - the page is populated with content from snapshotted memory file
-                // 3. Removed - MADV_DONTNEED was called due to balloon inflation
-                // 4. Anonymous - page was zeroed out -> this implies that more than one page fault
-                //    event was received. This can be a consequence of guest reclaiming back its
-                //    memory from the host (through balloon device)
-                Some(MemPageState::Uninitialized) | Some(MemPageState::FromFile) => {
-                    let (start, end) = self.populate_from_file(region);
-                    self.update_mem_state_mappings(start, end, &MemPageState::FromFile);
-                    return;
-                }
-                Some(MemPageState::Removed) | Some(MemPageState::Anonymous) => {
-                    let (start, end) = self.zero_out(fault_page_addr);
-                    self.update_mem_state_mappings(start, end, &MemPageState::Anonymous);
-                    return;
-                }
-                None => {
-                    ();
-                }
+        let found = self
+            .mem_regions
+            .iter()
+            .enumerate()
+            .find(|(_, region)| region.contains(fault_page_addr))
+            .and_then(|(idx, region)| {
+                let page = (fault_page_addr - region.mapping.base_host_virt_addr) as usize
+                    / self.page_size;
+                region.page_states.get(page).map(|state| (idx, state))
+            });
+
+        match found {
+            // Our simple PF handler has a simple strategy:
+            // There exist 4 states in which a memory page can be in:
+            // 1. Uninitialized - page was never touched
+            // 2. FromFile - the page is populated with content from snapshotted memory file
+            // 3. Removed - MADV_DONTNEED was called due to balloon inflation
+            // 4. Anonymous - page was zeroed out -> this implies that more than one page fault
+            //    event was received. This can be a consequence of guest reclaiming back its
+            //    memory from the host (through balloon device)
+            Some((idx, MemPageState::Uninitialized)) | Some((idx, MemPageState::FromFile)) => {
+                let (start, end) = self.populate_from_source(idx)?;
+                self.update_mem_state_mappings(start, end, &MemPageState::FromFile);
+                Ok(())
             }
+            Some((_, MemPageState::Removed)) | Some((_, MemPageState::Anonymous)) => {
+                let (start, end) = self.zero_out(fault_page_addr)?;
+                self.update_mem_state_mappings(start, end, &MemPageState::Anonymous);
+                Ok(())
+            }
+            None => Err(Error::UnknownAddress(addr as u64)),
         }
+    }
 
-        panic!(
-            "Could not find addr: {:?} within guest region mappings.",
-            addr
-        );
+    /// Handles one event read from the userfaultfd.
+    pub fn handle_event(&mut self, event: Event) -> Result<()> {
+        // We expect to receive either a Page Fault or Removed
+        // event (if the balloon device is enabled).
+        match event {
+            Event::Pagefault { addr, .. } => {
+                let received = Instant::now();
+                self.serve_pf(addr.cast())?;
+                self.stats.record_fault(received, received.elapsed());
+                Ok(())
+            }
+            Event::Remove { start, end } => {
+                self.stats.record_remove();
+                self.update_mem_state_mappings(start as u64, end as u64, &MemPageState::Removed);
+                Ok(())
+            }
+            event => Err(Error::UnexpectedEvent(format!("{:?}", event))),
+        }
+    }
+
+    /// Serves events on the userfaultfd until the Firecracker process goes away.
+    ///
+    /// The statistics are written out whenever a dump is requested through
+    /// [`fault_stats::register_dump_signal`] and once the session ends.
+    pub fn run(&mut self) -> Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd: self.uffd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        loop {
+            // The signal may have arrived while faults were being served, rather than while
+            // waiting in poll().
+            if fault_stats::dump_requested() {
+                self.write_stats()?;
+            }
+
+            // See what poll() tells us about the userfaultfd.
+            // SAFETY: `pollfd` is a valid pollfd structure.
+            let nready = unsafe { libc::poll(&mut pollfd, 1, -1) };
+            if nready == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // Keep the statistics gathered so far. The poll error is the one reported.
+                self.stats.end_session();
+                let _ = self.write_stats();
+                return Err(Error::Poll(err));
+            }
+
+            if pollfd.revents & libc::POLLIN == 0 {
+                // POLLHUP or POLLERR: the guest memory is gone.
+                self.stats.end_session();
+                return self.write_stats();
+            }
+
+            // Read an event from the userfaultfd.
+            match self.uffd.read_event().map_err(Error::Uffd)? {
+                Some(event) => self.handle_event(event)?,
+                // Another reader consumed the event.
+                None => continue,
+            }
+        }
     }
 }
 
-fn get_peer_process_credentials(stream: UnixStream) -> libc::ucred {
+fn get_peer_process_credentials(stream: &UnixStream) -> Result<libc::ucred> {
     let mut creds: libc::ucred = libc::ucred {
         pid: 0,
         gid: 0,
@@ -187,6 +404,7 @@ fn get_peer_process_credentials(stream: UnixStream) -> libc::ucred {
     };
     let mut creds_size = mem::size_of::<libc::ucred>() as u32;
 
+    // SAFETY: `creds` and `creds_size` are valid for the size passed in.
     let ret = unsafe {
         libc::getsockopt(
             stream.as_raw_fd(),
@@ -197,29 +415,20 @@ fn get_peer_process_credentials(stream: UnixStream) -> libc::ucred {
         )
     };
     if ret != 0 {
-        panic!("Failed to get peer process credentials");
+        return Err(Error::PeerCredentials(io::Error::last_os_error()));
     }
 
-    creds
+    Ok(creds)
 }
 
-fn create_mem_regions(mappings: &Vec<GuestRegionUffdMapping>) -> Vec<MemRegion> {
+fn create_mem_regions(mappings: &[GuestRegionUffdMapping]) -> Vec<MemRegion> {
     let page_size = get_page_size().unwrap();
     let mut mem_regions: Vec<MemRegion> = Vec::with_capacity(mappings.len());
 
     for r in mappings.iter() {
-        let mapping = r.clone();
-        let mut addr = r.base_host_virt_addr;
-        let end_addr = r.base_host_virt_addr + r.size as u64;
-        let mut page_states = HashMap::new();
-
-        while addr < end_addr {
-            page_states.insert(addr, MemPageState::Uninitialized);
-            addr += page_size as u64;
-        }
         mem_regions.push(MemRegion {
-            mapping,
-            page_states,
+            mapping: r.clone(),
+            page_states: PageStateMap::new((r.size + page_size - 1) / page_size),
         });
     }
 
@@ -268,140 +477,17 @@ fn create_mem_regions(mappings: &Vec<GuestRegionUffdMapping>) -> Vec<MemRegion>
 //     Ok(())
 // }
 
-pub fn create_pf_handler() -> UffdPfHandler {
-    let uffd_sock_path = std::env::args().nth(1).expect("No socket path given");
-    let mem4fun = std::env::args().nth(2).expect("No snapshot memory given"); //e.g., "recognition"
-    // // check the state difference between the origianal snapshot memory file and the byte-adressable snapshot memory on PMem
-    // let func_snap_pos:HashMap<&str, i64> = HashMap::from([
-    //     ("image", 0),
-    //     ("rnn", 1),
-    //     ("ffmpeg", 2),
-    //     ("recognition", 3),
-    //     ("pagerank", 4),
-    //     ("mobilenet", 5),
-    //     ("compression", 6),
-    //     ("sentiment", 7),
-    //     ("json", 8),
-    //     ("pyaes", 9),
-    //     ("chameleon", 10),
-    //     ("matmul", 11)
-    //   ]);
-    
-    //   let funcs =["image", "rnn", "ffmpeg", "recognition",
-    //             "pagerank", "mobilenet", "compression", "sentiment", 
-    //             "json", "pyaes", "chameleon", "matmul"];
-    
-    //   let mut kv_tuples: Vec<(&str, usize)> = Vec::new();
-    //   for (i, func) in funcs.iter().enumerate() {
-    //     // println!("hash[{}]={}, i: {}", func,func_snap_pos[func], i);
-    //     assert!(func_snap_pos[func] == i as i64);
-    //   }
-      
-    // let check_str = mem_file_path.as_str();
-    // let parts = check_str.split("/").collect::<Vec<_>>();
-    // let func = parts[parts.len()-2];
-    // let value = func_snap_pos.get(&func).cloned().unwrap_or(-1);
-    // if value >= 0 {
-    //     println!("func {} at {}", func, value<<30);
-    // }
-    // else{
-    //     println!("func {} not found", func);
-    //     std::process::exit(0); 
-    // }
-
-    let file = File::open("/dev/shm/snapshot_index.json").unwrap();
-    let func_snap_pos: Value = serde_json::from_reader(file).unwrap();
-    // let search_key="matmul";
-    // if !func_snap_pos.contains_key(search_key) {
-    //     println!("Can not locate & refer to the snapshot memory of {}.", search_key);
-    // } 
-
-    // Check the hash table for snapshot memory index
-    for (key, value) in func_snap_pos.as_object().unwrap().iter() {
-        let value = value.as_i64().unwrap();
-        println!("{}: {}", key, value);
-        // assert_eq!(*func_snap_pos.get(key.as_str()).unwrap(), value as usize);
-    }
-
-    // first address of VM snapshot memory for "recognition" workload on PMem
-    // println!("test {}",mem4fun);
-    // let add_addr=func_snap_pos["recognition"].as_i64().unwrap()<<30;
-    let add_addr=func_snap_pos[mem4fun].as_i64().unwrap()<<30;
-    // let file = File::open(mem_file_path).expect("Cannot open memfile");
-    // let size = file.metadata().unwrap().len() as usize;
-    // // mmap a memory area used to bring in the faulting regions.
-    // let ret = unsafe {
-    //     libc::mmap(
-    //         ptr::null_mut(),
-    //         size,
-    //         libc::PROT_READ,
-    //         libc::MAP_PRIVATE,
-    //         file.as_raw_fd(),
-    //         0,
-    //     )
-    // };
-    // if ret == libc::MAP_FAILED {
-    //     panic!("mmap failed");
-    // }
-    // let memfile_buffer = ret as *const u8;
-    let path_cstring = CString::new("/dev/dax1.0").unwrap();
-    let dax_fd = unsafe { libc::open(path_cstring.as_ptr(), libc::O_RDWR) };
-
-    if dax_fd < 0 {
-        panic!("memory file open failed");
-    }
-    
-    let mut size = 1_073_741_824;
-    let dax_addr = unsafe {
-        libc::mmap(
-            std::ptr::null_mut(),
-            1_073_741_824,
-            libc::PROT_READ|libc::PROT_WRITE,
-            libc::MAP_SHARED, //libc::MAP_SHARED|libc::MAP_POPULATE,
-            dax_fd,
-            add_addr //idx * 1024 * 1024 * 1024,
-        )
-    };
-    if dax_addr == libc::MAP_FAILED {
-        panic!("mmap failed");
-    }
-    println!("dax_addr: {:p}", dax_addr);
-    // ==== page frames to pages =====
-    // let add_addr=value<<30;
-    // let memfile_buffer = unsafe {dax_addr.add(4096) } as *const u8;
-    // size = 1_073_741_824+(value<<30) as usize;
-    /// emulate the page table forked from the PM manager
-    let PAGE_SIZE = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
-    let total_pages = size / PAGE_SIZE;
-    let mut cnt=0;
-    // deliberately trigger page faults by accessing all virtual pages. 
-    // establish the actual MMU page mappings 
-    for page_id in 0..total_pages {
-        let page_offset = page_id * PAGE_SIZE;
-        let page_addr = unsafe { dax_addr.add(page_offset) } as *mut u8;
-
-        let page_data = page_id.to_ne_bytes(); // Convert page ID to byte array
-        let read_data = unsafe {
-            slice::from_raw_parts(page_addr, page_data.len())
-        };
-        // println!("page_id: {}, page_addr: {:p}, page_data: {:?}, read_data: {:?}", page_id, page_addr, page_data, read_data);
-        // if read_data != page_data {
-        //     panic!("Data mismatch at page ID {}", page_id);
-        // }
-    }
-
-    println!("loaded data");
-
-    let memfile_buffer = dax_addr  as *const u8;
-    println!("mem_buffer: {:p}", memfile_buffer);
-    
-    let len_size = 1_073_741_824;
+/// Listens on `uffd_sock_path` for the Firecracker process restoring a snapshot and returns a
+/// handler serving its guest memory from `source`.
+pub fn create_pf_handler<P: AsRef<Path>>(
+    uffd_sock_path: P,
+    source: Box<dyn PageSource>,
+) -> Result<UffdPfHandler> {
     // Get Uffd from UDS. We'll use the uffd to handle PFs for Firecracker.
-    let listener = UnixListener::bind(&uffd_sock_path).expect("Cannot bind to socket path");
-
-    let (stream, _) = listener.accept().expect("Cannot listen on UDS socket");
+    let listener = UnixListener::bind(uffd_sock_path).map_err(Error::Accept)?;
+    let (stream, _) = listener.accept().map_err(Error::Accept)?;
 
-    UffdPfHandler::from_unix_stream(stream, memfile_buffer, len_size)
+    UffdPfHandler::from_unix_stream(stream, source)
 }
 
 #[cfg(test)]
@@ -429,6 +515,163 @@ mod tests {
     // use pmem::is_pmem;
     // use pmem::persistentmap::PersistentMap;
 
+    use userfaultfd::UffdBuilder;
+
+    use crate::page_source::BufferSource;
+
+    struct AnonRegion {
+        addr: *mut u8,
+        len: usize,
+    }
+
+    impl AnonRegion {
+        fn new(len: usize) -> Self {
+            let addr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            assert_ne!(addr, libc::MAP_FAILED);
+            AnonRegion {
+                addr: addr.cast(),
+                len,
+            }
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.addr, self.len) }
+        }
+    }
+
+    impl Drop for AnonRegion {
+        fn drop(&mut self) {
+            unsafe { libc::munmap(self.addr.cast(), self.len) };
+        }
+    }
+
+    fn register(region: &AnonRegion) -> Uffd {
+        let uffd = UffdBuilder::new()
+            .close_on_exec(true)
+            .non_blocking(true)
+            .user_mode_only(true)
+            .create()
+            .unwrap();
+        uffd.register(region.addr.cast(), region.len).unwrap();
+        uffd
+    }
+
+    // Reads the byte at `addr` from another thread, serving the resulting fault.
+    fn touch(handler: &mut UffdPfHandler, addr: u64) -> u8 {
+        let reader = spawn(move || unsafe { std::ptr::read_volatile(addr as *const u8) });
+
+        let mut pollfd = libc::pollfd {
+            fd: handler.uffd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 5000) }, 1);
+        let event = handler.uffd.read_event().unwrap().unwrap();
+        handler.handle_event(event).unwrap();
+
+        reader.join().unwrap()
+    }
+
+    #[test]
+    fn test_serve_pf_from_buffer_source() {
+        let page_size = get_page_size().unwrap();
+        let region = AnonRegion::new(4 * page_size);
+        let data: Vec<u8> = (0..region.len).map(|i| (i / page_size + 1) as u8).collect();
+        let uffd = register(&region);
+
+        let base = region.addr as u64;
+        let mappings = [GuestRegionUffdMapping {
+            base_host_virt_addr: base,
+            size: region.len,
+            offset: 0,
+        }];
+        let source = Box::new(BufferSource::new(data.clone()));
+        let mut handler = UffdPfHandler::new(uffd, &mappings, source, 0).unwrap();
+
+        // The first fault brings in the whole region.
+        assert_eq!(touch(&mut handler, base + 2 * page_size as u64 + 1), 3);
+        assert_eq!(region.as_slice(), &data[..]);
+
+        // Pages given back by the guest are served as zero pages.
+        let removed = base + page_size as u64;
+        assert_eq!(
+            unsafe { libc::madvise(removed as *mut _, page_size, libc::MADV_DONTNEED) },
+            0
+        );
+        handler
+            .handle_event(Event::Remove {
+                start: removed as *mut _,
+                end: (removed + page_size as u64) as *mut _,
+            })
+            .unwrap();
+        assert_eq!(touch(&mut handler, removed), 0);
+        assert!(region.as_slice()[page_size..2 * page_size]
+            .iter()
+            .all(|b| *b == 0));
+        assert_eq!(region.as_slice()[..page_size], data[..page_size]);
+        assert_eq!(region.as_slice()[2 * page_size..], data[2 * page_size..]);
+
+        let states = &handler.mem_regions[0].page_states;
+        assert_eq!(states.get(0), Some(MemPageState::FromFile));
+        assert_eq!(states.get(1), Some(MemPageState::Anonymous));
+        assert_eq!(states.count(MemPageState::FromFile), 3);
+
+        let stats = handler.stats();
+        assert_eq!(stats.faults, 2);
+        assert_eq!(stats.pages_copied, 4);
+        assert_eq!(stats.bytes_copied, region.len as u64);
+        assert_eq!(stats.zero_pages, 1);
+        assert_eq!(stats.remove_events, 1);
+        assert_eq!(stats.fault_latency.count, 2);
+        assert!(stats.time_to_first_fault_us.is_some());
+    }
+
+    #[test]
+    fn test_region_outside_source() {
+        let page_size = get_page_size().unwrap();
+        let region = AnonRegion::new(2 * page_size);
+        let uffd = register(&region);
+
+        let mappings = [GuestRegionUffdMapping {
+            base_host_virt_addr: region.addr as u64,
+            size: region.len,
+            offset: page_size as u64,
+        }];
+        let source = Box::new(BufferSource::new(vec![0u8; 2 * page_size]));
+        assert!(matches!(
+            UffdPfHandler::new(uffd, &mappings, source, 0),
+            Err(Error::SourceTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_address() {
+        let page_size = get_page_size().unwrap();
+        let region = AnonRegion::new(page_size);
+        let uffd = register(&region);
+
+        let mappings = [GuestRegionUffdMapping {
+            base_host_virt_addr: region.addr as u64,
+            size: region.len,
+            offset: 0,
+        }];
+        let source = Box::new(BufferSource::new(vec![0u8; page_size]));
+        let mut handler = UffdPfHandler::new(uffd, &mappings, source, 0).unwrap();
+        let outside = (region.addr as u64 + page_size as u64) as *mut u8;
+        assert!(matches!(
+            handler.serve_pf(outside),
+            Err(Error::UnknownAddress(_))
+        ));
+    }
 
     #[test]
     fn observe_pm() {
@@ -0,0 +1,253 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authorization of API requests based on the credentials of the connected peer.
+//!
+//! The policy is a list of rules, each allowing the processes matching its `uid`, `gid` and
+//! `pid` to send requests with the listed methods on the listed endpoints. A request is allowed
+//! if at least one rule allows it. Fields left out of a rule match anything.
+
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use micro_http::Method;
+use serde::Deserialize;
+
+/// Errors associated with loading the API access policy.
+#[derive(Debug, thiserror::Error)]
+pub enum AccessPolicyError {
+    /// The policy file cannot be read.
+    #[error("Cannot read the API access policy: {0}")]
+    Read(std::io::Error),
+    /// The policy file is not a valid policy.
+    #[error("Cannot parse the API access policy: {0}")]
+    Parse(serde_json::Error),
+}
+
+/// Credentials of the process on the other end of an API connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// PID of the peer process.
+    pub pid: i32,
+    /// Effective UID of the peer process.
+    pub uid: u32,
+    /// Effective GID of the peer process.
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    /// Reads the credentials of the peer connected to the Unix socket `fd`.
+    pub fn from_fd(fd: RawFd) -> std::io::Result<Self> {
+        let mut ucred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `ucred` and `len` are valid for writes and `len` holds the size of `ucred`.
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: ucred.pid,
+            uid: ucred.uid,
+            gid: ucred.gid,
+        })
+    }
+}
+
+/// HTTP method allowed by an access rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AccessMethod {
+    /// GET requests.
+    Get,
+    /// PUT requests.
+    Put,
+    /// PATCH requests.
+    Patch,
+}
+
+impl AccessMethod {
+    fn matches(self, method: Method) -> bool {
+        matches!(
+            (self, method),
+            (AccessMethod::Get, Method::Get)
+                | (AccessMethod::Put, Method::Put)
+                | (AccessMethod::Patch, Method::Patch)
+        )
+    }
+}
+
+/// Rule allowing a set of peers to send a set of requests.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    /// UID the peer must run as.
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// GID the peer must run as.
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// PID of the peer.
+    #[serde(default)]
+    pub pid: Option<i32>,
+    /// Methods the peer may use.
+    #[serde(default)]
+    pub methods: Option<Vec<AccessMethod>>,
+    /// Endpoints the peer may send requests on. An endpoint also covers the paths below it, so
+    /// `/snapshot` covers `/snapshot/create`.
+    #[serde(default)]
+    pub endpoints: Option<Vec<String>>,
+}
+
+impl AccessRule {
+    fn allows(&self, peer: &PeerCredentials, method: Method, path: &str) -> bool {
+        self.uid.map_or(true, |uid| uid == peer.uid)
+            && self.gid.map_or(true, |gid| gid == peer.gid)
+            && self.pid.map_or(true, |pid| pid == peer.pid)
+            && self.methods.as_ref().map_or(true, |methods| {
+                methods.iter().any(|allowed| allowed.matches(method))
+            })
+            && self.endpoints.as_ref().map_or(true, |endpoints| {
+                endpoints
+                    .iter()
+                    .any(|endpoint| endpoint_covers(endpoint, path))
+            })
+    }
+}
+
+fn endpoint_covers(endpoint: &str, path: &str) -> bool {
+    let endpoint = endpoint.trim_end_matches('/');
+    match path.strip_prefix(endpoint) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Policy deciding which peers may send which API requests.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicy {
+    /// Rules allowing requests. Requests no rule allows are denied.
+    pub rules: Vec<AccessRule>,
+}
+
+impl AccessPolicy {
+    /// Loads the policy from the JSON file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, AccessPolicyError> {
+        let json = fs::read_to_string(path).map_err(AccessPolicyError::Read)?;
+        serde_json::from_str(&json).map_err(AccessPolicyError::Parse)
+    }
+
+    /// Returns whether `peer` may send a `method` request on `path`.
+    pub fn allows(&self, peer: &PeerCredentials, method: Method, path: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.allows(peer, method, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    const POLICY: &str = r#"{
+        "rules": [
+            { "uid": 1000, "methods": ["GET"] },
+            { "uid": 0, "gid": 0, "endpoints": ["/snapshot", "/vm/"] }
+        ]
+    }"#;
+
+    #[test]
+    fn test_peer_credentials() {
+        let (sock, _peer) = UnixStream::pair().unwrap();
+        let peer = PeerCredentials::from_fd(sock.as_raw_fd()).unwrap();
+        // SAFETY: These calls cannot fail.
+        unsafe {
+            assert_eq!(peer.pid, libc::getpid());
+            assert_eq!(peer.uid, libc::geteuid());
+            assert_eq!(peer.gid, libc::getegid());
+        }
+
+        assert!(PeerCredentials::from_fd(-1).is_err());
+    }
+
+    #[test]
+    fn test_access_policy() {
+        let policy: AccessPolicy = serde_json::from_str(POLICY).unwrap();
+        let monitor = PeerCredentials {
+            pid: 42,
+            uid: 1000,
+            gid: 1000,
+        };
+        let root = PeerCredentials {
+            pid: 1,
+            uid: 0,
+            gid: 0,
+        };
+        let other = PeerCredentials {
+            pid: 43,
+            uid: 1001,
+            gid: 0,
+        };
+
+        assert!(policy.allows(&monitor, Method::Get, "/vm/config"));
+        assert!(!policy.allows(&monitor, Method::Put, "/snapshot/create"));
+        assert!(policy.allows(&root, Method::Patch, "/vm"));
+        assert!(policy.allows(&root, Method::Put, "/snapshot/create"));
+        assert!(!policy.allows(&root, Method::Put, "/snapshots"));
+        assert!(!policy.allows(&root, Method::Put, "/actions"));
+        assert!(!policy.allows(&other, Method::Get, "/"));
+
+        // An empty policy denies everything, an empty rule allows everything.
+        assert!(!AccessPolicy::default().allows(&root, Method::Get, "/"));
+        let policy = AccessPolicy {
+            rules: vec![AccessRule::default()],
+        };
+        assert!(policy.allows(&other, Method::Put, "/actions"));
+    }
+
+    #[test]
+    fn test_access_policy_from_file() {
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(POLICY.as_bytes()).unwrap();
+        let policy = AccessPolicy::from_file(file.as_path()).unwrap();
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(policy.rules[0].methods, Some(vec![AccessMethod::Get]));
+
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(b"{}").unwrap();
+        assert!(matches!(
+            AccessPolicy::from_file(file.as_path()),
+            Err(AccessPolicyError::Parse(_))
+        ));
+        assert!(matches!(
+            AccessPolicy::from_file(Path::new("/nonexistent")),
+            Err(AccessPolicyError::Read(_))
+        ));
+
+        let policy: Result<AccessPolicy, _> =
+            serde_json::from_str(r#"{ "rules": [{ "user": 0 }] }"#);
+        assert!(policy.is_err());
+        let policy: Result<AccessPolicy, _> =
+            serde_json::from_str(r#"{ "rules": [{ "methods": ["POST"] }] }"#);
+        assert!(policy.is_err());
+    }
+}
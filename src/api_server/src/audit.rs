@@ -0,0 +1,401 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Audit log of the API requests.
+//!
+//! Each request served by the API server is written as a single line JSON record holding the
+//! peer which sent it, the request, the resulting `VmmAction` and the response. Secrets carried
+//! by request bodies, such as snapshot encryption keys and the MMDS contents, are redacted.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use logger::{warn, IncMetric, METRICS};
+use micro_http::{Request, Response};
+use serde::Serialize;
+use serde_json::Value;
+use utils::time::{get_time_us, ClockType};
+use vmm::rpc_interface::VmmAction;
+
+use crate::access::PeerCredentials;
+
+/// Placeholder written instead of redacted values.
+const REDACTED: &str = "<redacted>";
+/// Placeholder written instead of the values dropped to keep a record short enough.
+const TRUNCATED: &str = "<truncated>";
+/// Maximum length of a record, line ending included. Writes of up to `PIPE_BUF` bytes to a pipe
+/// are atomic, so records from several writers sharing a pipe do not interleave.
+const MAX_RECORD_LEN: usize = libc::PIPE_BUF;
+/// Fields of the request bodies whose values are redacted, wherever they are nested.
+const REDACTED_FIELDS: [&str; 2] = ["key", "body"];
+/// Endpoints whose request bodies are redacted as a whole.
+const REDACTED_PATHS: [&str; 1] = ["/mmds"];
+
+/// What the API server did to serve a request, as reported in its audit record.
+#[derive(Debug, Default)]
+pub(crate) struct RequestOutcome {
+    /// Name of the `VmmAction` the request resulted in.
+    pub action: Option<String>,
+    /// Time spent waiting for the VMM to serve the action, in microseconds.
+    pub serve_us: Option<u64>,
+}
+
+impl RequestOutcome {
+    /// Records `vmm_action` as the action the request resulted in.
+    pub fn set_action(&mut self, vmm_action: &VmmAction) {
+        self.action = Some(action_name(vmm_action));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp_us: u64,
+    peer_pid: Option<i32>,
+    method: String,
+    path: &'a str,
+    body: Option<Value>,
+    action: Option<&'a str>,
+    status: u16,
+    fault_message: Option<String>,
+    serve_us: Option<u64>,
+}
+
+/// Destination of the audit records, a regular file or a named pipe.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating it if it does not exist.
+    ///
+    /// Named pipes are opened in non blocking mode, so that a full pipe fails the writes instead
+    /// of stalling the API server. Opening a named pipe fails if no process reads it.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(AuditLog {
+            file: open(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Writes the record of `request`, received on the connection `peer_fd`, and of its
+    /// `response`.
+    pub(crate) fn record(
+        &mut self,
+        peer_fd: RawFd,
+        request: &Request,
+        response: &Response,
+        outcome: &RequestOutcome,
+    ) {
+        let path = request.uri().get_abs_path();
+        let mut record = AuditRecord {
+            timestamp_us: get_time_us(ClockType::Real),
+            peer_pid: PeerCredentials::from_fd(peer_fd).ok().map(|peer| peer.pid),
+            method: format!("{:?}", request.method()).to_uppercase(),
+            path,
+            body: request
+                .body
+                .as_ref()
+                .map(|body| redacted_body(path, body.raw())),
+            action: outcome.action.as_deref(),
+            status: status_code(response),
+            fault_message: fault_message(response),
+            serve_us: outcome.serve_us,
+        };
+
+        // The body, the fault message and the path are the only fields of unbounded length.
+        let mut line = serialize(&record);
+        if line.len() > MAX_RECORD_LEN {
+            record.body = record.body.map(|_| Value::from(TRUNCATED));
+            record.fault_message = record.fault_message.map(|_| TRUNCATED.to_string());
+            line = serialize(&record);
+        }
+        if line.len() > MAX_RECORD_LEN {
+            record.path = TRUNCATED;
+            line = serialize(&record);
+        }
+
+        if let Err(err) = self.write_record(line.as_bytes()) {
+            METRICS.api_server.audit_log_fails.inc();
+            warn!("Failed to write to the API audit log: {}", err);
+        }
+    }
+
+    fn write_record(&mut self, line: &[u8]) -> io::Result<()> {
+        match write_once(&mut self.file, line) {
+            // The process reading the named pipe may have been restarted since the last record.
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                self.file = open(&self.path)?;
+                write_once(&mut self.file, line)
+            }
+            result => result,
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .custom_flags(libc::O_NONBLOCK)
+        .append(true)
+        .create(true)
+        .open(path)
+}
+
+// Writes `line` with a single write, so that records are not interleaved with the ones of other
+// writers. A record only partially written is a failure.
+fn write_once(file: &mut File, line: &[u8]) -> io::Result<()> {
+    let written = file.write(line)?;
+    if written < line.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!(
+                "wrote {} of the {} bytes of the record",
+                written,
+                line.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Serializes `record` as a line.
+fn serialize(record: &AuditRecord) -> String {
+    // The record only holds strings, integers and JSON values, so it always serializes.
+    let mut line = serde_json::to_string(record).unwrap();
+    line.push('\n');
+    line
+}
+
+// Returns the body of a request on `path`, with its secrets redacted.
+fn redacted_body(path: &str, body: &[u8]) -> Value {
+    if REDACTED_PATHS.contains(&path) {
+        return Value::from(REDACTED);
+    }
+    // Bodies which are not JSON cannot be checked for secrets.
+    match serde_json::from_slice(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value
+        }
+        Err(_) => Value::from(REDACTED),
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (field, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&field.as_str()) && !value.is_null() {
+                    *value = Value::from(REDACTED);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+fn status_code(response: &Response) -> u16 {
+    let raw = response.status().raw();
+    raw.iter()
+        .fold(0, |code, digit| code * 10 + u16::from(digit - b'0'))
+}
+
+fn fault_message(response: &Response) -> Option<String> {
+    let body = response.body()?;
+    let value: Value = serde_json::from_slice(body.raw()).ok()?;
+    value.get("fault_message")?.as_str().map(str::to_string)
+}
+
+// Returns the name of the `vmm_action` variant, without its payload.
+fn action_name(vmm_action: &VmmAction) -> String {
+    let debug = format!("{:?}", vmm_action);
+    let end = debug
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(debug.len());
+    debug[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    use micro_http::{Body, HttpConnection, StatusCode, Version};
+    use serde_json::json;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_redacted_body() {
+        let body = br#"{
+            "snapshot_path": "vm.snap",
+            "encryption": { "key": "00112233", "key_path": null },
+            "nested": [{ "body": "payload" }]
+        }"#;
+        assert_eq!(
+            redacted_body("/snapshot/load", body),
+            json!({
+                "snapshot_path": "vm.snap",
+                "encryption": { "key": REDACTED, "key_path": null },
+                "nested": [{ "body": REDACTED }]
+            })
+        );
+        assert_eq!(
+            redacted_body("/mmds", br#"{ "token": "secret" }"#),
+            json!(REDACTED)
+        );
+        assert_eq!(redacted_body("/drives/root", b"not json"), json!(REDACTED));
+    }
+
+    #[test]
+    fn test_action_name() {
+        assert_eq!(action_name(&VmmAction::Pause), "Pause");
+        assert_eq!(
+            action_name(&VmmAction::GetBlockDevice("root".to_string())),
+            "GetBlockDevice"
+        );
+        assert_eq!(
+            action_name(&VmmAction::PatchMMDS(json!({ "key": "value" }))),
+            "PatchMMDS"
+        );
+    }
+
+    #[test]
+    fn test_record() {
+        let file = TempFile::new().unwrap();
+        let mut audit_log = AuditLog::from_path(file.as_path()).unwrap();
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let peer_fd = receiver.as_raw_fd();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /invoke HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 38\r\n\r\n\
+                { \"guest_port\": 52, \"body\": \"secret\" }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let request = connection.pop_parsed_request().unwrap();
+
+        let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
+        response.set_body(Body::new(r#"{ "fault_message": "No vsock." }"#));
+        let mut outcome = RequestOutcome::default();
+        outcome.set_action(&VmmAction::GetInvokeTarget);
+        outcome.serve_us = Some(42);
+        audit_log.record(peer_fd, &request, &response, &outcome);
+        audit_log.record(-1, &request, &response, &RequestOutcome::default());
+
+        let mut contents = String::new();
+        file.as_file().read_to_string(&mut contents).unwrap();
+        let records: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+
+        let record = &records[0];
+        assert!(record["timestamp_us"].as_u64().unwrap() > 0);
+        assert_eq!(record["peer_pid"], json!(std::process::id()));
+        assert_eq!(record["method"], "PUT");
+        assert_eq!(record["path"], "/invoke");
+        assert_eq!(
+            record["body"],
+            json!({ "guest_port": 52, "body": REDACTED })
+        );
+        assert_eq!(record["action"], "GetInvokeTarget");
+        assert_eq!(record["status"], 400);
+        assert_eq!(record["fault_message"], "No vsock.");
+        assert_eq!(record["serve_us"], 42);
+
+        let record = &records[1];
+        assert!(record["peer_pid"].is_null());
+        assert!(record["action"].is_null());
+        assert!(record["serve_us"].is_null());
+    }
+
+    #[test]
+    fn test_record_truncated() {
+        let file = TempFile::new().unwrap();
+        let mut audit_log = AuditLog::from_path(file.as_path()).unwrap();
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = format!(
+            r#"{{ "kernel_image_path": "{}" }}"#,
+            "a".repeat(MAX_RECORD_LEN)
+        );
+        sender
+            .write_all(
+                format!(
+                    "PUT /boot-source HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let request = connection.pop_parsed_request().unwrap();
+
+        let response = Response::new(Version::Http11, StatusCode::NoContent);
+        audit_log.record(-1, &request, &response, &RequestOutcome::default());
+
+        let mut contents = String::new();
+        file.as_file().read_to_string(&mut contents).unwrap();
+        assert!(contents.len() <= MAX_RECORD_LEN);
+        let record: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(record["path"], "/boot-source");
+        assert_eq!(record["body"], TRUNCATED);
+    }
+
+    #[test]
+    fn test_named_pipe() {
+        let mut fifo = TempFile::new().unwrap();
+        fifo.remove().unwrap();
+        let path = CString::new(fifo.as_path().to_str().unwrap()).unwrap();
+        // SAFETY: `path` is a valid C string, and the return value is checked.
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+        let open_reader = || {
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(fifo.as_path())
+                .unwrap()
+        };
+
+        // Nothing reads the pipe.
+        assert_eq!(
+            AuditLog::from_path(fifo.as_path())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENXIO)
+        );
+
+        let reader = open_reader();
+        let mut audit_log = AuditLog::from_path(fifo.as_path()).unwrap();
+        // The reader is restarted between two records.
+        drop(reader);
+        let mut reader = open_reader();
+        audit_log.write_record(b"record\n").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 7);
+        assert_eq!(&buf[..7], b"record\n");
+
+        // The reader is gone for good.
+        drop(reader);
+        assert!(audit_log.write_record(b"record\n").is_err());
+        std::fs::remove_file(fifo.as_path()).unwrap();
+    }
+}
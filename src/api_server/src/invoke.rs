@@ -0,0 +1,419 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding of function invocations to an HTTP server of the guest.
+//!
+//! The invocation is sent through the Unix socket on which the vsock device accepts host
+//! initiated connections: the API server asks the device to connect to the guest port, then
+//! exchanges a single HTTP/1.1 request and response with the guest. The VMM thread moves the
+//! data between the socket and the guest meanwhile, so it must not be the one waiting for the
+//! response. The whole exchange must complete within the timeout of the invocation, so that
+//! the API thread is not held for longer, however the guest paces its response.
+
+use std::io::{self, BufRead, BufReader, Read, Take, Write};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use utils::time::{get_time_us, ClockType};
+use vmm::vmm_config::invoke::{InvokeConfig, InvokeTarget};
+
+/// Maximum size of the guest response, headers included.
+const MAX_RESPONSE_LEN: u64 = 1 << 20;
+
+/// Errors associated with forwarding a function invocation to the guest.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum InvokeError {
+    /// Cannot connect to the Unix socket of the vsock device.
+    #[error("Cannot connect to the vsock device: {0}")]
+    Connect(io::Error),
+    /// Nothing accepted the connection on the guest port.
+    #[error("The guest did not accept the connection on vsock port {0}")]
+    Refused(u32),
+    /// Failed to send the request to the guest.
+    #[error("Failed to send the invocation to the guest: {0}")]
+    Send(io::Error),
+    /// Failed to receive the response of the guest.
+    #[error("Failed to receive the guest response: {0}")]
+    Receive(io::Error),
+    /// The response of the guest is not a valid HTTP response.
+    #[error("Invalid guest response: {0}")]
+    InvalidResponse(&'static str),
+    /// The response of the guest is too large.
+    #[error("The guest response is larger than {0} bytes")]
+    ResponseTooLarge(u64),
+}
+
+/// Response of the guest to a function invocation.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct InvokeResponse {
+    /// The HTTP status code of the guest response.
+    pub status_code: u16,
+    /// The body of the guest response, with invalid UTF-8 sequences replaced.
+    pub body: String,
+    /// Time from the start of the snapshot restore to the guest response, in microseconds.
+    /// Only present if the microVM was restored from a snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore_to_response_us: Option<u64>,
+    /// Time spent forwarding the invocation and waiting for the guest response, in
+    /// microseconds.
+    pub invoke_us: u64,
+}
+
+// The connection to the vsock device, whose reads and writes fail once `deadline` passes.
+#[derive(Clone, Copy)]
+struct DeadlineStream<'a> {
+    stream: &'a UnixStream,
+    deadline: Instant,
+}
+
+impl DeadlineStream<'_> {
+    fn remaining(&self) -> io::Result<Duration> {
+        self.deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))
+    }
+}
+
+// Reads and writes on a socket with a timeout fail with `WouldBlock` once it expires.
+fn timed_out(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => io::Error::from(io::ErrorKind::TimedOut),
+        _ => err,
+    }
+}
+
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        let mut stream = self.stream;
+        stream.read(buf).map_err(timed_out)
+    }
+}
+
+impl Write for DeadlineStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        let mut stream = self.stream;
+        stream.write(buf).map_err(timed_out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Forwards the invocation described by `config` to the guest, and waits for its response.
+pub(crate) fn invoke(
+    target: &InvokeTarget,
+    config: &InvokeConfig,
+) -> Result<InvokeResponse, InvokeError> {
+    let start_us = get_time_us(ClockType::Monotonic);
+    let deadline = Instant::now() + Duration::from_millis(config.timeout_ms);
+    let stream = UnixStream::connect(&target.uds_path).map_err(InvokeError::Connect)?;
+    let mut conn = DeadlineStream {
+        stream: &stream,
+        deadline,
+    };
+    let mut reader = BufReader::new(conn.take(MAX_RESPONSE_LEN));
+
+    // The vsock device answers with the host side port once connected to the guest, or
+    // closes the connection.
+    conn.write_all(format!("CONNECT {}\n", config.guest_port).as_bytes())
+        .map_err(InvokeError::Send)?;
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(InvokeError::Receive)?;
+    if !line.starts_with("OK ") {
+        return Err(InvokeError::Refused(config.guest_port));
+    }
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        config.path,
+        config.content_type,
+        config.body.len()
+    );
+    conn.write_all(request.as_bytes())
+        .and_then(|()| conn.write_all(config.body.as_bytes()))
+        .map_err(InvokeError::Send)?;
+    let (status_code, body) = read_response(&mut reader)?;
+
+    let end_us = get_time_us(ClockType::Monotonic);
+    Ok(InvokeResponse {
+        status_code,
+        body: String::from_utf8_lossy(&body).into_owned(),
+        restore_to_response_us: target
+            .restore_start_us
+            .map(|restore_start_us| end_us.saturating_sub(restore_start_us)),
+        invoke_us: end_us - start_us,
+    })
+}
+
+// Read an HTTP/1.1 response, whose body is delimited by its length, by chunks or by the end of
+// the connection. Returns the status code and the body.
+fn read_response<R: Read>(reader: &mut BufReader<Take<R>>) -> Result<(u16, Vec<u8>), InvokeError> {
+    let status_line = read_line(reader)?;
+    let status_code = status_line
+        .strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.split(' ').nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(InvokeError::InvalidResponse("invalid status line"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(InvokeError::InvalidResponse("invalid header"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| InvokeError::InvalidResponse("invalid content length"))?,
+            );
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let line = read_line(reader)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| InvokeError::InvalidResponse("invalid chunk size"))?;
+            if size == 0 {
+                // Skip the trailers.
+                while !read_line(reader)?.is_empty() {}
+                break;
+            }
+            read_body(reader, size, &mut body)?;
+            if !read_line(reader)?.is_empty() {
+                return Err(InvokeError::InvalidResponse("invalid chunk"));
+            }
+        }
+    } else if let Some(len) = content_length {
+        read_body(reader, len, &mut body)?;
+    } else {
+        reader
+            .read_to_end(&mut body)
+            .map_err(InvokeError::Receive)?;
+        if reader.get_ref().limit() == 0 {
+            return Err(InvokeError::ResponseTooLarge(MAX_RESPONSE_LEN));
+        }
+    }
+    Ok((status_code, body))
+}
+
+// Read a line of the response, without its line ending.
+fn read_line<R: Read>(reader: &mut BufReader<Take<R>>) -> Result<String, InvokeError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(InvokeError::Receive)?;
+    match line.strip_suffix('\n') {
+        Some(line) => Ok(line.strip_suffix('\r').unwrap_or(line).to_string()),
+        None => Err(truncated(reader)),
+    }
+}
+
+// Read `len` bytes of the body of the response.
+fn read_body<R: Read>(
+    reader: &mut BufReader<Take<R>>,
+    len: u64,
+    body: &mut Vec<u8>,
+) -> Result<(), InvokeError> {
+    let read = reader
+        .by_ref()
+        .take(len)
+        .read_to_end(body)
+        .map_err(InvokeError::Receive)?;
+    if (read as u64) < len {
+        return Err(truncated(reader));
+    }
+    Ok(())
+}
+
+// The error for a response which ended early, which is too large if it hit the size limit.
+fn truncated<R>(reader: &BufReader<Take<R>>) -> InvokeError {
+    if reader.get_ref().limit() == 0 {
+        InvokeError::ResponseTooLarge(MAX_RESPONSE_LEN)
+    } else {
+        InvokeError::InvalidResponse("the response ended early")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn parse(response: &str) -> Result<(u16, Vec<u8>), InvokeError> {
+        read_response(&mut BufReader::new(
+            response.as_bytes().take(MAX_RESPONSE_LEN),
+        ))
+    }
+
+    #[test]
+    fn test_read_response() {
+        assert_eq!(
+            parse("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap(),
+            (200, b"hello".to_vec())
+        );
+        assert_eq!(
+            parse(
+                "HTTP/1.1 201 Created\r\ntransfer-encoding: chunked\r\n\r\n3;ext\r\nhel\r\n2\r\nlo\
+                 \r\n0\r\nTrailer: 1\r\n\r\n"
+            )
+            .unwrap(),
+            (201, b"hello".to_vec())
+        );
+        assert_eq!(
+            parse("HTTP/1.0 500 Internal Server Error\nServer: test\n\nhello").unwrap(),
+            (500, b"hello".to_vec())
+        );
+
+        for response in [
+            "",
+            "HTTP/2 200 OK\r\n\r\n",
+            "HTTP/1.1 OK\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nz\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhello\r\n0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(response), Err(InvokeError::InvalidResponse(_))),
+                "{:?}",
+                response
+            );
+        }
+
+        let large = format!(
+            "HTTP/1.1 200 OK\r\n\r\n{}",
+            "a".repeat(MAX_RESPONSE_LEN as usize)
+        );
+        assert!(matches!(
+            parse(&large),
+            Err(InvokeError::ResponseTooLarge(MAX_RESPONSE_LEN))
+        ));
+    }
+
+    #[test]
+    fn test_invoke() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let uds_path = tmp_sock_file.as_path().to_str().unwrap().to_string();
+        let listener = UnixListener::bind(&uds_path).unwrap();
+        let target = InvokeTarget {
+            uds_path,
+            restore_start_us: Some(0),
+        };
+        let config = InvokeConfig {
+            guest_port: 5000,
+            path: "/invoke".to_string(),
+            body: "hello".to_string(),
+            content_type: "text/plain".to_string(),
+            timeout_ms: 1000,
+        };
+
+        // Play the vsock device and the guest.
+        let guest = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "CONNECT 5000\n");
+            stream.write_all(b"OK 1073741824\n").unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\nhello") {
+                let mut byte = [0u8];
+                reader.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("POST /invoke HTTP/1.1\r\n"));
+            assert!(request.contains("Content-Type: text/plain\r\n"));
+            assert!(request.contains("Content-Length: 5\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nworld")
+                .unwrap();
+
+            // The guest port is closed.
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+        });
+
+        let response = invoke(&target, &config).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, "world");
+        assert!(response.restore_to_response_us.unwrap() >= response.invoke_us);
+
+        assert!(matches!(
+            invoke(&target, &config),
+            Err(InvokeError::Refused(5000))
+        ));
+        guest.join().unwrap();
+
+        let target = InvokeTarget {
+            uds_path: "/invalid/path".to_string(),
+            restore_start_us: None,
+        };
+        assert!(matches!(
+            invoke(&target, &config),
+            Err(InvokeError::Connect(_))
+        ));
+    }
+
+    #[test]
+    fn test_invoke_deadline() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let uds_path = tmp_sock_file.as_path().to_str().unwrap().to_string();
+        let listener = UnixListener::bind(&uds_path).unwrap();
+        let target = InvokeTarget {
+            uds_path,
+            restore_start_us: None,
+        };
+        let config = InvokeConfig {
+            guest_port: 5000,
+            path: "/invoke".to_string(),
+            body: String::new(),
+            content_type: "text/plain".to_string(),
+            timeout_ms: 100,
+        };
+
+        // A guest sending its response a byte at a time, each within the timeout.
+        let guest = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"OK 1073741824\n").unwrap();
+            for _ in 0..100 {
+                thread::sleep(Duration::from_millis(10));
+                if stream.write_all(b"H").is_err() {
+                    break;
+                }
+            }
+        });
+
+        let start = Instant::now();
+        assert!(matches!(
+            invoke(&target, &config),
+            Err(InvokeError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        guest.join().unwrap();
+    }
+}
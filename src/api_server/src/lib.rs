@@ -7,14 +7,20 @@
 //! and responding to the user.
 //! It is constructed on top of an HTTP Server that uses Unix Domain Sockets and `EPOLL` to
 //! handle multiple connections on the same thread.
+pub mod access;
+pub mod audit;
+mod invoke;
+mod operations;
 mod parsed_request;
 mod request;
 
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 use std::sync::mpsc;
 
 use logger::{
-    debug, error, info, update_metric_with_elapsed_time, warn, ProcessTimeReporter, METRICS,
+    debug, error, info, update_metric_with_elapsed_time, warn, IncMetric, ProcessTimeReporter,
+    METRICS,
 };
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, ServerError, ServerRequest,
@@ -24,8 +30,12 @@ use seccompiler::BpfProgramRef;
 use serde_json::json;
 use utils::eventfd::EventFd;
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
+use vmm::vmm_config::invoke::InvokeConfig;
 use vmm::vmm_config::snapshot::SnapshotType;
 
+use crate::access::{AccessPolicy, PeerCredentials};
+use crate::audit::{AuditLog, RequestOutcome};
+use crate::operations::{OperationState, Operations};
 use crate::parsed_request::{ParsedRequest, RequestAction};
 use crate::Error::ServerCreation;
 
@@ -54,6 +64,12 @@ pub struct ApiServer {
     to_vmm_fd: EventFd,
     /// If this flag is set, the API thread will go down.
     shutdown_flag: bool,
+    /// Asynchronous operations, at most one of which is handled by the VMM at a time.
+    operations: Operations,
+    /// Policy restricting the requests each peer may send, if any.
+    access_policy: Option<AccessPolicy>,
+    /// Log to which a record of each request is written, if any.
+    audit_log: Option<AuditLog>,
 }
 
 impl ApiServer {
@@ -70,9 +86,23 @@ impl ApiServer {
             vmm_response_receiver,
             to_vmm_fd,
             shutdown_flag: false,
+            operations: Operations::default(),
+            access_policy: None,
+            audit_log: None,
         }
     }
 
+    /// Only serves the requests `access_policy` allows, based on the credentials of the process
+    /// which sent them.
+    pub fn set_access_policy(&mut self, access_policy: AccessPolicy) {
+        self.access_policy = Some(access_policy);
+    }
+
+    /// Writes a record of each request and of its response to `audit_log`.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log);
+    }
+
     /// Starts the HTTP Server by binding to the socket path provided as
     /// an argument.
     ///
@@ -192,11 +222,24 @@ impl ApiServer {
             for server_request in request_vec {
                 let request_processing_start_us =
                     utils::time::get_time_us(utils::time::ClockType::Monotonic);
+                let peer_fd = connection_fd(&server_request);
                 server
                     .respond(
                         // Use `self.handle_request()` as the processing callback.
                         server_request.process(|request| {
-                            self.handle_request(request, request_processing_start_us)
+                            let mut outcome = RequestOutcome::default();
+                            let response =
+                                self.check_access(peer_fd, request).unwrap_or_else(|| {
+                                    self.handle_request_with_outcome(
+                                        request,
+                                        request_processing_start_us,
+                                        &mut outcome,
+                                    )
+                                });
+                            if let Some(audit_log) = self.audit_log.as_mut() {
+                                audit_log.record(peer_fd, request, &response, &outcome);
+                            }
+                            response
                         }),
                     )
                     .or_else(|err| {
@@ -225,11 +268,52 @@ impl ApiServer {
         request: &Request,
         request_processing_start_us: u64,
     ) -> Response {
+        self.handle_request_with_outcome(
+            request,
+            request_processing_start_us,
+            &mut RequestOutcome::default(),
+        )
+    }
+
+    // Handles `request`, filling in `outcome` with what was done to serve it when requests are
+    // audited.
+    fn handle_request_with_outcome(
+        &mut self,
+        request: &Request,
+        request_processing_start_us: u64,
+        outcome: &mut RequestOutcome,
+    ) -> Response {
+        self.poll_operation();
         match ParsedRequest::try_from_request(request).map(|r| r.into_parts()) {
             Ok((req_action, mut parsing_info)) => {
+                if self.audit_log.is_some() {
+                    match req_action {
+                        RequestAction::Sync(ref vmm_action)
+                        | RequestAction::Async(ref vmm_action) => outcome.set_action(vmm_action),
+                        RequestAction::Invoke(_) => outcome.set_action(&VmmAction::GetInvokeTarget),
+                        _ => (),
+                    }
+                }
                 let mut response = match req_action {
+                    RequestAction::Sync(vmm_action) if self.operations.pending_id().is_some() => {
+                        self.serve_during_operation(*vmm_action)
+                    }
                     RequestAction::Sync(vmm_action) => {
-                        self.serve_vmm_action_request(vmm_action, request_processing_start_us)
+                        let serve_start_us =
+                            utils::time::get_time_us(utils::time::ClockType::Monotonic);
+                        let response =
+                            self.serve_vmm_action_request(vmm_action, request_processing_start_us);
+                        outcome.serve_us = Some(
+                            utils::time::get_time_us(utils::time::ClockType::Monotonic)
+                                - serve_start_us,
+                        );
+                        response
+                    }
+                    RequestAction::Async(vmm_action) => self.start_operation(vmm_action),
+                    RequestAction::GetOperation(id) => self.operation_status(&id),
+                    RequestAction::CancelOperation(id) => self.cancel_operation(&id),
+                    RequestAction::Invoke(config) => {
+                        self.invoke(&config, request_processing_start_us)
                     }
                     RequestAction::ShutdownInternal => {
                         self.shutdown_flag = true;
@@ -249,6 +333,30 @@ impl ApiServer {
         }
     }
 
+    /// Returns the response denying `request`, sent on the connection `peer_fd`, if the access
+    /// policy does not allow it. Requests whose sender cannot be identified are denied.
+    fn check_access(&self, peer_fd: RawFd, request: &Request) -> Option<Response> {
+        let access_policy = self.access_policy.as_ref()?;
+        let method = request.method();
+        let path = request.uri().get_abs_path();
+        match PeerCredentials::from_fd(peer_fd) {
+            Ok(peer) if access_policy.allows(&peer, method, path) => return None,
+            Ok(peer) => warn!(
+                "Denied {:?} request on {:?} from pid {}, uid {}, gid {}.",
+                method, path, peer.pid, peer.uid, peer.gid
+            ),
+            Err(err) => warn!(
+                "Denied {:?} request on {:?} from an unknown peer: {}",
+                method, path, err
+            ),
+        }
+        METRICS.api_server.access_denied_count.inc();
+        Some(ApiServer::json_response(
+            StatusCode::Unauthorized,
+            ApiServer::json_fault_message("The API access policy does not allow this request."),
+        ))
+    }
+
     fn serve_vmm_action_request(
         &mut self,
         vmm_action: Box<VmmAction>,
@@ -273,10 +381,7 @@ impl ApiServer {
             _ => None,
         };
 
-        self.api_request_sender
-            .send(vmm_action)
-            .expect("Failed to send VMM message");
-        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+        self.send_to_vmm(vmm_action);
         let vmm_outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
         let response = ParsedRequest::convert_to_response(&vmm_outcome);
 
@@ -290,6 +395,146 @@ impl ApiServer {
         response
     }
 
+    fn send_to_vmm(&mut self, vmm_action: Box<VmmAction>) {
+        self.api_request_sender
+            .send(vmm_action)
+            .expect("Failed to send VMM message");
+        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+    }
+
+    // Hands `vmm_action` over to the VMM without waiting for its outcome, which is reported
+    // through the status of a new operation.
+    fn start_operation(&mut self, vmm_action: Box<VmmAction>) -> Response {
+        if let Some(id) = self.operations.pending_id() {
+            return Self::json_response(
+                StatusCode::BadRequest,
+                Self::json_fault_message(format!("Operation {} is already in progress.", id)),
+            );
+        }
+        // The VMM cannot serve the microVM information until the operation is done.
+        self.send_to_vmm(Box::new(VmmAction::GetVmInstanceInfo));
+        let instance_info = match *self.vmm_response_receiver.recv().expect("VMM disconnected") {
+            Ok(VmmData::InstanceInformation(instance_info)) => instance_info,
+            outcome => return ParsedRequest::convert_to_response(&outcome),
+        };
+        vmm::operations::begin();
+        let status = self.operations.start(&vmm_action, instance_info);
+        self.send_to_vmm(vmm_action);
+        info!(
+            "Started operation {}. Status code: 202 Accepted.",
+            status.id
+        );
+        let mut response = Response::new(Version::Http11, StatusCode::Accepted);
+        response.set_body(Body::new(serde_json::to_string(&status).unwrap()));
+        response
+    }
+
+    // Completes the operation in progress if the VMM is done with it.
+    fn poll_operation(&mut self) {
+        if self.operations.pending_id().is_none() {
+            return;
+        }
+        match self.vmm_response_receiver.try_recv() {
+            Ok(vmm_outcome) => self
+                .operations
+                .complete(&vmm_outcome, vmm::operations::finish()),
+            Err(mpsc::TryRecvError::Empty) => (),
+            Err(mpsc::TryRecvError::Disconnected) => panic!("VMM disconnected"),
+        }
+    }
+
+    fn operation_status(&self, id: &str) -> Response {
+        match self.operations.status(id) {
+            Ok(mut status) => {
+                if status.state == OperationState::InProgress {
+                    status.phase = vmm::operations::phase();
+                }
+                ParsedRequest::success_response_with_data(&status)
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    fn cancel_operation(&self, id: &str) -> Response {
+        let cancelled = self.operations.check_pending(id).and_then(|()| {
+            vmm::operations::cancel().map_err(|err| {
+                parsed_request::Error::Generic(StatusCode::BadRequest, err.to_string())
+            })
+        });
+        match cancelled {
+            Ok(()) => {
+                info!("Cancelling operation {}. Status code: 204 No Content.", id);
+                Response::new(Version::Http11, StatusCode::NoContent)
+            }
+            Err(err) => err.into(),
+        }
+    }
+
+    // Forwards a function invocation to the guest through the Unix socket of the vsock device,
+    // and responds with the guest response.
+    fn invoke(&mut self, config: &InvokeConfig, request_processing_start_us: u64) -> Response {
+        if self.operations.pending_id().is_some() {
+            return self.serve_during_operation(VmmAction::GetInvokeTarget);
+        }
+        self.send_to_vmm(Box::new(VmmAction::GetInvokeTarget));
+        let target = match *self.vmm_response_receiver.recv().expect("VMM disconnected") {
+            Ok(VmmData::InvokeTarget(target)) => target,
+            outcome => {
+                METRICS.put_api_requests.invoke_fails.inc();
+                return ParsedRequest::convert_to_response(&outcome);
+            }
+        };
+
+        match invoke::invoke(&target, config) {
+            Ok(response) => {
+                let elapsed_time_us = update_metric_with_elapsed_time(
+                    &METRICS.latencies_us.invoke,
+                    request_processing_start_us,
+                );
+                info!("'invoke' API request took {} us.", elapsed_time_us);
+                ParsedRequest::success_response_with_data(&response)
+            }
+            Err(err) => {
+                METRICS.put_api_requests.invoke_fails.inc();
+                error!(
+                    "Received Error. Status code: 400 Bad Request. Message: {}",
+                    err
+                );
+                Self::json_response(
+                    StatusCode::BadRequest,
+                    Self::json_fault_message(err.to_string()),
+                )
+            }
+        }
+    }
+
+    // Serves the requests which do not need the VMM while it runs an operation.
+    fn serve_during_operation(&mut self, vmm_action: VmmAction) -> Response {
+        let instance_info = self
+            .operations
+            .instance_info()
+            .expect("Missing pending operation");
+        let vmm_outcome = match vmm_action {
+            VmmAction::FlushMetrics => METRICS
+                .write()
+                .map(|_| VmmData::Empty)
+                .map_err(|err| VmmActionError::InternalVmm(vmm::Error::Metrics(err))),
+            VmmAction::GetVmInstanceInfo => Ok(VmmData::InstanceInformation(instance_info.clone())),
+            VmmAction::GetVmmVersion => Ok(VmmData::VmmVersion(instance_info.vmm_version.clone())),
+            _ => {
+                return Self::json_response(
+                    StatusCode::BadRequest,
+                    Self::json_fault_message(format!(
+                        "Operation {} is in progress, only instance information, version and \
+                         metrics flush requests are served until it is done.",
+                        self.operations.pending_id().unwrap_or_default()
+                    )),
+                )
+            }
+        };
+        ParsedRequest::convert_to_response(&vmm_outcome)
+    }
+
     /// An HTTP response which also includes a body.
     pub(crate) fn json_response<T: Into<String>>(status: StatusCode, body: T) -> Response {
         let mut response = Response::new(Version::Http11, status);
@@ -302,9 +547,16 @@ impl ApiServer {
     }
 }
 
+/// Returns the socket of the connection `server_request` was received on, which `micro_http`
+/// uses as the ID of the request.
+fn connection_fd(server_request: &ServerRequest) -> RawFd {
+    server_request.id() as RawFd
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
     use std::os::unix::net::UnixStream;
     use std::sync::mpsc::channel;
     use std::thread;
@@ -317,7 +569,7 @@ mod tests {
     use vmm::rpc_interface::VmmActionError;
     use vmm::seccomp_filters::{get_filters, SeccompConfig};
     use vmm::vmm_config::instance_info::InstanceInfo;
-    use vmm::vmm_config::snapshot::CreateSnapshotParams;
+    use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotFiles};
 
     use super::*;
     use crate::request::cpu_configuration::parse_put_cpu_config;
@@ -380,8 +632,15 @@ mod tests {
             Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
                 version: None,
+                encryption: None,
+                background: false,
+                chain_metadata: false,
+                asynchronous: false,
+                files: SnapshotFiles::default(),
             })),
             start_time_us,
         );
@@ -394,8 +653,15 @@ mod tests {
             Box::new(VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
                 version: None,
+                encryption: None,
+                background: false,
+                chain_metadata: false,
+                asynchronous: false,
+                files: SnapshotFiles::default(),
             })),
             start_time_us,
         );
@@ -404,6 +670,49 @@ mod tests {
         assert_eq!(METRICS.latencies_us.full_create_snapshot.fetch(), 0);
     }
 
+    #[test]
+    fn test_async_operation() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let response = api_server.start_operation(Box::new(VmmAction::Pause));
+        assert_eq!(response.status(), StatusCode::Accepted);
+        assert_eq!(*from_api.try_recv().unwrap(), VmmAction::GetVmInstanceInfo);
+        assert_eq!(*from_api.try_recv().unwrap(), VmmAction::Pause);
+
+        // Only one operation runs at a time.
+        let response = api_server.start_operation(Box::new(VmmAction::Pause));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        // The requests which do not need the VMM are served in the meantime.
+        let response = api_server.serve_during_operation(VmmAction::GetVmInstanceInfo);
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api_server.serve_during_operation(VmmAction::GetVmmVersion);
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api_server.serve_during_operation(VmmAction::Resume);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert!(from_api.try_recv().is_err());
+        assert_eq!(api_server.operation_status("1").status(), StatusCode::OK);
+        assert_eq!(
+            api_server.operation_status("2").status(),
+            StatusCode::NotFound
+        );
+
+        api_server.poll_operation();
+        assert_eq!(api_server.operations.pending_id(), Some("1"));
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        api_server.poll_operation();
+        assert!(api_server.operations.pending_id().is_none());
+        let response = api_server.cancel_operation("1");
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+
     #[test]
     fn test_handle_request() {
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -456,6 +765,90 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
+    #[test]
+    fn test_handle_request_with_outcome() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+
+        // The action is only filled in when requests are audited, the serve time always is.
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let mut outcome = RequestOutcome::default();
+        api_server.handle_request_with_outcome(&req, 0, &mut outcome);
+        assert!(outcome.action.is_none());
+        assert!(outcome.serve_us.is_some());
+
+        let audit_file = TempFile::new().unwrap();
+        api_server.set_audit_log(AuditLog::from_path(audit_file.as_path()).unwrap());
+        to_api
+            .send(Box::new(Ok(VmmData::InstanceInformation(
+                InstanceInfo::default(),
+            ))))
+            .unwrap();
+        let mut outcome = RequestOutcome::default();
+        let response = api_server.handle_request_with_outcome(&req, 0, &mut outcome);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(outcome.action.as_deref(), Some("GetVmInstanceInfo"));
+        assert!(outcome.serve_us.is_some());
+    }
+
+    #[test]
+    fn test_check_access() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let peer_fd = receiver.as_raw_fd();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /vm/config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+
+        // Without a policy, every request is allowed.
+        assert!(api_server.check_access(peer_fd, &req).is_none());
+
+        // SAFETY: This call cannot fail.
+        let uid = unsafe { libc::geteuid() };
+        let policy: AccessPolicy = serde_json::from_str(&format!(
+            r#"{{ "rules": [{{ "uid": {}, "methods": ["GET"], "endpoints": ["/vm"] }}] }}"#,
+            uid
+        ))
+        .unwrap();
+        api_server.set_access_policy(policy);
+        assert!(api_server.check_access(peer_fd, &req).is_none());
+
+        api_server.set_access_policy(AccessPolicy::default());
+        let denied_count = METRICS.api_server.access_denied_count.count();
+        let response = api_server.check_access(peer_fd, &req).unwrap();
+        assert_eq!(response.status(), StatusCode::Unauthorized);
+        assert_eq!(
+            METRICS.api_server.access_denied_count.count(),
+            denied_count + 1
+        );
+
+        // Requests from unknown peers are denied.
+        api_server.set_access_policy(AccessPolicy {
+            rules: vec![Default::default()],
+        });
+        assert!(api_server.check_access(peer_fd, &req).is_none());
+        assert!(api_server.check_access(-1, &req).is_some());
+    }
+
     #[test]
     fn test_handle_request_logging() {
         let cpu_template_json = TEST_UNESCAPED_JSON_TEMPLATE;
@@ -0,0 +1,244 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asynchronous API operations.
+//!
+//! An asynchronous request is answered as soon as its action is handed over to the VMM thread,
+//! with the ID of an operation tracking it. Until the VMM thread responds, which completes the
+//! operation, the API server only serves the requests which do not need the VMM thread.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
+use vmm::vmm_config::instance_info::InstanceInfo;
+
+use crate::parsed_request::Error;
+use crate::StatusCode;
+
+/// Number of completed operations whose status is kept.
+const MAX_COMPLETED_OPERATIONS: usize = 16;
+
+/// State of an asynchronous operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum OperationState {
+    /// The VMM thread has not responded yet.
+    InProgress,
+    /// The action succeeded.
+    Done,
+    /// The action failed.
+    Failed,
+    /// The action was cancelled.
+    Cancelled,
+}
+
+/// Status of an asynchronous operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct OperationStatus {
+    /// ID of the operation.
+    pub id: String,
+    /// Action run by the operation.
+    pub action: &'static str,
+    /// State of the operation.
+    pub state: OperationState,
+    /// Phase of the action, while in progress. Filled in by the caller.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<&'static str>,
+    /// Time elapsed since the operation started, while in progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_us: Option<u64>,
+    /// Data returned by the action, if it succeeded and returned some.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Why the action failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault_message: Option<String>,
+}
+
+/// Update of an asynchronous operation requested by the user.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OperationUpdate {
+    /// The state to move the operation to.
+    pub state: OperationState,
+}
+
+#[derive(Debug)]
+struct PendingOperation {
+    id: String,
+    action: &'static str,
+    start_us: u64,
+    // Served while the VMM thread is busy.
+    instance_info: InstanceInfo,
+}
+
+/// Asynchronous operations started through the API.
+#[derive(Debug, Default)]
+pub(crate) struct Operations {
+    last_id: u64,
+    pending: Option<PendingOperation>,
+    completed: VecDeque<OperationStatus>,
+}
+
+impl Operations {
+    /// Returns the ID of the operation in progress, if there is one.
+    pub fn pending_id(&self) -> Option<&str> {
+        self.pending.as_ref().map(|pending| pending.id.as_str())
+    }
+
+    /// Returns the microVM information recorded when the operation in progress started.
+    pub fn instance_info(&self) -> Option<&InstanceInfo> {
+        self.pending.as_ref().map(|pending| &pending.instance_info)
+    }
+
+    /// Registers an operation running `action`, which is about to be handed over to the VMM
+    /// thread.
+    pub fn start(&mut self, action: &VmmAction, instance_info: InstanceInfo) -> OperationStatus {
+        self.last_id += 1;
+        self.pending = Some(PendingOperation {
+            id: self.last_id.to_string(),
+            action: action_name(action),
+            start_us: utils::time::get_time_us(utils::time::ClockType::Monotonic),
+            instance_info,
+        });
+        self.pending_status().expect("Missing pending operation")
+    }
+
+    /// Completes the operation in progress with the response of the VMM thread. A failed
+    /// operation whose cancellation was requested counts as cancelled.
+    pub fn complete(&mut self, outcome: &Result<VmmData, VmmActionError>, cancelled: bool) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let (state, result, fault_message) = match outcome {
+            Ok(VmmData::RestoreTimings(timings)) => (
+                OperationState::Done,
+                serde_json::to_value(timings).ok(),
+                None,
+            ),
+            Ok(_) => (OperationState::Done, None, None),
+            Err(_) if cancelled => (OperationState::Cancelled, None, None),
+            Err(err) => (OperationState::Failed, None, Some(err.to_string())),
+        };
+        if self.completed.len() == MAX_COMPLETED_OPERATIONS {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(OperationStatus {
+            id: pending.id,
+            action: pending.action,
+            state,
+            phase: None,
+            elapsed_us: None,
+            result,
+            fault_message,
+        });
+    }
+
+    /// Returns the status of the operation with the given ID.
+    pub fn status(&self, id: &str) -> Result<OperationStatus, Error> {
+        if self.pending_id() == Some(id) {
+            return Ok(self.pending_status().expect("Missing pending operation"));
+        }
+        self.completed
+            .iter()
+            .find(|status| status.id == id)
+            .cloned()
+            .ok_or_else(|| unknown_operation(id))
+    }
+
+    /// Checks that the operation with the given ID is in progress.
+    pub fn check_pending(&self, id: &str) -> Result<(), Error> {
+        if self.pending_id() == Some(id) {
+            return Ok(());
+        }
+        if self.completed.iter().any(|status| status.id == id) {
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                format!("Operation {} is already completed.", id),
+            ));
+        }
+        Err(unknown_operation(id))
+    }
+
+    fn pending_status(&self) -> Option<OperationStatus> {
+        self.pending.as_ref().map(|pending| OperationStatus {
+            id: pending.id.clone(),
+            action: pending.action,
+            state: OperationState::InProgress,
+            phase: None,
+            elapsed_us: Some(
+                utils::time::get_time_us(utils::time::ClockType::Monotonic) - pending.start_us,
+            ),
+            result: None,
+            fault_message: None,
+        })
+    }
+}
+
+fn action_name(action: &VmmAction) -> &'static str {
+    match action {
+        VmmAction::CreateSnapshot(_) => "create_snapshot",
+        VmmAction::LoadSnapshot(_) => "load_snapshot",
+        _ => "other",
+    }
+}
+
+fn unknown_operation(id: &str) -> Error {
+    Error::Generic(StatusCode::NotFound, format!("Unknown operation: {}.", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::persist::RestoreTimings;
+
+    use super::*;
+
+    #[test]
+    fn test_operations() {
+        let mut operations = Operations::default();
+        assert!(operations.pending_id().is_none());
+        assert!(operations.status("1").is_err());
+        assert!(operations.check_pending("1").is_err());
+
+        let status = operations.start(&VmmAction::Pause, InstanceInfo::default());
+        assert_eq!(status.id, "1");
+        assert_eq!(status.state, OperationState::InProgress);
+        assert_eq!(operations.pending_id(), Some("1"));
+        assert_eq!(operations.instance_info(), Some(&InstanceInfo::default()));
+        assert_eq!(
+            operations.status("1").unwrap().state,
+            OperationState::InProgress
+        );
+
+        operations.check_pending("1").unwrap();
+        operations.complete(&Err(VmmActionError::OperationNotSupportedPreBoot), true);
+        assert!(operations.pending_id().is_none());
+        assert_eq!(
+            operations.status("1").unwrap().state,
+            OperationState::Cancelled
+        );
+        assert!(operations.check_pending("1").is_err());
+
+        operations.start(&VmmAction::Pause, InstanceInfo::default());
+        operations.complete(&Err(VmmActionError::OperationNotSupportedPreBoot), false);
+        let status = operations.status("2").unwrap();
+        assert_eq!(status.state, OperationState::Failed);
+        assert!(status.fault_message.is_some());
+
+        for _ in 0..MAX_COMPLETED_OPERATIONS {
+            operations.start(&VmmAction::Pause, InstanceInfo::default());
+            operations.complete(
+                &Ok(VmmData::RestoreTimings(RestoreTimings::default())),
+                true,
+            );
+        }
+        // Only the most recent operations are kept.
+        assert!(operations.status("2").is_err());
+        let status = operations.status("3").unwrap();
+        assert_eq!(status.state, OperationState::Done);
+        assert_eq!(status.action, "other");
+        assert_eq!(status.result.unwrap()["total_us"], 0);
+    }
+}
@@ -6,30 +6,39 @@ use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use serde::ser::Serialize;
 use serde_json::Value;
 use vmm::rpc_interface::{VmmAction, VmmActionError};
+use vmm::vmm_config::invoke::InvokeConfig;
 
 use super::VmmData;
 use crate::request::actions::parse_put_actions;
 use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use crate::request::boot_source::parse_put_boot_source;
 use crate::request::cpu_configuration::parse_put_cpu_config;
-use crate::request::drive::{parse_patch_drive, parse_put_drive};
-use crate::request::entropy::parse_put_entropy;
+use crate::request::drive::{parse_get_drive, parse_patch_drive, parse_put_drive};
+use crate::request::entropy::{parse_get_entropy, parse_put_entropy};
 use crate::request::instance_info::parse_get_instance_info;
+use crate::request::invoke::parse_put_invoke;
 use crate::request::logger::parse_put_logger;
 use crate::request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
 use crate::request::metrics::parse_put_metrics;
+use crate::request::migration::parse_put_migration;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use crate::request::net::{parse_patch_net, parse_put_net};
-use crate::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
+use crate::request::net::{parse_get_net, parse_patch_net, parse_put_net};
+use crate::request::operations::{parse_get_operation, parse_patch_operation};
+use crate::request::snapshot::{parse_get_snapshot, parse_patch_vm_state, parse_put_snapshot};
 use crate::request::version::parse_get_version;
-use crate::request::vsock::parse_put_vsock;
+use crate::request::vm_config::parse_put_vm_config;
+use crate::request::vsock::{parse_get_vsock, parse_put_vsock};
 use crate::ApiServer;
 
 #[cfg_attr(test, derive(Debug))]
 pub(crate) enum RequestAction {
     Sync(Box<VmmAction>),
+    Async(Box<VmmAction>),
+    GetOperation(String),
+    CancelOperation(String),
+    Invoke(InvokeConfig),
     ShutdownInternal, // !!! not an API, used by shutdown to thread::join the API thread
 }
 
@@ -98,21 +107,31 @@ impl ParsedRequest {
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
+            (Method::Get, "drives", None) => parse_get_drive(path_tokens.get(1)),
+            (Method::Get, "entropy", None) => parse_get_entropy(),
+            (Method::Get, "network-interfaces", None) => parse_get_net(path_tokens.get(1)),
             (Method::Get, "version", None) => parse_get_version(),
             (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"config") => {
                 Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
             }
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "operations", None) => parse_get_operation(path_tokens.get(1)),
+            (Method::Get, "snapshot", None) => parse_get_snapshot(path_tokens.get(1)),
+            (Method::Get, "vsock", None) => parse_get_vsock(),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
             (Method::Put, "cpu-config", Some(body)) => parse_put_cpu_config(body),
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.get(1)),
+            (Method::Put, "invoke", Some(body)) => parse_put_invoke(body),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
+            (Method::Put, "migration", Some(body)) => {
+                parse_put_migration(body, path_tokens.get(1), &request.files)
+            }
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.get(1)),
             (Method::Put, "network-interfaces", Some(body)) => {
                 parse_put_net(body, path_tokens.get(1))
@@ -120,7 +139,12 @@ impl ParsedRequest {
             (Method::Put, "shutdown-internal", None) => {
                 Ok(ParsedRequest::new(RequestAction::ShutdownInternal))
             }
-            (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
+            (Method::Put, "snapshot", Some(body)) => {
+                parse_put_snapshot(body, path_tokens.get(1), &request.files)
+            }
+            (Method::Put, "vm", Some(body)) if path_tokens.get(1) == Some(&"config") => {
+                parse_put_vm_config(body)
+            }
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
             (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
@@ -131,6 +155,9 @@ impl ParsedRequest {
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.get(1))
             }
+            (Method::Patch, "operations", Some(body)) => {
+                parse_patch_operation(body, path_tokens.get(1))
+            }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
             (method, unknown_uri, _) => {
@@ -172,16 +199,25 @@ impl ParsedRequest {
                 VmmData::MachineConfiguration(vm_config) => {
                     Self::success_response_with_data(vm_config)
                 }
+                VmmData::MemoryDumpStatus(status) => Self::success_response_with_data(status),
                 VmmData::MmdsValue(value) => Self::success_response_with_mmds_value(value),
                 VmmData::BalloonConfig(balloon_config) => {
                     Self::success_response_with_data(balloon_config)
                 }
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::MigrationStats(stats) => Self::success_response_with_data(stats),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
+                VmmData::RestoreTimings(timings) => Self::success_response_with_data(timings),
+                VmmData::ValidationReport(report) => Self::success_response_with_data(report),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
                 VmmData::FullVmConfig(config) => Self::success_response_with_data(config),
+                VmmData::InvokeTarget(target) => Self::success_response_with_data(target),
+                VmmData::BlockDeviceState(state) => Self::success_response_with_data(state),
+                VmmData::EntropyDeviceState(state) => Self::success_response_with_data(state),
+                VmmData::NetworkInterfaceState(state) => Self::success_response_with_data(state),
+                VmmData::VsockDeviceState(state) => Self::success_response_with_data(state),
             },
             Err(vmm_action_error) => {
                 let mut response = match vmm_action_error {
@@ -192,6 +228,13 @@ impl ParsedRequest {
                         );
                         Response::new(Version::Http11, StatusCode::PayloadTooLarge)
                     }
+                    VmmActionError::DeviceNotFound(_) => {
+                        error!(
+                            "Received Error. Status code: 404 Not Found. Message: {}",
+                            vmm_action_error
+                        );
+                        Response::new(Version::Http11, StatusCode::NotFound)
+                    }
                     _ => {
                         error!(
                             "Received Error. Status code: 400 Bad Request. Message: {}",
@@ -212,6 +255,11 @@ impl ParsedRequest {
     pub(crate) fn new_sync(vmm_action: VmmAction) -> ParsedRequest {
         ParsedRequest::new(RequestAction::Sync(Box::new(vmm_action)))
     }
+
+    /// Helper function to avoid boiler-plate code.
+    pub(crate) fn new_async(vmm_action: VmmAction) -> ParsedRequest {
+        ParsedRequest::new(RequestAction::Async(Box::new(vmm_action)))
+    }
 }
 
 /// Helper function for writing the received API requests to the log.
@@ -313,16 +361,25 @@ pub(crate) fn checked_id(id: &str) -> Result<&str, Error> {
 pub mod tests {
     use std::io::{Cursor, Write};
     use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
     use std::str::FromStr;
 
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
     use vmm::cpu_config::templates::test_utils::build_test_template;
+    use vmm::devices::virtio::rng::EntropyDeviceStats;
+    use vmm::devices::virtio::vsock::VsockDeviceStats;
+    use vmm::persist::RestoreTimings;
     use vmm::resources::VmmConfig;
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
+    use vmm::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceState};
     use vmm::vmm_config::instance_info::InstanceInfo;
     use vmm::vmm_config::machine_config::MachineConfig;
+    use vmm::vmm_config::migration::MigrationStats;
+    use vmm::vmm_config::snapshot::{MemoryDumpState, MemoryDumpStatus};
+    use vmm::vmm_config::validation::ValidationReport;
+    use vmm::vmm_config::vsock::{VsockDeviceConfig, VsockDeviceState};
 
     use super::*;
 
@@ -336,6 +393,20 @@ pub mod tests {
                 (RequestAction::Sync(ref sync_req), RequestAction::Sync(ref other_sync_req)) => {
                     sync_req == other_sync_req
                 }
+                (RequestAction::Async(ref req), RequestAction::Async(ref other_req)) => {
+                    req == other_req
+                }
+                (
+                    RequestAction::GetOperation(ref id),
+                    RequestAction::GetOperation(ref other_id),
+                )
+                | (
+                    RequestAction::CancelOperation(ref id),
+                    RequestAction::CancelOperation(ref other_id),
+                ) => id == other_id,
+                (RequestAction::Invoke(ref config), RequestAction::Invoke(ref other_config)) => {
+                    config == other_config
+                }
                 _ => false,
             }
         }
@@ -560,6 +631,18 @@ pub mod tests {
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::BlockDeviceState(state) => {
+                    http_response(&serde_json::to_string(state).unwrap(), 200)
+                }
+                VmmData::EntropyDeviceState(state) => {
+                    http_response(&serde_json::to_string(state).unwrap(), 200)
+                }
+                VmmData::NetworkInterfaceState(state) => {
+                    http_response(&serde_json::to_string(state).unwrap(), 200)
+                }
+                VmmData::VsockDeviceState(state) => {
+                    http_response(&serde_json::to_string(state).unwrap(), 200)
+                }
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
@@ -567,12 +650,24 @@ pub mod tests {
                 VmmData::MachineConfiguration(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                VmmData::MemoryDumpStatus(status) => {
+                    http_response(&serde_json::to_string(status).unwrap(), 200)
+                }
+                VmmData::MigrationStats(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
                 VmmData::MmdsValue(value) => {
                     http_response(&serde_json::to_string(value).unwrap(), 200)
                 }
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::RestoreTimings(timings) => {
+                    http_response(&serde_json::to_string(timings).unwrap(), 200)
+                }
+                VmmData::ValidationReport(report) => {
+                    http_response(&serde_json::to_string(report).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
@@ -592,9 +687,36 @@ pub mod tests {
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
         verify_ok_response_with(VmmData::MachineConfiguration(MachineConfig::default()));
+        verify_ok_response_with(VmmData::MemoryDumpStatus(MemoryDumpStatus {
+            state: MemoryDumpState::InProgress,
+            mem_file_path: PathBuf::from("mem"),
+            bytes_written: 2 << 20,
+            total_bytes: 128 << 20,
+        }));
+        verify_ok_response_with(VmmData::MigrationStats(MigrationStats::default()));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
+        verify_ok_response_with(VmmData::RestoreTimings(RestoreTimings::default()));
+        verify_ok_response_with(VmmData::ValidationReport(ValidationReport::default()));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
+        verify_ok_response_with(VmmData::EntropyDeviceState(EntropyDeviceState {
+            config: EntropyDeviceConfig::default(),
+            activated: true,
+            rate_limiter_budget: None,
+            stats: EntropyDeviceStats {
+                entropy_bytes: 64,
+                request_count: 1,
+            },
+        }));
+        verify_ok_response_with(VmmData::VsockDeviceState(VsockDeviceState {
+            config: VsockDeviceConfig {
+                vsock_id: None,
+                guest_cid: 3,
+                uds_path: String::from("v.sock"),
+            },
+            activated: false,
+            stats: VsockDeviceStats::default(),
+        }));
 
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
@@ -605,6 +727,15 @@ pub mod tests {
 
         let expected_response = http_response(&json, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        let error = VmmActionError::DeviceNotFound(String::from("vsock device"));
+        let mut buf = Cursor::new(vec![0]);
+        let json = ApiServer::json_fault_message(error.to_string());
+        let response = ParsedRequest::convert_to_response(&Err(error));
+        response.write_all(&mut buf).unwrap();
+
+        let expected_response = http_response(&json, 404);
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
     }
 
     #[test]
@@ -631,6 +762,34 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_device_state() {
+        for path in [
+            "/drives/rootfs",
+            "/network-interfaces/eth0",
+            "/vsock",
+            "/entropy",
+        ] {
+            let (mut sender, receiver) = UnixStream::pair().unwrap();
+            let mut connection = HttpConnection::new(receiver);
+            sender
+                .write_all(http_request("GET", path, None).as_bytes())
+                .unwrap();
+            assert!(connection.try_read().is_ok());
+            let req = connection.pop_parsed_request().unwrap();
+            assert!(ParsedRequest::try_from_request(&req).is_ok());
+        }
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/drives", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
     #[test]
     fn test_try_from_get_balloon_stats() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -655,6 +814,18 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_snapshot_create() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/snapshot/create", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -721,6 +892,27 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_vm_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"boot-source\": { \"kernel_image_path\": \"string\" }, \"drives\": [] }";
+        sender
+            .write_all(http_request("PUT", "/vm/config", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        // Only the configuration of the microVM can be replaced.
+        sender
+            .write_all(http_request("PUT", "/vm", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
     #[test]
     fn test_try_from_put_boot() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -751,6 +943,19 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_invoke() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"guest_port\": 5000, \"body\": \"hello\" }";
+        sender
+            .write_all(http_request("PUT", "/invoke", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_logger() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -841,6 +1046,26 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_migration() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"socket_path\": \"/tmp/migration.sock\" }";
+        sender
+            .write_all(http_request("PUT", "/migration/send", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(http_request("PUT", "/migration/receive", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_snapshot() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -903,6 +1128,32 @@ pub mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_operations() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/operations/1", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            ParsedRequest::try_from_request(&req).unwrap(),
+            ParsedRequest::new(RequestAction::GetOperation("1".to_string()))
+        );
+
+        let body = "{ \"state\": \"Cancelled\" }";
+        sender
+            .write_all(http_request("PATCH", "/operations/1", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            ParsedRequest::try_from_request(&req).unwrap(),
+            ParsedRequest::new(RequestAction::CancelOperation("1".to_string()))
+        );
+    }
+
     #[test]
     fn test_try_from_put_vsock() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -18,6 +18,7 @@ enum ActionType {
     FlushMetrics,
     InstanceStart,
     SendCtrlAltDel,
+    Validate,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -49,6 +50,7 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::new_sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::Validate => Ok(ParsedRequest::new_sync(VmmAction::ValidateMicroVm)),
     }
 }
 
@@ -103,5 +105,16 @@ mod tests {
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
         }
+
+        {
+            let json = r#"{
+                "action_type": "Validate"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::ValidateMicroVm);
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
     }
 }
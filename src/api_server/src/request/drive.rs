@@ -8,6 +8,14 @@ use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 
+pub(crate) fn parse_get_drive(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.drive_count.inc();
+    let id = checked_id(id_from_path.ok_or(Error::EmptyID)?)?;
+    Ok(ParsedRequest::new_sync(VmmAction::GetBlockDevice(
+        id.to_string(),
+    )))
+}
+
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -89,6 +97,16 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_drive_request() {
+        assert!(parse_get_drive(None).is_err());
+        assert!(parse_get_drive(Some(&"bad id")).is_err());
+        assert_eq!(
+            vmm_action_from_request(parse_get_drive(Some(&"rootfs")).unwrap()),
+            VmmAction::GetBlockDevice(String::from("rootfs"))
+        );
+    }
+
     #[test]
     fn test_parse_patch_drive_request() {
         assert!(parse_patch_drive(&Body::new("invalid_payload"), None).is_err());
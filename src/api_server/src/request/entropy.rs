@@ -7,6 +7,10 @@ use vmm::vmm_config::entropy::EntropyDeviceConfig;
 use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
 
+pub(crate) fn parse_get_entropy() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetEntropyDevice))
+}
+
 pub(crate) fn parse_put_entropy(body: &Body) -> Result<ParsedRequest, Error> {
     let cfg = serde_json::from_slice::<EntropyDeviceConfig>(body.raw())?;
     Ok(ParsedRequest::new_sync(VmmAction::SetEntropyDevice(cfg)))
@@ -15,6 +19,15 @@ pub(crate) fn parse_put_entropy(body: &Body) -> Result<ParsedRequest, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_entropy_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_entropy().unwrap()),
+            VmmAction::GetEntropyDevice
+        );
+    }
 
     #[test]
     fn test_parse_put_entropy_request() {
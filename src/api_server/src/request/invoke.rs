@@ -0,0 +1,78 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::invoke::{InvokeConfig, MAX_INVOKE_TIMEOUT_MS};
+
+use crate::parsed_request::{Error, ParsedRequest, RequestAction};
+use crate::request::{Body, StatusCode};
+
+pub(crate) fn parse_put_invoke(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.invoke_count.inc();
+    let config = serde_json::from_slice::<InvokeConfig>(body.raw()).map_err(|err| {
+        METRICS.put_api_requests.invoke_fails.inc();
+        err
+    })?;
+
+    // The path and the content type are written as they are in the request sent to the guest.
+    let message = if !config.path.starts_with('/')
+        || !config.path.bytes().all(|byte| byte.is_ascii_graphic())
+    {
+        "The invocation path must start with '/' and only hold visible ASCII characters."
+            .to_string()
+    } else if !config
+        .content_type
+        .bytes()
+        .all(|byte| byte == b' ' || byte.is_ascii_graphic())
+    {
+        "The invocation content type must only hold printable ASCII characters.".to_string()
+    } else if config.timeout_ms == 0 || config.timeout_ms > MAX_INVOKE_TIMEOUT_MS {
+        format!(
+            "The invocation timeout must be between 1 and {} milliseconds.",
+            MAX_INVOKE_TIMEOUT_MS
+        )
+    } else {
+        return Ok(ParsedRequest::new(RequestAction::Invoke(config)));
+    };
+    METRICS.put_api_requests.invoke_fails.inc();
+    Err(Error::Generic(StatusCode::BadRequest, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::invoke::{DEFAULT_INVOKE_PATH, DEFAULT_INVOKE_TIMEOUT_MS};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_put_invoke_request() {
+        let body = r#"{ "guest_port": 5000, "body": "{\"name\": \"world\"}" }"#;
+        match parse_put_invoke(&Body::new(body)).unwrap().into_parts() {
+            (RequestAction::Invoke(config), _) => assert_eq!(
+                config,
+                InvokeConfig {
+                    guest_port: 5000,
+                    path: DEFAULT_INVOKE_PATH.to_string(),
+                    body: r#"{"name": "world"}"#.to_string(),
+                    content_type: "application/json".to_string(),
+                    timeout_ms: DEFAULT_INVOKE_TIMEOUT_MS,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{ "guest_port": 5000, "path": "/run?id=1", "content_type": "text/plain" }"#;
+        assert!(parse_put_invoke(&Body::new(body)).is_ok());
+
+        for body in [
+            r#"{ "body": "" }"#,
+            r#"{ "guest_port": 5000, "path": "run" }"#,
+            r#"{ "guest_port": 5000, "path": "/run HTTP/1.0\r\nHost: evil" }"#,
+            r#"{ "guest_port": 5000, "content_type": "text/plain\r\nHost: evil" }"#,
+            r#"{ "guest_port": 5000, "timeout_ms": 0 }"#,
+            r#"{ "guest_port": 5000, "timeout_ms": 60001 }"#,
+        ] {
+            assert!(parse_put_invoke(&Body::new(body)).is_err());
+        }
+    }
+}
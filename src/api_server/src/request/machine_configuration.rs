@@ -85,6 +85,7 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(false),
+            vmgenid: Some(false),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -104,6 +105,7 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(true),
+            vmgenid: Some(false),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -128,6 +130,7 @@ mod tests {
                 smt: Some(false),
                 cpu_template: Some(StaticCpuTemplate::T2),
                 track_dirty_pages: Some(true),
+                vmgenid: Some(false),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -157,6 +160,7 @@ mod tests {
                 smt: Some(true),
                 cpu_template: Some(StaticCpuTemplate::None),
                 track_dirty_pages: Some(true),
+                vmgenid: Some(false),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -169,6 +173,36 @@ mod tests {
         {
             assert!(parse_put_machine_config(&Body::new(body)).is_err());
         }
+
+        // 6. Test that setting `vmgenid: true` is successful on aarch64 while on x86_64, it is
+        // not.
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "vmgenid": true
+          }"#;
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let expected_config = MachineConfigUpdate {
+                vcpu_count: Some(8),
+                mem_size_mib: Some(1024),
+                smt: Some(false),
+                cpu_template: Some(StaticCpuTemplate::None),
+                track_dirty_pages: Some(false),
+                vmgenid: Some(true),
+            };
+
+            match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+                VmmAction::UpdateVmConfiguration(config) => assert_eq!(config, expected_config),
+                _ => panic!("Test failed."),
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        }
     }
 
     #[test]
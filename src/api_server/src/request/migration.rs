@@ -0,0 +1,117 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::migration::{MigrationReceiveParams, MigrationSendParams, MigrationSocket};
+
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::snapshot::received_file;
+use crate::request::{Body, Method, StatusCode};
+
+pub(crate) fn parse_put_migration(
+    body: &Body,
+    request_type_from_path: Option<&&str>,
+    files: &[File],
+) -> Result<ParsedRequest, Error> {
+    match request_type_from_path {
+        Some(&"send") => {
+            let mut params = serde_json::from_slice::<MigrationSendParams>(body.raw())?;
+            params.socket = MigrationSocket(received_file(
+                "socket",
+                params.socket_path.is_some(),
+                params.socket_fd,
+                files,
+            )?);
+            Ok(ParsedRequest::new_sync(VmmAction::SendMigration(params)))
+        }
+        Some(&"receive") => {
+            let mut params = serde_json::from_slice::<MigrationReceiveParams>(body.raw())?;
+            params.socket = MigrationSocket(received_file(
+                "socket",
+                params.socket_path.is_some(),
+                params.socket_fd,
+                files,
+            )?);
+            Ok(ParsedRequest::new_sync(VmmAction::ReceiveMigration(params)))
+        }
+        Some(&request_type) => Err(Error::InvalidPathMethod(
+            format!("/migration/{}", request_type),
+            Method::Put,
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing migration operation type.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::AsRawFd;
+    use std::path::PathBuf;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_migration() {
+        let body = r#"{
+                "socket_path": "/tmp/migration.sock",
+                "max_rounds": 3,
+                "dirty_pages_threshold": 64
+              }"#;
+        let expected_cfg = MigrationSendParams {
+            socket_path: Some(PathBuf::from("/tmp/migration.sock")),
+            socket_fd: None,
+            max_rounds: 3,
+            dirty_pages_threshold: 64,
+            socket: MigrationSocket::default(),
+        };
+        match vmm_action_from_request(
+            parse_put_migration(&Body::new(body), Some(&"send"), &[]).unwrap(),
+        ) {
+            VmmAction::SendMigration(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let files = [TempFile::new().unwrap().as_file().try_clone().unwrap()];
+        let body = r#"{
+                "socket_fd": 0,
+                "resume_vm": true
+              }"#;
+        match vmm_action_from_request(
+            parse_put_migration(&Body::new(body), Some(&"receive"), &files).unwrap(),
+        ) {
+            VmmAction::ReceiveMigration(cfg) => {
+                assert_eq!(cfg.socket_path, None);
+                assert_eq!(cfg.socket_fd, Some(0));
+                assert!(!cfg.enable_diff_snapshots);
+                assert!(cfg.resume_vm);
+                // The received descriptor is duplicated.
+                assert_ne!(cfg.socket.0.unwrap().as_raw_fd(), files[0].as_raw_fd());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // Send-only fields are rejected when receiving.
+        let body = r#"{ "socket_fd": 0, "max_rounds": 3 }"#;
+        assert!(parse_put_migration(&Body::new(body), Some(&"receive"), &files).is_err());
+        // Both the path and the descriptor, or neither of them.
+        let body = r#"{ "socket_path": "/tmp/migration.sock", "socket_fd": 0 }"#;
+        assert!(parse_put_migration(&Body::new(body), Some(&"send"), &files).is_err());
+        assert!(parse_put_migration(&Body::new("{}"), Some(&"send"), &files).is_err());
+        // Descriptor not received.
+        let body = r#"{ "socket_fd": 1 }"#;
+        assert!(matches!(
+            parse_put_migration(&Body::new(body), Some(&"send"), &files),
+            Err(Error::Generic(StatusCode::BadRequest, _))
+        ));
+
+        assert!(parse_put_migration(&Body::new("{}"), Some(&"invalid"), &[]).is_err());
+        assert!(parse_put_migration(&Body::new("{}"), None, &[]).is_err());
+    }
+}
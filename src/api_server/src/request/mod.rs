@@ -8,13 +8,17 @@ pub mod cpu_configuration;
 pub mod drive;
 pub mod entropy;
 pub mod instance_info;
+pub mod invoke;
 pub mod logger;
 pub mod machine_configuration;
 pub mod metrics;
+pub mod migration;
 pub mod mmds;
 pub mod net;
+pub mod operations;
 pub mod snapshot;
 pub mod version;
+pub mod vm_config;
 pub mod vsock;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
@@ -8,6 +8,14 @@ use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 
+pub(crate) fn parse_get_net(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.network_count.inc();
+    let id = checked_id(id_from_path.ok_or(Error::EmptyID)?)?;
+    Ok(ParsedRequest::new_sync(VmmAction::GetNetworkInterface(
+        id.to_string(),
+    )))
+}
+
 pub(crate) fn parse_put_net(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -78,6 +86,16 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_net_request() {
+        assert!(parse_get_net(None).is_err());
+        assert!(parse_get_net(Some(&"bad id")).is_err());
+        assert_eq!(
+            vmm_action_from_request(parse_get_net(Some(&"eth0")).unwrap()),
+            VmmAction::GetNetworkInterface(String::from("eth0"))
+        );
+    }
+
     #[test]
     fn test_parse_put_net_request() {
         let body = r#"{
@@ -0,0 +1,70 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::operations::{OperationState, OperationUpdate};
+use crate::parsed_request::{checked_id, Error, ParsedRequest, RequestAction};
+use crate::request::{Body, StatusCode};
+
+pub(crate) fn parse_get_operation(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    let id = operation_id(id_from_path)?;
+    Ok(ParsedRequest::new(RequestAction::GetOperation(
+        id.to_string(),
+    )))
+}
+
+pub(crate) fn parse_patch_operation(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = operation_id(id_from_path)?;
+    let update = serde_json::from_slice::<OperationUpdate>(body.raw())?;
+    if update.state != OperationState::Cancelled {
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Operations can only be moved to the `Cancelled` state.".to_string(),
+        ));
+    }
+    Ok(ParsedRequest::new(RequestAction::CancelOperation(
+        id.to_string(),
+    )))
+}
+
+fn operation_id<'a>(id_from_path: Option<&&'a str>) -> Result<&'a str, Error> {
+    match id_from_path {
+        Some(&id) => checked_id(id),
+        None => Err(Error::EmptyID),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_operation() {
+        assert!(parse_get_operation(None).is_err());
+        assert!(parse_get_operation(Some(&"bad-id")).is_err());
+        match parse_get_operation(Some(&"1")).unwrap().into_parts() {
+            (RequestAction::GetOperation(id), _) => assert_eq!(id, "1"),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_patch_operation() {
+        let body = r#"{ "state": "Cancelled" }"#;
+        assert!(parse_patch_operation(&Body::new(body), None).is_err());
+        match parse_patch_operation(&Body::new(body), Some(&"1"))
+            .unwrap()
+            .into_parts()
+        {
+            (RequestAction::CancelOperation(id), _) => assert_eq!(id, "1"),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{ "state": "Done" }"#;
+        assert!(parse_patch_operation(&Body::new(body), Some(&"1")).is_err());
+        let body = r#"{ "state": "Cancelled", "force": true }"#;
+        assert!(parse_patch_operation(&Body::new(body), Some(&"1")).is_err());
+    }
+}
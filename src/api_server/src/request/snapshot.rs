@@ -1,13 +1,14 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use std::fs::File;
 use std::path::PathBuf;
 use std::collections::HashMap; 
 use std::string::String;
 use logger::{IncMetric, METRICS,info};
 use serde::de::Error as DeserializeError;
 use vmm::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    CreateSnapshotParams, DeviceOverrides, LoadSnapshotConfig, LoadSnapshotParams,
+    MemBackendConfig, MemBackendType, SnapshotFiles, Vm, VmState,
 };
 
 use super::super::VmmAction;
@@ -23,17 +24,18 @@ pub const MISSING_FIELD: &str =
 /// Only specifying one of them is allowed.
 pub const TOO_MANY_FIELDS: &str =
     "too many fields: either `mem_backend` or `mem_file_path` exclusively is required";
+/// Diff snapshot chains are located through the path of their base memory file.
+pub const DIFF_CHAIN_FROM_FD: &str = "`diff_chain` cannot be used with `backend_fd`";
 
 pub(crate) fn parse_put_snapshot(
     body: &Body,
     request_type_from_path: Option<&&str>,
+    files: &[File],
 ) -> Result<ParsedRequest, Error> {
     match request_type_from_path {
         Some(&request_type) => match request_type {
-            "create" => Ok(ParsedRequest::new_sync(VmmAction::CreateSnapshot(
-                serde_json::from_slice::<CreateSnapshotParams>(body.raw())?,
-            ))),
-            "load" => parse_put_snapshot_load(body),
+            "create" => parse_put_snapshot_create(body, files),
+            "load" => parse_put_snapshot_load(body, files),
             _ => Err(Error::InvalidPathMethod(
                 format!("/snapshot/{}", request_type),
                 Method::Put,
@@ -46,6 +48,22 @@ pub(crate) fn parse_put_snapshot(
     }
 }
 
+pub(crate) fn parse_get_snapshot(
+    request_type_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match request_type_from_path {
+        Some(&"create") => Ok(ParsedRequest::new_sync(VmmAction::GetMemoryDumpStatus)),
+        Some(&request_type) => Err(Error::InvalidPathMethod(
+            format!("/snapshot/{}", request_type),
+            Method::Get,
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing snapshot operation type.".to_string(),
+        )),
+    }
+}
+
 pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error> {
     let vm = serde_json::from_slice::<Vm>(body.raw())?;
 
@@ -55,8 +73,71 @@ pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error>
     }
 }
 
-fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
+// Returns the file received with the request that `field` designates instead of a path, if any.
+// Exactly one of the `<field>_path` and `<field>_fd` fields must be given.
+pub(crate) fn received_file(
+    field: &str,
+    has_path: bool,
+    fd: Option<usize>,
+    files: &[File],
+) -> Result<Option<File>, Error> {
+    let index = match (has_path, fd) {
+        (true, None) => return Ok(None),
+        (false, Some(index)) => index,
+        _ => {
+            return Err(Error::SerdeJson(serde_json::Error::custom(format!(
+                "exactly one of `{0}_path` and `{0}_fd` is required",
+                field
+            ))))
+        }
+    };
+    let file = files.get(index).ok_or_else(|| {
+        Error::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "`{}_fd` is {} but {} file descriptors were received with the request.",
+                field,
+                index,
+                files.len()
+            ),
+        )
+    })?;
+    file.try_clone().map(Some).map_err(|err| {
+        Error::Generic(
+            StatusCode::InternalServerError,
+            format!("Cannot duplicate the file descriptor {}: {}", index, err),
+        )
+    })
+}
+
+fn parse_put_snapshot_create(body: &Body, files: &[File]) -> Result<ParsedRequest, Error> {
+    let mut snapshot_params = serde_json::from_slice::<CreateSnapshotParams>(body.raw())?;
+    snapshot_params.files = SnapshotFiles {
+        snapshot: received_file(
+            "snapshot",
+            !snapshot_params.snapshot_path.as_os_str().is_empty(),
+            snapshot_params.snapshot_fd,
+            files,
+        )?,
+        mem: received_file(
+            "mem_file",
+            !snapshot_params.mem_file_path.as_os_str().is_empty(),
+            snapshot_params.mem_file_fd,
+            files,
+        )?,
+    };
+    let asynchronous = snapshot_params.asynchronous;
+    let action = VmmAction::CreateSnapshot(snapshot_params);
+    if asynchronous {
+        Ok(ParsedRequest::new_async(action))
+    } else {
+        Ok(ParsedRequest::new_sync(action))
+    }
+}
+
+fn parse_put_snapshot_load(body: &Body, files: &[File]) -> Result<ParsedRequest, Error> {
     let snapshot_config = serde_json::from_slice::<LoadSnapshotConfig>(body.raw())?;
+    let asynchronous = snapshot_config.asynchronous;
 
     match (&snapshot_config.mem_backend, &snapshot_config.mem_file_path) {
         // Ensure `mem_file_path` and `mem_backend` fields are not present at the same time.
@@ -86,17 +167,45 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
                 // This is safe to unwrap() because we ensure above that one of the two:
                 // either `mem_file_path` or `mem_backend` field is always specified.
                 backend_path: snapshot_config.mem_file_path.unwrap(),
+                backend_fd: None,
                 backend_type: MemBackendType::File,
             }
         }
     };
+    let snapshot_files = SnapshotFiles {
+        snapshot: received_file(
+            "snapshot",
+            !snapshot_config.snapshot_path.as_os_str().is_empty(),
+            snapshot_config.snapshot_fd,
+            files,
+        )?,
+        mem: received_file(
+            "backend",
+            !mem_backend.backend_path.as_os_str().is_empty(),
+            mem_backend.backend_fd,
+            files,
+        )?,
+    };
+    if snapshot_files.mem.is_some() && !snapshot_config.diff_chain.is_empty() {
+        return Err(Error::SerdeJson(serde_json::Error::custom(
+            DIFF_CHAIN_FROM_FD,
+        )));
+    }
     info!("PASS_debug decode snapshot params and re-encode them...");
     info!("snapshot_path: {:?}", snapshot_config.snapshot_path);
     info!("mem_backend: {:?}", mem_backend);
     let snapshot_params = LoadSnapshotParams {
         snapshot_path: snapshot_config.snapshot_path,
         mem_backend,
+        diff_chain: snapshot_config.diff_chain,
         enable_diff_snapshots: snapshot_config.enable_diff_snapshots,
+        encryption: snapshot_config.encryption,
+        device_overrides: DeviceOverrides {
+            network_overrides: snapshot_config.network_overrides,
+            drive_overrides: snapshot_config.drive_overrides,
+            vsock_override: snapshot_config.vsock_override,
+        },
+        files: snapshot_files,
         enable_user_page_faults: false,
         sock_file_path: PathBuf::from("/tmp/PASS.socket"),
         overlay_file_path: PathBuf::from("/tmp/overlay_file"),
@@ -109,7 +218,12 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
     };
 
     // Construct the `ParsedRequest` object.
-    let mut parsed_req = ParsedRequest::new_sync(VmmAction::LoadSnapshot(snapshot_params));
+    let action = VmmAction::LoadSnapshot(snapshot_params);
+    let mut parsed_req = if asynchronous {
+        ParsedRequest::new_async(action)
+    } else {
+        ParsedRequest::new_sync(action)
+    };
 
     // If `mem_file_path` was present, set the deprecation message in `parsing_info`.
     if let Some(msg) = deprecation_message {
@@ -125,6 +239,7 @@ mod tests {
 
     use super::*;
     use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
+    use crate::RequestAction;
 
     #[test]
     fn test_parse_put_snapshot() {
@@ -142,12 +257,19 @@ mod tests {
         let mut expected_cfg = CreateSnapshotParams {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
             version: Some(String::from("0.23.0")),
+            encryption: None,
+            background: false,
+            chain_metadata: false,
+            asynchronous: false,
+            files: SnapshotFiles::default(),
         };
 
         match vmm_action_from_request(
-            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
         ) {
             VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
@@ -161,23 +283,62 @@ mod tests {
         expected_cfg = CreateSnapshotParams {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
+            snapshot_fd: None,
             mem_file_path: PathBuf::from("bar"),
+            mem_file_fd: None,
             version: None,
+            encryption: None,
+            background: false,
+            chain_metadata: false,
+            asynchronous: false,
+            files: SnapshotFiles::default(),
         };
 
         match vmm_action_from_request(
-            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "background": true
+              }"#;
+        expected_cfg.background = true;
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
         ) {
             VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
         }
 
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "background": true,
+                "async": true
+              }"#;
+        expected_cfg.asynchronous = true;
+
+        match parse_put_snapshot(&Body::new(body), Some(&"create"), &[])
+            .unwrap()
+            .into_parts()
+        {
+            (RequestAction::Async(action), _) => {
+                assert_eq!(*action, VmmAction::CreateSnapshot(expected_cfg))
+            }
+            _ => panic!("Test failed."),
+        }
+
         let invalid_body = r#"{
                 "invalid_field": "foo",
                 "mem_file_path": "bar"
               }"#;
 
-        assert!(parse_put_snapshot(&Body::new(invalid_body), Some(&"create")).is_err());
+        assert!(parse_put_snapshot(&Body::new(invalid_body), Some(&"create"), &[]).is_err());
 
         body = r#"{
                 "snapshot_path": "foo",
@@ -191,13 +352,18 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
+                backend_fd: None,
                 backend_type: MemBackendType::File,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: false,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -221,13 +387,18 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
+                backend_fd: None,
                 backend_type: MemBackendType::File,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: true,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: false,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -250,13 +421,18 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
+                backend_fd: None,
                 backend_type: MemBackendType::Uffd,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: true,
         };
 
-        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let mut parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         assert!(parsed_request
             .parsing_info()
             .take_deprecation_message()
@@ -276,13 +452,18 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_backend: MemBackendConfig {
                 backend_path: PathBuf::from("bar"),
+                backend_fd: None,
                 backend_type: MemBackendType::File,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: true,
         };
 
-        let parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap();
+        let parsed_request = parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap();
         match depr_action_from_req(parsed_request, Some(LOAD_DEPRECATION_MESSAGE.to_string())) {
             VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
@@ -296,7 +477,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -312,7 +493,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -330,7 +511,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -342,7 +523,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -357,7 +538,7 @@ mod tests {
               }"#;
 
         assert_eq!(
-            parse_put_snapshot(&Body::new(body), Some(&"load"))
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[])
                 .err()
                 .unwrap()
                 .to_string(),
@@ -365,8 +546,235 @@ mod tests {
              `snapshot_path` at line 6 column 15."
         );
 
-        assert!(parse_put_snapshot(&Body::new(body), Some(&"invalid")).is_err());
-        assert!(parse_put_snapshot(&Body::new(body), None).is_err());
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"invalid"), &[]).is_err());
+        assert!(parse_put_snapshot(&Body::new(body), None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_encryption() {
+        use vmm::vmm_config::snapshot::SnapshotEncryptionConfig;
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "encryption": {
+                    "key_path": "key"
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(
+                cfg.encryption,
+                Some(SnapshotEncryptionConfig {
+                    key: None,
+                    key_path: Some(PathBuf::from("key")),
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "bar",
+                    "backend_type": "Uffd"
+                },
+                "encryption": {
+                    "key": "00ff"
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.encryption,
+                Some(SnapshotEncryptionConfig {
+                    key: Some("00ff".to_string()),
+                    key_path: None,
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "encryption": {
+                    "password": "foo"
+                }
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"create"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_diff_chain() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_path": "base",
+                    "backend_type": "File"
+                },
+                "diff_chain": ["diff1", "diff2"]
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.diff_chain,
+                vec![PathBuf::from("diff1"), PathBuf::from("diff2")]
+            ),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_device_overrides() {
+        use vmm::vmm_config::snapshot::{DriveOverride, NetworkOverride, VsockOverride};
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "network_overrides": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "vmtap1"
+                    }
+                ],
+                "drive_overrides": [
+                    {
+                        "drive_id": "rootfs",
+                        "path_on_host": "/srv/clone1/rootfs.ext4"
+                    }
+                ],
+                "vsock_override": {
+                    "vsock_id": "vsock0",
+                    "uds_path": "/srv/clone1/v.sock"
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.device_overrides,
+                DeviceOverrides {
+                    network_overrides: vec![NetworkOverride {
+                        iface_id: "eth0".to_string(),
+                        host_dev_name: "vmtap1".to_string(),
+                    }],
+                    drive_overrides: vec![DriveOverride {
+                        drive_id: "rootfs".to_string(),
+                        path_on_host: "/srv/clone1/rootfs.ext4".to_string(),
+                    }],
+                    vsock_override: Some(VsockOverride {
+                        vsock_id: "vsock0".to_string(),
+                        uds_path: "/srv/clone1/v.sock".to_string(),
+                    }),
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "network_overrides": [
+                    {
+                        "iface_id": "eth0",
+                        "guest_mac": "06:00:00:00:00:01"
+                    }
+                ]
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_snapshot_fds() {
+        use std::os::unix::io::AsRawFd;
+
+        use utils::tempfile::TempFile;
+
+        let files = [
+            TempFile::new().unwrap().as_file().try_clone().unwrap(),
+            TempFile::new().unwrap().as_file().try_clone().unwrap(),
+        ];
+        let body = r#"{
+                "snapshot_fd": 1,
+                "mem_file_fd": 0
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &files).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => {
+                assert!(cfg.snapshot_path.as_os_str().is_empty());
+                // The received descriptors are duplicated.
+                assert_ne!(
+                    cfg.files.snapshot.unwrap().as_raw_fd(),
+                    files[1].as_raw_fd()
+                );
+                assert!(cfg.files.mem.is_some());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot_fd": 0,
+                "mem_backend": {
+                    "backend_fd": 1,
+                    "backend_type": "Uffd"
+                }
+              }"#;
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"load"), &files).unwrap(),
+        ) {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert!(cfg.files.snapshot.is_some());
+                assert!(cfg.files.mem.is_some());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // Both the path and the descriptor.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "snapshot_fd": 0,
+                "mem_file_path": "bar"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"create"), &files).is_err());
+        // Neither of them.
+        let body = r#"{
+                "mem_file_path": "bar"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"create"), &files).is_err());
+        // Descriptor not received.
+        let body = r#"{
+                "snapshot_fd": 2,
+                "mem_file_path": "bar"
+              }"#;
+        assert!(matches!(
+            parse_put_snapshot(&Body::new(body), Some(&"create"), &files),
+            Err(Error::Generic(StatusCode::BadRequest, _))
+        ));
+        // Diff chains are found through the path of the base memory file.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_backend": {
+                    "backend_fd": 0,
+                    "backend_type": "File"
+                },
+                "diff_chain": ["diff1"]
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load"), &files).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_snapshot() {
+        match vmm_action_from_request(parse_get_snapshot(Some(&"create")).unwrap()) {
+            VmmAction::GetMemoryDumpStatus => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_snapshot(Some(&"load")).is_err());
+        assert!(parse_get_snapshot(None).is_err());
     }
 
     #[test]
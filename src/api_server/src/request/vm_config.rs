@@ -0,0 +1,41 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::resources::VmmConfig;
+use vmm::rpc_interface::VmmAction;
+
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+
+pub(crate) fn parse_put_vm_config(body: &Body) -> Result<ParsedRequest, Error> {
+    let config = serde_json::from_slice::<VmmConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetFullVmConfig(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_put_vm_config() {
+        assert!(parse_put_vm_config(&Body::new("invalid_payload")).is_err());
+
+        // The boot source and drives are mandatory.
+        assert!(parse_put_vm_config(&Body::new("{}")).is_err());
+
+        let body = r#"{
+            "boot-source": {
+                "kernel_image_path": "/foo/bar"
+            },
+            "drives": [],
+            "machine-config": {
+                "vcpu_count": 2,
+                "mem_size_mib": 256
+            }
+        }"#;
+        let config = serde_json::from_str::<VmmConfig>(body).unwrap();
+        assert!(parse_put_vm_config(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::SetFullVmConfig(config))));
+    }
+}
@@ -8,6 +8,11 @@ use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
 
+pub(crate) fn parse_get_vsock() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.vsock_count.inc();
+    Ok(ParsedRequest::new_sync(VmmAction::GetVsockDevice))
+}
+
 pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.vsock_count.inc();
     let vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|err| {
@@ -36,7 +41,15 @@ pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parsed_request::tests::depr_action_from_req;
+    use crate::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
+
+    #[test]
+    fn test_parse_get_vsock_request() {
+        assert_eq!(
+            vmm_action_from_request(parse_get_vsock().unwrap()),
+            VmmAction::GetVsockDevice
+        );
+    }
 
     #[test]
     fn test_parse_put_vsock_request() {
@@ -9,6 +9,8 @@ use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use api_server::access::AccessPolicy;
+use api_server::audit::AuditLog;
 use api_server::{ApiRequest, ApiResponse, ApiServer, ServerError};
 use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
 use logger::{error, warn, ProcessTimeReporter};
@@ -125,6 +127,8 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    access_policy: Option<AccessPolicy>,
+    audit_log: Option<AuditLog>,
 ) -> FcExitCode {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -148,7 +152,14 @@ pub(crate) fn run_with_api(
     let api_thread = thread::Builder::new()
         .name("fc_api".to_owned())
         .spawn(move || {
-            match ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd).bind_and_run(
+            let mut api_server = ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd);
+            if let Some(access_policy) = access_policy {
+                api_server.set_access_policy(access_policy);
+            }
+            if let Some(audit_log) = audit_log {
+                api_server.set_audit_log(audit_log);
+            }
+            match api_server.bind_and_run(
                 &api_bind_path,
                 process_time_reporter,
                 &api_seccomp_filter,
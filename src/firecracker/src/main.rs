@@ -3,26 +3,34 @@
 
 mod api_server_adapter;
 mod metrics;
+mod zygote;
 
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{io, panic, process};
 
+use api_server::access::AccessPolicy;
+use api_server::audit::AuditLog;
 use event_manager::SubscriberOps;
-use logger::{error, info, ProcessTimeReporter, StoreMetric, LOGGER, METRICS};
+use logger::{
+    error, info, update_metric_with_elapsed_time, ProcessTimeReporter, StoreMetric, LOGGER, METRICS,
+};
 use seccompiler::BpfThreadMap;
 use snapshot::Snapshot;
 use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
-use vmm::resources::VmResources;
+use vmm::lifecycle::LifecycleEvent;
+use vmm::persist::{restore_from_snapshot, RestoreTimings};
+use vmm::resources::{RestoreVmmConfig, VmResources};
 use vmm::seccomp_filters::{get_filters, SeccompConfig};
 use vmm::signal_handler::register_signal_handlers;
 use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
 use vmm::vmm_config::logger::{init_logger, LoggerConfig, LoggerLevel};
 use vmm::vmm_config::metrics::{init_metrics, MetricsConfig};
+use vmm::vmm_config::snapshot::{MemBackendConfig, MemBackendType, StartupSnapshotConfig};
 use vmm::{EventManager, FcExitCode, HTTP_MAX_PAYLOAD_SIZE};
 
 // The reason we place default API socket under /run is that API socket is a
@@ -117,6 +125,27 @@ fn main_exitable() -> FcExitCode {
                 .default_value(DEFAULT_API_SOCK_PATH)
                 .help("Path to unix domain socket used by the API."),
         )
+        .arg(
+            Argument::new("api-access-policy")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Path to a file that contains the policy restricting which processes may \
+                     send which API requests, in JSON format.",
+                ),
+        )
+        .arg(
+            Argument::new("api-audit-log")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Path to a file or named pipe to which to write a JSON line for each API \
+                     request.",
+                ),
+        )
+        .arg(Argument::new("events-sock").takes_value(true).help(
+            "Path to a unix domain socket on which to stream lifecycle events to its clients.",
+        ))
         .arg(
             Argument::new("id")
                 .takes_value(true)
@@ -164,15 +193,48 @@ fn main_exitable() -> FcExitCode {
                 .takes_value(true)
                 .help("Path to a file that contains metadata in JSON format to add to the mmds."),
         )
+        .arg(Argument::new("no-api").takes_value(false).help(
+            "Optional parameter which allows starting and using a microVM without an active \
+             API socket. Requires either `--config-file`, `--restore-from` or `--zygote`.",
+        ))
         .arg(
-            Argument::new("no-api")
-                .takes_value(false)
-                .requires("config-file")
+            Argument::new("restore-from")
+                .takes_value(true)
+                .requires("no-api")
+                .forbids(vec!["config-file"])
+                .help(
+                    "Path to the snapshot state file to restore the microVM from, instead of \
+                     booting it. Requires `--restore-mem`.",
+                ),
+        )
+        .arg(
+            Argument::new("zygote")
+                .takes_value(true)
+                .requires("no-api")
+                .forbids(vec![
+                    "config-file",
+                    "restore-from",
+                    "log-path",
+                    "metrics-path",
+                    "events-sock",
+                ])
                 .help(
-                    "Optional parameter which allows starting and using a microVM without an \
-                     active API socket.",
+                    "Path to the unix domain socket on which to receive requests to restore \
+                     microVMs from snapshots, each in a new process forked from this one.",
                 ),
         )
+        .arg(
+            Argument::new("restore-mem")
+                .takes_value(true)
+                .requires("restore-from")
+                .help("Path to the guest memory file of the snapshot to restore the microVM from."),
+        )
+        .arg(
+            Argument::new("restore-resume")
+                .takes_value(false)
+                .requires("restore-from")
+                .help("Whether or not to resume the microVM once restored from the snapshot."),
+        )
         .arg(
             Argument::new("log-path")
                 .takes_value(true)
@@ -313,6 +375,12 @@ fn main_exitable() -> FcExitCode {
         };
     }
 
+    if let Some(events_sock) = arguments.single_value("events-sock") {
+        if let Err(err) = vmm::lifecycle::bind(Path::new(events_sock)) {
+            return generic_error_exit(&format!("Could not bind the events socket: {}", err));
+        }
+    }
+
     let mut seccomp_filters: BpfThreadMap = match SeccompConfig::from_args(
         arguments.flag_present("no-seccomp"),
         arguments.single_value("seccomp-filter"),
@@ -359,11 +427,38 @@ fn main_exitable() -> FcExitCode {
         .unwrap_or_else(|| api_payload_limit);
 
     if api_enabled {
+        // The configuration file of a restored microVM is only read without the API.
+        if let Some(Ok(Some(_))) = vmm_config_json.as_deref().map(RestoreVmmConfig::from_json) {
+            return generic_error_exit(
+                "The snapshot-load section of the configuration file requires --no-api.",
+            );
+        }
+
         let bind_path = arguments
             .single_value("api-sock")
             .map(PathBuf::from)
             .expect("Missing argument: api-sock");
 
+        let access_policy = match arguments
+            .single_value("api-access-policy")
+            .map(|path| AccessPolicy::from_file(Path::new(path)))
+            .transpose()
+        {
+            Ok(access_policy) => access_policy,
+            Err(err) => return generic_error_exit(&err.to_string()),
+        };
+
+        let audit_log = match arguments
+            .single_value("api-audit-log")
+            .map(|path| AuditLog::from_path(Path::new(path)))
+            .transpose()
+        {
+            Ok(audit_log) => audit_log,
+            Err(err) => {
+                return generic_error_exit(&format!("Cannot open the API audit log: {}", err))
+            }
+        };
+
         let start_time_us = arguments.single_value("start-time-us").map(|s| {
             s.parse::<u64>()
                 .expect("'start-time-us' parameter expected to be of 'u64' type.")
@@ -392,15 +487,78 @@ fn main_exitable() -> FcExitCode {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            access_policy,
+            audit_log,
         )
     } else {
         let seccomp_filters: BpfThreadMap = seccomp_filters
             .into_iter()
             .filter(|(k, _)| k != "api")
             .collect();
+
+        if let Some(control_path) = arguments.single_value("zygote") {
+            return zygote::run_zygote(
+                &seccomp_filters,
+                Path::new(control_path),
+                instance_info,
+                mmds_size_limit,
+                metadata_json.as_deref(),
+            );
+        }
+
+        let restore_config = match arguments.single_value("restore-from") {
+            Some(snapshot_path) => match arguments.single_value("restore-mem") {
+                Some(mem_file_path) => Some(RestoreVmmConfig {
+                    snapshot_load: StartupSnapshotConfig {
+                        snapshot_path: PathBuf::from(snapshot_path),
+                        mem_backend: MemBackendConfig {
+                            backend_path: PathBuf::from(mem_file_path),
+                            backend_fd: None,
+                            backend_type: MemBackendType::File,
+                        },
+                        diff_chain: Vec::new(),
+                        enable_diff_snapshots: false,
+                        encryption: None,
+                        network_overrides: Vec::new(),
+                        drive_overrides: Vec::new(),
+                        vsock_override: None,
+                        resume_vm: arguments.flag_present("restore-resume"),
+                    },
+                    logger: None,
+                    metrics: None,
+                }),
+                None => {
+                    error!(
+                        "Arguments parsing error: Argument 'restore-mem' required, but not \
+                         found. \n\nFor more information try --help."
+                    );
+                    return vmm::FcExitCode::ArgParsing;
+                }
+            },
+            None => match vmm_config_json
+                .as_deref()
+                .map(RestoreVmmConfig::from_json)
+                .transpose()
+            {
+                Ok(restore_config) => restore_config.flatten(),
+                Err(err) => {
+                    error!("Configuration for VMM from one single json failed: {}", err);
+                    return vmm::FcExitCode::BadConfiguration;
+                }
+            },
+        };
+        if vmm_config_json.is_none() && restore_config.is_none() {
+            error!(
+                "Arguments parsing error: Argument 'config-file', 'restore-from' or 'zygote' \
+                 required, but not found. \n\nFor more information try --help."
+            );
+            return vmm::FcExitCode::ArgParsing;
+        }
+
         run_without_api(
             &seccomp_filters,
             vmm_config_json,
+            restore_config,
             instance_info,
             boot_timer_enabled,
             mmds_size_limit,
@@ -421,6 +579,9 @@ fn main() {
     // See process_exitable() method of Subscriber trait for what triggers the exit_code.
     //
     let exit_code = main_exitable();
+    vmm::lifecycle::emit(LifecycleEvent::Shutdown {
+        exit_code: exit_code as i32,
+    });
     std::process::exit(exit_code as i32);
 }
 
@@ -512,9 +673,61 @@ fn build_microvm_from_json(
     Ok((vm_resources, vmm))
 }
 
+// Restore a microVM from the snapshot described by the command line, the JSON or a zygote
+// request. On failure, returns the reason to report.
+fn restore_microvm_from_config(
+    seccomp_filters: &BpfThreadMap,
+    event_manager: &mut EventManager,
+    restore_config: RestoreVmmConfig,
+    instance_info: InstanceInfo,
+    mmds_size_limit: usize,
+    metadata_json: Option<&str>,
+) -> std::result::Result<(Arc<Mutex<vmm::Vmm>>, RestoreTimings), String> {
+    let (mut vm_resources, params) = VmResources::from_restore_config(
+        restore_config,
+        &instance_info,
+        mmds_size_limit,
+        metadata_json,
+    )
+    .map_err(|err| format!("Configuration for VMM restore failed: {}", err))?;
+
+    let load_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+    let (vmm, mut timings) = restore_from_snapshot(
+        &instance_info,
+        event_manager,
+        seccomp_filters,
+        &params,
+        VERSION_MAP.clone(),
+        &mut vm_resources,
+    )
+    .map_err(|err| format!("Restoring VMM from snapshot failed: {:?}", err))?;
+    if params.resume_vm {
+        let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        vmm.lock()
+            .expect("Poisoned lock")
+            .resume_vm()
+            .map_err(|err| format!("Resuming restored VMM failed: {:?}", err))?;
+        timings.resume_vm_us =
+            utils::time::get_time_us(utils::time::ClockType::Monotonic) - resume_start_us;
+    }
+    timings.total_us =
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
+    timings.update_metrics();
+    info!(
+        "Successfully restored microvm from snapshot in {} us ({:?})",
+        timings.total_us, timings
+    );
+    vmm::lifecycle::emit(LifecycleEvent::SnapshotLoaded {
+        timings: timings.clone(),
+    });
+
+    Ok((vmm, timings))
+}
+
 fn run_without_api(
     seccomp_filters: &BpfThreadMap,
     config_json: Option<String>,
+    restore_config: Option<RestoreVmmConfig>,
     instance_info: InstanceInfo,
     bool_timer_enabled: bool,
     mmds_size_limit: usize,
@@ -526,21 +739,48 @@ fn run_without_api(
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
 
-    // Build the microVm. We can ignore VmResources since it's not used without api.
-    let (_, vmm) = match build_microvm_from_json(
-        seccomp_filters,
-        &mut event_manager,
-        // Safe to unwrap since '--no-api' requires this to be set.
-        config_json.unwrap(),
-        instance_info,
-        bool_timer_enabled,
-        mmds_size_limit,
-        metadata_json,
-    ) {
-        Ok((res, vmm)) => (res, vmm),
-        Err(exit_code) => return exit_code,
+    // Build the microVm, or restore it from a snapshot. We can ignore VmResources since it's
+    // not used without api.
+    let vmm = match restore_config {
+        Some(restore_config) => match restore_microvm_from_config(
+            seccomp_filters,
+            &mut event_manager,
+            restore_config,
+            instance_info,
+            mmds_size_limit,
+            metadata_json,
+        ) {
+            Ok((vmm, _)) => vmm,
+            Err(msg) => {
+                error!("{}", msg);
+                return vmm::FcExitCode::BadConfiguration;
+            }
+        },
+        None => match build_microvm_from_json(
+            seccomp_filters,
+            &mut event_manager,
+            // Safe to unwrap since '--no-api' requires this to be set unless a snapshot is
+            // restored.
+            config_json.unwrap(),
+            instance_info,
+            bool_timer_enabled,
+            mmds_size_limit,
+            metadata_json,
+        ) {
+            Ok((_, vmm)) => vmm,
+            Err(exit_code) => return exit_code,
+        },
     };
 
+    run_microvm(&mut event_manager, &firecracker_metrics, &vmm)
+}
+
+// Run the EventManager that drives everything in a started microVM, until the microVM exits.
+fn run_microvm(
+    event_manager: &mut EventManager,
+    firecracker_metrics: &Mutex<metrics::PeriodicMetrics>,
+    vmm: &Mutex<vmm::Vmm>,
+) -> FcExitCode {
     // Start the metrics.
     firecracker_metrics
         .lock()
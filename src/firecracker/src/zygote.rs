@@ -0,0 +1,264 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use event_manager::SubscriberOps;
+use logger::{error, info, warn, LOGGER};
+use seccompiler::BpfThreadMap;
+use utils::validators::validate_instance_id;
+use vmm::vmm_config::instance_info::InstanceInfo;
+use vmm::vmm_config::zygote::{ZygoteRequest, ZygoteResponse};
+use vmm::{EventManager, FcExitCode};
+
+use crate::metrics::PeriodicMetrics;
+use crate::{restore_microvm_from_config, run_microvm};
+
+/// Maximum size of a request received on the control socket.
+const MAX_REQUEST_SIZE: usize = 64 << 10;
+/// Time a client has to send its whole request once connected to the control socket. Requests
+/// are read one at a time, so a slow client delays the ones connected after it by this much.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(100);
+/// Longest time the exit status of a process forked from the zygote stays uncollected.
+const REAP_INTERVAL_MS: i32 = 1000;
+
+/// Runs Firecracker as a zygote: the process opens KVM and prepares the event loop of a
+/// microVM ahead of time, then forks a new process restoring a microVM from a snapshot for
+/// every request received on the control socket.
+///
+/// The zygote must stay single threaded, so that forking it is safe.
+pub(crate) fn run_zygote(
+    seccomp_filters: &BpfThreadMap,
+    control_path: &Path,
+    instance_info: InstanceInfo,
+    mmds_size_limit: usize,
+    metadata_json: Option<&str>,
+) -> FcExitCode {
+    if let Err(err) = vmm::builder::preopen_kvm() {
+        error!("Could not open KVM: {}", err);
+        return FcExitCode::GenericError;
+    }
+
+    let listener = match UnixListener::bind(control_path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Could not bind the zygote control socket: {}", err);
+            return FcExitCode::GenericError;
+        }
+    };
+    info!("Zygote waiting for restore requests on {:?}", control_path);
+
+    loop {
+        // Prepare the event loop of the next microVM before waiting for its request.
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let firecracker_metrics = Arc::new(Mutex::new(PeriodicMetrics::new()));
+        event_manager.add_subscriber(firecracker_metrics.clone());
+
+        let (stream, request) = loop {
+            reap_children();
+            match wait_for_connection(&listener) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(err) => {
+                    error!("Failed to wait for a zygote connection: {}", err);
+                    continue;
+                }
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    error!("Failed to accept a zygote connection: {}", err);
+                    continue;
+                }
+            };
+
+            match read_request(&stream) {
+                Ok(request) => break (stream, request),
+                Err(msg) => {
+                    warn!("{}", msg);
+                    respond(&stream, &ZygoteResponse::fault(None, msg));
+                }
+            }
+        };
+
+        // SAFETY: The zygote is single threaded, so the child process gets a consistent copy
+        // of its memory. The return value is checked below.
+        match unsafe { libc::fork() } {
+            -1 => {
+                let msg = format!(
+                    "Could not fork the zygote: {}",
+                    std::io::Error::last_os_error()
+                );
+                error!("{}", msg);
+                respond(&stream, &ZygoteResponse::fault(None, msg));
+            }
+            0 => {
+                // The control socket belongs to the zygote.
+                drop(listener);
+                return run_child(
+                    seccomp_filters,
+                    event_manager,
+                    firecracker_metrics,
+                    stream,
+                    request,
+                    instance_info,
+                    mmds_size_limit,
+                    metadata_json,
+                );
+            }
+            pid => {
+                info!("Restoring microVM {} in process {}", request.id, pid);
+                // The child took the KVM context opened ahead of time. Open one for the next
+                // child, closing the copy inherited by this one.
+                if let Err(err) = vmm::builder::preopen_kvm() {
+                    error!("Could not open KVM: {}", err);
+                    return FcExitCode::GenericError;
+                }
+            }
+        }
+    }
+}
+
+// Restore the microVM of `request` in a process forked from the zygote, then run it.
+#[allow(clippy::too_many_arguments)]
+fn run_child(
+    seccomp_filters: &BpfThreadMap,
+    mut event_manager: EventManager,
+    firecracker_metrics: Arc<Mutex<PeriodicMetrics>>,
+    stream: UnixStream,
+    request: ZygoteRequest,
+    instance_info: InstanceInfo,
+    mmds_size_limit: usize,
+    metadata_json: Option<&str>,
+) -> FcExitCode {
+    let (id, restore_config) = request.into_parts();
+    LOGGER.set_instance_id(id.clone());
+    let instance_info = InstanceInfo {
+        id,
+        ..instance_info
+    };
+    let pid = Some(std::process::id() as i32);
+
+    let vmm = match restore_microvm_from_config(
+        seccomp_filters,
+        &mut event_manager,
+        restore_config,
+        instance_info,
+        mmds_size_limit,
+        metadata_json,
+    ) {
+        Ok((vmm, timings)) => {
+            respond(
+                &stream,
+                &ZygoteResponse {
+                    pid,
+                    timings: Some(timings),
+                    fault_message: None,
+                },
+            );
+            vmm
+        }
+        Err(msg) => {
+            error!("{}", msg);
+            respond(&stream, &ZygoteResponse::fault(pid, msg));
+            return FcExitCode::BadConfiguration;
+        }
+    };
+    drop(stream);
+
+    run_microvm(&mut event_manager, &firecracker_metrics, &vmm)
+}
+
+// Wait until a client connects to the control socket, for at most `REAP_INTERVAL_MS`.
+fn wait_for_connection(listener: &UnixListener) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `pollfd` is a valid pointer to a single element, and the return value is checked.
+    match unsafe { libc::poll(&mut pollfd, 1, REAP_INTERVAL_MS) } {
+        -1 => {
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(err),
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+// Read the single line JSON request sent on a connection to the control socket. The client
+// has `REQUEST_TIMEOUT` to send the whole request, however it splits it.
+fn read_request(mut stream: &UnixStream) -> Result<ZygoteRequest, String> {
+    let read_err = |err| format!("Failed to read the zygote request: {}", err);
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut line = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !line.ends_with(b"\n") {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| read_err(io::Error::from(io::ErrorKind::TimedOut)))?;
+        stream.set_read_timeout(Some(remaining)).map_err(read_err)?;
+        let count = stream.read(&mut buf).map_err(read_err)?;
+        if count == 0 {
+            break;
+        }
+        line.extend_from_slice(&buf[..count]);
+        if line.len() > MAX_REQUEST_SIZE {
+            return Err(format!(
+                "The zygote request is larger than {} bytes",
+                MAX_REQUEST_SIZE
+            ));
+        }
+    }
+
+    let request: ZygoteRequest =
+        serde_json::from_slice(&line).map_err(|err| format!("Invalid zygote request: {}", err))?;
+    validate_instance_id(&request.id)
+        .map_err(|err| format!("Invalid instance ID {}: {}", request.id, err))?;
+    Ok(request)
+}
+
+// Send the single line JSON response to a request.
+fn respond(mut stream: &UnixStream, response: &ZygoteResponse) {
+    // The response only holds strings and integers, so it always serializes.
+    let mut body = serde_json::to_string(response).unwrap();
+    body.push('\n');
+    if let Err(err) = stream.write_all(body.as_bytes()) {
+        warn!("Failed to send the zygote response: {}", err);
+    }
+}
+
+// Collect the exit status of the processes forked from the zygote which exited.
+fn reap_children() {
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` is a valid pointer, and the return value is checked.
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+        if pid <= 0 {
+            break;
+        }
+        if libc::WIFEXITED(status) {
+            info!(
+                "MicroVM process {} exited with code {}",
+                pid,
+                libc::WEXITSTATUS(status)
+            );
+        } else if libc::WIFSIGNALED(status) {
+            warn!(
+                "MicroVM process {} killed by signal {}",
+                pid,
+                libc::WTERMSIG(status)
+            );
+        }
+    }
+}
@@ -354,19 +354,29 @@ pub struct ApiServerMetrics {
     pub sync_response_fails: SharedIncMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedIncMetric,
+    /// Number of API requests denied by the access policy.
+    pub access_denied_count: SharedIncMetric,
+    /// Number of failures in writing to the API audit log.
+    pub audit_log_fails: SharedIncMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct GetRequestsMetrics {
+    /// Number of GETs for getting the state of a block device.
+    pub drive_count: SharedIncMetric,
     /// Number of GETs for getting information on the instance.
     pub instance_info_count: SharedIncMetric,
     /// Number of GETs for getting status on attaching machine configuration.
     pub machine_cfg_count: SharedIncMetric,
     /// Number of GETs for getting mmds.
     pub mmds_count: SharedIncMetric,
+    /// Number of GETs for getting the state of a network interface.
+    pub network_count: SharedIncMetric,
     /// Number of GETs for getting the VMM version.
     pub vmm_version_count: SharedIncMetric,
+    /// Number of GETs for getting the state of the vsock device.
+    pub vsock_count: SharedIncMetric,
 }
 
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
@@ -384,6 +394,10 @@ pub struct PutRequestsMetrics {
     pub drive_count: SharedIncMetric,
     /// Number of failures in attaching a block device.
     pub drive_fails: SharedIncMetric,
+    /// Number of PUTs forwarding a function invocation to the guest.
+    pub invoke_count: SharedIncMetric,
+    /// Number of failures in forwarding a function invocation to the guest.
+    pub invoke_fails: SharedIncMetric,
     /// Number of PUTs for initializing the logging system.
     pub logger_count: SharedIncMetric,
     /// Number of failures in initializing the logging system.
@@ -637,12 +651,31 @@ pub struct PerformanceMetrics {
     pub pause_vm: SharedStoreMetric,
     /// Measures the microVM resuming duration, at the API (user) level, in microseconds.
     pub resume_vm: SharedStoreMetric,
+    /// Measures the function invocations forwarded to the guest, from the API request to the
+    /// guest response, in microseconds.
+    pub invoke: SharedStoreMetric,
     /// Measures the snapshot full create time, at the VMM level, in microseconds.
     pub vmm_full_create_snapshot: SharedStoreMetric,
     /// Measures the snapshot diff create time, at the VMM level, in microseconds.
     pub vmm_diff_create_snapshot: SharedStoreMetric,
     /// Measures the snapshot load time, at the VMM level, in microseconds.
     pub vmm_load_snapshot: SharedStoreMetric,
+    /// Measures the reading and decoding of the snapshot state file during a snapshot load, in
+    /// microseconds.
+    pub load_snapshot_read_state: SharedStoreMetric,
+    /// Measures the snapshot state sanity check during a snapshot load, in microseconds.
+    pub load_snapshot_sanity_check: SharedStoreMetric,
+    /// Measures the guest memory mapping during a snapshot load, in microseconds.
+    pub load_snapshot_map_memory: SharedStoreMetric,
+    /// Measures the handshake with the page fault handler during a snapshot load, in
+    /// microseconds.
+    pub load_snapshot_uffd_handshake: SharedStoreMetric,
+    /// Measures the VM and device state restore during a snapshot load, in microseconds.
+    pub load_snapshot_restore_devices: SharedStoreMetric,
+    /// Measures the vCPU state restore during a snapshot load, in microseconds.
+    pub load_snapshot_restore_vcpus: SharedStoreMetric,
+    /// Measures the microVM resume at the end of a snapshot load, in microseconds.
+    pub load_snapshot_resume_vm: SharedStoreMetric,
     /// Measures the microVM pausing duration, at the VMM level, in microseconds.
     pub vmm_pause_vm: SharedStoreMetric,
     /// Measures the microVM resuming duration, at the VMM level, in microseconds.
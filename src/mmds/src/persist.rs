@@ -6,6 +6,7 @@
 use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex};
 
+use serde::Serialize;
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -15,7 +16,7 @@ use super::ns::MmdsNetworkStack;
 use crate::Mmds;
 
 /// State of a MmdsNetworkStack.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct MmdsNetworkStackState {
     mac_addr: [u8; MAC_ADDR_LEN],
@@ -3,6 +3,7 @@
 
 //! Defines the structures needed for saving/restoring a RateLimiter.
 
+use serde::Serialize;
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -10,7 +11,7 @@ use versionize_derive::Versionize;
 use super::*;
 
 /// State for saving a TokenBucket.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct TokenBucketState {
     size: u64,
@@ -53,7 +54,7 @@ impl Persist<'_> for TokenBucket {
 }
 
 /// State for saving a RateLimiter.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct RateLimiterState {
     ops: Option<TokenBucketState>,
@@ -0,0 +1,206 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Merges consecutive snapshots of a diff snapshot chain into a single memory file.
+
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use vmm::snapshot_chain::{self, data_extents, validate_links, SnapshotMetadata};
+
+/// Size of the bounce buffer used when copying data extents.
+const CHUNK_SIZE: usize = 2 << 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The chain segments are invalid.
+    #[error("{0}")]
+    Chain(#[from] snapshot_chain::Error),
+    /// The output would overwrite one of the merged files.
+    #[error("The output path {0:?} is one of the merged memory files")]
+    OutputIsSegment(PathBuf),
+    /// Failed to read a memory file.
+    #[error("Failed to read {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    /// Fewer than two memory files were given.
+    #[error("At least two memory files are needed")]
+    TooFewSegments,
+    /// Failed to write the merged memory file.
+    #[error("Failed to write {0:?}: {1}")]
+    Write(PathBuf, std::io::Error),
+}
+
+// Whether `a` and `b` name the same file, when both exist.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// Copies the data extents of `src` to the same offsets in `dst`.
+fn copy_extents(src_path: &Path, dst: &File, dst_path: &Path) -> Result<(), Error> {
+    let read_err = |err| Error::Read(src_path.to_path_buf(), err);
+    let mut src = File::open(src_path).map_err(read_err)?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for (start, end) in data_extents(&mut src).map_err(read_err)? {
+        let mut pos = start;
+        while pos < end {
+            let chunk = &mut buf[..CHUNK_SIZE.min((end - pos) as usize)];
+            src.read_exact_at(chunk, pos).map_err(read_err)?;
+            dst.write_all_at(chunk, pos)
+                .map_err(|err| Error::Write(dst_path.to_path_buf(), err))?;
+            pos += chunk.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+/// Merges the memory files `segments`, oldest first, into `output_path`.
+///
+/// The segments must be consecutive snapshots of a chain. The merged file takes their place
+/// in the chain: it has the parent of the first segment and the content identifier of the
+/// last one, so the diff snapshots taken on top of the last segment remain valid children.
+/// Returns the metadata of the merged file.
+pub fn compact(segments: &[PathBuf], output_path: &Path) -> Result<SnapshotMetadata, Error> {
+    if segments.len() < 2 {
+        return Err(Error::TooFewSegments);
+    }
+    if segments.iter().any(|path| same_file(path, output_path)) {
+        return Err(Error::OutputIsSegment(output_path.to_path_buf()));
+    }
+
+    let chain = segments
+        .iter()
+        .map(|path| Ok((path.clone(), SnapshotMetadata::load(path)?)))
+        .collect::<Result<Vec<_>, snapshot_chain::Error>>()?;
+    validate_links(&chain)?;
+
+    let write_err = |err| Error::Write(output_path.to_path_buf(), err);
+    let first = &chain[0].1;
+    let output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_path)
+        .map_err(write_err)?;
+    // Pages left untouched by every segment stay holes.
+    output.set_len(first.mem_size).map_err(write_err)?;
+    for (path, _) in chain.iter() {
+        copy_extents(path, &output, output_path)?;
+    }
+    output.sync_all().map_err(write_err)?;
+
+    // Safe to unwrap because the chain holds at least two segments.
+    let last = &chain.last().unwrap().1;
+    let metadata = SnapshotMetadata {
+        id: last.id.clone(),
+        parent_id: first.parent_id.clone(),
+        snapshot_type: first.snapshot_type,
+        mem_size: first.mem_size,
+        children: last.children.clone(),
+    };
+    metadata.save(output_path)?;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::utils::get_page_size;
+    use ::utils::tempfile::TempFile;
+    use vmm::snapshot_chain::{content_id, metadata_path, record_snapshot, ChainLink};
+    use vmm::vmm_config::snapshot::SnapshotType;
+
+    use super::*;
+
+    // A sparse file of 4 pages holding `fill` in the pages listed in `data_pages`.
+    fn memory_file(data_pages: &[usize], fill: u8) -> TempFile {
+        let page_size = get_page_size().unwrap();
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len((4 * page_size) as u64).unwrap();
+        for page in data_pages {
+            file.as_file()
+                .write_all_at(&vec![fill; page_size], (page * page_size) as u64)
+                .unwrap();
+        }
+        file
+    }
+
+    fn record(
+        file: &TempFile,
+        snapshot_type: SnapshotType,
+        parent: Option<&ChainLink>,
+    ) -> ChainLink {
+        let parent_id = parent.map(|link| link.id.as_str());
+        let id = content_id(&mut File::open(file.as_path()).unwrap(), parent_id).unwrap();
+        record_snapshot(file.as_path(), snapshot_type, parent, id).unwrap()
+    }
+
+    fn read_page(path: &Path, page: usize) -> Vec<u8> {
+        let page_size = get_page_size().unwrap();
+        let mut buf = vec![0u8; page_size];
+        File::open(path)
+            .unwrap()
+            .read_exact_at(&mut buf, (page * page_size) as u64)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_compact() {
+        let page_size = get_page_size().unwrap();
+        let base = memory_file(&[0, 1, 2, 3], 1);
+        let diff1 = memory_file(&[1, 2], 2);
+        let diff2 = memory_file(&[2], 3);
+        let diff3 = memory_file(&[3], 4);
+        let base_link = record(&base, SnapshotType::Full, None);
+        let link1 = record(&diff1, SnapshotType::Diff, Some(&base_link));
+        let link2 = record(&diff2, SnapshotType::Diff, Some(&link1));
+        record(&diff3, SnapshotType::Diff, Some(&link2));
+        let path = |file: &TempFile| file.as_path().to_path_buf();
+
+        // Merging diffs gives a diff that replaces them in the chain.
+        let merged = TempFile::new().unwrap();
+        let metadata = compact(&[path(&diff1), path(&diff2)], merged.as_path()).unwrap();
+        assert_eq!(metadata.id, link2.id);
+        assert_eq!(metadata.parent_id, Some(base_link.id.clone()));
+        assert_eq!(metadata.snapshot_type, SnapshotType::Diff);
+        assert_eq!(read_page(merged.as_path(), 0), vec![0u8; page_size]);
+        assert_eq!(read_page(merged.as_path(), 1), vec![2u8; page_size]);
+        assert_eq!(read_page(merged.as_path(), 2), vec![3u8; page_size]);
+        assert_eq!(
+            snapshot_chain::validate_chain(base.as_path(), &[path(&merged), path(&diff3)])
+                .unwrap()
+                .id,
+            SnapshotMetadata::load(diff3.as_path()).unwrap().id
+        );
+
+        // Merging from the base gives a full snapshot.
+        let full = TempFile::new().unwrap();
+        let metadata =
+            compact(&[path(&base), path(&merged), path(&diff3)], full.as_path()).unwrap();
+        assert_eq!(metadata.parent_id, None);
+        assert_eq!(metadata.snapshot_type, SnapshotType::Full);
+        for (page, fill) in [(0, 1u8), (1, 2), (2, 3), (3, 4)] {
+            assert_eq!(read_page(full.as_path(), page), vec![fill; page_size]);
+        }
+
+        assert!(matches!(
+            compact(&[path(&diff2), path(&diff1)], full.as_path()),
+            Err(Error::Chain(snapshot_chain::Error::BrokenChain(..)))
+        ));
+        assert!(matches!(
+            compact(&[path(&diff1), path(&diff2)], diff1.as_path()),
+            Err(Error::OutputIsSegment(_))
+        ));
+        assert!(matches!(
+            compact(&[path(&diff1)], full.as_path()),
+            Err(Error::TooFewSegments)
+        ));
+
+        for file in [&base, &diff1, &diff2, &diff3, &merged, &full] {
+            fs::remove_file(metadata_path(file.as_path())).unwrap();
+        }
+    }
+}
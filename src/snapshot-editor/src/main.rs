@@ -0,0 +1,251 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+mod chain;
+mod memory;
+mod utils;
+mod vmstate;
+
+const EXIT_CODE_ERROR: i32 = 1;
+const EXIT_CODE_DIFFERENT: i32 = 2;
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("{0}")]
+    Chain(#[from] chain::Error),
+    #[error("{0}")]
+    Memory(#[from] memory::Error),
+    #[error("{0}")]
+    Utils(#[from] utils::Error),
+    #[error("{0}")]
+    Vmstate(#[from] vmstate::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Parser)]
+#[command(version = format!("v{}", crate::utils::SNAPSHOT_EDITOR_VERSION))]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Guest memory related operations
+    #[command(subcommand)]
+    Memory(MemoryOperation),
+    /// Snapshot state file related operations
+    #[command(subcommand)]
+    Vmstate(VmstateOperation),
+}
+
+#[derive(Subcommand)]
+enum MemoryOperation {
+    /// Convert guest memory between a memory file, a sparse diff file and a PMem slot, using
+    /// the region layout recorded in the snapshot state file.
+    Convert {
+        /// Path of the snapshot state file describing the guest memory regions.
+        #[arg(long, value_name = "PATH")]
+        snapshot_path: PathBuf,
+        /// Representation of the source guest memory.
+        #[arg(long, value_enum)]
+        src_format: memory::MemoryFormat,
+        /// Path of the source memory file or PMem device.
+        #[arg(long, value_name = "PATH")]
+        src_path: PathBuf,
+        /// Byte offset at which the source guest memory starts.
+        #[arg(long, default_value_t = 0)]
+        src_offset: u64,
+        /// Representation of the destination guest memory.
+        #[arg(long, value_enum)]
+        dst_format: memory::MemoryFormat,
+        /// Path of the destination memory file or PMem device.
+        #[arg(long, value_name = "PATH")]
+        dst_path: PathBuf,
+        /// Byte offset at which the destination guest memory starts (e.g. the start of a
+        /// PMem slot).
+        #[arg(long, default_value_t = 0)]
+        dst_offset: u64,
+        /// Lay the regions out back to back in the destination, each one starting at a
+        /// multiple of ALIGN bytes. The region offsets in the snapshot state file are
+        /// rewritten to match.
+        #[arg(long)]
+        align: Option<u64>,
+        /// Path of the snapshot state file to write, even if the region offsets do not
+        /// change. By default, `snapshot_path` is rewritten when they do.
+        #[arg(long, value_name = "PATH")]
+        output_snapshot_path: Option<PathBuf>,
+    },
+    /// Merge consecutive snapshots of a diff snapshot chain into one memory file that takes
+    /// their place in the chain.
+    Compact {
+        /// Memory files to merge, oldest first. The result is a full snapshot if the first
+        /// one is.
+        #[arg(value_name = "PATH", num_args = 2.., required = true)]
+        mem_file_paths: Vec<PathBuf>,
+        /// Path of the merged memory file.
+        #[arg(long, value_name = "PATH")]
+        output_path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum VmstateOperation {
+    /// Print the whole microVM state stored in a snapshot state file as JSON.
+    Dump {
+        /// Path of the snapshot state file.
+        #[arg(long, value_name = "PATH")]
+        snapshot_path: PathBuf,
+        /// Path of the JSON file to write. Defaults to stdout.
+        #[arg(long, value_name = "PATH")]
+        output_path: Option<PathBuf>,
+    },
+    /// Compare two snapshot state files field by field, printing one line per difference.
+    /// Exits with code 2 if the files differ.
+    Diff {
+        /// Path of the first snapshot state file.
+        #[arg(value_name = "PATH")]
+        left: PathBuf,
+        /// Path of the second snapshot state file.
+        #[arg(value_name = "PATH")]
+        right: PathBuf,
+    },
+    /// Convert a snapshot state file to the snapshot data version of another Firecracker
+    /// release, printing one line per versioned type encoded differently and per field whose
+    /// value is lost.
+    Convert {
+        /// Path of the snapshot state file.
+        #[arg(long, value_name = "PATH")]
+        snapshot_path: PathBuf,
+        /// Firecracker release to convert the file for, e.g. `1.2.0`. Defaults to the release
+        /// of this tool.
+        #[arg(long, value_name = "VERSION")]
+        target_version: Option<String>,
+        /// Path of the converted snapshot state file. Defaults to `snapshot_path`.
+        #[arg(long, value_name = "PATH")]
+        output_path: Option<PathBuf>,
+        /// Only print the changes, without writing the converted file.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Memory(op) => match op {
+            MemoryOperation::Convert {
+                snapshot_path,
+                src_format,
+                src_path,
+                src_offset,
+                dst_format,
+                dst_path,
+                dst_offset,
+                align,
+                output_snapshot_path,
+            } => {
+                let (mut microvm_state, data_version) = utils::open_vmstate(&snapshot_path)?;
+                let memory_state = &microvm_state.memory_state;
+
+                let src = memory::MemoryLocation {
+                    format: src_format,
+                    path: src_path,
+                    offset: src_offset,
+                };
+                let dst = memory::MemoryLocation {
+                    format: dst_format,
+                    path: dst_path,
+                    offset: dst_offset,
+                };
+                let dst_offsets = align
+                    .map(|align| memory::packed_offsets(memory_state, align))
+                    .transpose()?;
+
+                let offsets = memory::convert(memory_state, &src, &dst, dst_offsets)?;
+
+                let layout_changed = microvm_state
+                    .memory_state
+                    .regions
+                    .iter()
+                    .zip(offsets.iter())
+                    .any(|(region, offset)| region.offset != *offset);
+                if layout_changed || output_snapshot_path.is_some() {
+                    for (region, offset) in microvm_state
+                        .memory_state
+                        .regions
+                        .iter_mut()
+                        .zip(offsets.into_iter())
+                    {
+                        region.offset = offset;
+                    }
+                    let output_snapshot_path = output_snapshot_path.unwrap_or(snapshot_path);
+                    utils::save_vmstate(&microvm_state, &output_snapshot_path, data_version)?;
+                }
+            }
+            MemoryOperation::Compact {
+                mem_file_paths,
+                output_path,
+            } => {
+                let metadata = chain::compact(&mem_file_paths, &output_path)?;
+                println!("{}", metadata.id);
+            }
+        },
+        Command::Vmstate(op) => match op {
+            VmstateOperation::Dump {
+                snapshot_path,
+                output_path,
+            } => {
+                let (microvm_state, data_version) = utils::open_vmstate(&snapshot_path)?;
+                let json = vmstate::to_json(&microvm_state, data_version)?;
+                vmstate::write_json(&json, output_path.as_deref())?;
+            }
+            VmstateOperation::Diff { left, right } => {
+                let (left_state, left_version) = utils::open_vmstate(&left)?;
+                let (right_state, right_version) = utils::open_vmstate(&right)?;
+                let differences = vmstate::diff(
+                    &vmstate::to_json(&left_state, left_version)?,
+                    &vmstate::to_json(&right_state, right_version)?,
+                );
+                for difference in differences.iter() {
+                    println!("{}", difference);
+                }
+                if !differences.is_empty() {
+                    std::process::exit(EXIT_CODE_DIFFERENT);
+                }
+            }
+            VmstateOperation::Convert {
+                snapshot_path,
+                target_version,
+                output_path,
+                dry_run,
+            } => {
+                let (microvm_state, data_version) = utils::open_vmstate(&snapshot_path)?;
+                let target_version = vmstate::target_data_version(target_version.as_deref())?;
+                let changes = vmstate::convert(&microvm_state, data_version, target_version)?;
+                for change in changes.iter() {
+                    println!("{}", change);
+                }
+                if !dry_run {
+                    let output_path = output_path.unwrap_or(snapshot_path);
+                    utils::save_vmstate(&microvm_state, &output_path, target_version)?;
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(EXIT_CODE_ERROR);
+    }
+}
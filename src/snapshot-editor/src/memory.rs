@@ -0,0 +1,651 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Moves guest memory between the host-side representations a snapshot can be restored from.
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use ::utils::get_page_size;
+use ::utils::seek_hole::SeekHole;
+use clap::ValueEnum;
+use vmm::memory_snapshot::GuestMemoryState;
+
+/// Size of the bounce buffer used when copying a region between backends.
+const CHUNK_SIZE: usize = 2 << 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Alignment is not a multiple of the page size.
+    #[error("Alignment must be a non-zero multiple of the page size: {0:#x}")]
+    InvalidAlignment(u64),
+    /// Failed to map a PMem slot.
+    #[error("Failed to map PMem slot of {0:?}: {1}")]
+    Mmap(PathBuf, std::io::Error),
+    /// Failed to open a memory backend.
+    #[error("Failed to open memory backend {0:?}: {1}")]
+    Open(PathBuf, std::io::Error),
+    /// The source and destination slots of the same PMem device overlap.
+    #[error("Source and destination slots of {0:?} overlap")]
+    OverlappingSlots(PathBuf),
+    /// Cannot fetch the system's page size.
+    #[error("Cannot fetch system's page size: {0}")]
+    PageSize(::utils::errno::Error),
+    /// Failed to read guest memory from the source.
+    #[error("Failed to read guest memory: {0}")]
+    Read(std::io::Error),
+    /// The source and the destination are the same file.
+    #[error("Source and destination are the same file: {0:?}")]
+    SameFile(PathBuf),
+    /// The memory regions described by the snapshot do not fit in the source.
+    #[error("Source {0:?} holds {2:#x} bytes, but the snapshot regions need {1:#x} bytes")]
+    SourceTooSmall(PathBuf, u64, u64),
+    /// Failed to flush the destination to persistent storage.
+    #[error("Failed to sync guest memory: {0}")]
+    Sync(std::io::Error),
+    /// The PMem slot does not start and end on page boundaries.
+    #[error("PMem slot at offset {0:#x} of {1:#x} bytes is not aligned to the page size")]
+    UnalignedPmemSlot(u64, u64),
+    /// Failed to write guest memory to the destination.
+    #[error("Failed to write guest memory: {0}")]
+    Write(std::io::Error),
+}
+
+/// Host-side representation of the guest memory contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MemoryFormat {
+    /// Dense memory file, as written by a full snapshot.
+    File,
+    /// Sparse memory file in which zero pages are left as holes, as written by a diff snapshot.
+    Sparse,
+    /// Slot of a PMem DAX device (e.g. `/dev/dax1.0`) starting at a byte offset.
+    Pmem,
+}
+
+/// Where guest memory lives and how it is laid out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryLocation {
+    /// Representation of the memory contents.
+    pub format: MemoryFormat,
+    /// Path of the memory file or of the PMem device.
+    pub path: PathBuf,
+    /// Offset at which the memory starts; region offsets are relative to it.
+    pub offset: u64,
+}
+
+// A writable or read-only `MAP_SHARED` mapping of a PMem slot.
+struct PmemSlot {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl PmemSlot {
+    fn map(file: &File, offset: u64, len: usize, writable: bool) -> std::io::Result<Self> {
+        let prot = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        // SAFETY: Safe because the file descriptor is valid and we check the return value.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                offset as libc::off_t,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(PmemSlot {
+            addr: addr.cast(),
+            len,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: Safe because the mapping is valid for `len` bytes until `self` is dropped.
+        unsafe { std::slice::from_raw_parts(self.addr, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: Safe because the mapping is valid for `len` bytes until `self` is dropped.
+        unsafe { std::slice::from_raw_parts_mut(self.addr, self.len) }
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        // SAFETY: Safe because the mapping is valid for `len` bytes.
+        let ret = unsafe { libc::msync(self.addr.cast(), self.len, libc::MS_SYNC) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PmemSlot {
+    fn drop(&mut self) {
+        // SAFETY: Safe because we own the mapping.
+        unsafe { libc::munmap(self.addr.cast(), self.len) };
+    }
+}
+
+enum Backend {
+    File(File),
+    Pmem(PmemSlot),
+}
+
+impl Backend {
+    // Returns whether any data (as opposed to a hole) is stored in `[pos, pos + len)`.
+    fn has_data(&mut self, pos: u64, len: usize) -> Result<bool, Error> {
+        match self {
+            Backend::File(file) => Ok(file
+                .seek_data(pos)
+                .map_err(Error::Read)?
+                .map_or(false, |data_start| data_start < pos + len as u64)),
+            Backend::Pmem(_) => Ok(true),
+        }
+    }
+
+    fn read_at(&self, buf: &mut [u8], pos: u64) -> Result<(), Error> {
+        match self {
+            Backend::File(file) => file.read_exact_at(buf, pos).map_err(Error::Read),
+            Backend::Pmem(slot) => {
+                let pos = pos as usize;
+                buf.copy_from_slice(&slot.as_slice()[pos..pos + buf.len()]);
+                Ok(())
+            }
+        }
+    }
+
+    fn write_at(&mut self, buf: &[u8], pos: u64) -> Result<(), Error> {
+        match self {
+            Backend::File(file) => file.write_all_at(buf, pos).map_err(Error::Write),
+            Backend::Pmem(slot) => {
+                let pos = pos as usize;
+                slot.as_mut_slice()[pos..pos + buf.len()].copy_from_slice(buf);
+                Ok(())
+            }
+        }
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        match self {
+            Backend::File(file) => file.sync_all().map_err(Error::Sync),
+            Backend::Pmem(slot) => slot.sync().map_err(Error::Sync),
+        }
+    }
+}
+
+impl MemoryLocation {
+    // Opens the location for reading `len` bytes of guest memory.
+    fn open_source(&self, len: u64) -> Result<Backend, Error> {
+        let file = File::open(&self.path).map_err(|err| Error::Open(self.path.clone(), err))?;
+        let metadata = file
+            .metadata()
+            .map_err(|err| Error::Open(self.path.clone(), err))?;
+        // Character devices such as `/dev/daxX.Y` do not report their size.
+        if metadata.is_file() && metadata.len() < self.offset + len {
+            return Err(Error::SourceTooSmall(
+                self.path.clone(),
+                self.offset + len,
+                metadata.len(),
+            ));
+        }
+
+        match self.format {
+            MemoryFormat::File | MemoryFormat::Sparse => Ok(Backend::File(file)),
+            MemoryFormat::Pmem => PmemSlot::map(&file, self.offset, len as usize, false)
+                .map(Backend::Pmem)
+                .map_err(|err| Error::Mmap(self.path.clone(), err)),
+        }
+    }
+
+    // Opens the location for writing `len` bytes of guest memory.
+    fn open_destination(&self, len: u64) -> Result<Backend, Error> {
+        let open_err = |err| Error::Open(self.path.clone(), err);
+        match self.format {
+            MemoryFormat::File | MemoryFormat::Sparse => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&self.path)
+                    .map_err(open_err)?;
+                file.set_len(self.offset + len).map_err(open_err)?;
+                Ok(Backend::File(file))
+            }
+            MemoryFormat::Pmem => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&self.path)
+                    .map_err(open_err)?;
+                let metadata = file.metadata().map_err(open_err)?;
+                // A regular file standing in for a DAX device has to be large enough to be mapped.
+                if metadata.is_file() && metadata.len() < self.offset + len {
+                    file.set_len(self.offset + len).map_err(open_err)?;
+                }
+                PmemSlot::map(&file, self.offset, len as usize, true)
+                    .map(Backend::Pmem)
+                    .map_err(|err| Error::Mmap(self.path.clone(), err))
+            }
+        }
+    }
+
+    // Checks that a PMem slot of `len` bytes can be mapped, which needs it to be page aligned.
+    fn check_pmem_slot(&self, len: u64, page_size: usize) -> Result<(), Error> {
+        if self.format == MemoryFormat::Pmem
+            && (self.offset % page_size as u64 != 0 || len % page_size as u64 != 0)
+        {
+            return Err(Error::UnalignedPmemSlot(self.offset, len));
+        }
+        Ok(())
+    }
+
+    // Offset of `pos` within the opened backend. PMem slots are mapped starting at `offset`.
+    fn backend_pos(&self, pos: u64) -> u64 {
+        match self.format {
+            MemoryFormat::File | MemoryFormat::Sparse => self.offset + pos,
+            MemoryFormat::Pmem => pos,
+        }
+    }
+}
+
+/// Computes region offsets that lay the regions out contiguously, each starting at a multiple
+/// of `align` bytes.
+pub fn packed_offsets(state: &GuestMemoryState, align: u64) -> Result<Vec<u64>, Error> {
+    let page_size = get_page_size().map_err(Error::PageSize)? as u64;
+    if align == 0 || align % page_size != 0 {
+        return Err(Error::InvalidAlignment(align));
+    }
+
+    let mut next_offset = 0u64;
+    Ok(state
+        .regions
+        .iter()
+        .map(|region| {
+            let offset = (next_offset + align - 1) / align * align;
+            next_offset = offset + region.size as u64;
+            offset
+        })
+        .collect())
+}
+
+// Number of bytes needed to hold the regions of `state` at the given `offsets`.
+fn layout_len<'a>(offsets: impl Iterator<Item = &'a u64>, state: &GuestMemoryState) -> u64 {
+    offsets
+        .zip(state.regions.iter())
+        .map(|(offset, region)| offset + region.size as u64)
+        .max()
+        .unwrap_or(0)
+}
+
+// Returns whether `src` and `dst` name the same file, also through links. A destination which
+// does not exist yet cannot be the source.
+fn same_file(src: &Path, dst: &Path) -> Result<bool, Error> {
+    let src_metadata = std::fs::metadata(src).map_err(|err| Error::Open(src.to_path_buf(), err))?;
+    match std::fs::metadata(dst) {
+        Ok(dst_metadata) => Ok(
+            src_metadata.dev() == dst_metadata.dev() && src_metadata.ino() == dst_metadata.ino()
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(Error::Open(dst.to_path_buf(), err)),
+    }
+}
+
+// Writes only the non-zero pages of `buf`, leaving holes for the zero ones.
+fn write_sparse(
+    backend: &mut Backend,
+    buf: &[u8],
+    pos: u64,
+    page_size: usize,
+) -> Result<(), Error> {
+    let mut run_start = None;
+    for (idx, page) in buf.chunks(page_size).enumerate() {
+        let page_start = idx * page_size;
+        match (run_start, page.iter().all(|&byte| byte == 0)) {
+            (None, false) => run_start = Some(page_start),
+            (Some(start), true) => {
+                backend.write_at(&buf[start..page_start], pos + start as u64)?;
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = run_start {
+        backend.write_at(&buf[start..], pos + start as u64)?;
+    }
+    Ok(())
+}
+
+/// Copies the guest memory described by `state` from `src` to `dst`.
+///
+/// Regions keep their offsets unless `dst_offsets` is given, in which case region `i` is
+/// written at `dst_offsets[i]`. Returns the offsets the regions were written at.
+pub fn convert(
+    state: &GuestMemoryState,
+    src: &MemoryLocation,
+    dst: &MemoryLocation,
+    dst_offsets: Option<Vec<u64>>,
+) -> Result<Vec<u64>, Error> {
+    let page_size = get_page_size().map_err(Error::PageSize)?;
+    let src_offsets: Vec<u64> = state.regions.iter().map(|region| region.offset).collect();
+    let dst_offsets = dst_offsets.unwrap_or_else(|| src_offsets.clone());
+    let src_len = layout_len(src_offsets.iter(), state);
+    let dst_len = layout_len(dst_offsets.iter(), state);
+
+    if same_file(&src.path, &dst.path)? {
+        match dst.format {
+            // Opening a file destination truncates it, which would wipe the source before it
+            // is read.
+            MemoryFormat::File | MemoryFormat::Sparse => {
+                return Err(Error::SameFile(dst.path.clone()))
+            }
+            // Slots of the same device can be copied between as long as they are disjoint.
+            MemoryFormat::Pmem => {
+                if src.offset < dst.offset + dst_len && dst.offset < src.offset + src_len {
+                    return Err(Error::OverlappingSlots(dst.path.clone()));
+                }
+            }
+        }
+    }
+    src.check_pmem_slot(src_len, page_size)?;
+    dst.check_pmem_slot(dst_len, page_size)?;
+
+    let mut src_backend = src.open_source(src_len)?;
+    let mut dst_backend = dst.open_destination(dst_len)?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for ((region, src_offset), dst_offset) in state
+        .regions
+        .iter()
+        .zip(src_offsets.iter())
+        .zip(dst_offsets.iter())
+    {
+        let mut copied = 0usize;
+        while copied < region.size {
+            let len = std::cmp::min(CHUNK_SIZE, region.size - copied);
+            let src_pos = src.backend_pos(src_offset + copied as u64);
+            let dst_pos = dst.backend_pos(dst_offset + copied as u64);
+            let chunk = &mut buf[..len];
+            copied += len;
+
+            if src.format == MemoryFormat::Sparse && !src_backend.has_data(src_pos, len)? {
+                // Freshly truncated files already read back as zeroes, PMem slots may still
+                // hold stale contents.
+                if dst.format == MemoryFormat::Pmem {
+                    chunk.fill(0);
+                    dst_backend.write_at(chunk, dst_pos)?;
+                }
+                continue;
+            }
+
+            src_backend.read_at(chunk, src_pos)?;
+            match dst.format {
+                MemoryFormat::Sparse => write_sparse(&mut dst_backend, chunk, dst_pos, page_size)?,
+                MemoryFormat::File | MemoryFormat::Pmem => dst_backend.write_at(chunk, dst_pos)?,
+            }
+        }
+    }
+    dst_backend.sync()?;
+
+    Ok(dst_offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::utils::tempfile::TempFile;
+    use vmm::memory_snapshot::GuestMemoryRegionState;
+
+    use super::*;
+
+    // Two regions: the first one holds a data page followed by a zero page, the second one a
+    // single data page.
+    fn sample_memory() -> (GuestMemoryState, TempFile, Vec<u8>) {
+        let page_size = get_page_size().unwrap();
+        let state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: page_size * 2,
+                    offset: 0,
+                },
+                GuestMemoryRegionState {
+                    base_address: page_size as u64 * 4,
+                    size: page_size,
+                    offset: page_size as u64 * 2,
+                },
+            ],
+        };
+
+        let mut contents = vec![0xAAu8; page_size];
+        contents.extend(vec![0u8; page_size]);
+        contents.extend(vec![0x55u8; page_size]);
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all_at(&contents, 0).unwrap();
+
+        (state, file, contents)
+    }
+
+    fn location(format: MemoryFormat, file: &TempFile, offset: u64) -> MemoryLocation {
+        MemoryLocation {
+            format,
+            path: file.as_path().to_path_buf(),
+            offset,
+        }
+    }
+
+    fn read_file(file: &TempFile, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        file.as_file().read_exact_at(&mut buf, offset).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_packed_offsets() {
+        let page_size = get_page_size().unwrap();
+        let (state, _, _) = sample_memory();
+
+        assert_eq!(
+            packed_offsets(&state, page_size as u64).unwrap(),
+            vec![0, page_size as u64 * 2]
+        );
+        assert_eq!(packed_offsets(&state, 2 << 20).unwrap(), vec![0, 2 << 20]);
+        assert!(matches!(
+            packed_offsets(&state, 0),
+            Err(Error::InvalidAlignment(0))
+        ));
+        assert!(matches!(
+            packed_offsets(&state, page_size as u64 + 1),
+            Err(Error::InvalidAlignment(_))
+        ));
+    }
+
+    #[test]
+    fn test_convert_file_to_sparse_and_back() {
+        let page_size = get_page_size().unwrap();
+        let (state, src_file, contents) = sample_memory();
+        let sparse_file = TempFile::new().unwrap();
+        let dense_file = TempFile::new().unwrap();
+
+        let offsets = convert(
+            &state,
+            &location(MemoryFormat::File, &src_file, 0),
+            &location(MemoryFormat::Sparse, &sparse_file, 0),
+            None,
+        )
+        .unwrap();
+        assert_eq!(offsets, vec![0, page_size as u64 * 2]);
+        assert_eq!(read_file(&sparse_file, 0, contents.len()), contents);
+
+        convert(
+            &state,
+            &location(MemoryFormat::Sparse, &sparse_file, 0),
+            &location(MemoryFormat::File, &dense_file, 0),
+            None,
+        )
+        .unwrap();
+        assert_eq!(read_file(&dense_file, 0, contents.len()), contents);
+    }
+
+    #[test]
+    fn test_convert_with_new_layout() {
+        let page_size = get_page_size().unwrap();
+        let (state, src_file, contents) = sample_memory();
+        let dst_file = TempFile::new().unwrap();
+
+        let dst_offsets = packed_offsets(&state, 2 << 20).unwrap();
+        let offsets = convert(
+            &state,
+            &location(MemoryFormat::File, &src_file, 0),
+            &location(MemoryFormat::File, &dst_file, 0),
+            Some(dst_offsets.clone()),
+        )
+        .unwrap();
+        assert_eq!(offsets, dst_offsets);
+        assert_eq!(
+            read_file(&dst_file, 0, page_size * 2),
+            contents[..page_size * 2]
+        );
+        assert_eq!(
+            read_file(&dst_file, 2 << 20, page_size),
+            contents[page_size * 2..]
+        );
+    }
+
+    #[test]
+    fn test_convert_to_pmem_slot() {
+        let page_size = get_page_size().unwrap();
+        let (state, src_file, contents) = sample_memory();
+        // A regular file stands in for the DAX device, with stale contents in the slot.
+        let device = TempFile::new().unwrap();
+        device
+            .as_file()
+            .write_all_at(&vec![0xFFu8; page_size * 4], 0)
+            .unwrap();
+
+        convert(
+            &state,
+            &location(MemoryFormat::Sparse, &src_file, 0),
+            &location(MemoryFormat::Pmem, &device, page_size as u64),
+            None,
+        )
+        .unwrap();
+        assert_eq!(read_file(&device, 0, page_size), vec![0xFFu8; page_size]);
+        assert_eq!(
+            read_file(&device, page_size as u64, contents.len()),
+            contents
+        );
+
+        let dense_file = TempFile::new().unwrap();
+        convert(
+            &state,
+            &location(MemoryFormat::Pmem, &device, page_size as u64),
+            &location(MemoryFormat::File, &dense_file, 0),
+            None,
+        )
+        .unwrap();
+        assert_eq!(read_file(&dense_file, 0, contents.len()), contents);
+    }
+
+    #[test]
+    fn test_source_too_small() {
+        let (state, _, _) = sample_memory();
+        let src_file = TempFile::new().unwrap();
+        let dst_file = TempFile::new().unwrap();
+
+        assert!(matches!(
+            convert(
+                &state,
+                &location(MemoryFormat::File, &src_file, 0),
+                &location(MemoryFormat::File, &dst_file, 0),
+                None,
+            ),
+            Err(Error::SourceTooSmall(..))
+        ));
+    }
+
+    #[test]
+    fn test_convert_to_same_file() {
+        let (state, src_file, contents) = sample_memory();
+        let link = TempFile::new().unwrap();
+        std::fs::remove_file(link.as_path()).unwrap();
+        std::os::unix::fs::symlink(src_file.as_path(), link.as_path()).unwrap();
+
+        for dst_file in [&src_file, &link] {
+            assert!(matches!(
+                convert(
+                    &state,
+                    &location(MemoryFormat::File, &src_file, 0),
+                    &location(MemoryFormat::Sparse, dst_file, 0),
+                    None,
+                ),
+                Err(Error::SameFile(_))
+            ));
+        }
+        assert_eq!(read_file(&src_file, 0, contents.len()), contents);
+    }
+
+    #[test]
+    fn test_convert_to_unaligned_pmem_slot() {
+        let (state, src_file, _) = sample_memory();
+        let device = TempFile::new().unwrap();
+
+        assert!(matches!(
+            convert(
+                &state,
+                &location(MemoryFormat::File, &src_file, 0),
+                &location(MemoryFormat::Pmem, &device, 512),
+                None,
+            ),
+            Err(Error::UnalignedPmemSlot(512, _))
+        ));
+        assert!(matches!(
+            convert(
+                &state,
+                &location(MemoryFormat::Pmem, &src_file, 512),
+                &location(MemoryFormat::File, &device, 0),
+                None,
+            ),
+            Err(Error::UnalignedPmemSlot(512, _))
+        ));
+    }
+
+    #[test]
+    fn test_convert_between_pmem_slots() {
+        let page_size = get_page_size().unwrap();
+        let (state, _, contents) = sample_memory();
+        let device = TempFile::new().unwrap();
+        device.as_file().write_all_at(&contents, 0).unwrap();
+
+        // The regions span three pages, so a slot starting one page further overlaps them.
+        assert!(matches!(
+            convert(
+                &state,
+                &location(MemoryFormat::Pmem, &device, 0),
+                &location(MemoryFormat::Pmem, &device, page_size as u64),
+                None,
+            ),
+            Err(Error::OverlappingSlots(_))
+        ));
+
+        let dst_offset = contents.len() as u64;
+        convert(
+            &state,
+            &location(MemoryFormat::Pmem, &device, 0),
+            &location(MemoryFormat::Pmem, &device, dst_offset),
+            None,
+        )
+        .unwrap();
+        assert_eq!(read_file(&device, dst_offset, contents.len()), contents);
+        assert_eq!(read_file(&device, 0, contents.len()), contents);
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+
+use snapshot::Snapshot;
+use vmm::persist::MicrovmState;
+use vmm::version_map::VERSION_MAP;
+
+pub const SNAPSHOT_EDITOR_VERSION: &str = env!("FIRECRACKER_VERSION");
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to open the snapshot state file.
+    #[error("Failed to open snapshot state file: {0}")]
+    OpenSnapshotFile(std::io::Error),
+    /// Failed to decode the snapshot state file.
+    #[error("Failed to load snapshot state file: {0}")]
+    LoadSnapshot(snapshot::Error),
+    /// Failed to create the snapshot state file.
+    #[error("Failed to create snapshot state file: {0}")]
+    CreateSnapshotFile(std::io::Error),
+    /// Failed to encode the snapshot state file.
+    #[error("Failed to save snapshot state file: {0}")]
+    SaveSnapshot(snapshot::Error),
+    /// Failed to replace the snapshot state file with the saved one.
+    #[error("Failed to replace snapshot state file: {0}")]
+    ReplaceSnapshotFile(std::io::Error),
+}
+
+/// Loads the microVM state stored at `path`, returning it together with the snapshot data
+/// version it was saved at.
+pub fn open_vmstate(path: &Path) -> Result<(MicrovmState, u16), Error> {
+    let mut snapshot_file = File::open(path).map_err(Error::OpenSnapshotFile)?;
+    let snapshot_len = snapshot_file
+        .metadata()
+        .map_err(Error::OpenSnapshotFile)?
+        .len() as usize;
+
+    let data_version = Snapshot::get_data_version(&mut snapshot_file, &VERSION_MAP)
+        .map_err(Error::LoadSnapshot)?;
+    snapshot_file.rewind().map_err(Error::OpenSnapshotFile)?;
+
+    let microvm_state = Snapshot::load(&mut snapshot_file, snapshot_len, VERSION_MAP.clone())
+        .map_err(Error::LoadSnapshot)?;
+
+    Ok((microvm_state, data_version))
+}
+
+/// Saves `microvm_state` to `path` at the given snapshot data version.
+///
+/// `path` is only replaced once the new file is completely written, so it is left untouched if
+/// saving fails, even when it is the file the state was loaded from.
+pub fn save_vmstate(
+    microvm_state: &MicrovmState,
+    path: &Path,
+    data_version: u16,
+) -> Result<(), Error> {
+    replace_file(path, |snapshot_file| {
+        let mut snapshot = Snapshot::new(VERSION_MAP.clone(), data_version);
+        snapshot
+            .save(snapshot_file, microvm_state)
+            .map_err(Error::SaveSnapshot)
+    })
+}
+
+// Path of the file `path` is written to before replacing it, in the same directory so that it
+// can be renamed over `path`.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(file_name)
+}
+
+// Writes a temporary file with `write`, syncs it and renames it over `path`. The temporary file
+// is removed if any step fails.
+fn replace_file<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut File) -> Result<(), Error>,
+{
+    let tmp_path = temporary_path(path);
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .map_err(Error::CreateSnapshotFile)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all().map_err(Error::CreateSnapshotFile)
+        })
+        .and_then(|()| std::fs::rename(&tmp_path, path).map_err(Error::ReplaceSnapshotFile));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use ::utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("vmstate");
+        std::fs::write(&path, b"old").unwrap();
+
+        // A failed save leaves the file and the directory as they were.
+        let result = replace_file(&path, |file| {
+            file.write_all(b"partial").unwrap();
+            Err(Error::CreateSnapshotFile(
+                std::io::Error::from_raw_os_error(libc::ENOSPC),
+            ))
+        });
+        assert!(matches!(result, Err(Error::CreateSnapshotFile(_))));
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert_eq!(std::fs::read_dir(dir.as_path()).unwrap().count(), 1);
+
+        replace_file(&path, |file| {
+            file.write_all(b"new").map_err(Error::CreateSnapshotFile)
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(std::fs::read_dir(dir.as_path()).unwrap().count(), 1);
+
+        // The destination does not have to exist yet.
+        let new_path = dir.as_path().join("new_vmstate");
+        replace_file(&new_path, |file| {
+            file.write_all(b"new").map_err(Error::CreateSnapshotFile)
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(&new_path).unwrap(), b"new");
+    }
+}
@@ -0,0 +1,421 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decodes snapshot state files to JSON, compares them and converts them between snapshot
+//! data versions.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+use snapshot::Snapshot;
+#[cfg(target_arch = "x86_64")]
+use vmm::persist::validate_devices_number;
+use vmm::persist::{validate_virtio_features, CreateSnapshotError, MicrovmState};
+#[cfg(target_arch = "x86_64")]
+use vmm::version_map::FC_V0_23_SNAP_VERSION;
+use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSIONED_TYPES, VERSION_MAP};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to create the output file.
+    #[error("Failed to create output file: {0}")]
+    CreateOutput(io::Error),
+    /// Failed to decode the converted microVM state.
+    #[error("Failed to decode the converted microVM state: {0}")]
+    Decode(snapshot::Error),
+    /// Failed to encode the microVM state at the target version.
+    #[error("Failed to encode the microVM state at the target version: {0}")]
+    Encode(snapshot::Error),
+    /// The microVM state cannot be restored at the target version.
+    #[error("Cannot convert the microVM state to the target version: {0}")]
+    Incompatible(CreateSnapshotError),
+    /// Failed to encode the microVM state as JSON.
+    #[error("Failed to encode microVM state: {0}")]
+    Serialize(serde_json::Error),
+    /// The target version is not a Firecracker version with a snapshot data version.
+    #[error("Unsupported target version: {0}")]
+    UnsupportedVersion(String),
+    /// Failed to write the output.
+    #[error("Failed to write output: {0}")]
+    Write(io::Error),
+}
+
+/// Describes the microVM state saved at snapshot data version `data_version` as JSON.
+pub fn to_json(microvm_state: &MicrovmState, data_version: u16) -> Result<Value, Error> {
+    Ok(json!({
+        "data_version": data_version,
+        "state": serde_json::to_value(microvm_state).map_err(Error::Serialize)?,
+    }))
+}
+
+/// Writes `value` as pretty-printed JSON to `output`, or to stdout if there is none.
+pub fn write_json(value: &Value, output: Option<&Path>) -> Result<(), Error> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).map_err(Error::CreateOutput)?),
+        None => Box::new(io::stdout()),
+    };
+    serde_json::to_writer_pretty(&mut writer, value).map_err(Error::Serialize)?;
+    writeln!(writer).map_err(Error::Write)
+}
+
+/// One field that differs between two snapshot state files.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// Path of the field, e.g. `state.vcpu_states[1].regs`.
+    pub path: String,
+    /// Value in the first file, `None` if the field is missing from it.
+    pub left: Option<Value>,
+    /// Value in the second file, `None` if the field is missing from it.
+    pub right: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "<missing>".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.path,
+            show(&self.left),
+            show(&self.right)
+        )
+    }
+}
+
+/// Compares two JSON descriptions field by field, returning the leaves that differ.
+pub fn diff(left: &Value, right: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_at(String::new(), left, right, &mut differences);
+    differences
+}
+
+fn push_missing(
+    path: String,
+    left: Option<&Value>,
+    right: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    differences.push(Difference {
+        path,
+        left: left.cloned(),
+        right: right.cloned(),
+    });
+}
+
+fn diff_at(path: String, left: &Value, right: &Value, differences: &mut Vec<Difference>) {
+    match (left, right) {
+        (Value::Object(left), Value::Object(right)) => {
+            for (key, left_value) in left {
+                let field_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match right.get(key) {
+                    Some(right_value) => diff_at(field_path, left_value, right_value, differences),
+                    None => push_missing(field_path, Some(left_value), None, differences),
+                }
+            }
+            for (key, right_value) in right {
+                if !left.contains_key(key) {
+                    let field_path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    push_missing(field_path, None, Some(right_value), differences);
+                }
+            }
+        }
+        (Value::Array(left), Value::Array(right)) => {
+            for idx in 0..left.len().max(right.len()) {
+                let item_path = format!("{}[{}]", path, idx);
+                match (left.get(idx), right.get(idx)) {
+                    (Some(left_item), Some(right_item)) => {
+                        diff_at(item_path, left_item, right_item, differences)
+                    }
+                    (left_item, right_item) => {
+                        push_missing(item_path, left_item, right_item, differences)
+                    }
+                }
+            }
+        }
+        (left, right) if left != right => differences.push(Difference {
+            path,
+            left: Some(left.clone()),
+            right: Some(right.clone()),
+        }),
+        _ => (),
+    }
+}
+
+/// Returns the snapshot data version of Firecracker `version` (e.g. `1.2.0`), or the latest one
+/// if there is none.
+pub fn target_data_version(version: Option<&str>) -> Result<u16, Error> {
+    match version {
+        Some(version) => FC_VERSION_TO_SNAP_VERSION
+            .get(version)
+            .copied()
+            .ok_or_else(|| Error::UnsupportedVersion(version.to_string())),
+        None => Ok(VERSION_MAP.latest_version()),
+    }
+}
+
+/// Change brought by converting a snapshot state file to another data version.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A versioned type is encoded at another version. The fields added in between are dropped
+    /// by a downgrade and filled with defaults by an upgrade.
+    TypeVersion {
+        /// Name of the type.
+        name: &'static str,
+        /// Version of the type in the original file.
+        from: u16,
+        /// Version of the type in the converted file.
+        to: u16,
+    },
+    /// A field decodes to another value from the converted file.
+    Field(Difference),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::TypeVersion { name, from, to } if from > to => write!(
+                f,
+                "{}: version {} -> {}, fields added after version {} are dropped",
+                name, from, to, to
+            ),
+            Change::TypeVersion { name, from, to } => write!(
+                f,
+                "{}: version {} -> {}, fields added after version {} are filled with defaults",
+                name, from, to, from
+            ),
+            Change::Field(difference) => write!(f, "{}", difference),
+        }
+    }
+}
+
+/// Lists the versioned types encoded differently at data versions `from` and `to`.
+pub fn type_version_changes(from: u16, to: u16) -> Vec<Change> {
+    VERSIONED_TYPES
+        .iter()
+        .filter_map(|&(name, type_id)| {
+            let from_version = VERSION_MAP.get_type_version(from, type_id);
+            let to_version = VERSION_MAP.get_type_version(to, type_id);
+            (from_version != to_version).then_some(Change::TypeVersion {
+                name,
+                from: from_version,
+                to: to_version,
+            })
+        })
+        .collect()
+}
+
+/// Checks that the microVM state described by `state` can be saved at data version
+/// `target_version`, as Firecracker does when asked to create a snapshot at that version.
+pub fn check_target_version(state: &Value, target_version: u16) -> Result<(), Error> {
+    let mut irqs = 0;
+    check_devices(state, target_version, &mut irqs).map_err(Error::Incompatible)?;
+    #[cfg(target_arch = "x86_64")]
+    if target_version <= FC_V0_23_SNAP_VERSION {
+        validate_devices_number(irqs).map_err(Error::Incompatible)?;
+    }
+    Ok(())
+}
+
+// Checks the virtio devices found in `value` and counts the interrupts of all devices.
+fn check_devices(
+    value: &Value,
+    target_version: u16,
+    irqs: &mut usize,
+) -> Result<(), CreateSnapshotError> {
+    match value {
+        Value::Object(object) => {
+            if let Some(virtio_state) = object.get("virtio_state") {
+                let virtio_type = virtio_state["device_type"].as_u64().unwrap_or_default();
+                let acked_features = virtio_state["acked_features"].as_u64().unwrap_or_default();
+                validate_virtio_features(virtio_type as u32, acked_features, target_version)?;
+            }
+            if let Some(Value::Array(device_irqs)) = object
+                .get("device_info")
+                .and_then(|device_info| device_info.get("irqs"))
+            {
+                *irqs += device_irqs.len();
+            }
+            object
+                .values()
+                .try_for_each(|value| check_devices(value, target_version, irqs))
+        }
+        Value::Array(values) => values
+            .iter()
+            .try_for_each(|value| check_devices(value, target_version, irqs)),
+        _ => Ok(()),
+    }
+}
+
+/// Converts `microvm_state`, saved at data version `data_version`, to `target_version`.
+/// Returns the changes that restoring the microVM from the converted file would see.
+pub fn convert(
+    microvm_state: &MicrovmState,
+    data_version: u16,
+    target_version: u16,
+) -> Result<Vec<Change>, Error> {
+    let state = serde_json::to_value(microvm_state).map_err(Error::Serialize)?;
+    check_target_version(&state, target_version)?;
+
+    let mut converted = Vec::new();
+    Snapshot::new(VERSION_MAP.clone(), target_version)
+        .save(&mut converted, microvm_state)
+        .map_err(Error::Encode)?;
+    let converted_state: MicrovmState = Snapshot::load(
+        &mut converted.as_slice(),
+        converted.len(),
+        VERSION_MAP.clone(),
+    )
+    .map_err(Error::Decode)?;
+
+    let mut changes = type_version_changes(data_version, target_version);
+    changes.extend(
+        diff(
+            &state,
+            &serde_json::to_value(&converted_state).map_err(Error::Serialize)?,
+        )
+        .into_iter()
+        .map(Change::Field),
+    );
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::version_map::{
+        FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_V1_4_SNAP_VERSION, FC_V1_5_SNAP_VERSION,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_diff_identical() {
+        let value = json!({"a": 1, "b": [1, 2, {"c": "x"}], "d": null});
+        assert!(diff(&value, &value).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let left = json!({
+            "data_version": 4,
+            "state": {
+                "memory_state": {"regions": [{"size": 4096, "offset": 0}]},
+                "vcpu_states": [{"regs": "00ff"}, {"regs": "0000"}],
+                "removed": true,
+            },
+        });
+        let right = json!({
+            "data_version": 4,
+            "state": {
+                "memory_state": {"regions": [{"size": 8192, "offset": 0}]},
+                "vcpu_states": [{"regs": "00ff"}, {"regs": "0001"}, {"regs": "0002"}],
+                "added": "x",
+            },
+        });
+
+        let differences = diff(&left, &right);
+        let lines: Vec<String> = differences.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "state.memory_state.regions[0].size: 4096 -> 8192",
+                "state.removed: true -> <missing>",
+                "state.vcpu_states[1].regs: \"0000\" -> \"0001\"",
+                "state.vcpu_states[2]: <missing> -> {\"regs\":\"0002\"}",
+                "state.added: <missing> -> \"x\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target_data_version() {
+        assert_eq!(
+            target_data_version(Some("1.4.0")).unwrap(),
+            FC_V1_4_SNAP_VERSION
+        );
+        assert_eq!(
+            target_data_version(None).unwrap(),
+            VERSION_MAP.latest_version()
+        );
+        assert!(matches!(
+            target_data_version(Some("1.4")),
+            Err(Error::UnsupportedVersion(version)) if version == "1.4"
+        ));
+    }
+
+    #[test]
+    fn test_type_version_changes() {
+        assert!(type_version_changes(FC_V1_4_SNAP_VERSION, FC_V1_4_SNAP_VERSION).is_empty());
+
+        let changes = type_version_changes(FC_V1_5_SNAP_VERSION, FC_V1_4_SNAP_VERSION);
+        assert_eq!(
+            changes,
+            vec![Change::TypeVersion {
+                name: "DeviceStates",
+                from: 5,
+                to: 4
+            }]
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "DeviceStates: version 5 -> 4, fields added after version 4 are dropped"
+        );
+        assert_eq!(
+            type_version_changes(FC_V1_4_SNAP_VERSION, FC_V1_5_SNAP_VERSION)[0].to_string(),
+            "DeviceStates: version 4 -> 5, fields added after version 4 are filled with defaults"
+        );
+    }
+
+    #[test]
+    fn test_check_target_version() {
+        let event_idx = 1u64 << 29;
+        let state = json!({
+            "device_states": {
+                "block_devices": [{
+                    "device_state": {"virtio_state": {"device_type": 2, "acked_features": event_idx}},
+                    "device_info": {"addr": 0, "len": 4096, "irqs": [5]},
+                }],
+                "net_devices": [{
+                    "device_state": {"virtio_state": {"device_type": 1, "acked_features": event_idx}},
+                    "device_info": {"addr": 4096, "len": 4096, "irqs": [6]},
+                }],
+            },
+        });
+
+        check_target_version(&state, FC_V1_1_SNAP_VERSION).unwrap();
+        // Notification suppression is supported by block devices from v1.0, by net devices
+        // from v1.1.
+        assert!(matches!(
+            check_target_version(&state, FC_V1_0_SNAP_VERSION),
+            Err(Error::Incompatible(
+                CreateSnapshotError::IncompatibleVirtioFeature(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_diff_type_change() {
+        let differences = diff(&json!({"a": [1]}), &json!({"a": {"b": 1}}));
+        assert_eq!(
+            differences,
+            vec![Difference {
+                path: "a".to_string(),
+                left: Some(json!([1])),
+                right: Some(json!({"b": 1})),
+            }]
+        );
+    }
+}
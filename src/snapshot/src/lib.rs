@@ -26,7 +26,7 @@
 //! implementation does not have any logic dependent on it.
 //!  - **the data version** which refers to the state.
 mod persist;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use versionize::crc::{CRC64Reader, CRC64Writer};
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -36,6 +36,10 @@ pub use crate::persist::Persist;
 
 const BASE_MAGIC_ID_MASK: u64 = !0xFFFFu64;
 
+/// Maximum size of a snapshot read from a stream that does not report its length, such as a
+/// pipe or a socket.
+pub const MAX_SNAPSHOT_LEN: usize = 64 << 20;
+
 #[cfg(target_arch = "x86_64")]
 const BASE_MAGIC_ID: u64 = 0x0710_1984_8664_0000u64;
 
@@ -63,6 +67,9 @@ pub enum Error {
     /// An IO error occurred.
     #[error("An IO error occurred: {0}")]
     Io(i32),
+    /// Decoding the snapshot required reading more bytes than allowed.
+    #[error("Snapshot is larger than the {0} bytes limit.")]
+    TooLarge(usize),
     /// A versioned serialization/deserialization error occurred.
     #[error("A versioned serialization/deserialization error occurred: {0}")]
     Versionize(versionize::VersionizeError),
@@ -143,6 +150,12 @@ impl Snapshot {
     }
 
     /// Attempts to load an existing snapshot and validate CRC.
+    ///
+    /// The snapshot is decoded as it is read, so `reader` can be a pipe or a socket. At most
+    /// `snapshot_len` bytes, checksum included, are read: a malformed or hostile snapshot cannot
+    /// make the decoder consume an unbounded stream, and fails with `TooLarge` instead. The
+    /// memory allocated for the collections it describes is bounded by the length checks of the
+    /// decoder itself, not by `snapshot_len`. Bytes following the checksum are left unread.
     pub fn load<T, O>(
         reader: &mut T,
         snapshot_len: usize,
@@ -152,16 +165,27 @@ impl Snapshot {
         T: Read,
         O: Versionize,
     {
-        let mut crc_reader = CRC64Reader::new(reader);
+        if snapshot_len < std::mem::size_of::<u64>() {
+            return Err(Error::InvalidSnapshotSize);
+        }
 
-        // Extract snapshot data without stored checksum, which is 8 bytes in size
-        let raw_snapshot_len = snapshot_len
-            .checked_sub(std::mem::size_of::<u64>())
-            .ok_or(Error::InvalidSnapshotSize)?;
-        let mut snapshot = vec![0u8; raw_snapshot_len];
-        crc_reader
-            .read_exact(&mut snapshot)
-            .map_err(|ref err| Error::Io(err.raw_os_error().unwrap_or(libc::EINVAL)))?;
+        let mut limited_reader = LimitedReader::new(reader, snapshot_len);
+        let result = Self::crc_load(&mut limited_reader, version_map);
+        // The deserializer only reports the IO error as a string, so check whether it was
+        // caused by the limit.
+        if limited_reader.exceeded {
+            return Err(Error::TooLarge(snapshot_len));
+        }
+        result
+    }
+
+    fn crc_load<T, O>(reader: &mut T, version_map: VersionMap) -> Result<O, Error>
+    where
+        T: Read,
+        O: Versionize,
+    {
+        let mut crc_reader = CRC64Reader::new(reader);
+        let object: O = Snapshot::unchecked_load(&mut crc_reader, version_map)?;
 
         // Since the reader updates the checksum as bytes ar being read from it, the order of these
         // 2 statements is important, we first get the checksum computed on the read bytes
@@ -174,9 +198,6 @@ impl Snapshot {
             return Err(Error::Crc64(computed_checksum));
         }
 
-        let mut snapshot_slice: &[u8] = snapshot.as_mut_slice();
-        let object: O = Snapshot::unchecked_load(&mut snapshot_slice, version_map)?;
-
         Ok(object)
     }
 
@@ -243,6 +264,42 @@ impl Snapshot {
     }
 }
 
+// Reader failing once more than `limit` bytes are requested from it.
+struct LimitedReader<T> {
+    reader: T,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<T: Read> LimitedReader<T> {
+    fn new(reader: T, limit: usize) -> Self {
+        LimitedReader {
+            reader,
+            remaining: limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl<T: Read> Read for LimitedReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "snapshot size limit exceeded",
+            ));
+        }
+        let len = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..len])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -563,6 +620,123 @@ mod tests {
         assert_eq!(load_result.unwrap_err(), expected_err);
     }
 
+    // Hands out the snapshot one byte at a time, like a slow pipe.
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if buf.is_empty() || self.0.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn test_state_snapshot() -> (VersionMap, Vec<u8>) {
+        let mut vm = VersionMap::new();
+        vm.new_version()
+            .set_type_version(Test::type_id(), 2)
+            .new_version()
+            .set_type_version(Test::type_id(), 3)
+            .new_version()
+            .set_type_version(Test::type_id(), 4);
+        let state = Test {
+            field0: 0,
+            field1: 1,
+            field2: 2,
+            field3: "test".to_owned(),
+            field4: vec![4, 3, 2, 1],
+            field_x: 0,
+        };
+
+        let mut snapshot_mem = Vec::new();
+        let mut snapshot = Snapshot::new(vm.clone(), 4);
+        snapshot.save(&mut snapshot_mem, &state).unwrap();
+        (vm, snapshot_mem)
+    }
+
+    #[test]
+    fn test_streaming_load() {
+        let (vm, mut snapshot_mem) = test_state_snapshot();
+        let snapshot_len = snapshot_mem.len();
+        snapshot_mem.extend_from_slice(b"trailer");
+
+        // The snapshot length is only an upper bound.
+        let mut reader = OneByteReader(&snapshot_mem);
+        let restored_state: Test =
+            Snapshot::load(&mut reader, MAX_SNAPSHOT_LEN, vm.clone()).unwrap();
+        assert_eq!(restored_state.field3, "test");
+        assert_eq!(restored_state.field4, vec![4, 3, 2, 1]);
+        // Bytes following the checksum are left in the stream.
+        assert_eq!(reader.0, b"trailer");
+
+        let mut reader = snapshot_mem.as_slice();
+        let _: Test = Snapshot::load(&mut reader, snapshot_len, vm.clone()).unwrap();
+        assert_eq!(reader, b"trailer");
+
+        let load_result: Result<Test, Error> =
+            Snapshot::load(&mut snapshot_mem.as_slice(), snapshot_len - 1, vm);
+        assert_eq!(load_result.unwrap_err(), Error::TooLarge(snapshot_len - 1));
+    }
+
+    #[test]
+    fn test_truncated_snapshot_fuzz() {
+        let (vm, snapshot_mem) = test_state_snapshot();
+
+        for len in 0..snapshot_mem.len() {
+            let truncated = &snapshot_mem[..len];
+            let load_result: Result<Test, Error> =
+                Snapshot::load(&mut &truncated[..], MAX_SNAPSHOT_LEN, vm.clone());
+            assert!(load_result.is_err(), "truncated at {} bytes", len);
+            let load_result: Result<Test, Error> =
+                Snapshot::load(&mut OneByteReader(truncated), len, vm.clone());
+            assert!(load_result.is_err(), "truncated at {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_snapshot_fuzz() {
+        let (vm, snapshot_mem) = test_state_snapshot();
+
+        for offset in 0..snapshot_mem.len() {
+            for mask in [0x01u8, 0x80, 0xff] {
+                let mut corrupted = snapshot_mem.clone();
+                corrupted[offset] ^= mask;
+                let load_result: Result<Test, Error> =
+                    Snapshot::load(&mut corrupted.as_slice(), corrupted.len(), vm.clone());
+                assert!(
+                    load_result.is_err(),
+                    "byte {} corrupted with {:#x}",
+                    offset,
+                    mask
+                );
+            }
+        }
+
+        // Length prefixes claiming more than the stream holds, even within the size limit.
+        let vec_len_offset = snapshot_mem.len() - 8 - 4 * 8 - 8;
+        let vec_len_max = (MAX_SNAPSHOT_LEN / std::mem::size_of::<u64>() - 1) as u64;
+        for vec_len in [u64::MAX, vec_len_max] {
+            let mut corrupted = snapshot_mem.clone();
+            corrupted[vec_len_offset..vec_len_offset + 8].copy_from_slice(&vec_len.to_le_bytes());
+            let load_result: Result<Test, Error> =
+                Snapshot::load(&mut corrupted.as_slice(), MAX_SNAPSHOT_LEN, vm.clone());
+            assert!(load_result.is_err(), "vector of {} elements", vec_len);
+        }
+
+        // Over a stream that never ends, such as a pipe, the size limit stops the decoder.
+        let mut corrupted = snapshot_mem[..vec_len_offset].to_vec();
+        corrupted.extend_from_slice(&4096u64.to_le_bytes());
+        let mut reader = corrupted.as_slice().chain(io::repeat(0));
+        let load_result: Result<Test, Error> = Snapshot::load(&mut reader, snapshot_mem.len(), vm);
+        assert_eq!(
+            load_result.unwrap_err(),
+            Error::TooLarge(snapshot_mem.len())
+        );
+    }
+
     #[allow(non_upper_case_globals)]
     #[allow(non_camel_case_types)]
     #[allow(non_snake_case)]
@@ -20,7 +20,7 @@ use vmm::persist::{MicrovmState, VmInfo};
 use vmm::utilities::mock_resources::NOISY_KERNEL_IMAGE;
 use vmm::utilities::test_utils::create_vmm;
 use vmm::version_map::VERSION_MAP;
-use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
+use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotFiles, SnapshotType};
 use vmm::{persist, FcExitCode};
 
 #[inline]
@@ -75,8 +75,15 @@ fn create_microvm_state(is_diff: bool) -> MicrovmState {
     let snapshot_params = CreateSnapshotParams {
         snapshot_type,
         snapshot_path: snapshot_file.as_path().to_path_buf(),
+        snapshot_fd: None,
         mem_file_path: memory_file.as_path().to_path_buf(),
+        mem_file_fd: None,
         version: None,
+        encryption: None,
+        background: false,
+        chain_metadata: false,
+        asynchronous: false,
+        files: SnapshotFiles::default(),
     };
     let vm_info = VmInfo {
         mem_size_mib: 1u64,
@@ -391,6 +391,24 @@ fn create_rtc_node<T: DeviceInfoForFDT + Clone + Debug>(
     Ok(())
 }
 
+fn create_vmgenid_node<T: DeviceInfoForFDT + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> Result<()> {
+    // Driver requirements:
+    // https://elixir.bootlin.com/linux/latest/source/Documentation/devicetree/bindings/rng/microsoft,vmgenid.yaml
+    let vmgenid = fdt.begin_node(&format!("vmgenid@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "microsoft,vmgenid")?;
+    fdt.property_array_u64("reg", &[dev_info.addr(), dev_info.length()])?;
+    fdt.property_array_u32(
+        "interrupts",
+        &[GIC_FDT_IRQ_TYPE_SPI, dev_info.irq(), IRQ_TYPE_EDGE_RISING],
+    )?;
+    fdt.end_node(vmgenid)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     fdt: &mut FdtWriter,
     dev_info: &HashMap<(DeviceType, String), T, S>,
@@ -403,6 +421,7 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
             DeviceType::BootTimer => (), // since it's not a real device
             DeviceType::Rtc => create_rtc_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
+            DeviceType::VmGenId => create_vmgenid_node(fdt, info)?,
             DeviceType::Virtio(_) => {
                 ordered_virtio_device.push(info);
             }
@@ -478,6 +497,13 @@ mod tests {
                     irq: 3,
                 },
             ),
+            (
+                (DeviceType::VmGenId, DeviceType::VmGenId.to_string()),
+                MMIODeviceInfo {
+                    addr: 3 * LEN,
+                    irq: 4,
+                },
+            ),
         ]
         .iter()
         .cloned()
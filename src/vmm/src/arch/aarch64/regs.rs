@@ -10,6 +10,7 @@ use std::{fs, mem, result, u32};
 
 use kvm_bindings::*;
 use kvm_ioctls::VcpuFd;
+use serde::Serialize;
 use utils::vm_memory::GuestMemoryMmap;
 use versionize::*;
 use versionize_derive::Versionize;
@@ -19,7 +20,7 @@ use super::get_fdt_addr;
 /// Struct describing a saved aarch64 register.
 ///
 /// Used for interacting with `KVM_GET/SET_ONE_REG`.
-#[derive(Debug, Clone, Versionize, PartialEq, Eq)]
+#[derive(Debug, Clone, Versionize, PartialEq, Eq, Serialize)]
 pub struct Aarch64Register {
     /// The KVM register ID.
     ///
@@ -30,9 +31,18 @@ pub struct Aarch64Register {
     ///
     /// 128 bit wide, as we want to restore the V0-V31 FP SIMD registers,
     /// which are this wide.
+    #[serde(serialize_with = "serialize_reg_value")]
     pub value: u128,
 }
 
+// JSON numbers cannot hold 128 bit values.
+fn serialize_reg_value<S: serde::Serializer>(
+    value: &u128,
+    serializer: S,
+) -> result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:#x}", value))
+}
+
 /// Errors thrown while setting aarch64 registers.
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum Error {
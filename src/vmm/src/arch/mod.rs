@@ -3,6 +3,7 @@
 
 use std::{fmt, result};
 
+use serde::Serialize;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
@@ -32,7 +33,7 @@ pub use crate::arch::x86_64::{
 pub type Result<T> = result::Result<T, Error>;
 
 /// Types of devices that can get attached to this platform.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Copy, Versionize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Copy, Versionize, Serialize)]
 pub enum DeviceType {
     /// Device Type: Virtio.
     Virtio(u32),
@@ -44,6 +45,8 @@ pub enum DeviceType {
     Rtc,
     /// Device Type: BootTimer.
     BootTimer,
+    /// Device Type: VmGenId.
+    VmGenId,
 }
 
 /// Type for passing information about the initrd in the guest memory.
@@ -44,14 +44,17 @@ use crate::devices::legacy::RTCDevice;
 use crate::devices::legacy::{
     EventFdTrigger, ReadableFd, SerialDevice, SerialEventsWrapper, SerialWrapper,
 };
+use crate::devices::pseudo::VmGenId;
 use crate::devices::virtio::{
     Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend,
 };
-use crate::persist::{MicrovmState, MicrovmStateError};
+use crate::persist::{lap_us, MicrovmState, MicrovmStateError, RestoreTimings};
 use crate::resources::VmResources;
 use crate::vmm_config::boot_source::BootConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{MachineConfigUpdate, VmConfig, VmConfigError};
+use crate::vmm_config::snapshot::DeviceOverrides;
+use crate::vmm_config::validation::ValidationReport;
 use crate::vstate::system::KvmContext;
 use crate::vstate::vcpu::{Vcpu, VcpuConfig};
 use crate::vstate::vm::Vm;
@@ -264,6 +267,9 @@ fn create_vmm_and_vcpus(
         vm,
         guest_memory,
         uffd,
+        last_snapshot: None,
+        restore_start_us: None,
+        memory_dump: None,
         vcpus_handles: Vec::new(),
         vcpus_exit_evt,
         mmio_device_manager,
@@ -346,6 +352,11 @@ pub fn build_microvm_for_boot(
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
+    // Attached last so that the addresses of the other devices do not change.
+    if vm_resources.vm_config.vmgenid {
+        attach_vmgenid_device(&mut vmm)?;
+    }
+
     configure_system_for_boot(
         &vmm,
         vcpus.as_mut(),
@@ -384,6 +395,149 @@ pub fn build_microvm_for_boot(
     Ok(vmm)
 }
 
+/// Runs the checks `build_microvm_for_boot` would run on the microVM configuration, without
+/// creating the vCPUs or attaching the devices, and reports the problems found for each resource.
+///
+/// The guest memory is mapped but not populated, so only the pages the kernel and the initrd
+/// are loaded into get backed. The KVM context opened ahead of time is left to the microVM
+/// built afterwards.
+pub fn validate_microvm_for_boot(
+    vm_resources: &VmResources,
+    seccomp_filters: &BpfThreadMap,
+) -> ValidationReport {
+    let mut report = ValidationReport::new();
+
+    let track_dirty_pages = vm_resources.track_dirty_pages();
+    let guest_memory =
+        match create_guest_memory(vm_resources.vm_config.mem_size_mib, track_dirty_pages) {
+            Ok(guest_memory) => Some(guest_memory),
+            Err(err) => {
+                report.add("machine-config", None, err);
+                None
+            }
+        };
+
+    match (vm_resources.boot_source_builder(), guest_memory.as_ref()) {
+        (None, _) => report.add("boot-source", None, StartMicrovmError::MissingKernelConfig),
+        (Some(boot_config), Some(guest_memory)) => {
+            if let Err(err) = load_kernel(boot_config, guest_memory) {
+                report.add("boot-source", None, err);
+            }
+            if let Err(err) = load_initrd_from_config(boot_config, guest_memory) {
+                report.add("boot-source", None, err);
+            }
+        }
+        (Some(_), None) => (),
+    }
+
+    if let Some(guest_memory) = guest_memory.as_ref() {
+        let vm = KvmContext::with_preopened_or_new(|kvm| {
+            create_vm(kvm, guest_memory, track_dirty_pages)
+        })
+        .map_err(Error::KvmContext)
+        .map_err(StartMicrovmError::Internal)
+        .and_then(|vm| vm);
+        match vm {
+            Ok(vm) => {
+                if let Err(err) = validate_cpu_template(&vm_resources.vm_config, &vm) {
+                    report.add("cpu-config", None, err);
+                }
+            }
+            Err(err) => report.add("machine-config", None, err),
+        }
+    }
+
+    for drive in vm_resources.block.configs() {
+        let is_read_only = drive.is_read_only;
+        let opened = std::fs::metadata(&drive.path_on_host).and_then(|metadata| {
+            use std::os::unix::fs::FileTypeExt;
+            let file_type = metadata.file_type();
+            if !file_type.is_file() && !file_type.is_block_device() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not a regular file or a block device",
+                ));
+            }
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(!is_read_only)
+                .open(&drive.path_on_host)
+        });
+        if let Err(err) = opened {
+            report.add(
+                "drives",
+                Some(&drive.drive_id),
+                StartMicrovmError::OpenBlockDevice(err),
+            );
+        }
+    }
+
+    for net in vm_resources.net_builder.iter() {
+        let net = net.lock().expect("Poisoned lock");
+        let iface_name = net.iface_name();
+        if !std::path::Path::new("/sys/class/net")
+            .join(&iface_name)
+            .exists()
+        {
+            report.add(
+                "network-interfaces",
+                Some(net.id()),
+                format!("The tap device {} does not exist.", iface_name),
+            );
+        }
+    }
+
+    if let Some(vsock) = vm_resources.vsock.config() {
+        if !std::path::Path::new(&vsock.uds_path).exists() {
+            report.add(
+                "vsock",
+                None,
+                format!("The vsock socket {} was removed.", vsock.uds_path),
+            );
+        }
+    }
+
+    for thread_category in ["vcpu", "vmm"] {
+        if seccomp_filters.get(thread_category).is_none() {
+            report.add(
+                "seccomp",
+                None,
+                StartMicrovmError::MissingSeccompFilters(thread_category.to_string()),
+            );
+        }
+    }
+
+    report
+}
+
+// Checks that the CPU template can be applied on this host. The registers which are read from a
+// vCPU when booting are only checked on x86_64, where KVM lists the MSRs it supports.
+#[cfg_attr(target_arch = "aarch64", allow(unused))]
+fn validate_cpu_template(
+    vm_config: &VmConfig,
+    vm: &Vm,
+) -> std::result::Result<(), StartMicrovmError> {
+    let cpu_template = vm_config.cpu_template.get_cpu_template()?;
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::cpu_config::x86_64::cpuid;
+        let cpuid = cpuid::Cpuid::try_from(vm.supported_cpuid().clone())
+            .map_err(GuestConfigError::CpuidFromKvmCpuid)?;
+        // KVM does not list the MSRs which cannot be read from a vCPU.
+        let supported_msrs = vm.msrs_to_save().as_slice();
+        let msrs = cpu_template
+            .get_msr_index_list()
+            .into_iter()
+            .filter(|index| supported_msrs.contains(index))
+            .map(|index| (index, 0))
+            .collect();
+        CpuConfiguration::apply_template(CpuConfiguration { cpuid, msrs }, &cpu_template)?;
+    }
+
+    Ok(())
+}
+
 /// Builds and boots a microVM based on the current Firecracker VmResources configuration.
 ///
 /// This is the default build recipe, one could build other microVM flavors by using the
@@ -474,6 +628,8 @@ pub fn build_microvm_from_snapshot(
     track_dirty_pages: bool,
     seccomp_filters: &BpfThreadMap,
     vm_resources: &mut VmResources,
+    device_overrides: &DeviceOverrides,
+    timings: &mut RestoreTimings,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, BuildMicrovmFromSnapshotError> {
     let vcpu_count = u8::try_from(microvm_state.vcpu_states.len()).map_err(|_| {
         BuildMicrovmFromSnapshotError::TooManyVCPUs(microvm_state.vcpu_states.len())
@@ -520,6 +676,7 @@ pub fn build_microvm_from_snapshot(
         smt: Some(microvm_state.vm_info.smt),
         cpu_template: Some(microvm_state.vm_info.cpu_template),
         track_dirty_pages: Some(track_dirty_pages),
+        vmgenid: Some(microvm_state.device_states.vmgenid_device.is_some()),
     })?;
 
     // Restore the boot source config paths.
@@ -533,6 +690,7 @@ pub fn build_microvm_from_snapshot(
         for_each_restored_device: VmResources::update_from_restored_device,
         vm_resources,
         instance_id: &instance_info.id,
+        device_overrides,
     };
 
     vmm.mmio_device_manager =
@@ -550,7 +708,9 @@ pub fn build_microvm_from_snapshot(
     )?;
 
     // Restore vcpus kvm state.
+    let mut vcpus_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
     vmm.restore_vcpu_states(microvm_state.vcpu_states)?;
+    timings.restore_vcpus_us = lap_us(&mut vcpus_start_us);
 
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager.add_subscriber(vmm.clone());
@@ -673,14 +833,30 @@ where
     })
 }
 
+/// Opens KVM ahead of time, so that the next microVM built by this process, or by a process
+/// forked from it, does not wait for it.
+pub fn preopen_kvm() -> std::result::Result<(), StartMicrovmError> {
+    KvmContext::preopen()
+        .map_err(Error::KvmContext)
+        .map_err(StartMicrovmError::Internal)
+}
+
 pub(crate) fn setup_kvm_vm(
     guest_memory: &GuestMemoryMmap,
     track_dirty_pages: bool,
 ) -> std::result::Result<Vm, StartMicrovmError> {
-    use self::StartMicrovmError::Internal;
-    let kvm = KvmContext::new()
+    let kvm = KvmContext::preopened_or_new()
         .map_err(Error::KvmContext)
-        .map_err(Internal)?;
+        .map_err(StartMicrovmError::Internal)?;
+    create_vm(&kvm, guest_memory, track_dirty_pages)
+}
+
+fn create_vm(
+    kvm: &KvmContext,
+    guest_memory: &GuestMemoryMmap,
+    track_dirty_pages: bool,
+) -> std::result::Result<Vm, StartMicrovmError> {
+    use self::StartMicrovmError::Internal;
     let mut vm = Vm::new(kvm.fd()).map_err(Error::Vm).map_err(Internal)?;
     vm.memory_init(guest_memory, kvm.max_memslots(), track_dirty_pages)
         .map_err(Error::Vm)
@@ -934,6 +1110,17 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+pub(crate) fn attach_vmgenid_device(vmm: &mut Vmm) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    let vmgenid = VmGenId::new().map_err(Error::VmGenId).map_err(Internal)?;
+    vmm.mmio_device_manager
+        .register_mmio_vmgenid(vmm.vm.fd(), Arc::new(Mutex::new(vmgenid)), None)
+        .map_err(RegisterMmioDevice)?;
+
+    Ok(())
+}
+
 fn attach_entropy_device(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -1043,7 +1230,7 @@ pub mod tests {
     use crate::devices::virtio::vsock::VSOCK_DEV_ID;
     use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_RNG, TYPE_VSOCK};
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
-    use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
+    use crate::vmm_config::boot_source::{BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType, FileEngineType};
     use crate::vmm_config::entropy::{EntropyDeviceBuilder, EntropyDeviceConfig};
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
@@ -1161,6 +1348,9 @@ pub mod tests {
             vm,
             guest_memory,
             uffd: None,
+            last_snapshot: None,
+            restore_start_us: None,
+            memory_dump: None,
             vcpus_handles: Vec::new(),
             vcpus_exit_evt,
             mmio_device_manager,
@@ -1378,6 +1568,142 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_validate_microvm_for_boot() {
+        let mut vm_resources = VmResources::default();
+        let drive_file = TempFile::new().unwrap();
+        vm_resources
+            .set_block_device(BlockDeviceConfig {
+                drive_id: String::from("rootfs"),
+                path_on_host: drive_file.as_path().to_str().unwrap().to_string(),
+                is_root_device: true,
+                partuuid: None,
+                is_read_only: false,
+                cache_type: CacheType::Unsafe,
+                rate_limiter: None,
+                file_engine_type: FileEngineType::default(),
+            })
+            .unwrap();
+        // The backing file is removed after the drive is configured.
+        drop(drive_file);
+
+        let report = validate_microvm_for_boot(&vm_resources, &BpfThreadMap::new());
+        assert!(!report.valid);
+        let problems: Vec<_> = report
+            .problems
+            .iter()
+            .map(|problem| (problem.resource, problem.id.as_deref()))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                ("boot-source", None),
+                ("drives", Some("rootfs")),
+                ("seccomp", None),
+                ("seccomp", None),
+            ]
+        );
+
+        // An empty kernel and an initrd larger than the guest memory.
+        let kernel_file = TempFile::new().unwrap();
+        let initrd_file = TempFile::new().unwrap();
+        initrd_file
+            .as_file()
+            .set_len(((vm_resources.vm_config.mem_size_mib as u64) << 20) + 1)
+            .unwrap();
+        vm_resources
+            .build_boot_source(BootSourceConfig {
+                kernel_image_path: kernel_file.as_path().to_str().unwrap().to_string(),
+                initrd_path: Some(initrd_file.as_path().to_str().unwrap().to_string()),
+                boot_args: None,
+            })
+            .unwrap();
+        let report = validate_microvm_for_boot(&vm_resources, &BpfThreadMap::new());
+        let messages: Vec<_> = report
+            .problems
+            .iter()
+            .filter(|problem| problem.resource == "boot-source")
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("Cannot load kernel"));
+        assert_eq!(
+            messages[1],
+            StartMicrovmError::InitrdLoad.to_string().as_str()
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_validate_unsupported_cpu_template() {
+        use crate::cpu_config::templates::{CustomCpuTemplate, RegisterValueFilter};
+        use crate::cpu_config::x86_64::custom_cpu_template::RegisterModifier;
+
+        let mut vm_resources = VmResources::default();
+        vm_resources.set_custom_cpu_template(CustomCpuTemplate {
+            cpuid_modifiers: vec![],
+            msr_modifiers: vec![RegisterModifier {
+                addr: 0x9999,
+                bitmap: RegisterValueFilter {
+                    filter: 0,
+                    value: 0,
+                },
+            }],
+        });
+
+        let report = validate_microvm_for_boot(&vm_resources, &BpfThreadMap::new());
+        let messages: Vec<_> = report
+            .problems
+            .iter()
+            .filter(|problem| problem.resource == "cpu-config")
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                StartMicrovmError::CreateGuestConfig(GuestConfigError::MsrNotSupported(0x9999))
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_missing_tap() {
+        let mut vm_resources = VmResources::default();
+        vm_resources
+            .build_net_device(NetworkInterfaceConfig {
+                iface_id: String::from("netif"),
+                host_dev_name: String::from("validatetap"),
+                guest_mac: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+            })
+            .unwrap();
+        let has_tap_problem = |vm_resources: &VmResources| {
+            validate_microvm_for_boot(vm_resources, &BpfThreadMap::new())
+                .problems
+                .iter()
+                .any(|problem| problem.resource == "network-interfaces")
+        };
+        assert!(!has_tap_problem(&vm_resources));
+
+        // The interface is renamed from under Firecracker.
+        let net = vm_resources.net_builder.iter().next().unwrap().clone();
+        crate::devices::virtio::net::test_utils::rename(&net.lock().unwrap().tap, "validatetap2");
+
+        let report = validate_microvm_for_boot(&vm_resources, &BpfThreadMap::new());
+        let problem = report
+            .problems
+            .iter()
+            .find(|problem| problem.resource == "network-interfaces")
+            .unwrap();
+        assert_eq!(problem.id.as_deref(), Some("netif"));
+        assert_eq!(
+            problem.message,
+            "The tap device validatetap does not exist."
+        );
+    }
+
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
@@ -1611,6 +1937,18 @@ pub mod tests {
             .is_some());
     }
 
+    #[test]
+    fn test_attach_vmgenid_device() {
+        let mut vmm = default_vmm();
+
+        attach_vmgenid_device(&mut vmm).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::VmGenId, &DeviceType::VmGenId.to_string())
+            .is_some());
+        vmm.mmio_device_manager.regenerate_vmgenid().unwrap();
+    }
+
     #[test]
     fn test_attach_balloon_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -11,6 +11,7 @@ use std::sync::{Arc, Mutex};
 use kvm_ioctls::{IoEventAddress, VmFd};
 use linux_loader::cmdline as kernel_cmdline;
 use logger::info;
+use serde::Serialize;
 #[cfg(target_arch = "x86_64")]
 use utils::vm_memory::GuestAddress;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -25,7 +26,8 @@ use crate::arch::DeviceType::Virtio;
 use crate::devices::legacy::RTCDevice;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::SerialDevice;
-use crate::devices::pseudo::BootTimer;
+use crate::devices::pseudo::{BootTimer, VmGenId, VmGenIdError};
+use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
 use crate::devices::virtio::{
     Balloon, Block, Entropy, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
     TYPE_RNG, TYPE_VSOCK,
@@ -73,7 +75,7 @@ type Result<T> = ::std::result::Result<T, Error>;
 pub const MMIO_LEN: u64 = 0x1000;
 
 /// Stores the address range and irq allocated to this device.
-#[derive(Clone, Debug, PartialEq, Eq, Versionize)]
+#[derive(Clone, Debug, PartialEq, Eq, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct MMIODeviceInfo {
     /// Mmio address at which the device is registered.
@@ -283,6 +285,68 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, device_info, Arc::new(Mutex::new(device)))
     }
 
+    /// Register a VM generation ID device at the specified MMIO configuration if given as
+    /// parameter, otherwise allocate new MMIO resources for it.
+    pub fn register_mmio_vmgenid(
+        &mut self,
+        vm: &VmFd,
+        vmgenid: Arc<Mutex<VmGenId>>,
+        device_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<()> {
+        // Create a new MMIODeviceInfo object on boot path or unwrap the
+        // existing object on restore path.
+        let device_info = if let Some(device_info) = device_info_opt {
+            device_info
+        } else {
+            self.allocate_mmio_resources(1)?
+        };
+
+        vm.register_irqfd(
+            vmgenid.lock().expect("Poisoned lock").interrupt_evt(),
+            device_info.irqs[0],
+        )
+        .map_err(Error::RegisterIrqFd)?;
+
+        let identifier = (DeviceType::VmGenId, DeviceType::VmGenId.to_string());
+        self.register_mmio_device(identifier, device_info, vmgenid)
+    }
+
+    /// Draws a new generation ID and notifies the guest, if the VM generation ID device is
+    /// registered.
+    pub fn regenerate_vmgenid(&self) -> std::result::Result<(), VmGenIdError> {
+        if let Some(busdev) = self.get_device(DeviceType::VmGenId, &DeviceType::VmGenId.to_string())
+        {
+            busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<VmGenId>()
+                .expect("Unexpected BusDevice type")
+                .regenerate()?;
+        }
+        Ok(())
+    }
+
+    /// Switches the entropy device to a new random source and hands fresh entropy to the guest,
+    /// if the device is attached.
+    pub fn reseed_entropy(&self) {
+        if let Some(busdev) = self.get_device(DeviceType::Virtio(TYPE_RNG), ENTROPY_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_any()
+                .downcast_ref::<MmioTransport>()
+                .expect("Unexpected BusDevice type")
+                .device();
+            let mut device = virtio_device.lock().expect("Poisoned lock");
+            device
+                .as_mut_any()
+                .downcast_mut::<Entropy>()
+                .expect("Unexpected VirtioDevice type")
+                .reseed();
+        }
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -10,6 +10,7 @@ use event_manager::{MutEventSubscriber, SubscriberOps};
 use kvm_ioctls::VmFd;
 use logger::{error, warn,info};
 use mmds::data_store::MmdsVersion;
+use serde::Serialize;
 use snapshot::Persist;
 use utils::vm_memory::GuestMemoryMmap;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
@@ -19,6 +20,7 @@ use vm_allocator::AllocPolicy;
 use super::mmio::*;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::DeviceType;
+use crate::devices::pseudo::{VmGenId, VmGenIdError, VMGENID_LEN};
 use crate::devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use crate::devices::virtio::balloon::{Balloon, BalloonError};
 use crate::devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
@@ -39,6 +41,7 @@ use crate::devices::virtio::{
 };
 use crate::resources::VmResources;
 use crate::vmm_config::mmds::MmdsConfigError;
+use crate::vmm_config::snapshot::DeviceOverrides;
 use crate::EventManager;
 
 /// Errors for (de)serialization of the MMIO device manager.
@@ -55,11 +58,12 @@ pub enum Error {
     VsockUnixBackend(VsockUnixBackendError),
     MmdsConfig(MmdsConfigError),
     Entropy(EntropyError),
+    VmGenId(VmGenIdError),
 }
 
 /// Holds the state of a balloon device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct ConnectedBalloonState {
     /// Device identifier.
     pub device_id: String,
@@ -73,7 +77,7 @@ pub struct ConnectedBalloonState {
 
 /// Holds the state of a block device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct ConnectedBlockState {
     /// Device identifier.
     pub device_id: String,
@@ -87,7 +91,7 @@ pub struct ConnectedBlockState {
 
 /// Holds the state of a net device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct ConnectedNetState {
     /// Device identifier.
     pub device_id: String,
@@ -101,7 +105,7 @@ pub struct ConnectedNetState {
 
 /// Holds the state of a vsock device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct ConnectedVsockState {
     /// Device identifier.
     pub device_id: String,
@@ -113,7 +117,7 @@ pub struct ConnectedVsockState {
     pub device_info: MMIODeviceInfo,
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 /// Holds the state of an entropy device connected to the MMIO space.
 // NOTICE: Any chages to this structure require a snapshot version bump.
 pub struct ConnectedEntropyState {
@@ -129,7 +133,7 @@ pub struct ConnectedEntropyState {
 
 /// Holds the state of a legacy device connected to the MMIO space.
 #[cfg(target_arch = "aarch64")]
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct ConnectedLegacyState {
     /// Device identifier.
     pub type_: DeviceType,
@@ -137,9 +141,19 @@ pub struct ConnectedLegacyState {
     pub device_info: MMIODeviceInfo,
 }
 
+/// Holds the state of the VM generation ID device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+#[derive(Clone, Versionize, Serialize)]
+pub struct ConnectedVmGenIdState {
+    /// Generation ID, as little endian bytes.
+    pub generation_id: Vec<u8>,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+}
+
 /// Holds the MMDS data store version.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Debug, Clone, PartialEq, Eq, Versionize)]
+#[derive(Debug, Clone, PartialEq, Eq, Versionize, Serialize)]
 pub enum MmdsVersionState {
     V1,
     V2,
@@ -165,7 +179,7 @@ impl From<MmdsVersion> for MmdsVersionState {
 
 /// Holds the device states.
 // NOTICE: Any changes to this structure require a snapshot version bump.
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct DeviceStates {
     #[cfg(target_arch = "aarch64")]
     // State of legacy devices in MMIO space.
@@ -185,6 +199,9 @@ pub struct DeviceStates {
     /// Entropy device state.
     #[version(start = 4, ser_fn = "entropy_serialize")]
     pub entropy_device: Option<ConnectedEntropyState>,
+    /// VM generation ID device state.
+    #[version(start = 5, ser_fn = "vmgenid_serialize")]
+    pub vmgenid_device: Option<ConnectedVmGenIdState>,
 }
 
 /// A type used to extract the concrete Arc<Mutex<T>> for each of the device types when restoring
@@ -228,6 +245,17 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    fn vmgenid_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 5 && self.vmgenid_device.is_some() {
+            warn!(
+                "Target version does not support persisting the VM generation ID device. The \
+                 restored microVM will not have one."
+            );
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
@@ -237,6 +265,7 @@ pub struct MMIODevManagerConstructorArgs<'a> {
     pub for_each_restored_device: fn(&mut VmResources, SharedDeviceType),
     pub vm_resources: &'a mut VmResources,
     pub instance_id: &'a str,
+    pub device_overrides: &'a DeviceOverrides,
 }
 
 impl<'a> Persist<'a> for MMIODeviceManager {
@@ -254,12 +283,25 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             legacy_devices: Vec::new(),
             mmds_version: None,
             entropy_device: None,
+            vmgenid_device: None,
         };
         let _: Result<(), ()> = self.for_each_device(|devtype, devid, device_info, bus_dev| {
             if *devtype == crate::arch::DeviceType::BootTimer {
                 // No need to save BootTimer state.
                 return Ok(());
             }
+            if *devtype == crate::arch::DeviceType::VmGenId {
+                let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
+                let vmgenid = locked_bus_dev
+                    .as_any()
+                    .downcast_ref::<VmGenId>()
+                    .expect("Unexpected BusDevice type");
+                states.vmgenid_device = Some(ConnectedVmGenIdState {
+                    generation_id: vmgenid.generation_id().to_le_bytes().to_vec(),
+                    device_info: device_info.clone(),
+                });
+                return Ok(());
+            }
 
             #[cfg(target_arch = "aarch64")]
             {
@@ -382,6 +424,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         .map_err(Self::Error::DeviceManager)?;
         let mem = &constructor_args.mem;
         let vm = constructor_args.vm;
+        let overrides = constructor_args.device_overrides;
         info!("PASS_debug Restoring MMIO devices...");
         #[cfg(target_arch = "aarch64")]
         {
@@ -485,7 +528,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         info!("PASS_debug block_state starting...");
         for block_state in &state.block_devices {
             let device = Arc::new(Mutex::new(Block::restore(
-                BlockConstructorArgs { mem: mem.clone() },
+                BlockConstructorArgs {
+                    mem: mem.clone(),
+                    path_on_host: overrides
+                        .path_on_host(&block_state.device_id)
+                        .map(str::to_string),
+                },
                 &block_state.device_state,
             )?));
 
@@ -531,6 +579,9 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         .as_ref()
                         // Clone the Arc reference.
                         .cloned(),
+                    host_dev_name: overrides
+                        .host_dev_name(&net_state.device_id)
+                        .map(str::to_string),
                 },
                 &net_state.device_state,
             )?));
@@ -553,6 +604,9 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         if let Some(vsock_state) = &state.vsock_device {
             let ctor_args = VsockUdsConstructorArgs {
                 cid: vsock_state.device_state.frontend.cid,
+                uds_path: overrides
+                    .uds_path(&vsock_state.device_id)
+                    .map(str::to_string),
             };
             let backend = VsockUnixBackend::restore(ctor_args, &vsock_state.device_state.backend)?;
             let device = Arc::new(Mutex::new(Vsock::restore(
@@ -601,6 +655,32 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        if let Some(vmgenid_state) = &state.vmgenid_device {
+            // Snapshot loads draw a new generation ID once the microVM is built, while
+            // migrated microVMs keep theirs.
+            let vmgenid =
+                match <[u8; VMGENID_LEN]>::try_from(vmgenid_state.generation_id.as_slice()) {
+                    Ok(generation_id) => {
+                        VmGenId::with_generation_id(u128::from_le_bytes(generation_id))
+                    }
+                    Err(_) => VmGenId::new(),
+                };
+            let vmgenid = Arc::new(Mutex::new(vmgenid?));
+            dev_manager
+                .address_allocator
+                .allocate(
+                    MMIO_LEN,
+                    MMIO_LEN,
+                    AllocPolicy::ExactMatch(vmgenid_state.device_info.addr),
+                )
+                .map_err(|e| Error::DeviceManager(super::mmio::Error::Allocator(e)))?;
+            dev_manager.register_mmio_vmgenid(
+                vm,
+                vmgenid,
+                Some(vmgenid_state.device_info.clone()),
+            )?;
+        }
+
         Ok(dev_manager)
     }
 }
@@ -614,6 +694,7 @@ mod tests {
     use crate::devices::virtio::block::CacheType;
     use crate::devices::virtio::net::persist::NetConfigSpaceState;
     use crate::resources::VmmConfig;
+    use crate::version_map::{FC_V1_4_SNAP_VERSION, FC_V1_5_SNAP_VERSION, VERSION_MAP};
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::entropy::EntropyDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
@@ -888,6 +969,7 @@ mod tests {
             for_each_restored_device: VmResources::update_from_restored_device,
             vm_resources,
             instance_id: "microvm-id",
+            device_overrides: &DeviceOverrides::default(),
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
@@ -970,4 +1052,56 @@ mod tests {
             serde_json::to_string_pretty(&VmmConfig::from(&*vm_resources)).unwrap()
         );
     }
+
+    #[test]
+    fn test_vmgenid_persistence() {
+        let mut buf = vec![0; 4096];
+        let mut vmm = default_vmm();
+        crate::builder::attach_vmgenid_device(&mut vmm).unwrap();
+        let states = vmm.mmio_device_manager.save();
+        let generation_id = states
+            .vmgenid_device
+            .as_ref()
+            .unwrap()
+            .generation_id
+            .clone();
+
+        // Older versions drop the device.
+        states
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, FC_V1_4_SNAP_VERSION)
+            .unwrap();
+        let device_states =
+            DeviceStates::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_4_SNAP_VERSION)
+                .unwrap();
+        assert!(device_states.vmgenid_device.is_none());
+
+        states
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, FC_V1_5_SNAP_VERSION)
+            .unwrap();
+        let device_states =
+            DeviceStates::deserialize(&mut buf.as_slice(), &VERSION_MAP, FC_V1_5_SNAP_VERSION)
+                .unwrap();
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let vmm = default_vmm();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory().clone(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            for_each_restored_device: VmResources::update_from_restored_device,
+            vm_resources: &mut VmResources::default(),
+            instance_id: "microvm-id",
+            device_overrides: &DeviceOverrides::default(),
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+        // The generation ID only changes once the whole microVM is restored.
+        let restored_states = restored_dev_manager.save();
+        let restored_vmgenid = restored_states.vmgenid_device.unwrap();
+        assert_eq!(restored_vmgenid.generation_id, generation_id);
+        assert_eq!(
+            restored_vmgenid.device_info,
+            states.vmgenid_device.unwrap().device_info
+        );
+    }
 }
@@ -2,5 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod boot_timer;
+mod vmgenid;
 
 pub use self::boot_timer::BootTimer;
+pub use self::vmgenid::{VmGenId, VmGenIdError, VMGENID_LEN};
@@ -0,0 +1,129 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! VM generation ID device.
+//!
+//! Exposes a 128-bit generation ID that changes every time the microVM is restored from a
+//! snapshot, so that the guest can tell it was cloned and reseed its random number generators.
+//! The ID is read from the start of the MMIO region of the device, and each change is signaled
+//! through an edge-triggered interrupt, as expected by the `microsoft,vmgenid` device tree
+//! binding of the Linux vmgenid driver. The device is only attached on aarch64, since x86_64
+//! guests look for it in ACPI tables, which Firecracker does not provide.
+
+use std::io;
+
+use aws_lc_rs::rand;
+use logger::info;
+use utils::eventfd::EventFd;
+
+use crate::devices::bus::BusDevice;
+
+/// Size of the generation ID, in bytes.
+pub const VMGENID_LEN: usize = 16;
+
+/// Errors associated with the VM generation ID device.
+#[derive(Debug, thiserror::Error)]
+pub enum VmGenIdError {
+    /// Failed to create the interrupt event.
+    #[error("Cannot create the interrupt event: {0}")]
+    EventFd(io::Error),
+    /// Failed to signal the new generation ID to the guest.
+    #[error("Cannot notify the guest of the new generation ID: {0}")]
+    Interrupt(io::Error),
+    /// Failed to draw a new generation ID.
+    #[error("Cannot draw a new generation ID: {0}")]
+    Random(aws_lc_rs::error::Unspecified),
+}
+
+/// VM generation ID device.
+#[derive(Debug)]
+pub struct VmGenId {
+    generation_id: [u8; VMGENID_LEN],
+    interrupt_evt: EventFd,
+}
+
+impl VmGenId {
+    /// Creates the device with a random generation ID.
+    pub fn new() -> Result<VmGenId, VmGenIdError> {
+        Self::with_generation_id(u128::from_le_bytes(random_generation_id()?))
+    }
+
+    /// Creates the device with the given generation ID.
+    pub fn with_generation_id(generation_id: u128) -> Result<VmGenId, VmGenIdError> {
+        Ok(VmGenId {
+            generation_id: generation_id.to_le_bytes(),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VmGenIdError::EventFd)?,
+        })
+    }
+
+    /// Returns the current generation ID.
+    pub fn generation_id(&self) -> u128 {
+        u128::from_le_bytes(self.generation_id)
+    }
+
+    /// Returns the event used to interrupt the guest.
+    pub fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    /// Draws a new generation ID and notifies the guest.
+    pub fn regenerate(&mut self) -> Result<(), VmGenIdError> {
+        self.generation_id = random_generation_id()?;
+        info!("New VM generation ID: {:032x}", self.generation_id());
+        self.interrupt_evt.write(1).map_err(VmGenIdError::Interrupt)
+    }
+}
+
+fn random_generation_id() -> Result<[u8; VMGENID_LEN], VmGenIdError> {
+    let mut generation_id = [0u8; VMGENID_LEN];
+    rand::fill(&mut generation_id).map_err(VmGenIdError::Random)?;
+    Ok(generation_id)
+}
+
+impl BusDevice for VmGenId {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        // The rest of the MMIO region reads as zeroes.
+        data.fill(0);
+        if let Some(id) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.generation_id.get(offset..))
+        {
+            let len = id.len().min(data.len());
+            data[..len].copy_from_slice(&id[..len]);
+        }
+    }
+
+    // The generation ID is read-only.
+    fn write(&mut self, _offset: u64, _data: &[u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vmgenid() {
+        let mut vmgenid = VmGenId::new().unwrap();
+        let generation_id = vmgenid.generation_id();
+
+        let mut data = [0xffu8; 8];
+        vmgenid.read(0, &mut data);
+        assert_eq!(data, generation_id.to_le_bytes()[..8]);
+        vmgenid.read(12, &mut data);
+        assert_eq!(data[..4], generation_id.to_le_bytes()[12..]);
+        assert_eq!(data[4..], [0; 4]);
+        vmgenid.read(0x100, &mut data);
+        assert_eq!(data, [0; 8]);
+
+        // Writes are ignored.
+        vmgenid.write(0, &[0; 8]);
+        assert_eq!(vmgenid.generation_id(), generation_id);
+
+        let restored = VmGenId::with_generation_id(generation_id).unwrap();
+        assert_eq!(restored.generation_id(), generation_id);
+
+        vmgenid.regenerate().unwrap();
+        assert_ne!(vmgenid.generation_id(), generation_id);
+        assert_eq!(vmgenid.interrupt_evt().read().unwrap(), 1);
+    }
+}
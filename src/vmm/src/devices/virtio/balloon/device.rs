@@ -28,6 +28,7 @@ use super::{
 };
 use crate::devices::virtio::balloon::BalloonError;
 use crate::devices::virtio::{IrqTrigger, IrqType};
+use crate::lifecycle::{self, LifecycleEvent};
 
 const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
 const SIZE_OF_STAT: usize = std::mem::size_of::<BalloonStat>();
@@ -560,7 +561,16 @@ impl VirtioDevice for Balloon {
             error!("Failed to write config space");
             return;
         }
+        let previous_actual_pages = self.config_space.actual_pages;
         config_space_bytes[offset as usize..(offset + data_len) as usize].copy_from_slice(data);
+        // The driver reports its progress by updating the actual number of pages.
+        if self.config_space.actual_pages != previous_actual_pages
+            && self.config_space.actual_pages == self.config_space.num_pages
+        {
+            lifecycle::emit(LifecycleEvent::BalloonTargetReached {
+                target_mib: pages_to_mib(self.config_space.num_pages),
+            });
+        }
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
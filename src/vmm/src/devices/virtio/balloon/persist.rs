@@ -7,6 +7,7 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
+use serde::Serialize;
 use snapshot::Persist;
 use timerfd::{SetTimeFlags, TimerState};
 use utils::vm_memory::GuestMemoryMmap;
@@ -18,14 +19,14 @@ use crate::devices::virtio::balloon::device::{BalloonStats, ConfigSpace};
 use crate::devices::virtio::persist::VirtioDeviceState;
 use crate::devices::virtio::{DeviceState, TYPE_BALLOON};
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct BalloonConfigSpaceState {
     num_pages: u32,
     actual_pages: u32,
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct BalloonStatsState {
     swap_in: Option<u64>,
@@ -76,7 +77,7 @@ impl BalloonStatsState {
     }
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct BalloonState {
     stats_polling_interval_s: u16,
@@ -188,6 +188,21 @@ impl DiskProperties {
     }
 }
 
+/// Counters of the requests completed by a block device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BlockDeviceStats {
+    /// Number of bytes read by the guest.
+    pub read_bytes: u64,
+    /// Number of bytes written by the guest.
+    pub write_bytes: u64,
+    /// Number of successful read requests.
+    pub read_count: u64,
+    /// Number of successful write requests.
+    pub write_count: u64,
+    /// Number of flush requests.
+    pub flush_count: u64,
+}
+
 /// Virtio device for exposing block level read/write operations on a host file.
 pub struct Block {
     // Host file and properties.
@@ -211,6 +226,7 @@ pub struct Block {
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
     is_io_engine_throttled: bool,
+    stats: BlockDeviceStats,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
@@ -276,6 +292,7 @@ impl Block {
             irq_trigger: IrqTrigger::new().map_err(BlockError::IrqTrigger)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(BlockError::EventFd)?,
             is_io_engine_throttled: false,
+            stats: BlockDeviceStats::default(),
         })
     }
 
@@ -344,7 +361,7 @@ impl Block {
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem)
+                    request.process(&mut self.disk, head.index, mem, &mut self.stats)
                 }
                 Err(err) => {
                     error!("Failed to parse available descriptor chain: {:?}", err);
@@ -413,7 +430,7 @@ impl Block {
                             ))),
                         ),
                     };
-                    let finished = pending.finish(mem, res);
+                    let finished = pending.finish(mem, res, &mut self.stats);
 
                     Self::add_used_descriptor(
                         queue,
@@ -500,6 +517,11 @@ impl Block {
         &self.rate_limiter
     }
 
+    /// Provides the counters of the requests completed by this block device.
+    pub fn stats(&self) -> BlockDeviceStats {
+        self.stats
+    }
+
     pub fn file_engine_type(&self) -> FileEngineType {
         match self.disk.file_engine() {
             FileEngine::Sync(_) => FileEngineType::Sync,
@@ -10,7 +10,7 @@ pub mod test_utils;
 
 use utils::vm_memory::GuestMemoryError;
 
-pub use self::device::{Block, CacheType};
+pub use self::device::{Block, BlockDeviceStats, CacheType};
 pub use self::event_handler::*;
 pub use self::request::*;
 
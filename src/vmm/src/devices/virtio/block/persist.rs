@@ -9,6 +9,7 @@ use std::sync::Arc;
 use logger::warn;
 use rate_limiter::persist::RateLimiterState;
 use rate_limiter::RateLimiter;
+use serde::Serialize;
 use snapshot::Persist;
 use utils::kernel_version::min_kernel_version_for_io_uring;
 use utils::vm_memory::GuestMemoryMmap;
@@ -21,7 +22,7 @@ use crate::devices::virtio::block::device::FileEngineType;
 use crate::devices::virtio::persist::VirtioDeviceState;
 use crate::devices::virtio::{DeviceState, TYPE_BLOCK};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Versionize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub enum CacheTypeState {
     Unsafe,
@@ -46,7 +47,7 @@ impl From<CacheTypeState> for CacheType {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Versionize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub enum FileEngineTypeState {
     // If the snap version does not contain the `FileEngineType`, it must have been snapshotted
@@ -74,7 +75,7 @@ impl From<FileEngineTypeState> for FileEngineType {
     }
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct BlockState {
     id: String,
@@ -115,6 +116,8 @@ impl BlockState {
 
 pub struct BlockConstructorArgs {
     pub mem: GuestMemoryMmap,
+    /// Backing file used instead of the one saved in the snapshot.
+    pub path_on_host: Option<String>,
 }
 
 impl Persist<'_> for Block {
@@ -143,12 +146,15 @@ impl Persist<'_> for Block {
         let is_disk_read_only = state.virtio_state.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0;
         let rate_limiter =
             RateLimiter::restore((), &state.rate_limiter_state).map_err(BlockError::RateLimiter)?;
+        let disk_path = constructor_args
+            .path_on_host
+            .unwrap_or_else(|| state.disk_path.clone());
 
         let mut block = Block::new(
             state.id.clone(),
             state.partuuid.clone(),
             state.cache_type.into(),
-            state.disk_path.clone(),
+            disk_path.clone(),
             is_disk_read_only,
             state.root_device,
             rate_limiter,
@@ -169,7 +175,7 @@ impl Persist<'_> for Block {
                     state.id.clone(),
                     state.partuuid.clone(),
                     state.cache_type.into(),
-                    state.disk_path.clone(),
+                    disk_path.clone(),
                     is_disk_read_only,
                     state.root_device,
                     rate_limiter,
@@ -327,7 +333,10 @@ mod tests {
 
             // Restore the block device.
             let restored_block = Block::restore(
-                BlockConstructorArgs { mem: default_mem() },
+                BlockConstructorArgs {
+                    mem: default_mem(),
+                    path_on_host: None,
+                },
                 &BlockState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
             )
             .unwrap();
@@ -368,7 +377,10 @@ mod tests {
 
         // Restore the block device.
         let restored_block = Block::restore(
-            BlockConstructorArgs { mem: guest_mem },
+            BlockConstructorArgs {
+                mem: guest_mem,
+                path_on_host: None,
+            },
             &BlockState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
         )
         .unwrap();
@@ -18,7 +18,7 @@ pub use virtio_gen::virtio_blk::{
 
 use super::super::DescriptorChain;
 use super::{io as block_io, BlockError, SECTOR_SHIFT};
-use crate::devices::virtio::block::device::DiskProperties;
+use crate::devices::virtio::block::device::{BlockDeviceStats, DiskProperties};
 use crate::devices::virtio::SECTOR_SIZE;
 
 #[derive(Debug, derive_more::From)]
@@ -133,26 +133,36 @@ impl PendingRequest {
         }
     }
 
-    pub fn finish(self, mem: &GuestMemoryMmap, res: Result<u32, IoErr>) -> FinishedRequest {
+    pub fn finish(
+        self,
+        mem: &GuestMemoryMmap,
+        res: Result<u32, IoErr>,
+        stats: &mut BlockDeviceStats,
+    ) -> FinishedRequest {
         let status = match (res, self.r#type) {
             (Ok(transferred_data_len), RequestType::In) => {
                 let status = Status::from_data(self.data_len, transferred_data_len, true);
                 METRICS.block.read_bytes.add(transferred_data_len as usize);
+                stats.read_bytes += u64::from(transferred_data_len);
                 if let Status::Ok { .. } = status {
                     METRICS.block.read_count.inc();
+                    stats.read_count += 1;
                 }
                 status
             }
             (Ok(transferred_data_len), RequestType::Out) => {
                 let status = Status::from_data(self.data_len, transferred_data_len, false);
                 METRICS.block.write_bytes.add(transferred_data_len as usize);
+                stats.write_bytes += u64::from(transferred_data_len);
                 if let Status::Ok { .. } = status {
                     METRICS.block.write_count.inc();
+                    stats.write_count += 1;
                 }
                 status
             }
             (Ok(_), RequestType::Flush) => {
                 METRICS.block.flush_count.inc();
+                stats.flush_count += 1;
                 Status::Ok {
                     num_bytes_to_mem: 0,
                 }
@@ -351,6 +361,7 @@ impl Request {
         disk: &mut DiskProperties,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
+        stats: &mut BlockDeviceStats,
     ) -> ProcessingResult {
         let pending = self.to_pending_request(desc_idx);
         let res = match self.r#type {
@@ -374,25 +385,27 @@ impl Request {
                     .write_slice(disk.image_id(), self.data_addr)
                     .map(|_| VIRTIO_BLK_ID_BYTES)
                     .map_err(IoErr::GetId);
-                return ProcessingResult::Executed(pending.finish(mem, res));
+                return ProcessingResult::Executed(pending.finish(mem, res, stats));
             }
             RequestType::Unsupported(_) => {
-                return ProcessingResult::Executed(pending.finish(mem, Ok(0)));
+                return ProcessingResult::Executed(pending.finish(mem, Ok(0), stats));
             }
         };
 
         match res {
             Ok(block_io::FileEngineOk::Submitted) => ProcessingResult::Submitted,
             Ok(block_io::FileEngineOk::Executed(res)) => {
-                ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count)))
+                ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count), stats))
             }
             Err(err) => {
                 if err.error.is_throttling_err() {
                     ProcessingResult::Throttled
                 } else {
-                    ProcessingResult::Executed(
-                        err.user_data.finish(mem, Err(IoErr::FileEngine(err.error))),
-                    )
+                    ProcessingResult::Executed(err.user_data.finish(
+                        mem,
+                        Err(IoErr::FileEngine(err.error)),
+                        stats,
+                    ))
                 }
             }
         }
@@ -18,6 +18,7 @@ use logger::{error, warn, IncMetric, METRICS};
 use mmds::data_store::Mmds;
 use mmds::ns::MmdsNetworkStack;
 use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use serde::Serialize;
 use utils::eventfd::EventFd;
 use utils::net::mac::MacAddr;
 use utils::vm_memory::{ByteValued, Bytes, GuestMemoryError, GuestMemoryMmap};
@@ -92,6 +93,19 @@ pub struct ConfigSpace {
 // SAFETY: `ConfigSpace` contains only PODs.
 unsafe impl ByteValued for ConfigSpace {}
 
+/// Counters of the frames exchanged with the guest by a network device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NetDeviceStats {
+    /// Number of bytes received by the guest.
+    pub rx_bytes: u64,
+    /// Number of frames received by the guest.
+    pub rx_packets: u64,
+    /// Number of bytes transmitted by the guest.
+    pub tx_bytes: u64,
+    /// Number of frames transmitted by the guest.
+    pub tx_packets: u64,
+}
+
 pub struct Net {
     pub(crate) id: String,
 
@@ -122,6 +136,8 @@ pub struct Net {
     pub(crate) activate_evt: EventFd,
 
     pub mmds_ns: Option<MmdsNetworkStack>,
+
+    stats: NetDeviceStats,
 }
 
 impl Net {
@@ -175,6 +191,7 @@ impl Net {
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(NetError::EventFd)?,
             mmds_ns: None,
+            stats: NetDeviceStats::default(),
         })
     }
 
@@ -246,6 +263,11 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Provides the counters of the frames exchanged with the guest.
+    pub fn stats(&self) -> NetDeviceStats {
+        self.stats
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> result::Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -378,6 +400,8 @@ impl Net {
             METRICS.net.rx_fails.inc();
             0
         } else {
+            self.stats.rx_bytes += self.rx_bytes_read as u64;
+            self.stats.rx_packets += 1;
             self.rx_bytes_read as u32
         };
         queue.add_used(mem, head_index, used_len).map_err(|err| {
@@ -588,6 +612,8 @@ impl Net {
                 self.guest_mac,
             )
             .unwrap_or(false);
+            self.stats.tx_bytes += buffer.len() as u64;
+            self.stats.tx_packets += 1;
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
@@ -20,7 +20,7 @@ pub mod test_utils;
 
 pub use tap::{Error as TapError, Tap};
 
-pub use self::device::Net;
+pub use self::device::{Net, NetDeviceStats};
 pub use self::event_handler::*;
 
 /// Enum representing the Net device queue types
@@ -13,6 +13,7 @@ use mmds::ns::MmdsNetworkStack;
 use mmds::persist::MmdsNetworkStackState;
 use rate_limiter::persist::RateLimiterState;
 use rate_limiter::RateLimiter;
+use serde::Serialize;
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use utils::vm_memory::GuestMemoryMmap;
@@ -24,7 +25,7 @@ use super::{NET_NUM_QUEUES, NET_QUEUE_SIZE};
 use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
 use crate::devices::virtio::{DeviceState, TYPE_NET};
 
-#[derive(Debug, Default, Clone, Versionize)]
+#[derive(Debug, Default, Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetConfigSpaceState {
     #[version(end = 2, default_fn = "def_guest_mac_old")]
@@ -59,7 +60,7 @@ impl NetConfigSpaceState {
     }
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetState {
     id: String,
@@ -74,6 +75,8 @@ pub struct NetState {
 pub struct NetConstructorArgs {
     pub mem: GuestMemoryMmap,
     pub mmds: Option<Arc<Mutex<Mmds>>>,
+    /// Tap device used instead of the one saved in the snapshot.
+    pub host_dev_name: Option<String>,
 }
 
 #[derive(Debug, derive_more::From)]
@@ -113,7 +116,10 @@ impl Persist<'_> for Net {
         let tx_rate_limiter = RateLimiter::restore((), &state.tx_rate_limiter_state)?;
         let mut net = Net::new(
             state.id.clone(),
-            &state.tap_if_name,
+            constructor_args
+                .host_dev_name
+                .as_deref()
+                .unwrap_or(&state.tap_if_name),
             state.config_space.guest_mac_v2,
             rx_rate_limiter,
             tx_rate_limiter,
@@ -198,6 +204,7 @@ mod tests {
                 NetConstructorArgs {
                     mem: guest_mem,
                     mmds: mmds_ds,
+                    host_dev_name: None,
                 },
                 &NetState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
             ) {
@@ -105,6 +105,12 @@ impl IfReqBuilder {
         self
     }
 
+    pub(crate) fn new_name(mut self, new_name: &str) -> Result<Self> {
+        let new_name = build_terminated_if_name(new_name)?;
+        self.0.ifr_ifru.ifru_newname = new_name.map(|byte| byte as c_char);
+        Ok(self)
+    }
+
     pub(crate) fn execute<F: AsRawFd>(mut self, socket: &F, ioctl: u64) -> std::io::Result<ifreq> {
         // SAFETY: ioctl is safe. Called with a valid socket fd, and we check the return.
         if unsafe { ioctl_with_mut_ref(socket, ioctl, &mut self.0) } < 0 {
@@ -279,6 +279,17 @@ pub fn enable(tap: &Tap) {
         .unwrap();
 }
 
+/// Renames the tap interface, as if it was changed from under Firecracker.
+pub fn rename(tap: &Tap, new_name: &str) {
+    let sock = create_socket();
+    IfReqBuilder::new()
+        .if_name(&tap.if_name)
+        .new_name(new_name)
+        .unwrap()
+        .execute(&sock, c_ulong::from(net_gen::sockios::SIOCSIFNAME))
+        .unwrap();
+}
+
 #[cfg(test)]
 pub(crate) fn inject_tap_tx_frame(net: &Net, len: usize) -> Vec<u8> {
     assert!(len >= vnet_hdr_len());
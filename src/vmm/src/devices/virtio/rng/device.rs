@@ -5,9 +5,10 @@ use std::io;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use aws_lc_rs::rand;
+use aws_lc_rs::rand::{self, SecureRandom};
 use logger::{debug, error, IncMetric, METRICS};
 use rate_limiter::{RateLimiter, TokenType};
+use serde::Serialize;
 use utils::eventfd::EventFd;
 use utils::vm_memory::{GuestMemoryError, GuestMemoryMmap};
 use virtio_gen::virtio_rng::VIRTIO_F_VERSION_1;
@@ -32,6 +33,15 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Counters of the requests served by an entropy device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct EntropyDeviceStats {
+    /// Number of random bytes provided to the guest.
+    pub entropy_bytes: u64,
+    /// Number of requests served.
+    pub request_count: u64,
+}
+
 pub struct Entropy {
     // VirtIO fields
     avail_features: u64,
@@ -46,6 +56,8 @@ pub struct Entropy {
 
     // Device specific fields
     rate_limiter: RateLimiter,
+    stats: EntropyDeviceStats,
+    rng: rand::SystemRandom,
 }
 
 impl Entropy {
@@ -70,6 +82,8 @@ impl Entropy {
             queue_events,
             irq_trigger,
             rate_limiter,
+            stats: EntropyDeviceStats::default(),
+            rng: rand::SystemRandom::new(),
         })
     }
 
@@ -109,7 +123,7 @@ impl Entropy {
         }
 
         let mut rand_bytes = vec![0; iovec.len()];
-        rand::fill(&mut rand_bytes).map_err(|err| {
+        self.rng.fill(&mut rand_bytes).map_err(|err| {
             METRICS.entropy.host_rng_fails.inc();
             err
         })?;
@@ -161,6 +175,8 @@ impl Entropy {
                 Ok(_) => {
                     used_any = true;
                     METRICS.entropy.entropy_bytes.add(bytes as usize);
+                    self.stats.entropy_bytes += u64::from(bytes);
+                    self.stats.request_count += 1;
                 }
                 Err(err) => {
                     error!("entropy: Could not add used descriptor to queue: {err}");
@@ -212,10 +228,25 @@ impl Entropy {
         self.process_entropy_queue();
     }
 
+    /// Switches to a new host random source and fills the buffers the guest has posted with
+    /// entropy drawn from it, signaling the used queue. Called when the microVM is restored from
+    /// a snapshot, so that the clones of a snapshot do not share a random source.
+    pub fn reseed(&mut self) {
+        self.rng = rand::SystemRandom::new();
+        if self.is_activated() {
+            self.process_entropy_queue();
+        }
+    }
+
     pub fn rate_limiter(&self) -> &RateLimiter {
         &self.rate_limiter
     }
 
+    /// Provides the counters of the requests served by this device.
+    pub fn stats(&self) -> EntropyDeviceStats {
+        self.stats
+    }
+
     pub(crate) fn set_avail_features(&mut self, features: u64) {
         self.avail_features = features;
     }
@@ -508,6 +539,22 @@ mod tests {
         assert_eq!(METRICS.entropy.host_rng_fails.count(), host_rng_fails);
     }
 
+    #[test]
+    fn test_reseed() {
+        // Nothing to notify before the device is activated.
+        default_entropy().reseed();
+
+        let mem = create_virtio_mem();
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, default_entropy());
+        th.activate_device(&mem);
+
+        // The buffer posted by the guest before the snapshot is filled on restore.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 16, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(METRICS.entropy.entropy_bytes, 16, th.device().reseed());
+        assert_eq!(th.device().stats().request_count, 1);
+        assert_eq!(th.device().interrupt_evt().read().unwrap(), 1);
+    }
+
     #[test]
     fn test_bad_rate_limiter_event() {
         let mem = create_virtio_mem();
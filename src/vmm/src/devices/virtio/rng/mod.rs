@@ -5,7 +5,7 @@ pub mod device;
 mod event_handler;
 pub mod persist;
 
-pub use self::device::{Entropy, Error};
+pub use self::device::{Entropy, EntropyDeviceStats, Error};
 
 pub(crate) const RNG_NUM_QUEUES: usize = 1;
 pub(crate) const RNG_QUEUE_SIZE: u16 = 256;
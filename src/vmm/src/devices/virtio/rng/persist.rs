@@ -5,6 +5,7 @@
 
 use rate_limiter::persist::RateLimiterState;
 use rate_limiter::RateLimiter;
+use serde::Serialize;
 use snapshot::Persist;
 use utils::vm_memory::GuestMemoryMmap;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -14,7 +15,7 @@ use crate::devices::virtio::persist::PersistError as VirtioStateError;
 use crate::devices::virtio::rng::{Entropy, Error as EntropyError, RNG_NUM_QUEUES, RNG_QUEUE_SIZE};
 use crate::devices::virtio::{VirtioDeviceState, TYPE_RNG};
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 pub struct EntropyState {
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
@@ -24,6 +24,7 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use logger::{debug, error, warn, IncMetric, METRICS};
+use serde::Serialize;
 use utils::byte_order;
 use utils::eventfd::EventFd;
 use utils::vm_memory::{Bytes, GuestMemoryMmap};
@@ -50,6 +51,19 @@ pub(crate) const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
 pub(crate) const AVAIL_FEATURES: u64 =
     1 << uapi::VIRTIO_F_VERSION_1 as u64 | 1 << uapi::VIRTIO_F_IN_ORDER as u64;
 
+/// Counters of the packets exchanged with the guest by a vsock device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VsockDeviceStats {
+    /// Number of payload bytes received by the guest.
+    pub rx_bytes: u64,
+    /// Number of packets received by the guest.
+    pub rx_packets: u64,
+    /// Number of payload bytes transmitted by the guest.
+    pub tx_bytes: u64,
+    /// Number of packets transmitted by the guest.
+    pub tx_packets: u64,
+}
+
 pub struct Vsock<B> {
     cid: u64,
     pub(crate) queues: Vec<VirtQueue>,
@@ -65,6 +79,7 @@ pub struct Vsock<B> {
     // continuous triggers from happening before the device gets activated.
     pub(crate) activate_evt: EventFd,
     pub(crate) device_state: DeviceState,
+    stats: VsockDeviceStats,
 }
 
 // TODO: Detect / handle queue deadlock:
@@ -92,6 +107,7 @@ where
             irq_trigger: IrqTrigger::new().map_err(VsockError::EventFd)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VsockError::EventFd)?,
             device_state: DeviceState::Inactive,
+            stats: VsockDeviceStats::default(),
         })
     }
 
@@ -116,6 +132,11 @@ where
         &self.backend
     }
 
+    /// Provides the counters of the packets exchanged with the guest.
+    pub fn stats(&self) -> VsockDeviceStats {
+        self.stats
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -143,7 +164,11 @@ where
                             // This addition cannot overflow, because packet length
                             // is previously validated against `MAX_PKT_BUF_SIZE`
                             // bound as part of `commit_hdr()`.
-                            Ok(()) => VSOCK_PKT_HDR_SIZE as u32 + pkt.len(),
+                            Ok(()) => {
+                                self.stats.rx_bytes += u64::from(pkt.len());
+                                self.stats.rx_packets += 1;
+                                VSOCK_PKT_HDR_SIZE as u32 + pkt.len()
+                            }
                             Err(err) => {
                                 warn!(
                                     "vsock: Error writing packet header to guest memory: \
@@ -207,6 +232,8 @@ where
                 self.queues[TXQ_INDEX].undo_pop();
                 break;
             }
+            self.stats.tx_bytes += u64::from(pkt.len());
+            self.stats.tx_packets += 1;
 
             have_used = true;
             self.queues[TXQ_INDEX]
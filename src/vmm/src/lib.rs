@@ -30,7 +30,15 @@ pub(crate) mod device_manager;
 /// Emulates virtual and hardware devices.
 #[allow(missing_docs)]
 pub mod devices;
+/// Stream of microVM lifecycle events.
+pub mod lifecycle;
+/// Background dumps of the guest memory.
+pub mod memory_dump;
 pub mod memory_snapshot;
+/// Pre-copy live migration between Firecracker processes.
+pub mod migration;
+/// Progress and cancellation of asynchronous API operations.
+pub mod operations;
 // pub mod pvm_memory;
 /// Save/restore utilities.
 pub mod persist;
@@ -42,6 +50,10 @@ pub mod rpc_interface;
 pub mod seccomp_filters;
 /// Signal handling utilities.
 pub mod signal_handler;
+/// Diff snapshot chains.
+pub mod snapshot_chain;
+/// Encryption of snapshot files.
+pub mod snapshot_crypto;
 /// Utility functions for integration and benchmark testing
 pub mod utilities;
 /// microVM state versions.
@@ -81,9 +93,13 @@ use crate::devices::virtio::{
     TYPE_BLOCK, TYPE_NET,
 };
 use crate::devices::BusDevice;
+use crate::lifecycle::LifecycleEvent;
+use crate::memory_dump::MemoryDump;
 use crate::memory_snapshot::SnapshotMemory;
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::snapshot_chain::ChainLink;
 use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::snapshot::MemoryDumpStatus;
 use crate::vstate::vcpu::VcpuState;
 pub use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuEvent, VcpuHandle, VcpuResponse};
 pub use crate::vstate::vm::Vm;
@@ -226,6 +242,9 @@ pub enum Error {
     /// Vm error.
     #[error("Vm error: {0}")]
     Vm(vstate::vm::Error),
+    /// VM generation ID device error.
+    #[error("VM generation ID device error: {0}")]
+    VmGenId(devices::pseudo::VmGenIdError),
     /// Error thrown by observer object on Vmm initialization.
     #[error("Error thrown by observer object on Vmm initialization: {0}")]
     VmmObserverInit(utils::errno::Error),
@@ -328,6 +347,12 @@ pub struct Vmm {
     // Since this field is never read again, we need to allow `dead_code`.
     #[allow(dead_code)]
     uffd: Option<Uffd>,
+    // Parent of the next diff snapshot.
+    last_snapshot: Option<ChainLink>,
+    // When the restore of the microVM from a snapshot started, if it was restored.
+    restore_start_us: Option<u64>,
+    // Memory dump started by the last background snapshot.
+    memory_dump: Option<MemoryDump>,
     vcpus_handles: Vec<VcpuHandle>,
     // Used by Vcpus and devices to initiate teardown; Vmm should never write here.
     vcpus_exit_evt: EventFd,
@@ -385,11 +410,22 @@ impl Vmm {
         self.instance_info.clone()
     }
 
+    /// Returns when the restore of the microVM from a snapshot started, on the monotonic clock,
+    /// in microseconds. None if the microVM was booted.
+    pub fn restore_start_us(&self) -> Option<u64> {
+        self.restore_start_us
+    }
+
     /// Provides the Vmm shutdown exit code if there is one.
     pub fn shutdown_exit_code(&self) -> Option<FcExitCode> {
         self.shutdown_exit_code
     }
 
+    fn set_state(&mut self, state: VmState) {
+        self.instance_info.state = state.clone();
+        lifecycle::emit(LifecycleEvent::StateChanged { state });
+    }
+
     /// Gets the specified bus device.
     pub fn get_bus_device(
         &self,
@@ -431,7 +467,7 @@ impl Vmm {
             self.vcpus_handles
                 .push(vcpu.start_threaded(vcpu_seccomp_filter.clone(), barrier.clone())?);
         }
-        self.instance_info.state = VmState::Paused;
+        self.set_state(VmState::Paused);
         // Wait for vCPUs to initialize their TLS before moving forward.
         barrier.wait();
 
@@ -458,7 +494,7 @@ impl Vmm {
             return Err(Error::VcpuMessage);
         }
 
-        self.instance_info.state = VmState::Running;
+        self.set_state(VmState::Running);
         Ok(())
     }
 
@@ -491,7 +527,7 @@ impl Vmm {
 
         // println!("Type of trans_addr is {:?}, {:?}", type_of::<decltype(trans_addr)>(), trans_addr as u64);
         warn!("Type of trans_addr is {:?}, {:?}", trans_addr, trans_addr as  u64);
-        self.instance_info.state = VmState::Paused;
+        self.set_state(VmState::Paused);
         Ok(())
     }
 
@@ -745,6 +781,18 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Returns the progress of the memory dump started by the last background snapshot.
+    /// The snapshot becomes the parent of the next diff snapshot once the dump is done.
+    pub fn memory_dump_status(
+        &mut self,
+    ) -> std::result::Result<MemoryDumpStatus, memory_dump::Error> {
+        let dump = self.memory_dump.as_mut().ok_or(memory_dump::Error::NoDump)?;
+        if let Some(link) = dump.poll()? {
+            self.last_snapshot = Some(link);
+        }
+        Ok(dump.status())
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> std::result::Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -1027,6 +1075,8 @@ impl MutEventSubscriber for Vmm {
                 }
             }
             self.stop(exit_code.unwrap_or(FcExitCode::Ok));
+        } else if Some(source) == lifecycle::listener_fd() && event_set == EventSet::IN {
+            lifecycle::accept_subscribers();
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
@@ -1036,5 +1086,10 @@ impl MutEventSubscriber for Vmm {
         if let Err(err) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", err);
         }
+        if let Some(events_fd) = lifecycle::listener_fd() {
+            if let Err(err) = ops.add(Events::new(&events_fd, EventSet::IN)) {
+                error!("Failed to register lifecycle events socket: {}", err);
+            }
+        }
     }
 }
@@ -0,0 +1,225 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stream of microVM lifecycle events.
+//!
+//! Clients connected to the events socket receive every event emitted after they were accepted,
+//! as one JSON object per line. Events can be emitted from any thread, but clients are only
+//! accepted by the VMM thread: by the event loop while the microVM runs, and before handling each
+//! API request otherwise. Clients that do not keep up with the stream are disconnected.
+
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Mutex;
+
+use logger::{error, warn};
+use serde::Serialize;
+
+use crate::persist::RestoreTimings;
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::snapshot::SnapshotType;
+
+/// Event in the lifecycle of the microVM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// The microVM changed state.
+    StateChanged {
+        /// New state of the microVM.
+        state: VmState,
+    },
+    /// A snapshot of the microVM was created.
+    SnapshotCreated {
+        /// Type of the snapshot.
+        snapshot_type: SnapshotType,
+        /// Time taken to create the snapshot, in microseconds.
+        duration_us: u64,
+    },
+    /// The microVM was restored from a snapshot.
+    SnapshotLoaded {
+        /// Time spent in each phase of the load.
+        timings: RestoreTimings,
+    },
+    /// A vCPU stopped running the guest.
+    VcpuExited {
+        /// Index of the vCPU.
+        vcpu: u8,
+        /// Why the vCPU stopped.
+        reason: String,
+    },
+    /// The balloon device reached its target size.
+    BalloonTargetReached {
+        /// Target size of the balloon, in MiB.
+        target_mib: u32,
+    },
+    /// The VMM is shutting down.
+    Shutdown {
+        /// Exit code of the Firecracker process.
+        exit_code: i32,
+    },
+}
+
+#[derive(Serialize)]
+struct TimestampedEvent<'a> {
+    timestamp_us: u64,
+    #[serde(flatten)]
+    event: &'a LifecycleEvent,
+}
+
+/// Sends lifecycle events to the clients connected to a Unix socket.
+#[derive(Debug)]
+pub struct EventStream {
+    listener: Option<UnixListener>,
+    subscribers: Vec<UnixStream>,
+}
+
+impl EventStream {
+    /// Creates a stream without subscribers nor socket.
+    pub const fn new() -> Self {
+        EventStream {
+            listener: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Starts listening for subscribers on the Unix socket at `path`.
+    pub fn bind(&mut self, path: &Path) -> io::Result<()> {
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        self.listener = Some(listener);
+        Ok(())
+    }
+
+    /// Returns the file descriptor of the listening socket, if there is one.
+    pub fn listener_fd(&self) -> Option<RawFd> {
+        self.listener.as_ref().map(|listener| listener.as_raw_fd())
+    }
+
+    /// Accepts the subscribers waiting on the listening socket.
+    pub fn accept(&mut self) {
+        let listener = match self.listener.as_ref() {
+            Some(listener) => listener,
+            None => return,
+        };
+        loop {
+            match listener.accept() {
+                Ok((subscriber, _)) => match subscriber.set_nonblocking(true) {
+                    Ok(()) => self.subscribers.push(subscriber),
+                    Err(err) => error!("Failed to set up an events subscriber: {}", err),
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Failed to accept an events subscriber: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sends `event` to all the subscribers.
+    pub fn emit(&mut self, event: &LifecycleEvent) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let timestamped = TimestampedEvent {
+            timestamp_us: utils::time::get_time_us(utils::time::ClockType::Real),
+            event,
+        };
+        let mut line = match serde_json::to_vec(&timestamped) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Failed to encode lifecycle event {:?}: {}", event, err);
+                return;
+            }
+        };
+        line.push(b'\n');
+        // A partial write would corrupt the stream, so the subscriber is dropped.
+        self.subscribers.retain(|mut subscriber| {
+            subscriber
+                .write_all(&line)
+                .map_err(|err| warn!("Disconnecting events subscriber: {}", err))
+                .is_ok()
+        });
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static EVENT_STREAM: Mutex<EventStream> = Mutex::new(EventStream::new());
+
+/// Starts listening for lifecycle events subscribers on the Unix socket at `path`.
+pub fn bind(path: &Path) -> io::Result<()> {
+    EVENT_STREAM.lock().expect("Poisoned lock").bind(path)
+}
+
+/// Returns the file descriptor of the events socket, if there is one.
+pub fn listener_fd() -> Option<RawFd> {
+    EVENT_STREAM.lock().expect("Poisoned lock").listener_fd()
+}
+
+/// Accepts the lifecycle events subscribers waiting on the events socket. Must only be called
+/// from the VMM thread, whose seccomp filter allows accepting connections.
+pub fn accept_subscribers() {
+    EVENT_STREAM.lock().expect("Poisoned lock").accept();
+}
+
+/// Sends `event` to all the lifecycle events subscribers.
+pub fn emit(event: LifecycleEvent) {
+    EVENT_STREAM.lock().expect("Poisoned lock").emit(&event);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_event_stream() {
+        let mut socket = TempFile::new().unwrap();
+        socket.remove().unwrap();
+        let mut stream = EventStream::new();
+        assert!(stream.listener_fd().is_none());
+        // Without subscribers, events go nowhere.
+        stream.emit(&LifecycleEvent::Shutdown { exit_code: 0 });
+        stream.accept();
+
+        stream.bind(socket.as_path()).unwrap();
+        assert!(stream.listener_fd().is_some());
+        let subscriber = UnixStream::connect(socket.as_path()).unwrap();
+        let late_subscriber = UnixStream::connect(socket.as_path()).unwrap();
+        stream.accept();
+        assert_eq!(stream.subscribers.len(), 2);
+
+        // Disconnected subscribers are dropped.
+        drop(late_subscriber);
+        stream.emit(&LifecycleEvent::StateChanged {
+            state: VmState::Running,
+        });
+        stream.emit(&LifecycleEvent::VcpuExited {
+            vcpu: 1,
+            reason: "KVM_EXIT_SHUTDOWN".to_string(),
+        });
+        assert_eq!(stream.subscribers.len(), 1);
+
+        let mut lines = BufReader::new(subscriber).lines();
+        let event: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(event["event"], "state_changed");
+        assert_eq!(event["state"], "Running");
+        assert!(event["timestamp_us"].as_u64().unwrap() > 0);
+        let event: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(event["event"], "vcpu_exited");
+        assert_eq!(event["vcpu"], 1);
+        assert_eq!(event["reason"], "KVM_EXIT_SHUTDOWN");
+    }
+}
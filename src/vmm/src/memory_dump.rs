@@ -0,0 +1,312 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dumps the guest memory in the background while the microVM runs.
+//!
+//! Once the microVM state is saved, Firecracker forks. The child shares the guest memory with
+//! the microVM copy-on-write, so it keeps seeing the memory as it was at the time of the fork
+//! while the microVM resumes: the kernel copies a page out before the guest writes to it. The
+//! child writes the memory file, records its metadata and reports the number of bytes written
+//! to the parent over a pipe.
+//!
+//! The child runs with the seccomp filter of the VMM thread and only uses the syscalls of a
+//! regular full snapshot, so it must not touch the state shared with the rest of the process
+//! (the logger, the metrics or the devices).
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use logger::error;
+use utils::vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use crate::snapshot_chain::{self, ChainLink, ContentHasher, HashingWriter, SnapshotMetadata};
+use crate::vmm_config::snapshot::{MemoryDumpState, MemoryDumpStatus, SnapshotType};
+
+/// Size of the writes issued by the dumping process, and granularity of its progress reports.
+const CHUNK_SIZE: usize = 2 << 20;
+/// Exit code of the dumping process when writing the memory file fails.
+const EXIT_WRITE: i32 = 1;
+/// Exit code of the dumping process when recording the metadata of the memory file fails.
+const EXIT_METADATA: i32 = 2;
+
+/// Errors associated with background memory dumps.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to fork the dumping process.
+    #[error("Cannot fork the memory dump process: {0}")]
+    Fork(io::Error),
+    /// The previous background memory dump is not done yet.
+    #[error("A background memory dump is already in progress")]
+    InProgress,
+    /// No background memory dump was started.
+    #[error("No background memory dump was started")]
+    NoDump,
+    /// Failed to create the progress pipe.
+    #[error("Cannot create the memory dump progress pipe: {0}")]
+    Pipe(io::Error),
+    /// The snapshot options cannot be used with a background memory dump.
+    #[error("Background memory dumps are not supported with {0}")]
+    Unsupported(&'static str),
+    /// Failed to wait for the dumping process.
+    #[error("Cannot wait for the memory dump process: {0}")]
+    Wait(io::Error),
+}
+
+/// A memory dump running in a child process.
+#[derive(Debug)]
+pub struct MemoryDump {
+    pid: libc::pid_t,
+    progress: File,
+    mem_file_path: PathBuf,
+    chain_metadata: bool,
+    state: MemoryDumpState,
+    bytes_written: u64,
+    total_bytes: u64,
+}
+
+/// Forks a process writing `guest_memory` to `file`, which must be empty, and recording the
+/// metadata of `mem_file_path` once done if `chain_metadata` is set. The memory is saved as a
+/// full snapshot.
+pub fn start(
+    guest_memory: &GuestMemoryMmap,
+    file: File,
+    mem_file_path: &Path,
+    chain_metadata: bool,
+) -> Result<MemoryDump, Error> {
+    // Everything the child needs is prepared before forking.
+    let regions: Vec<(usize, usize)> = guest_memory
+        .iter()
+        .map(|region| (region.as_ptr() as usize, region.len() as usize))
+        .collect();
+    let total_bytes = regions.iter().map(|(_, len)| *len as u64).sum();
+
+    let mut fds = [0; 2];
+    // SAFETY: Safe because `fds` has room for the two descriptors of the pipe.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+        return Err(Error::Pipe(io::Error::last_os_error()));
+    }
+    // SAFETY: Safe because both descriptors were just created and nothing else owns them.
+    let (progress, progress_writer) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: Safe because the child only writes the memory file and exits without returning.
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::Fork(io::Error::last_os_error())),
+        0 => run_child(
+            &regions,
+            file,
+            chain_metadata.then_some(mem_file_path),
+            progress_writer.as_raw_fd(),
+        ),
+        pid => Ok(MemoryDump {
+            pid,
+            progress,
+            mem_file_path: mem_file_path.to_path_buf(),
+            chain_metadata,
+            state: MemoryDumpState::InProgress,
+            bytes_written: 0,
+            total_bytes,
+        }),
+    }
+}
+
+// Body of the dumping process. The metadata of `mem_file_path` is recorded when it is set.
+fn run_child(
+    regions: &[(usize, usize)],
+    file: File,
+    mem_file_path: Option<&Path>,
+    progress_fd: RawFd,
+) -> ! {
+    // The memory is hashed as it is written, rather than read back from the file once written.
+    let hasher = mem_file_path.map(|_| ContentHasher::new(None));
+    let mut writer = HashingWriter::new(file, hasher);
+    let code = match write_memory(regions, &mut writer, progress_fd) {
+        Ok(()) => match mem_file_path.zip(writer.into_parts().1) {
+            Some((path, hasher)) => {
+                match snapshot_chain::record_snapshot(
+                    path,
+                    SnapshotType::Full,
+                    None,
+                    hasher.finish(),
+                ) {
+                    Ok(_) => 0,
+                    Err(_) => EXIT_METADATA,
+                }
+            }
+            None => 0,
+        },
+        Err(_) => EXIT_WRITE,
+    };
+    // SAFETY: Safe because `_exit` terminates the child without running the destructors of the
+    // state it shares with the microVM.
+    unsafe { libc::_exit(code) }
+}
+
+fn write_memory(
+    regions: &[(usize, usize)],
+    writer: &mut HashingWriter<File>,
+    progress_fd: RawFd,
+) -> io::Result<()> {
+    let mut bytes_written = 0u64;
+    for &(addr, len) in regions {
+        let mut offset = 0;
+        while offset < len {
+            let chunk_len = CHUNK_SIZE.min(len - offset);
+            // SAFETY: Safe because the range lies within a guest memory region mapped in this
+            // process, and nothing else runs in it.
+            let chunk =
+                unsafe { std::slice::from_raw_parts((addr + offset) as *const u8, chunk_len) };
+            writer.write_all(chunk)?;
+            offset += chunk_len;
+            bytes_written += chunk_len as u64;
+
+            let report = bytes_written.to_le_bytes();
+            // SAFETY: Safe because `report` is valid for its length. The pipe is non-blocking
+            // and writes of this size are atomic, so a report is either sent whole or dropped
+            // when the parent lags behind.
+            unsafe { libc::write(progress_fd, report.as_ptr().cast(), report.len()) };
+        }
+    }
+    writer.get_ref().sync_all()
+}
+
+impl MemoryDump {
+    /// Collects the progress reported by the dumping process and reaps it once it exits.
+    /// Returns the link to the memory file when this call observes the dump completing.
+    pub fn poll(&mut self) -> Result<Option<ChainLink>, Error> {
+        if self.state != MemoryDumpState::InProgress {
+            return Ok(None);
+        }
+        self.read_progress();
+
+        let mut status = 0;
+        // SAFETY: Safe because `status` is a valid location and `pid` is a child of ours that
+        // was not reaped yet.
+        match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } {
+            -1 => return Err(Error::Wait(io::Error::last_os_error())),
+            0 => return Ok(None),
+            _ => (),
+        }
+
+        if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+            error!(
+                "Background memory dump to {:?} failed with wait status {}",
+                self.mem_file_path, status
+            );
+            self.state = MemoryDumpState::Failed;
+            return Ok(None);
+        }
+        if !self.chain_metadata {
+            self.state = MemoryDumpState::Done;
+            self.bytes_written = self.total_bytes;
+            return Ok(None);
+        }
+        match SnapshotMetadata::load(&self.mem_file_path) {
+            Ok(metadata) => {
+                self.state = MemoryDumpState::Done;
+                self.bytes_written = self.total_bytes;
+                Ok(Some(ChainLink {
+                    id: metadata.id,
+                    mem_file_path: self.mem_file_path.clone(),
+                }))
+            }
+            Err(err) => {
+                error!("Background memory dump: {}", err);
+                self.state = MemoryDumpState::Failed;
+                Ok(None)
+            }
+        }
+    }
+
+    // Drains the progress pipe, keeping the latest report.
+    fn read_progress(&mut self) {
+        let mut buf = [0u8; 512];
+        loop {
+            match self.progress.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => {
+                    let end = len - len % 8;
+                    if end > 0 {
+                        let mut report = [0u8; 8];
+                        report.copy_from_slice(&buf[end - 8..end]);
+                        self.bytes_written = u64::from_le_bytes(report);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the progress observed by the last call to `poll`.
+    pub fn status(&self) -> MemoryDumpStatus {
+        MemoryDumpStatus {
+            state: self.state,
+            mem_file_path: self.mem_file_path.clone(),
+            bytes_written: self.bytes_written,
+            total_bytes: self.total_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempfile::TempFile;
+    use utils::vm_memory::{Bytes, GuestAddress};
+
+    use super::*;
+
+    #[test]
+    fn test_background_dump() {
+        let page_size = utils::get_page_size().unwrap();
+        let guest_memory = utils::vm_memory::test_utils::create_anon_guest_memory(
+            &[
+                (GuestAddress(0), 2 * page_size),
+                (GuestAddress(4 * page_size as u64), page_size),
+            ],
+            false,
+        )
+        .unwrap();
+        guest_memory
+            .write_slice(&vec![1u8; page_size], GuestAddress(page_size as u64))
+            .unwrap();
+
+        let mem_file = TempFile::new().unwrap();
+        let file = mem_file.as_file().try_clone().unwrap();
+        let mut dump = start(&guest_memory, file, mem_file.as_path(), true).unwrap();
+        // Writes done after the fork do not reach the memory file.
+        guest_memory
+            .write_slice(&vec![2u8; page_size], GuestAddress(page_size as u64))
+            .unwrap();
+
+        let link = loop {
+            if let Some(link) = dump.poll().unwrap() {
+                break link;
+            }
+            assert_eq!(dump.status().state, MemoryDumpState::InProgress);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        let status = dump.status();
+        assert_eq!(status.state, MemoryDumpState::Done);
+        assert_eq!(status.bytes_written, 3 * page_size as u64);
+        assert_eq!(status.total_bytes, 3 * page_size as u64);
+        assert_eq!(link.mem_file_path, mem_file.as_path());
+        let mut file = File::open(mem_file.as_path()).unwrap();
+        assert_eq!(
+            link.id,
+            snapshot_chain::content_id(&mut file, None).unwrap()
+        );
+        assert!(dump.poll().unwrap().is_none());
+
+        let contents = fs::read(mem_file.as_path()).unwrap();
+        assert_eq!(contents.len(), 3 * page_size);
+        assert!(contents[..page_size].iter().all(|&byte| byte == 0));
+        assert!(contents[page_size..2 * page_size]
+            .iter()
+            .all(|&byte| byte == 1));
+
+        fs::remove_file(snapshot_chain::metadata_path(mem_file.as_path())).unwrap();
+    }
+}
@@ -6,6 +6,7 @@ use logger::{error, info, warn};
 use std::fs::File;
 use std::io::SeekFrom;
 
+use serde::Serialize;
 use utils::vm_memory::{
     Bitmap, Bytes, FileOffset, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
     GuestMemoryRegion, MemoryRegionAddress,
@@ -17,7 +18,7 @@ use versionize_derive::Versionize;
 use crate::DirtyBitmap;
 
 /// State of a guest memory region saved to file/buffer.
-#[derive(Debug, PartialEq, Eq, Versionize)]
+#[derive(Debug, PartialEq, Eq, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct GuestMemoryRegionState {
     // This should have been named `base_guest_addr` since it's _guest_ addr, but for
@@ -31,7 +32,7 @@ pub struct GuestMemoryRegionState {
 }
 
 /// Describes guest memory regions and their snapshot file mappings.
-#[derive(Debug, Default, PartialEq, Eq, Versionize)]
+#[derive(Debug, Default, PartialEq, Eq, Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct GuestMemoryState {
     /// List of regions.
@@ -0,0 +1,635 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pre-copy live migration of a microVM between two Firecracker processes.
+//!
+//! The sending side streams the whole guest memory over a Unix stream socket while the vCPUs
+//! keep running, then sends the pages dirtied meanwhile in successive rounds until a round is
+//! small enough or the maximum number of rounds is reached. It then pauses the microVM, sends the
+//! last dirty pages followed by the microVM state and waits for the receiving side to acknowledge
+//! it restored the microVM. If anything fails before that, the microVM is resumed on the sending
+//! side.
+//!
+//! The stream starts with the `FCMIGR01` magic and the layout of the guest memory regions (a u32
+//! count followed by the u64 base address and size of each region). Then come messages, each one
+//! starting with a one byte tag: a page run (u64 guest address, u64 length and the page bytes) or
+//! the final microVM state (u64 length and the state serialized as in a snapshot file). All
+//! integers are little endian. The receiving side answers with a single status byte.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use logger::{error, info};
+use seccompiler::BpfThreadMap;
+use snapshot::Snapshot;
+use utils::get_page_size;
+use utils::time::{get_time_us, ClockType};
+use utils::vm_memory::{
+    Bitmap, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, GuestMemoryRegion,
+    GuestRegionMmap, MemoryRegionAddress,
+};
+use versionize::VersionMap;
+
+use crate::builder::{self, BuildMicrovmFromSnapshotError};
+use crate::memory_snapshot::{self, GuestMemoryRegionState, GuestMemoryState, SnapshotMemory};
+use crate::persist::{
+    snapshot_state_sanity_check, MicrovmState, MicrovmStateError, RestoreTimings,
+    SnapShotStateSanityCheckError, VmInfo,
+};
+use crate::resources::VmResources;
+use crate::vmm_config::instance_info::InstanceInfo;
+use crate::vmm_config::migration::{MigrationReceiveParams, MigrationSendParams, MigrationStats};
+use crate::vmm_config::snapshot::DeviceOverrides;
+use crate::{DirtyBitmap, EventManager, Vmm};
+
+const MAGIC: &[u8; 8] = b"FCMIGR01";
+const MSG_PAGES: u8 = 1;
+const MSG_STATE: u8 = 2;
+const ACK_OK: u8 = 0;
+const ACK_ERROR: u8 = 1;
+/// Upper bound on the size of the serialized microVM state accepted from the stream.
+const MAX_STATE_LEN: u64 = 64 << 20;
+
+/// Errors associated with live migration.
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    /// The stream does not start with the migration magic.
+    #[error("The peer is not a Firecracker migration stream")]
+    BadMagic,
+    /// Failed to build the received microVM.
+    #[error("Failed to build the received microVM: {0}")]
+    Build(#[from] BuildMicrovmFromSnapshotError),
+    /// Failed to read or write the migration stream.
+    #[error("Migration stream error: {0}")]
+    Io(#[from] io::Error),
+    /// The memory layout in the microVM state differs from the streamed one.
+    #[error("The microVM state does not match the streamed memory layout")]
+    MemoryLayout,
+    /// Failed to create or describe the guest memory.
+    #[error("Guest memory error: {0}")]
+    Memory(#[from] memory_snapshot::Error),
+    /// The file descriptor received for the migration is not a socket.
+    #[error("The migration file descriptor is not a socket")]
+    NotASocket,
+    /// Failed to read guest memory.
+    #[error("Failed to read guest memory: {0}")]
+    ReadMemory(GuestMemoryError),
+    /// The migration can only be received before the microVM is configured.
+    #[error("Receiving a migration is not allowed after configuring boot-specific resources.")]
+    ReceiveNotAllowed,
+    /// The receiving side failed to restore the microVM.
+    #[error("The receiving Firecracker failed to restore the microVM")]
+    Rejected,
+    /// The received microVM state failed the sanity checks.
+    #[error("Invalid microVM state: {0}")]
+    SanityCheck(#[from] SnapShotStateSanityCheckError),
+    /// Failed to save the microVM state.
+    #[error("Failed to save the microVM state: {0}")]
+    SaveState(#[from] MicrovmStateError),
+    /// Failed to serialize or deserialize the microVM state.
+    #[error("Failed to (de)serialize the microVM state: {0}")]
+    Snapshot(#[from] snapshot::Error),
+    /// Failed to open the migration socket.
+    #[error("Failed to open the migration socket: {0}")]
+    Socket(io::Error),
+    /// Exactly one of the socket path and descriptor must be given.
+    #[error("Exactly one of `socket_path` and `socket_fd` must be specified")]
+    SocketSource,
+    /// The microVM state announced by the peer is too large.
+    #[error("The microVM state is too large: {0} bytes")]
+    StateTooLarge(u64),
+    /// The peer sent an unknown message.
+    #[error("Unexpected migration message: {0:#x}")]
+    UnexpectedMessage(u8),
+    /// Failed to operate on the microVM.
+    #[error("{0}")]
+    Vmm(#[from] crate::Error),
+    /// Failed to write guest memory.
+    #[error("Failed to write guest memory: {0}")]
+    WriteMemory(GuestMemoryError),
+}
+
+type Result<T> = std::result::Result<T, MigrationError>;
+
+/// Duplicates the stream socket passed over the API socket.
+fn stream_from_file(file: &File) -> Result<UnixStream> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `fstat` only writes to the buffer, and we check its return value.
+    if unsafe { libc::fstat(file.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+        return Err(MigrationError::Socket(io::Error::last_os_error()));
+    }
+    // SAFETY: `fstat` succeeded so the buffer is initialized.
+    let stat = unsafe { stat.assume_init() };
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(MigrationError::NotASocket);
+    }
+    let file = file.try_clone().map_err(MigrationError::Socket)?;
+    Ok(UnixStream::from(OwnedFd::from(file)))
+}
+
+fn connect(socket_path: &Option<PathBuf>, socket: Option<&File>) -> Result<UnixStream> {
+    match (socket_path, socket) {
+        (Some(path), None) => UnixStream::connect(path).map_err(MigrationError::Socket),
+        (None, Some(file)) => stream_from_file(file),
+        _ => Err(MigrationError::SocketSource),
+    }
+}
+
+fn accept(socket_path: &Option<PathBuf>, socket: Option<&File>) -> Result<UnixStream> {
+    match (socket_path, socket) {
+        (Some(path), None) => {
+            let listener = UnixListener::bind(path).map_err(MigrationError::Socket)?;
+            info!("Waiting for an incoming migration on {:?}", path);
+            let accepted = listener.accept().map(|(stream, _)| stream);
+            // Only one migration is received, the socket is not needed any more.
+            if let Err(err) = std::fs::remove_file(path) {
+                error!("Failed to remove the migration socket {:?}: {}", path, err);
+            }
+            accepted.map_err(MigrationError::Socket)
+        }
+        (None, Some(file)) => stream_from_file(file),
+        _ => Err(MigrationError::SocketSource),
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Writes the magic and the layout of the guest memory regions.
+fn write_header<W: Write>(writer: &mut W, guest_memory: &GuestMemoryMmap) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    // There are never more than a few memory regions.
+    writer.write_all(&(guest_memory.num_regions() as u32).to_le_bytes())?;
+    for region in guest_memory.iter() {
+        writer.write_all(&region.start_addr().0.to_le_bytes())?;
+        writer.write_all(&region.len().to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the magic and the guest memory layout, described as `SnapshotMemory::describe` would.
+fn read_header<R: Read>(reader: &mut R) -> Result<GuestMemoryState> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(MigrationError::BadMagic);
+    }
+    let mut mem_state = GuestMemoryState::default();
+    let mut offset = 0;
+    for _ in 0..read_u32(reader)? {
+        let base_address = read_u64(reader)?;
+        let size = read_u64(reader)?;
+        mem_state.regions.push(GuestMemoryRegionState {
+            base_address,
+            size: usize::try_from(size).map_err(|_| MigrationError::MemoryLayout)?,
+            offset,
+        });
+        offset = offset
+            .checked_add(size)
+            .ok_or(MigrationError::MemoryLayout)?;
+    }
+    Ok(mem_state)
+}
+
+fn write_pages<W: Write>(
+    writer: &mut W,
+    region: &GuestRegionMmap,
+    offset: usize,
+    len: usize,
+) -> Result<()> {
+    writer.write_all(&[MSG_PAGES])?;
+    writer.write_all(&(region.start_addr().0 + offset as u64).to_le_bytes())?;
+    writer.write_all(&(len as u64).to_le_bytes())?;
+    region
+        .write_all_to(MemoryRegionAddress(offset as u64), writer, len)
+        .map_err(MigrationError::ReadMemory)
+}
+
+/// Sends the whole guest memory, returning the number of pages sent.
+fn send_memory<W: Write>(writer: &mut W, guest_memory: &GuestMemoryMmap) -> Result<u64> {
+    let page_size = get_page_size().map_err(memory_snapshot::Error::PageSize)?;
+    let mut pages = 0;
+    for region in guest_memory.iter() {
+        write_pages(writer, region, 0, region.len() as usize)?;
+        pages += region.len() / page_size as u64;
+    }
+    Ok(pages)
+}
+
+/// Sends the pages present in `dirty_bitmap` or in the Firecracker bitmap, which is reset, and
+/// returns the number of pages sent.
+fn send_dirty_pages<W: Write>(
+    writer: &mut W,
+    guest_memory: &GuestMemoryMmap,
+    dirty_bitmap: &DirtyBitmap,
+) -> Result<u64> {
+    let page_size = get_page_size().map_err(memory_snapshot::Error::PageSize)?;
+    let mut pages = 0;
+    for (slot, region) in guest_memory.iter().enumerate() {
+        let kvm_bitmap = dirty_bitmap.get(&slot).map_or(&[][..], Vec::as_slice);
+        let firecracker_bitmap = region.bitmap();
+        // Start offset and length of the current run of dirty pages.
+        let mut run: Option<(usize, usize)> = None;
+        for page in 0..region.len() as usize / page_size {
+            let page_offset = page * page_size;
+            let is_kvm_page_dirty = kvm_bitmap
+                .get(page / 64)
+                .map_or(false, |word| (word >> (page % 64)) & 1 != 0);
+            if is_kvm_page_dirty || firecracker_bitmap.dirty_at(page_offset) {
+                pages += 1;
+                match run.as_mut() {
+                    Some((_, len)) => *len += page_size,
+                    None => run = Some((page_offset, page_size)),
+                }
+            } else if let Some((start, len)) = run.take() {
+                write_pages(writer, region, start, len)?;
+            }
+        }
+        if let Some((start, len)) = run {
+            write_pages(writer, region, start, len)?;
+        }
+        if let Some(bitmap) = firecracker_bitmap {
+            bitmap.reset();
+        }
+    }
+    Ok(pages)
+}
+
+fn write_state<W: Write>(writer: &mut W, state: &[u8]) -> io::Result<()> {
+    writer.write_all(&[MSG_STATE])?;
+    writer.write_all(&(state.len() as u64).to_le_bytes())?;
+    writer.write_all(state)
+}
+
+/// Writes the received pages to `guest_memory` until the microVM state arrives, and returns it.
+fn receive_memory<R: Read>(reader: &mut R, guest_memory: &GuestMemoryMmap) -> Result<Vec<u8>> {
+    loop {
+        match read_u8(reader)? {
+            MSG_PAGES => {
+                let addr = read_u64(reader)?;
+                let len = read_u64(reader)?;
+                let len = usize::try_from(len).map_err(|_| MigrationError::MemoryLayout)?;
+                guest_memory
+                    .read_exact_from(GuestAddress(addr), reader, len)
+                    .map_err(MigrationError::WriteMemory)?;
+            }
+            MSG_STATE => {
+                let len = read_u64(reader)?;
+                if len > MAX_STATE_LEN {
+                    return Err(MigrationError::StateTooLarge(len));
+                }
+                let mut state = vec![0u8; len as usize];
+                reader.read_exact(&mut state)?;
+                return Ok(state);
+            }
+            tag => return Err(MigrationError::UnexpectedMessage(tag)),
+        }
+    }
+}
+
+fn read_ack<R: Read>(reader: &mut R) -> Result<()> {
+    match read_u8(reader)? {
+        ACK_OK => Ok(()),
+        _ => Err(MigrationError::Rejected),
+    }
+}
+
+/// Sends the last dirty pages and the state of the paused microVM, then waits for the target.
+fn stop_and_copy(
+    vmm: &mut Vmm,
+    vm_info: &VmInfo,
+    stream: &UnixStream,
+    version_map: VersionMap,
+    stats: &mut MigrationStats,
+) -> Result<()> {
+    let mut writer = BufWriter::new(stream);
+    let dirty_bitmap = vmm.get_dirty_bitmap()?;
+    stats.stop_copy_pages = send_dirty_pages(&mut writer, vmm.guest_memory(), &dirty_bitmap)?;
+    stats.pages_sent += stats.stop_copy_pages;
+
+    let microvm_state = vmm.save_state(vm_info)?;
+    let mut state = Vec::new();
+    let latest_version = version_map.latest_version();
+    Snapshot::new(version_map, latest_version).save(&mut state, &microvm_state)?;
+    write_state(&mut writer, &state)?;
+    writer.flush()?;
+    drop(writer);
+
+    read_ack(&mut &*stream)
+}
+
+/// Migrates the running microVM to the Firecracker listening on the configured socket.
+///
+/// KVM dirty page tracking must be enabled. On success the microVM is left paused and the
+/// process should be shut down: the receiving Firecracker now owns the guest.
+pub fn send_migration(
+    vmm: &mut Vmm,
+    vm_info: &VmInfo,
+    params: &MigrationSendParams,
+    version_map: VersionMap,
+) -> Result<MigrationStats> {
+    let start_us = get_time_us(ClockType::Monotonic);
+    let mut stats = MigrationStats::default();
+    let stream = connect(&params.socket_path, params.socket.0.as_ref())?;
+
+    {
+        let mut writer = BufWriter::new(&stream);
+        write_header(&mut writer, vmm.guest_memory())?;
+        // Start from clean bitmaps so that the first round only holds pages dirtied during
+        // the full copy.
+        vmm.get_dirty_bitmap()?;
+        for region in vmm.guest_memory().iter() {
+            if let Some(bitmap) = region.bitmap() {
+                bitmap.reset();
+            }
+        }
+        stats.pages_sent = send_memory(&mut writer, vmm.guest_memory())?;
+
+        while stats.rounds < params.max_rounds {
+            let dirty_bitmap = vmm.get_dirty_bitmap()?;
+            let pages = send_dirty_pages(&mut writer, vmm.guest_memory(), &dirty_bitmap)?;
+            stats.rounds += 1;
+            stats.pages_sent += pages;
+            info!(
+                "Migration pre-copy round {} sent {} pages",
+                stats.rounds, pages
+            );
+            if pages <= params.dirty_pages_threshold {
+                break;
+            }
+        }
+        writer.flush()?;
+    }
+
+    let pause_start_us = get_time_us(ClockType::Monotonic);
+    vmm.pause_vm()?;
+    if let Err(err) = stop_and_copy(vmm, vm_info, &stream, version_map, &mut stats) {
+        // The target did not take over the guest, so it keeps running here.
+        if let Err(resume_err) = vmm.resume_vm() {
+            error!(
+                "Failed to resume the microVM after migration failure: {}",
+                resume_err
+            );
+        }
+        return Err(err);
+    }
+
+    let end_us = get_time_us(ClockType::Monotonic);
+    stats.downtime_us = end_us - pause_start_us;
+    stats.total_us = end_us - start_us;
+    Ok(stats)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_received_state(
+    instance_info: &InstanceInfo,
+    event_manager: &mut EventManager,
+    seccomp_filters: &BpfThreadMap,
+    state: &[u8],
+    mem_state: &GuestMemoryState,
+    guest_memory: GuestMemoryMmap,
+    track_dirty_pages: bool,
+    version_map: VersionMap,
+    vm_resources: &mut VmResources,
+) -> Result<Arc<Mutex<Vmm>>> {
+    let microvm_state: MicrovmState = Snapshot::load(&mut &state[..], state.len(), version_map)?;
+    snapshot_state_sanity_check(&microvm_state)?;
+    if &microvm_state.memory_state != mem_state {
+        return Err(MigrationError::MemoryLayout);
+    }
+    Ok(builder::build_microvm_from_snapshot(
+        instance_info,
+        event_manager,
+        microvm_state,
+        guest_memory,
+        None,
+        track_dirty_pages,
+        seccomp_filters,
+        vm_resources,
+        &DeviceOverrides::default(),
+        &mut RestoreTimings::default(),
+    )?)
+}
+
+/// Receives a microVM migrated by another Firecracker, producing a 'paused' microVM.
+pub fn receive_migration(
+    instance_info: &InstanceInfo,
+    event_manager: &mut EventManager,
+    seccomp_filters: &BpfThreadMap,
+    params: &MigrationReceiveParams,
+    version_map: VersionMap,
+    vm_resources: &mut VmResources,
+) -> Result<Arc<Mutex<Vmm>>> {
+    let stream = accept(&params.socket_path, params.socket.0.as_ref())?;
+    let mut reader = BufReader::new(&stream);
+    let mem_state = read_header(&mut reader)?;
+    let guest_memory = GuestMemoryMmap::restore(None, &mem_state, params.enable_diff_snapshots)?;
+    let state = receive_memory(&mut reader, &guest_memory)?;
+
+    let result = restore_received_state(
+        instance_info,
+        event_manager,
+        seccomp_filters,
+        &state,
+        &mem_state,
+        guest_memory,
+        params.enable_diff_snapshots,
+        version_map,
+        vm_resources,
+    );
+    // The sender resumes the microVM on its side unless it reads a successful status.
+    let status = if result.is_ok() { ACK_OK } else { ACK_ERROR };
+    (&stream).write_all(&[status])?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use utils::tempfile::TempFile;
+    use utils::vm_memory::create_guest_memory;
+
+    use super::*;
+
+    fn create_memory(page_size: usize, track_dirty_pages: bool) -> GuestMemoryMmap {
+        // Two regions of four and two pages, with a gap between them.
+        let regions = [
+            (None, GuestAddress(0), page_size * 4),
+            (None, GuestAddress(page_size as u64 * 8), page_size * 2),
+        ];
+        create_guest_memory(&regions[..], track_dirty_pages).unwrap()
+    }
+
+    fn read_memory(guest_memory: &GuestMemoryMmap) -> Vec<u8> {
+        let mut contents = Vec::new();
+        for region in guest_memory.iter() {
+            let mut buf = vec![0u8; region.len() as usize];
+            guest_memory
+                .read_slice(&mut buf, region.start_addr())
+                .unwrap();
+            contents.extend(buf);
+        }
+        contents
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let page_size = get_page_size().unwrap();
+        let source = create_memory(page_size, true);
+        for (idx, region) in source.iter().enumerate() {
+            let pattern = vec![idx as u8 + 1; region.len() as usize];
+            source.write_slice(&pattern, region.start_addr()).unwrap();
+        }
+
+        let mut stream = Vec::new();
+        write_header(&mut stream, &source).unwrap();
+        assert_eq!(send_memory(&mut stream, &source).unwrap(), 6);
+
+        // A pre-copy round: the guest dirtied the fourth page of the first region (as seen by
+        // KVM) while a device wrote to the first page of the second one.
+        for region in source.iter() {
+            region.bitmap().as_ref().unwrap().reset();
+        }
+        let guest_write = vec![0xaa; page_size];
+        source
+            .write_slice(&guest_write, GuestAddress(page_size as u64 * 3))
+            .unwrap();
+        source
+            .iter()
+            .next()
+            .unwrap()
+            .bitmap()
+            .as_ref()
+            .unwrap()
+            .reset();
+        source
+            .write_slice(&[0xbb; 16], GuestAddress(page_size as u64 * 8))
+            .unwrap();
+        let mut dirty_bitmap: DirtyBitmap = HashMap::new();
+        dirty_bitmap.insert(0, vec![0b1000]);
+        dirty_bitmap.insert(1, vec![0]);
+        assert_eq!(
+            send_dirty_pages(&mut stream, &source, &dirty_bitmap).unwrap(),
+            2
+        );
+        // The Firecracker bitmap was reset.
+        dirty_bitmap.insert(0, vec![0]);
+        let mut empty_round = Vec::new();
+        assert_eq!(
+            send_dirty_pages(&mut empty_round, &source, &dirty_bitmap).unwrap(),
+            0
+        );
+        assert!(empty_round.is_empty());
+        write_state(&mut stream, b"microvm state").unwrap();
+
+        let mut reader = stream.as_slice();
+        let mem_state = read_header(&mut reader).unwrap();
+        assert_eq!(mem_state, source.describe());
+        let target = create_memory(page_size, false);
+        assert_eq!(
+            receive_memory(&mut reader, &target).unwrap(),
+            b"microvm state"
+        );
+        assert!(reader.is_empty());
+        assert_eq!(read_memory(&target), read_memory(&source));
+    }
+
+    #[test]
+    fn test_invalid_stream() {
+        let page_size = get_page_size().unwrap();
+        let guest_memory = create_memory(page_size, false);
+
+        let mut reader = &b"FCSNAPE1\0\0\0\0"[..];
+        assert!(matches!(
+            read_header(&mut reader),
+            Err(MigrationError::BadMagic)
+        ));
+
+        let mut reader = &[0x42u8][..];
+        assert!(matches!(
+            receive_memory(&mut reader, &guest_memory),
+            Err(MigrationError::UnexpectedMessage(0x42))
+        ));
+
+        // Pages that fall in the gap between the regions.
+        let mut stream = vec![MSG_PAGES];
+        stream.extend((page_size as u64 * 5).to_le_bytes());
+        stream.extend((page_size as u64).to_le_bytes());
+        stream.extend(vec![0u8; page_size]);
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &guest_memory),
+            Err(MigrationError::WriteMemory(_))
+        ));
+
+        let mut stream = vec![MSG_STATE];
+        stream.extend((MAX_STATE_LEN + 1).to_le_bytes());
+        assert!(matches!(
+            receive_memory(&mut stream.as_slice(), &guest_memory),
+            Err(MigrationError::StateTooLarge(_))
+        ));
+
+        // The stream ends before the microVM state.
+        let mut reader = &[MSG_PAGES, 0][..];
+        assert!(matches!(
+            receive_memory(&mut reader, &guest_memory),
+            Err(MigrationError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_ack() {
+        read_ack(&mut &[ACK_OK][..]).unwrap();
+        assert!(matches!(
+            read_ack(&mut &[ACK_ERROR][..]),
+            Err(MigrationError::Rejected)
+        ));
+        assert!(matches!(read_ack(&mut &[][..]), Err(MigrationError::Io(_))));
+    }
+
+    #[test]
+    fn test_socket_source() {
+        assert!(matches!(
+            connect(&None, None),
+            Err(MigrationError::SocketSource)
+        ));
+        let file = TempFile::new().unwrap();
+        assert!(matches!(
+            accept(&Some(PathBuf::from("/tmp/foo")), Some(file.as_file())),
+            Err(MigrationError::SocketSource)
+        ));
+        assert!(matches!(
+            connect(&None, Some(file.as_file())),
+            Err(MigrationError::NotASocket)
+        ));
+
+        // The stream is a duplicate, the received socket stays open.
+        let (local, peer) = UnixStream::pair().unwrap();
+        let socket = File::from(OwnedFd::from(local));
+        let mut stream = connect(&None, Some(&socket)).unwrap();
+        assert_ne!(stream.as_raw_fd(), socket.as_raw_fd());
+        stream.write_all(&[ACK_OK]).unwrap();
+        drop(stream);
+        read_ack(&mut &peer).unwrap();
+        assert!(socket.metadata().is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Progress and cancellation of the asynchronous API operation run by the VMM thread.
+//!
+//! The API server registers an operation before handing its action over to the VMM thread,
+//! and reports its progress or forwards cancellation requests while the VMM thread runs it. The
+//! VMM thread marks the start of each phase of the operation and gives up at the start of the
+//! next phase once the operation is cancelled. Cancellation is refused once the operation has
+//! entered a phase which cannot be undone. Actions which are run synchronously find no
+//! registered operation, so they are neither tracked nor cancelled.
+
+use std::sync::Mutex;
+
+/// Errors associated with asynchronous operations.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum OperationError {
+    /// The operation was cancelled.
+    #[error("The operation was cancelled")]
+    Cancelled,
+    /// No operation is registered.
+    #[error("No operation is in progress")]
+    NoOperation,
+    /// The operation can no longer be cancelled.
+    #[error("The operation can no longer be cancelled, it is in the '{0}' phase")]
+    NotCancellable(&'static str),
+}
+
+/// Progress of an asynchronous operation.
+#[derive(Debug)]
+pub struct OperationProgress {
+    phase: &'static str,
+    cancellable: bool,
+    cancelled: bool,
+}
+
+impl OperationProgress {
+    /// Creates the progress of an operation waiting for the VMM thread.
+    pub const fn new() -> Self {
+        OperationProgress {
+            phase: "queued",
+            cancellable: true,
+            cancelled: false,
+        }
+    }
+
+    /// Returns the phase the operation is in.
+    pub fn phase(&self) -> &'static str {
+        self.phase
+    }
+
+    /// Returns whether the cancellation of the operation was requested.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Requests the cancellation of the operation.
+    pub fn cancel(&mut self) -> Result<(), OperationError> {
+        if !self.cancellable {
+            return Err(OperationError::NotCancellable(self.phase));
+        }
+        self.cancelled = true;
+        Ok(())
+    }
+
+    /// Moves the operation to `phase`, unless it was cancelled.
+    pub fn enter_phase(
+        &mut self,
+        phase: &'static str,
+        cancellable: bool,
+    ) -> Result<(), OperationError> {
+        if self.cancelled {
+            return Err(OperationError::Cancelled);
+        }
+        self.phase = phase;
+        self.cancellable = cancellable;
+        Ok(())
+    }
+}
+
+impl Default for OperationProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static OPERATION: Mutex<Option<OperationProgress>> = Mutex::new(None);
+
+/// Registers an operation about to be handed over to the VMM thread.
+pub fn begin() {
+    *OPERATION.lock().expect("Poisoned lock") = Some(OperationProgress::new());
+}
+
+/// Unregisters the operation once the VMM thread is done with it. Returns whether its
+/// cancellation was requested.
+pub fn finish() -> bool {
+    OPERATION
+        .lock()
+        .expect("Poisoned lock")
+        .take()
+        .map_or(false, |progress| progress.cancelled())
+}
+
+/// Returns the phase of the registered operation, if there is one.
+pub fn phase() -> Option<&'static str> {
+    OPERATION
+        .lock()
+        .expect("Poisoned lock")
+        .as_ref()
+        .map(OperationProgress::phase)
+}
+
+/// Requests the cancellation of the registered operation.
+pub fn cancel() -> Result<(), OperationError> {
+    OPERATION
+        .lock()
+        .expect("Poisoned lock")
+        .as_mut()
+        .ok_or(OperationError::NoOperation)?
+        .cancel()
+}
+
+/// Moves the registered operation, if there is one, to `phase`. Fails if the operation was
+/// cancelled, in which case the VMM thread must stop it. Must only be called from the VMM
+/// thread.
+pub fn enter_phase(phase: &'static str, cancellable: bool) -> Result<(), OperationError> {
+    match OPERATION.lock().expect("Poisoned lock").as_mut() {
+        Some(progress) => progress.enter_phase(phase, cancellable),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_progress() {
+        let mut progress = OperationProgress::new();
+        assert_eq!(progress.phase(), "queued");
+        progress.enter_phase("reading", true).unwrap();
+        assert_eq!(progress.phase(), "reading");
+        assert!(!progress.cancelled());
+
+        // A cancelled operation stops at the start of its next phase.
+        progress.cancel().unwrap();
+        assert!(progress.cancelled());
+        assert_eq!(
+            progress.enter_phase("writing", false),
+            Err(OperationError::Cancelled)
+        );
+        assert_eq!(progress.phase(), "reading");
+
+        let mut progress = OperationProgress::new();
+        progress.enter_phase("writing", false).unwrap();
+        assert_eq!(
+            progress.cancel(),
+            Err(OperationError::NotCancellable("writing"))
+        );
+        assert!(!progress.cancelled());
+        progress.enter_phase("done", false).unwrap();
+    }
+}
@@ -8,20 +8,21 @@ use std::time::Duration;
 use libc::c_void;
 use utils::vm_memory::VolatileMemory;
 use std::ptr::{read_volatile, write_volatile};
+use std::collections::HashSet;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::os::unix::io::AsRawFd;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use logger::{error, info, warn};
+use logger::{error, info, warn, StoreMetric, METRICS};
 use seccompiler::BpfThreadMap;
 use serde::Serialize;
 use snapshot::Snapshot;
 use userfaultfd::{FeatureFlags, Uffd, UffdBuilder};
 use utils::sock_ctrl_msg::ScmSocket;
-use utils::vm_memory::{GuestMemory, GuestMemoryMmap};
+use utils::vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 use utils::vm_memory::mmap::print_guest_memory;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -36,9 +37,13 @@ use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 #[cfg(target_arch = "x86_64")]
 use crate::cpu_config::x86_64::cpuid::CpuidTrait;
 use crate::device_manager::persist::{DeviceStates, Error as DevicePersistError};
+use crate::devices::pseudo::VmGenIdError;
 use crate::devices::virtio::TYPE_NET;
 use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
+use crate::operations::{self, OperationError};
 use crate::resources::VmResources;
+use crate::snapshot_chain::{self, ChainLink, ContentHasher, HashingWriter, SnapshotMetadata};
+use crate::snapshot_crypto::{self, EncryptedFile, EncryptingWriter, SnapshotKey};
 #[cfg(target_arch = "x86_64")]
 use crate::version_map::FC_V0_23_SNAP_VERSION;
 use crate::version_map::{FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION};
@@ -46,11 +51,14 @@ use crate::vmm_config::boot_source::BootSourceConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS;
 use crate::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType,
+    CreateSnapshotParams, DeviceOverrides, LoadSnapshotParams, MemBackendType, MemoryDumpState,
+    SnapshotEncryptionConfig, SnapshotEncryptionConfigError, SnapshotFiles, SnapshotType,
 };
 use crate::vstate::vcpu::{VcpuSendEventError, VcpuState};
 use crate::vstate::vm::VmState;
-use crate::{mem_size_mib, memory_snapshot, vstate, Error as VmmError, EventManager, Vmm};
+use crate::{
+    mem_size_mib, memory_dump, memory_snapshot, vstate, Error as VmmError, EventManager, Vmm,
+};
 use libc::{mmap, munmap, MAP_FAILED, MAP_FIXED, MAP_POPULATE, MAP_PRIVATE, PROT_READ, PROT_WRITE};
 
 #[cfg(target_arch = "x86_64")]
@@ -124,7 +132,7 @@ impl From<&VmResources> for VmInfo {
 }
 
 /// Contains the necesary state for saving/restoring a microVM.
-#[derive(Versionize)]
+#[derive(Versionize, Serialize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct MicrovmState {
     /// Miscellaneous VM info.
@@ -196,9 +204,21 @@ pub enum MicrovmStateError {
 /// Errors associated with creating a snapshot.
 #[derive(Debug, thiserror::Error)]
 pub enum CreateSnapshotError {
+    /// Failed to start or poll a background memory dump.
+    #[error("Background memory dump error: {0}")]
+    BackgroundDump(memory_dump::Error),
     /// Failed to get dirty bitmap.
     #[error("Cannot get dirty bitmap: {0}")]
     DirtyBitmap(VmmError),
+    /// Failed to encrypt the snapshot files.
+    #[error("Cannot encrypt the snapshot files: {0}")]
+    Encrypt(snapshot_crypto::Error),
+    /// Diff snapshots are sparse and cannot be encrypted.
+    #[error("Diff snapshots cannot be encrypted")]
+    EncryptedDiffSnapshot,
+    /// Failed to load the encryption key.
+    #[error("Cannot load the snapshot encryption key: {0}")]
+    EncryptionKey(SnapshotEncryptionConfigError),
     /// The virtio devices uses a features that is incompatible with older versions of Firecracker.
     #[error(
         "The virtio devices use a features that is incompatible with older versions of \
@@ -217,6 +237,9 @@ pub enum CreateSnapshotError {
     /// Failed to open memory backing file.
     #[error("Cannot perform {0} on the memory backing file: {1}")]
     MemoryBackingFile(&'static str, io::Error),
+    /// The asynchronous snapshot creation was cancelled.
+    #[error("{0}")]
+    Operation(OperationError),
     /// Failed to save MicrovmState.
     #[error("Cannot save the microVM state: {0}")]
     MicrovmState(MicrovmStateError),
@@ -245,40 +268,173 @@ pub fn create_snapshot(
     // Fail early from invalid target version.
     let snapshot_data_version = get_snapshot_data_version(&params.version, &version_map, vmm)?;
 
+    let key = params
+        .encryption
+        .as_ref()
+        .map(SnapshotEncryptionConfig::load_key)
+        .transpose()
+        .map_err(CreateSnapshotError::EncryptionKey)?;
+    if key.is_some() && params.snapshot_type == SnapshotType::Diff {
+        return Err(CreateSnapshotError::EncryptedDiffSnapshot);
+    }
+    if params.background {
+        check_background_dump(vmm, params, key.is_some())
+            .map_err(CreateSnapshotError::BackgroundDump)?;
+    }
+    // The parent of a diff snapshot is only known once the previous dump is done.
+    match vmm.memory_dump_status() {
+        Ok(status) if status.state == MemoryDumpState::InProgress => {
+            return Err(CreateSnapshotError::BackgroundDump(
+                memory_dump::Error::InProgress,
+            ));
+        }
+        Ok(_) | Err(memory_dump::Error::NoDump) => (),
+        Err(err) => return Err(CreateSnapshotError::BackgroundDump(err)),
+    }
+
+    operations::enter_phase("saving_state", true).map_err(CreateSnapshotError::Operation)?;
     let microvm_state = vmm
         .save_state(vm_info)
         .map_err(CreateSnapshotError::MicrovmState)?;
 
+    let snapshot_file = create_snapshot_file(params.files.snapshot.as_ref(), &params.snapshot_path)
+        .map_err(|err| CreateSnapshotError::SnapshotBackingFile("open", err))?;
     snapshot_state_to_file(
         &microvm_state,
-        &params.snapshot_path,
+        snapshot_file,
         snapshot_data_version,
         version_map,
+        key.as_ref(),
     )?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
+    // Cancelling past this point leaves the snapshot state file without its memory file.
+    operations::enter_phase("saving_memory", false).map_err(CreateSnapshotError::Operation)?;
+    if params.background {
+        let file = create_snapshot_file(None, &params.mem_file_path)
+            .map_err(|err| CreateSnapshotError::MemoryBackingFile("open", err))?;
+        vmm.memory_dump = Some(
+            memory_dump::start(
+                vmm.guest_memory(),
+                file,
+                &params.mem_file_path,
+                params.chain_metadata,
+            )
+            .map_err(CreateSnapshotError::BackgroundDump)?,
+        );
+        // Set once the dump is done.
+        vmm.last_snapshot = None;
+        return Ok(());
+    }
+
+    // Diff snapshots are taken on top of the last snapshot of the microVM, which is replaced
+    // by this one once its metadata is written. Memory files passed as descriptors have no
+    // path to record metadata next to.
+    let parent = vmm
+        .last_snapshot
+        .take()
+        .filter(|_| params.snapshot_type == SnapshotType::Diff);
+    let hasher = (params.chain_metadata && params.files.mem.is_none())
+        .then(|| ContentHasher::new(parent.as_ref().map(|link| link.id.as_str())));
+
+    let mem_file = create_snapshot_file(params.files.mem.as_ref(), &params.mem_file_path)
+        .map_err(|err| CreateSnapshotError::MemoryBackingFile("open", err))?;
+    let content_id =
+        snapshot_memory_to_file(vmm, mem_file, &params.snapshot_type, key.as_ref(), hasher)?;
+
+    // The snapshot is usable whether or not its metadata is written: without it, the next diff
+    // snapshot cannot name a parent.
+    if let Some(id) = content_id {
+        match snapshot_chain::record_snapshot(
+            &params.mem_file_path,
+            params.snapshot_type,
+            parent.as_ref(),
+            id,
+        ) {
+            Ok(link) => vmm.last_snapshot = Some(link),
+            Err(err) => warn!("Cannot write the snapshot metadata: {}", err),
+        }
+    }
+
+    Ok(())
+}
 
+// Checks that the snapshot can be taken with a background memory dump.
+fn check_background_dump(
+    vmm: &Vmm,
+    params: &CreateSnapshotParams,
+    encrypted: bool,
+) -> std::result::Result<(), memory_dump::Error> {
+    if params.snapshot_type == SnapshotType::Diff {
+        return Err(memory_dump::Error::Unsupported("diff snapshots"));
+    }
+    if encrypted {
+        return Err(memory_dump::Error::Unsupported("encryption"));
+    }
+    // The child process would not see the pages not faulted in yet.
+    if vmm.uffd.is_some() {
+        return Err(memory_dump::Error::Unsupported("the Uffd memory backend"));
+    }
+    if params.files.mem.is_some() {
+        return Err(memory_dump::Error::Unsupported(
+            "memory files passed as file descriptors",
+        ));
+    }
     Ok(())
 }
 
+// Truncates the file passed over the API socket, or creates the file at `path`.
+fn create_snapshot_file(passed: Option<&File>, path: &Path) -> io::Result<File> {
+    match passed {
+        Some(file) => {
+            let mut file = file.try_clone()?;
+            file.set_len(0)?;
+            file.rewind()?;
+            Ok(file)
+        }
+        None => OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path),
+    }
+}
+
+// Rewinds the file passed over the API socket, or opens the file at `path`.
+fn open_snapshot_file(passed: Option<&File>, path: &Path) -> io::Result<File> {
+    match passed {
+        Some(file) => {
+            let mut file = file.try_clone()?;
+            // Pipes and sockets cannot be rewound and are read from where they are.
+            if file.metadata()?.is_file() {
+                file.rewind()?;
+            }
+            Ok(file)
+        }
+        None => File::open(path),
+    }
+}
+
 fn snapshot_state_to_file(
     microvm_state: &MicrovmState,
-    snapshot_path: &Path,
+    mut snapshot_file: File,
     snapshot_data_version: u16,
     version_map: VersionMap,
+    key: Option<&SnapshotKey>,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut snapshot_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(snapshot_path)
-        .map_err(|err| SnapshotBackingFile("open", err))?;
-
     let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
-    snapshot
-        .save(&mut snapshot_file, microvm_state)
-        .map_err(SerializeMicrovmState)?;
+    match key {
+        Some(key) => {
+            let mut state = Vec::new();
+            snapshot
+                .save(&mut state, microvm_state)
+                .map_err(SerializeMicrovmState)?;
+            snapshot_crypto::encrypt_to(&mut snapshot_file, key, &state).map_err(Encrypt)?;
+        }
+        None => snapshot
+            .save(&mut snapshot_file, microvm_state)
+            .map_err(SerializeMicrovmState)?,
+    }
     snapshot_file
         .flush()
         .map_err(|err| SnapshotBackingFile("flush", err))?;
@@ -287,37 +443,59 @@ fn snapshot_state_to_file(
         .map_err(|err| SnapshotBackingFile("sync_all", err))
 }
 
+// Writes the guest memory to `file`, feeding it to `hasher` if any. Returns the content
+// identifier computed by `hasher`.
 fn snapshot_memory_to_file(
     vmm: &Vmm,
-    mem_file_path: &Path,
+    file: File,
     snapshot_type: &SnapshotType,
-) -> std::result::Result<(), CreateSnapshotError> {
+    key: Option<&SnapshotKey>,
+    hasher: Option<ContentHasher>,
+) -> std::result::Result<Option<String>, CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(mem_file_path)
-        .map_err(|err| MemoryBackingFile("open", err))?;
-
-    // Set the length of the file to the full size of the memory area.
-    let mem_size_mib = mem_size_mib(vmm.guest_memory());
-    file.set_len(mem_size_mib * 1024 * 1024)
-        .map_err(|err| MemoryBackingFile("set_length", err))?;
-
-    match snapshot_type {
-        SnapshotType::Diff => {
-            let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
-            vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
-                .map_err(Memory)
-        }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
-    }?;
+
+    let mut file = HashingWriter::new(file, hasher);
+
+    if let Some(key) = key {
+        let mem_size = vmm
+            .guest_memory()
+            .describe()
+            .regions
+            .iter()
+            .map(|region| region.size as u64)
+            .sum();
+        let mut writer = EncryptingWriter::new(
+            &mut file,
+            key,
+            mem_size,
+            snapshot_crypto::DEFAULT_CHUNK_SIZE,
+        )
+        .map_err(Encrypt)?;
+        vmm.guest_memory().dump(&mut writer).map_err(Memory)?;
+        writer.finish().map_err(Encrypt)?;
+    } else {
+        // Set the length of the file to the full size of the memory area.
+        let mem_size_mib = mem_size_mib(vmm.guest_memory());
+        file.get_ref()
+            .set_len(mem_size_mib * 1024 * 1024)
+            .map_err(|err| MemoryBackingFile("set_length", err))?;
+
+        match snapshot_type {
+            SnapshotType::Diff => {
+                let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
+                vmm.guest_memory()
+                    .dump_dirty(&mut file, &dirty_bitmap)
+                    .map_err(Memory)
+            }
+            SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
+        }?;
+    }
+    let (mut file, hasher) = file.into_parts();
     file.flush()
         .map_err(|err| MemoryBackingFile("flush", err))?;
     file.sync_all()
-        .map_err(|err| MemoryBackingFile("sync_all", err))
+        .map_err(|err| MemoryBackingFile("sync_all", err))?;
+    Ok(hasher.map(ContentHasher::finish))
 }
 
 /// Validate the microVM version and translate it to its corresponding snapshot data format.
@@ -343,25 +521,34 @@ pub fn get_snapshot_data_version(
     if data_version < FC_V1_1_SNAP_VERSION {
         vmm.mmio_device_manager
             .for_each_virtio_device(|virtio_type, _id, _info, dev| {
-                // Incompatibility between current version and all versions smaller than 1.0.
-                // Also, incompatibility between v1.1 and v1.0 for VirtIO net device
-                if dev
-                    .lock()
-                    .expect("Poisoned lock")
-                    .has_feature(u64::from(VIRTIO_RING_F_EVENT_IDX))
-                    && (data_version < FC_V1_0_SNAP_VERSION || virtio_type == TYPE_NET)
-                {
-                    return Err(CreateSnapshotError::IncompatibleVirtioFeature(
-                        "notification suppression",
-                    ));
-                }
-                Ok(())
+                let acked_features = dev.lock().expect("Poisoned lock").acked_features();
+                validate_virtio_features(virtio_type, acked_features, data_version)
             })?;
     }
 
     Ok(data_version)
 }
 
+/// Checks that a virtio device of type `virtio_type` which acked `acked_features` can be saved
+/// at snapshot data version `data_version`.
+pub fn validate_virtio_features(
+    virtio_type: u32,
+    acked_features: u64,
+    data_version: u16,
+) -> std::result::Result<(), CreateSnapshotError> {
+    // Incompatibility between current version and all versions smaller than 1.0.
+    // Also, incompatibility between v1.1 and v1.0 for VirtIO net device
+    if data_version < FC_V1_1_SNAP_VERSION
+        && acked_features & (1u64 << VIRTIO_RING_F_EVENT_IDX) != 0
+        && (data_version < FC_V1_0_SNAP_VERSION || virtio_type == TYPE_NET)
+    {
+        return Err(CreateSnapshotError::IncompatibleVirtioFeature(
+            "notification suppression",
+        ));
+    }
+    Ok(())
+}
+
 /// Error type for [`validate_cpu_vendor`].
 #[cfg(target_arch = "x86_64")]
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
@@ -493,21 +680,100 @@ pub fn snapshot_state_sanity_check(
     Ok(())
 }
 
+/// Error type for [`validate_device_overrides`].
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DeviceOverrideError {
+    /// A block device is overridden more than once.
+    #[error("Block device {0} is overridden more than once.")]
+    DuplicateDrive(String),
+    /// A network interface is overridden more than once.
+    #[error("Network interface {0} is overridden more than once.")]
+    DuplicateNetworkInterface(String),
+    /// The snapshot has no block device with this ID.
+    #[error("The snapshot has no block device with ID {0}.")]
+    UnknownDrive(String),
+    /// The snapshot has no network interface with this ID.
+    #[error("The snapshot has no network interface with ID {0}.")]
+    UnknownNetworkInterface(String),
+    /// The snapshot has no vsock device with this ID.
+    #[error("The snapshot has no vsock device with ID {0}.")]
+    UnknownVsock(String),
+}
+
+/// Checks that each device override targets a device saved in the snapshot, at most once.
+pub fn validate_device_overrides(
+    device_states: &DeviceStates,
+    overrides: &DeviceOverrides,
+) -> std::result::Result<(), DeviceOverrideError> {
+    use self::DeviceOverrideError::*;
+
+    let mut seen = HashSet::new();
+    for entry in &overrides.network_overrides {
+        if !device_states
+            .net_devices
+            .iter()
+            .any(|dev| dev.device_id == entry.iface_id)
+        {
+            return Err(UnknownNetworkInterface(entry.iface_id.clone()));
+        }
+        if !seen.insert(entry.iface_id.as_str()) {
+            return Err(DuplicateNetworkInterface(entry.iface_id.clone()));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for entry in &overrides.drive_overrides {
+        if !device_states
+            .block_devices
+            .iter()
+            .any(|dev| dev.device_id == entry.drive_id)
+        {
+            return Err(UnknownDrive(entry.drive_id.clone()));
+        }
+        if !seen.insert(entry.drive_id.as_str()) {
+            return Err(DuplicateDrive(entry.drive_id.clone()));
+        }
+    }
+
+    if let Some(entry) = &overrides.vsock_override {
+        match &device_states.vsock_device {
+            Some(dev) if dev.device_id == entry.vsock_id => (),
+            _ => return Err(UnknownVsock(entry.vsock_id.clone())),
+        }
+    }
+    Ok(())
+}
+
 /// Error type for [`restore_from_snapshot`].
 #[derive(Debug, thiserror::Error)]
 pub enum RestoreFromSnapshotError {
+    /// Failed to load the encryption key.
+    #[error("Failed to load the snapshot encryption key: {0}")]
+    EncryptionKey(#[from] SnapshotEncryptionConfigError),
     /// Failed to get snapshot state from file.
     #[error("Failed to get snapshot state from file: {0}")]
     File(#[from] SnapshotStateFromFileError),
     /// Invalid snapshot state.
     #[error("Invalid snapshot state: {0}")]
     Invalid(#[from] SnapShotStateSanityCheckError),
+    /// Invalid device overrides.
+    #[error("Invalid device overrides: {0}")]
+    DeviceOverride(#[from] DeviceOverrideError),
+    /// Invalid diff snapshot chain.
+    #[error("Invalid diff snapshot chain: {0}")]
+    Chain(#[from] snapshot_chain::Error),
     /// Failed to load guest memory
     #[error("Failed to load guest memory: {0}")]
     GuestMemory(#[from] RestoreFromSnapshotGuestMemoryError),
+    /// The asynchronous snapshot load was cancelled.
+    #[error("{0}")]
+    Operation(#[from] OperationError),
     /// Failed to build microVM from snapshot.
     #[error("Failed to build microVM from snapshot: {0}")]
     Build(#[from] BuildMicrovmFromSnapshotError),
+    /// Failed to update the VM generation ID.
+    #[error("Failed to update the VM generation ID: {0}")]
+    VmGenId(#[from] VmGenIdError),
 }
 /// Sub-Error type for [`restore_from_snapshot`] to contain either [`GuestMemoryFromFileError`] or
 /// [`GuestMemoryFromUffdError`] within [`RestoreFromSnapshotError`].
@@ -521,7 +787,66 @@ pub enum RestoreFromSnapshotGuestMemoryError {
     Uffd(#[from] GuestMemoryFromUffdError),
 }
 
+/// Time spent in each phase of a snapshot load, in microseconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RestoreTimings {
+    /// Reading the snapshot state file and decoding the microVM state.
+    pub read_state_us: u64,
+    /// Checking the microVM state against the host.
+    pub sanity_check_us: u64,
+    /// Mapping the guest memory (and registering it with the userfaultfd).
+    pub map_memory_us: u64,
+    /// Handing the guest memory mappings and the userfaultfd to the page fault handler.
+    pub uffd_handshake_us: u64,
+    /// Creating the VM and vCPUs and restoring the VM and device states.
+    pub restore_devices_us: u64,
+    /// Restoring the vCPU states.
+    pub restore_vcpus_us: u64,
+    /// Resuming the microVM, if requested.
+    pub resume_vm_us: u64,
+    /// The whole snapshot load.
+    pub total_us: u64,
+}
+
+impl RestoreTimings {
+    /// Records the timings in the snapshot load metrics.
+    pub fn update_metrics(&self) {
+        let latencies = &METRICS.latencies_us;
+        for (metric, value_us) in [
+            (&latencies.load_snapshot_read_state, self.read_state_us),
+            (&latencies.load_snapshot_sanity_check, self.sanity_check_us),
+            (&latencies.load_snapshot_map_memory, self.map_memory_us),
+            (
+                &latencies.load_snapshot_uffd_handshake,
+                self.uffd_handshake_us,
+            ),
+            (
+                &latencies.load_snapshot_restore_devices,
+                self.restore_devices_us,
+            ),
+            (
+                &latencies.load_snapshot_restore_vcpus,
+                self.restore_vcpus_us,
+            ),
+            (&latencies.load_snapshot_resume_vm, self.resume_vm_us),
+        ] {
+            metric.store(value_us as usize);
+        }
+    }
+}
+
+/// Returns the microseconds elapsed since `start_us` and moves `start_us` to now.
+pub(crate) fn lap_us(start_us: &mut u64) -> u64 {
+    let now_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+    let elapsed_us = now_us - *start_us;
+    *start_us = now_us;
+    elapsed_us
+}
+
 /// Loads a Microvm snapshot producing a 'paused' Microvm.
+///
+/// Also returns the time spent in each phase of the restore. The `resume_vm_us` and `total_us`
+/// timings are left for the caller to fill in.
 pub fn restore_from_snapshot(
     instance_info: &InstanceInfo,
     event_manager: &mut EventManager,
@@ -529,33 +854,86 @@ pub fn restore_from_snapshot(
     params: &LoadSnapshotParams,
     version_map: VersionMap,
     vm_resources: &mut VmResources,
-) -> std::result::Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+) -> std::result::Result<(Arc<Mutex<Vmm>>, RestoreTimings), RestoreFromSnapshotError> {
+    let mut timings = RestoreTimings::default();
+    let restore_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+    let mut phase_start_us = restore_start_us;
+
+    operations::enter_phase("reading_state", true)?;
+    let key = params
+        .encryption
+        .as_ref()
+        .map(SnapshotEncryptionConfig::load_key)
+        .transpose()?;
+    let snapshot_file = open_snapshot_file(params.files.snapshot.as_ref(), &params.snapshot_path)
+        .map_err(SnapshotStateFromFileError::Open)?;
+    let microvm_state = snapshot_state_from_file(snapshot_file, version_map, key.as_ref())?;
+    timings.read_state_us = lap_us(&mut phase_start_us);
 
     // Some sanity checks before building the microvm.
+    operations::enter_phase("checking_state", true)?;
     snapshot_state_sanity_check(&microvm_state)?;
+    validate_device_overrides(&microvm_state.device_states, &params.device_overrides)?;
+    timings.sanity_check_us = lap_us(&mut phase_start_us);
 
     let mem_backend_path = &params.mem_backend.backend_path;
     let mem_state = &microvm_state.memory_state;
     let track_dirty_pages = params.enable_diff_snapshots;
 
-    let (guest_memory, uffd) = match params.mem_backend.backend_type {
-        MemBackendType::File => (
-            guest_memory_from_file(mem_backend_path, mem_state, track_dirty_pages)
-                .map_err(RestoreFromSnapshotGuestMemoryError::File)?,
-            None,
-        ),
-        MemBackendType::Uffd => guest_memory_from_uffd(
+    // The last snapshot of the chain is the parent of the next diff snapshot.
+    let last_snapshot = if params.files.mem.is_some() {
+        None
+    } else if params.diff_chain.is_empty() {
+        SnapshotMetadata::load(mem_backend_path)
+            .ok()
+            .map(|metadata| ChainLink {
+                id: metadata.id,
+                mem_file_path: mem_backend_path.clone(),
+            })
+    } else {
+        if params.mem_backend.backend_type != MemBackendType::File {
+            return Err(snapshot_chain::Error::InvalidBackend.into());
+        }
+        Some(snapshot_chain::validate_chain(
             mem_backend_path,
-            mem_state,
-            track_dirty_pages,
-            // We enable the UFFD_FEATURE_EVENT_REMOVE feature only if a balloon device
-            // is present in the microVM state.
-            microvm_state.device_states.balloon_device.is_some(),
-        )
-        .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?,
+            &params.diff_chain,
+        )?)
     };
-    builder::build_microvm_from_snapshot(
+
+    operations::enter_phase("mapping_memory", true)?;
+    let (guest_memory, uffd) = match params.mem_backend.backend_type {
+        MemBackendType::File => {
+            let mem_file = open_snapshot_file(params.files.mem.as_ref(), mem_backend_path)
+                .map_err(|err| RestoreFromSnapshotGuestMemoryError::File(err.into()))?;
+            let guest_memory =
+                guest_memory_from_file(mem_file, mem_state, track_dirty_pages, key.as_ref())
+                    .map_err(RestoreFromSnapshotGuestMemoryError::File)?;
+            for diff_path in params.diff_chain.iter() {
+                snapshot_chain::map_diff(&guest_memory, mem_state, diff_path)?;
+            }
+            timings.map_memory_us = lap_us(&mut phase_start_us);
+            (guest_memory, None)
+        }
+        MemBackendType::Uffd => {
+            let socket = uffd_handler_socket(&params.files, mem_backend_path)
+                .map_err(|err| RestoreFromSnapshotGuestMemoryError::Uffd(err.into()))?;
+            let memory = guest_memory_from_uffd(
+                socket,
+                mem_state,
+                track_dirty_pages,
+                // We enable the UFFD_FEATURE_EVENT_REMOVE feature only if a balloon device
+                // is present in the microVM state.
+                microvm_state.device_states.balloon_device.is_some(),
+                &mut timings,
+            )
+            .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?;
+            phase_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+            memory
+        }
+    };
+    // The microVM is registered with the event manager as it is built, so this cannot be undone.
+    operations::enter_phase("restoring_devices", false)?;
+    let vmm = builder::build_microvm_from_snapshot(
         instance_info,
         event_manager,
         microvm_state,
@@ -564,8 +942,22 @@ pub fn restore_from_snapshot(
         track_dirty_pages,
         seccomp_filters,
         vm_resources,
+        &params.device_overrides,
+        &mut timings,
     )
-    .map_err(RestoreFromSnapshotError::Build)
+    .map_err(RestoreFromSnapshotError::Build)?;
+    // Whatever `build_microvm_from_snapshot` did not spend on the vCPU states.
+    timings.restore_devices_us = lap_us(&mut phase_start_us) - timings.restore_vcpus_us;
+    let mut locked_vmm = vmm.lock().expect("Poisoned lock");
+    locked_vmm.last_snapshot = last_snapshot;
+    locked_vmm.restore_start_us = Some(restore_start_us);
+    // Tell the guest that it runs from a snapshot and give it fresh entropy. This is done once
+    // the interrupt controller and vCPU states are restored, so that the interrupts are not lost.
+    locked_vmm.mmio_device_manager.regenerate_vmgenid()?;
+    locked_vmm.mmio_device_manager.reseed_entropy();
+    drop(locked_vmm);
+
+    Ok((vmm, timings))
 }
 
 /// Error type for [`snapshot_state_from_file`]
@@ -580,18 +972,48 @@ pub enum SnapshotStateFromFileError {
     /// Failed to load snapshot state from file.
     #[error("Failed to load snapshot state from file: {0}")]
     Load(#[from] snapshot::Error),
+    /// Failed to decrypt snapshot file.
+    #[error("Failed to decrypt snapshot file: {0}")]
+    Decrypt(snapshot_crypto::Error),
+    /// The snapshot file is encrypted but no key was provided.
+    #[error("The snapshot file is encrypted but no encryption key was provided")]
+    MissingKey,
 }
 
 fn snapshot_state_from_file(
-    snapshot_path: &Path,
+    snapshot_reader: File,
     version_map: VersionMap,
+    key: Option<&SnapshotKey>,
 ) -> std::result::Result<MicrovmState, SnapshotStateFromFileError> {
-    let mut snapshot_reader =
-        File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
-    let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotStateFromFileError::Meta)?;
-    let snapshot_len = metadata.len() as usize;
-    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map)
-        .map_err(SnapshotStateFromFileError::Load)
+    if let Some(key) = key {
+        // Plaintext files are rejected so that they cannot be swapped in for encrypted ones.
+        let state = EncryptedFile::open(snapshot_reader, key)
+            .and_then(|encrypted| encrypted.read_all())
+            .map_err(SnapshotStateFromFileError::Decrypt)?;
+        return Snapshot::load(&mut state.as_slice(), state.len(), version_map)
+            .map_err(SnapshotStateFromFileError::Load);
+    }
+    let metadata = snapshot_reader
+        .metadata()
+        .map_err(SnapshotStateFromFileError::Meta)?;
+    // Pipes and sockets can neither be probed for encryption nor report their length, so the
+    // state read from them is only bounded by the maximum snapshot size.
+    let snapshot_len = if metadata.is_file() {
+        if snapshot_crypto::is_encrypted(&snapshot_reader)
+            .map_err(SnapshotStateFromFileError::Open)?
+        {
+            return Err(SnapshotStateFromFileError::MissingKey);
+        }
+        metadata.len() as usize
+    } else {
+        snapshot::MAX_SNAPSHOT_LEN
+    };
+    Snapshot::load(
+        &mut BufReader::new(snapshot_reader),
+        snapshot_len,
+        version_map,
+    )
+    .map_err(SnapshotStateFromFileError::Load)
 }
 
 /// Error type for [`guest_memory_from_file`].
@@ -603,6 +1025,12 @@ pub enum GuestMemoryFromFileError {
     /// Failed to restore guest memory.
     #[error("Failed to restore guest memory: {0}")]
     Restore(#[from] crate::memory_snapshot::Error),
+    /// Failed to decrypt guest memory.
+    #[error("Failed to decrypt guest memory: {0}")]
+    Decrypt(#[from] snapshot_crypto::Error),
+    /// The memory file is encrypted but no key was provided.
+    #[error("The memory file is encrypted but no encryption key was provided")]
+    MissingKey,
 }
 fn monitor_pages(addr: *mut libc::c_void, lenm: usize, interval: Duration) {
 // fn monitor_pages(addr: Arc<Mutex<*mut u8>>, lenm: usize, interval: Duration) {
@@ -782,14 +1210,47 @@ fn parse_path(input: &Path) -> String {
 // }
 
 fn guest_memory_from_file(
-    mem_file_path: &Path,
+    mem_file: File,
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
+    key: Option<&SnapshotKey>,
 ) -> std::result::Result<GuestMemoryMmap, GuestMemoryFromFileError> {
-    let mem_file = File::open(mem_file_path)?;
+    if let Some(key) = key {
+        let encrypted = EncryptedFile::open(mem_file, key)?;
+        let guest_mem = GuestMemoryMmap::restore(None, mem_state, track_dirty_pages)?;
+        decrypt_guest_memory(&encrypted, mem_state, &guest_mem)?;
+        return Ok(guest_mem);
+    }
+    if snapshot_crypto::is_encrypted(&mem_file)? {
+        return Err(GuestMemoryFromFileError::MissingKey);
+    }
     let guest_mem = GuestMemoryMmap::restore(Some(&mem_file), mem_state, track_dirty_pages)?;
     Ok(guest_mem)
 }
+
+/// Decrypts the guest memory regions one chunk at a time into anonymous guest memory.
+fn decrypt_guest_memory(
+    encrypted: &EncryptedFile,
+    mem_state: &GuestMemoryState,
+    guest_mem: &GuestMemoryMmap,
+) -> std::result::Result<(), GuestMemoryFromFileError> {
+    let mut buf = vec![0u8; snapshot_crypto::DEFAULT_CHUNK_SIZE as usize];
+    for region in mem_state.regions.iter() {
+        let mut done = 0;
+        while done < region.size {
+            let count = buf.len().min(region.size - done);
+            encrypted.read_at(region.offset + done as u64, &mut buf[..count])?;
+            guest_mem
+                .write_slice(
+                    &buf[..count],
+                    GuestAddress(region.base_address + done as u64),
+                )
+                .map_err(memory_snapshot::Error::WriteMemory)?;
+            done += count;
+        }
+    }
+    Ok(())
+}
 /// Error type for [`guest_memory_from_uffd`]
 #[derive(Debug, thiserror::Error)]
 pub enum GuestMemoryFromUffdError {
@@ -810,12 +1271,22 @@ pub enum GuestMemoryFromUffdError {
     Send(#[from] utils::errno::Error),
 }
 
+// Takes the connected socket passed over the API socket, or connects to the one at `path`.
+fn uffd_handler_socket(files: &SnapshotFiles, path: &Path) -> io::Result<UnixStream> {
+    match files.mem.as_ref() {
+        Some(file) => Ok(UnixStream::from(OwnedFd::from(file.try_clone()?))),
+        None => UnixStream::connect(path),
+    }
+}
+
 fn guest_memory_from_uffd(
-    mem_uds_path: &Path,
+    socket: UnixStream,
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
     enable_balloon: bool,
+    timings: &mut RestoreTimings,
 ) -> std::result::Result<(GuestMemoryMmap, Option<Uffd>), GuestMemoryFromUffdError> {
+    let mut phase_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
     let guest_memory = GuestMemoryMmap::restore(None, mem_state, track_dirty_pages)?;
 
     let mut uffd_builder = UffdBuilder::new();
@@ -846,11 +1317,12 @@ fn guest_memory_from_uffd(
         });
     }
 
+    timings.map_memory_us = lap_us(&mut phase_start_us);
+
     // This is safe to unwrap() because we control the contents of the vector
     // (i.e GuestRegionUffdMapping entries).
     let backend_mappings = serde_json::to_string(&backend_mappings).unwrap();
 
-    let socket = UnixStream::connect(mem_uds_path)?;
     socket.send_with_fd(
         backend_mappings.as_bytes(),
         // Calling get_user_pages() on a range of user memory that has been mmapped from a DAX device
@@ -863,12 +1335,16 @@ fn guest_memory_from_uffd(
         // `libc::SO_PEERCRED` option 
         uffd.as_raw_fd(),
     )?;
+    timings.uffd_handshake_us = lap_us(&mut phase_start_us);
 
     Ok((guest_memory, Some(uffd)))
 }
 
+/// Checks that `device_number` devices fit in a snapshot saved for Firecracker v0.23.
 #[cfg(target_arch = "x86_64")]
-fn validate_devices_number(device_number: usize) -> std::result::Result<(), CreateSnapshotError> {
+pub fn validate_devices_number(
+    device_number: usize,
+) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::TooManyDevices;
     if device_number > FC_V0_23_MAX_DEVICES as usize {
         return Err(TooManyDevices(device_number));
@@ -893,6 +1369,8 @@ fn validate_fc_version_format(version: &str) -> Result<(), CreateSnapshotError>
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::io::FromRawFd;
+
     use snapshot::Persist;
     use utils::errno;
     use utils::tempfile::TempFile;
@@ -1015,7 +1493,101 @@ mod tests {
         assert_eq!(
             restored_microvm_state.device_states,
             microvm_state.device_states
-        )
+        );
+
+        // The state can also be streamed from a pipe, which does not report its length.
+        let mut fds = [0; 2];
+        // SAFETY: Safe because `fds` has room for the two descriptors of the pipe.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: Safe because both descriptors were just created and nothing else owns them.
+        let (reader, mut writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        Snapshot::new(VERSION_MAP.clone(), VERSION_MAP.latest_version())
+            .save(&mut writer, &microvm_state)
+            .unwrap();
+        drop(writer);
+
+        let restored_microvm_state =
+            snapshot_state_from_file(reader, VERSION_MAP.clone(), None).unwrap();
+        assert_eq!(
+            restored_microvm_state.device_states,
+            microvm_state.device_states
+        );
+    }
+
+    #[test]
+    fn test_validate_device_overrides() {
+        use crate::vmm_config::snapshot::{DriveOverride, NetworkOverride, VsockOverride};
+
+        let vmm = default_vmm_with_devices();
+        let states = vmm.mmio_device_manager.save();
+        let vsock_id = states.vsock_device.as_ref().unwrap().device_id.clone();
+
+        let network_override = |iface_id: &str| NetworkOverride {
+            iface_id: iface_id.to_string(),
+            host_dev_name: "tap1".to_string(),
+        };
+        let drive_override = |drive_id: &str| DriveOverride {
+            drive_id: drive_id.to_string(),
+            path_on_host: "/clones/1/rootfs".to_string(),
+        };
+        let vsock_override = |vsock_id: &str| VsockOverride {
+            vsock_id: vsock_id.to_string(),
+            uds_path: "/clones/1/v.sock".to_string(),
+        };
+
+        validate_device_overrides(&states, &DeviceOverrides::default()).unwrap();
+        let overrides = DeviceOverrides {
+            network_overrides: vec![network_override("netif")],
+            drive_overrides: vec![drive_override("root")],
+            vsock_override: Some(vsock_override(&vsock_id)),
+        };
+        validate_device_overrides(&states, &overrides).unwrap();
+
+        let overrides = DeviceOverrides {
+            network_overrides: vec![network_override("eth9")],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_device_overrides(&states, &overrides),
+            Err(DeviceOverrideError::UnknownNetworkInterface(
+                "eth9".to_string()
+            ))
+        );
+        let overrides = DeviceOverrides {
+            network_overrides: vec![network_override("netif"), network_override("netif")],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_device_overrides(&states, &overrides),
+            Err(DeviceOverrideError::DuplicateNetworkInterface(
+                "netif".to_string()
+            ))
+        );
+        let overrides = DeviceOverrides {
+            drive_overrides: vec![drive_override("scratch")],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_device_overrides(&states, &overrides),
+            Err(DeviceOverrideError::UnknownDrive("scratch".to_string()))
+        );
+        let overrides = DeviceOverrides {
+            drive_overrides: vec![drive_override("root"), drive_override("root")],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_device_overrides(&states, &overrides),
+            Err(DeviceOverrideError::DuplicateDrive("root".to_string()))
+        );
+        let overrides = DeviceOverrides {
+            vsock_override: Some(vsock_override("other")),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_device_overrides(&states, &overrides),
+            Err(DeviceOverrideError::UnknownVsock("other".to_string()))
+        );
     }
 
     #[test]
@@ -1058,12 +1630,70 @@ mod tests {
         assert!(get_snapshot_data_version(&Some("0.24.0".to_string()), &VERSION_MAP, &vmm).is_ok());
     }
 
+    #[test]
+    fn test_check_background_dump() {
+        let vmm = default_vmm();
+        let mut params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: "vmstate".into(),
+            snapshot_fd: None,
+            mem_file_path: "mem".into(),
+            mem_file_fd: None,
+            version: None,
+            encryption: None,
+            background: true,
+            chain_metadata: false,
+            asynchronous: false,
+            files: SnapshotFiles::default(),
+        };
+        check_background_dump(&vmm, &params, false).unwrap();
+        assert!(matches!(
+            check_background_dump(&vmm, &params, true),
+            Err(memory_dump::Error::Unsupported("encryption"))
+        ));
+
+        params.snapshot_type = SnapshotType::Diff;
+        assert!(matches!(
+            check_background_dump(&vmm, &params, false),
+            Err(memory_dump::Error::Unsupported("diff snapshots"))
+        ));
+
+        params.snapshot_type = SnapshotType::Full;
+        params.files.mem = Some(TempFile::new().unwrap().into_file());
+        assert!(matches!(
+            check_background_dump(&vmm, &params, false),
+            Err(memory_dump::Error::Unsupported(
+                "memory files passed as file descriptors"
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_files() {
+        let passed = TempFile::new().unwrap();
+        passed.as_file().write_all(b"stale contents").unwrap();
+
+        let mut file = create_snapshot_file(Some(passed.as_file()), Path::new("unused")).unwrap();
+        assert_eq!(passed.as_file().metadata().unwrap().len(), 0);
+        file.write_all(b"state").unwrap();
+
+        let mut contents = String::new();
+        open_snapshot_file(Some(passed.as_file()), Path::new("unused"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "state");
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use utils::vm_memory::GuestMemoryError;
 
         use crate::persist::CreateSnapshotError::*;
 
+        let err = BackgroundDump(memory_dump::Error::Unsupported("diff snapshots"));
+        let _ = format!("{}{:?}", err, err);
+
         let err = DirtyBitmap(VmmError::DirtyBitmap(kvm_ioctls::Error::new(20)));
         let _ = format!("{}{:?}", err, err);
 
@@ -2,10 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::From;
+use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
 
-use logger::info;
+use logger::{info, warn};
 use mmds::data_store::{Mmds, MmdsVersion};
 use mmds::ns::MmdsNetworkStack;
 use serde::{Deserialize, Serialize};
@@ -27,6 +28,7 @@ use crate::vmm_config::machine_config::{
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
+use crate::vmm_config::snapshot::{LoadSnapshotParams, StartupSnapshotConfig};
 use crate::vmm_config::vsock::*;
 
 type Result<E> = std::result::Result<(), E>;
@@ -73,6 +75,20 @@ pub enum Error {
     /// Entropy device configuration error.
     #[error("Entropy device error: {0}")]
     EntropyDevice(EntropyDeviceError),
+    /// A section of the configuration can only be set when Firecracker starts.
+    #[from(ignore)]
+    #[error("The {0} can only be configured when Firecracker starts")]
+    StartupOnly(&'static str),
+    /// A device would keep its host resource with a different configuration.
+    #[from(ignore)]
+    #[error(
+        "Device {0} keeps its host resource but changes its configuration, update it through its \
+         own endpoint instead"
+    )]
+    DeviceInUse(String),
+    /// File descriptors can only be passed along with API requests.
+    #[error("The snapshot to load at startup cannot be given by file descriptor")]
+    SnapshotFd,
 }
 
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
@@ -102,6 +118,34 @@ pub struct VmmConfig {
     entropy_device: Option<EntropyDeviceConfig>,
 }
 
+/// Used for configuring a vmm restored from a snapshot, instead of booted, from one single json
+/// passed to the Firecracker process or from the command line.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestoreVmmConfig {
+    /// The snapshot to restore the microVM from.
+    #[serde(rename = "snapshot-load")]
+    pub snapshot_load: StartupSnapshotConfig,
+    /// The logger configuration.
+    #[serde(rename = "logger")]
+    pub logger: Option<LoggerConfig>,
+    /// The metrics configuration.
+    #[serde(rename = "metrics")]
+    pub metrics: Option<MetricsConfig>,
+}
+
+impl RestoreVmmConfig {
+    /// Parses `config_json`, unless it has no `snapshot-load` section, in which case it
+    /// describes a microVM to boot and `None` is returned.
+    pub fn from_json(config_json: &str) -> std::result::Result<Option<Self>, Error> {
+        let value: serde_json::Value = serde_json::from_str(config_json)?;
+        if value.get("snapshot-load").is_none() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(value)?))
+    }
+}
+
 /// A data structure that encapsulates the device configurations
 /// held in the Vmm.
 #[derive(Default)]
@@ -138,13 +182,63 @@ impl VmResources {
         mmds_size_limit: usize,
         metadata_json: Option<&str>,
     ) -> std::result::Result<Self, Error> {
-        let vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())?;
+        let mut vmm_config: VmmConfig =
+            serde_json::from_slice::<VmmConfig>(config_json.as_bytes())?;
+
+        let mut resources = Self::init_startup(
+            vmm_config.logger.take(),
+            vmm_config.metrics.take(),
+            instance_info,
+            mmds_size_limit,
+            metadata_json,
+        )?;
+        resources.configure(vmm_config, &instance_info.id, None)?;
+
+        Ok(resources)
+    }
+
+    /// Configures the logger and metrics of a microVM restored from a snapshot as described by
+    /// `vmm_config`, and returns the resources for the snapshot load to fill in along with the
+    /// parameters of the load.
+    pub fn from_restore_config(
+        vmm_config: RestoreVmmConfig,
+        instance_info: &InstanceInfo,
+        mmds_size_limit: usize,
+        metadata_json: Option<&str>,
+    ) -> std::result::Result<(Self, LoadSnapshotParams), Error> {
+        if vmm_config.snapshot_load.mem_backend.backend_fd.is_some() {
+            return Err(Error::SnapshotFd);
+        }
+
+        let mut resources = Self::init_startup(
+            vmm_config.logger,
+            vmm_config.metrics,
+            instance_info,
+            mmds_size_limit,
+            metadata_json,
+        )?;
+        let params = LoadSnapshotParams::from(vmm_config.snapshot_load);
+        if params.enable_diff_snapshots {
+            resources.set_track_dirty_pages(true);
+        }
 
-        if let Some(logger) = vmm_config.logger {
+        Ok((resources, params))
+    }
+
+    // Initialises the logger, the metrics and the MMDS contents given when Firecracker starts,
+    // and returns otherwise empty resources.
+    fn init_startup(
+        logger: Option<LoggerConfig>,
+        metrics: Option<MetricsConfig>,
+        instance_info: &InstanceInfo,
+        mmds_size_limit: usize,
+        metadata_json: Option<&str>,
+    ) -> std::result::Result<Self, Error> {
+        if let Some(logger) = logger {
             init_logger(logger, instance_info)?;
         }
 
-        if let Some(metrics) = vmm_config.metrics {
+        if let Some(metrics) = metrics {
             init_metrics(metrics)?;
         }
 
@@ -152,52 +246,147 @@ impl VmResources {
             mmds_size_limit,
             ..Default::default()
         };
+
+        // Init the data store from file, if present.
+        if let Some(data) = metadata_json {
+            resources.locked_mmds_or_default().put_data(
+                serde_json::from_str(data).expect("MMDS error: metadata provided not valid json"),
+            )?;
+            info!("Successfully added metadata to mmds from file");
+        }
+
+        Ok(resources)
+    }
+
+    /// Replaces the whole configuration with the one described by `vmm_config`, or leaves it
+    /// untouched if any part of it is invalid. The MMDS contents are kept. Devices which keep
+    /// their configuration keep their tap device or vsock socket.
+    pub fn swap_config(
+        &mut self,
+        vmm_config: VmmConfig,
+        instance_id: &str,
+    ) -> std::result::Result<(), Error> {
+        // The logger and metrics cannot be initialised twice.
+        if vmm_config.logger.is_some() {
+            return Err(Error::StartupOnly("logger"));
+        }
+        if vmm_config.metrics.is_some() {
+            return Err(Error::StartupOnly("metrics"));
+        }
+
+        let mut staged: Self = Self {
+            mmds: self.mmds.clone(),
+            mmds_size_limit: self.mmds_size_limit,
+            boot_timer: self.boot_timer,
+            ..Default::default()
+        };
+        let old_uds_path = self.vsock.config().map(|config| config.uds_path);
+        if let Err(err) = staged.configure(vmm_config, instance_id, Some(self)) {
+            // Remove the socket of a vsock device built for the new configuration.
+            if let Some(config) = staged.vsock.config() {
+                if Some(&config.uds_path) != old_uds_path.as_ref() {
+                    let _ = std::fs::remove_file(config.uds_path);
+                }
+            }
+            return Err(err);
+        }
+
+        // Remove the socket of a vsock device left out of the new configuration.
+        if let Some(uds_path) = old_uds_path {
+            if staged.vsock.config().map(|config| config.uds_path).as_ref() != Some(&uds_path) {
+                if let Err(err) = std::fs::remove_file(&uds_path) {
+                    warn!("Could not remove the vsock socket {}: {}", uds_path, err);
+                }
+            }
+        }
+        *self = staged;
+        Ok(())
+    }
+
+    // Configures the resources as described by `vmm_config`, except for the logger and the
+    // metrics. Devices of `current` which keep their tap device or vsock socket are reused as
+    // they are, as those cannot be opened twice. The MMDS is configured last, as it changes the
+    // reused network devices.
+    fn configure(
+        &mut self,
+        vmm_config: VmmConfig,
+        instance_id: &str,
+        current: Option<&VmResources>,
+    ) -> std::result::Result<(), Error> {
         if let Some(machine_config) = vmm_config.machine_config {
             let machine_config = MachineConfigUpdate::from(machine_config);
-            resources.update_vm_config(&machine_config)?;
+            self.update_vm_config(&machine_config)?;
         }
 
         if let Some(cpu_config) = vmm_config.cpu_config {
             let cpu_config_json = std::fs::read_to_string(cpu_config).map_err(Error::File)?;
             let cpu_template: CustomCpuTemplate = serde_json::from_str(&cpu_config_json)?;
-            resources.set_custom_cpu_template(cpu_template);
+            self.set_custom_cpu_template(cpu_template);
         }
 
-        resources.build_boot_source(vmm_config.boot_source)?;
+        self.build_boot_source(vmm_config.boot_source)?;
 
         for drive_config in vmm_config.block_devices.into_iter() {
-            resources.set_block_device(drive_config)?;
+            self.set_block_device(drive_config)?;
         }
 
         for net_config in vmm_config.net_devices.into_iter() {
-            resources.build_net_device(net_config)?;
-        }
-
-        if let Some(vsock_config) = vmm_config.vsock_device {
-            resources.set_vsock_device(vsock_config)?;
+            let existing = current.and_then(|current| {
+                current.net_builder.iter().find(|net| {
+                    net.lock().expect("Poisoned lock").iface_name() == net_config.host_dev_name
+                })
+            });
+            match existing {
+                Some(net) => {
+                    if NetworkInterfaceConfig::from(net.lock().expect("Poisoned lock").deref())
+                        != net_config
+                    {
+                        return Err(Error::DeviceInUse(net_config.iface_id));
+                    }
+                    self.net_builder.reuse_device(net.clone())?;
+                }
+                None => self.build_net_device(net_config)?,
+            }
         }
 
         if let Some(balloon_config) = vmm_config.balloon_device {
-            resources.set_balloon_device(balloon_config)?;
+            self.set_balloon_device(balloon_config)?;
         }
 
-        // Init the data store from file, if present.
-        if let Some(data) = metadata_json {
-            resources.locked_mmds_or_default().put_data(
-                serde_json::from_str(data).expect("MMDS error: metadata provided not valid json"),
-            )?;
-            info!("Successfully added metadata to mmds from file");
+        if let Some(entropy_device_config) = vmm_config.entropy_device {
+            self.build_entropy_device(entropy_device_config)?;
         }
 
-        if let Some(mmds_config) = vmm_config.mmds_config {
-            resources.set_mmds_config(mmds_config, &instance_info.id)?;
+        if let Some(vsock_config) = vmm_config.vsock_device {
+            let existing = current.and_then(|current| {
+                current
+                    .vsock
+                    .config()
+                    .filter(|config| config.uds_path == vsock_config.uds_path)
+                    .zip(current.vsock.get())
+            });
+            match existing {
+                Some((config, vsock)) => {
+                    if config.guest_cid != vsock_config.guest_cid {
+                        return Err(Error::DeviceInUse("vsock".to_string()));
+                    }
+                    self.vsock.set_device(vsock.clone());
+                }
+                None => self.set_vsock_device(vsock_config)?,
+            }
         }
 
-        if let Some(entropy_device_config) = vmm_config.entropy_device {
-            resources.build_entropy_device(entropy_device_config)?;
+        match vmm_config.mmds_config {
+            Some(mmds_config) => self.set_mmds_config(mmds_config, instance_id)?,
+            // Reused network devices may still forward requests to the MMDS.
+            None => self.net_builder.iter_mut().for_each(|net| {
+                net.lock()
+                    .expect("Poisoned lock")
+                    .disable_mmds_network_stack()
+            }),
         }
 
-        Ok(resources)
+        Ok(())
     }
 
     /// If not initialised, create the mmds data store with the default config.
@@ -1046,6 +1235,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_restore_config_from_json() {
+        let default_instance_info = InstanceInfo::default();
+
+        // A configuration without a `snapshot-load` section describes a microVM to boot.
+        assert_eq!(
+            RestoreVmmConfig::from_json(r#"{ "boot-source": {} }"#).unwrap(),
+            None
+        );
+        assert!(matches!(
+            RestoreVmmConfig::from_json(r#"}"#),
+            Err(Error::InvalidJson(_))
+        ));
+
+        // Boot-specific sections cannot be given along with the snapshot.
+        let json = r#"{
+                "snapshot-load": {
+                    "snapshot_path": "vm.snap",
+                    "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" }
+                },
+                "drives": []
+            }"#;
+        assert!(matches!(
+            RestoreVmmConfig::from_json(json),
+            Err(Error::InvalidJson(_))
+        ));
+
+        let json = r#"{
+                "snapshot-load": {
+                    "snapshot_path": "vm.snap",
+                    "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" },
+                    "enable_diff_snapshots": true,
+                    "resume_vm": true
+                }
+            }"#;
+        let vmm_config = RestoreVmmConfig::from_json(json).unwrap().unwrap();
+        assert!(vmm_config.logger.is_none() && vmm_config.metrics.is_none());
+        let (resources, params) = VmResources::from_restore_config(
+            vmm_config,
+            &default_instance_info,
+            HTTP_MAX_PAYLOAD_SIZE,
+            Some(r#"{ "latest": {} }"#),
+        )
+        .unwrap();
+        assert_eq!(params.snapshot_path, PathBuf::from("vm.snap"));
+        assert!(params.resume_vm);
+        assert!(resources.track_dirty_pages());
+        assert!(resources.mmds.is_some());
+
+        // File descriptors are only received with API requests.
+        let json = r#"{
+                "snapshot-load": {
+                    "snapshot_path": "vm.snap",
+                    "mem_backend": { "backend_fd": 0, "backend_type": "File" }
+                }
+            }"#;
+        let vmm_config = RestoreVmmConfig::from_json(json).unwrap().unwrap();
+        assert!(matches!(
+            VmResources::from_restore_config(
+                vmm_config,
+                &default_instance_info,
+                HTTP_MAX_PAYLOAD_SIZE,
+                None
+            ),
+            Err(Error::SnapshotFd)
+        ));
+    }
+
     #[test]
     fn test_cpu_config_from_invalid_json() {
         // Invalid cpu config file path.
@@ -1341,6 +1598,7 @@ mod tests {
             #[cfg(target_arch = "aarch64")]
             cpu_template: Some(StaticCpuTemplate::V1N1),
             track_dirty_pages: Some(false),
+            vmgenid: Some(false),
         };
 
         assert_ne!(
@@ -1535,6 +1793,76 @@ mod tests {
         assert_eq!(actual_vsock_cfg.lock().unwrap().id(), VSOCK_DEV_ID);
     }
 
+    #[test]
+    fn test_swap_config() {
+        let mut vm_resources = default_vm_resources();
+        let net = vm_resources.net_builder.iter().next().unwrap().clone();
+        let net_cfg = vm_resources.net_builder.configs().pop().unwrap();
+        let kernel_file = TempFile::new().unwrap();
+        let swap_config = |machine_config: &str, guest_mac: &str, extra: &str| {
+            serde_json::from_str::<VmmConfig>(&format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}"
+                    }},
+                    "drives": [],
+                    "machine-config": {},
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "{}",
+                            "host_dev_name": "{}",
+                            "guest_mac": "{}"
+                        }}
+                    ]{}
+                }}"#,
+                kernel_file.as_path().to_str().unwrap(),
+                machine_config,
+                net_cfg.iface_id,
+                net_cfg.host_dev_name,
+                guest_mac,
+                extra
+            ))
+            .unwrap()
+        };
+        let mac = net_cfg.guest_mac.unwrap().to_string();
+        let machine_config = r#"{ "vcpu_count": 2, "mem_size_mib": 256 }"#;
+
+        // The logger cannot be configured again.
+        let logger = r#", "logger": { "log_path": "/dev/null" }"#;
+        assert!(matches!(
+            vm_resources.swap_config(swap_config(machine_config, &mac, logger), ""),
+            Err(Error::StartupOnly("logger"))
+        ));
+
+        // A tap device in use cannot be reconfigured.
+        assert!(matches!(
+            vm_resources.swap_config(swap_config(machine_config, "01:23:45:67:89:0b", ""), ""),
+            Err(Error::DeviceInUse(_))
+        ));
+
+        // Nothing is applied when any section is invalid.
+        let invalid_machine_config = r#"{ "vcpu_count": 2, "mem_size_mib": 0 }"#;
+        assert!(matches!(
+            vm_resources.swap_config(swap_config(invalid_machine_config, &mac, ""), ""),
+            Err(Error::VmConfig(VmConfigError::InvalidMemorySize))
+        ));
+        assert_eq!(vm_resources.vm_config, VmConfig::default());
+        assert_eq!(vm_resources.block.list.len(), 1);
+
+        // A valid configuration replaces the previous one, and unchanged devices are kept.
+        vm_resources
+            .swap_config(swap_config(machine_config, &mac, ""), "")
+            .unwrap();
+        assert_eq!(vm_resources.vm_config.vcpu_count, 2);
+        assert_eq!(vm_resources.vm_config.mem_size_mib, 256);
+        assert!(vm_resources.block.list.is_empty());
+        assert_eq!(vm_resources.net_builder.len(), 1);
+        assert!(Arc::ptr_eq(
+            vm_resources.net_builder.iter().next().unwrap(),
+            &net
+        ));
+    }
+
     #[test]
     fn test_set_net_device() {
         let mut vm_resources = default_vm_resources();
@@ -10,38 +10,54 @@ use seccompiler::BpfThreadMap;
 use serde_json::Value;
 #[cfg(test)]
 use tests::{
-    build_and_boot_microvm, create_snapshot, restore_from_snapshot, MockVmRes as VmResources,
-    MockVmm as Vmm,
+    build_and_boot_microvm, create_snapshot, receive_migration, restore_from_snapshot,
+    send_migration, validate_microvm_for_boot, MockVmRes as VmResources, MockVmm as Vmm,
 };
 
 use super::Error as VmmError;
 #[cfg(not(test))]
 use super::{
-    builder::build_and_boot_microvm, persist::create_snapshot, persist::restore_from_snapshot,
-    resources::VmResources, Vmm,
+    builder::build_and_boot_microvm, builder::validate_microvm_for_boot,
+    migration::receive_migration, migration::send_migration, persist::create_snapshot,
+    persist::restore_from_snapshot, resources::VmResources, Vmm,
 };
 use crate::builder::StartMicrovmError;
 use crate::cpu_config::templates::{CustomCpuTemplate, GuestConfigError};
-use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
-use crate::resources::VmmConfig;
+use crate::lifecycle::{self, LifecycleEvent};
+use crate::memory_dump;
+use crate::migration::MigrationError;
+use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, RestoreTimings, VmInfo};
+use crate::resources::{Error as ResourcesError, VmmConfig};
 use crate::version_map::VERSION_MAP;
 use crate::vmm_config::balloon::{
     BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
-use crate::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceError};
+use crate::vmm_config::drive::{
+    BlockBuilder, BlockDeviceConfig, BlockDeviceState, BlockDeviceUpdateConfig, DriveError,
+};
+use crate::vmm_config::entropy::{
+    EntropyDeviceBuilder, EntropyDeviceConfig, EntropyDeviceError, EntropyDeviceState,
+};
 use crate::vmm_config::instance_info::InstanceInfo;
+use crate::vmm_config::invoke::InvokeTarget;
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate, VmConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
+use crate::vmm_config::migration::{MigrationReceiveParams, MigrationSendParams, MigrationStats};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetBuilder, NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceState,
+    NetworkInterfaceUpdateConfig,
+};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, MemoryDumpStatus, SnapshotType,
+};
+use crate::vmm_config::validation::ValidationReport;
+use crate::vmm_config::vsock::{
+    VsockBuilder, VsockConfigError, VsockDeviceConfig, VsockDeviceState,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
 use crate::vmm_config::{self, RateLimiterUpdate};
 use crate::{EventManager, FcExitCode};
 
@@ -65,16 +81,29 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the configuration and live state of the block device with the given ID.
+    GetBlockDevice(String),
+    /// Get the configuration and live state of the entropy device.
+    GetEntropyDevice,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
+    /// Get where the function invocations are forwarded to the guest. This action can only be
+    /// called after the microVM has booted.
+    GetInvokeTarget,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the progress of the background memory dump started by the last snapshot creation.
+    GetMemoryDumpStatus,
+    /// Get the configuration and live state of the network interface with the given ID.
+    GetNetworkInterface(String),
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
     /// Get microVM instance information.
     GetVmInstanceInfo,
     /// Get microVM version.
     GetVmmVersion,
+    /// Get the configuration and live state of the vsock device.
+    GetVsockDevice,
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
@@ -96,6 +125,9 @@ pub enum VmmAction {
     PutMMDS(Value),
     /// Configure the guest vCPU features.
     PutCpuConfiguration(CustomCpuTemplate),
+    /// Receive a microVM migrated from another Firecracker using as input the
+    /// `MigrationReceiveParams`. This action can only be called before the microVM has booted.
+    ReceiveMigration(MigrationReceiveParams),
     /// Resume the guest, by resuming the microVM VCPUs.
     Resume,
     /// Set the balloon device or update the one that already exists using the
@@ -111,8 +143,15 @@ pub enum VmmAction {
     /// Set the entropy device using `EntropyDeviceConfig` as input. This action can only be called
     /// before the microVM has booted.
     SetEntropyDevice(EntropyDeviceConfig),
+    /// Replace the whole microVM configuration using as input the `VmmConfig` returned by
+    /// `GetFullVmConfig`. Either all of it is applied or none of it. This action can only be
+    /// called before the microVM has booted.
+    SetFullVmConfig(VmmConfig),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
+    /// Migrate the microVM to another Firecracker using as input the `MigrationSendParams`. This
+    /// action can only be called after the microVM has booted.
+    SendMigration(MigrationSendParams),
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
@@ -129,6 +168,9 @@ pub enum VmmAction {
     /// Update the microVM configuration (memory & vcpu) using `VmUpdateConfig` as input. This
     /// action can only be called before the microVM has booted.
     UpdateVmConfiguration(MachineConfigUpdate),
+    /// Run the checks done when launching the microVM, without launching it, and report the
+    /// problems found. This action can only be called before the microVM has booted.
+    ValidateMicroVm,
 }
 
 /// Wrapper for all errors associated with VMM actions.
@@ -137,6 +179,11 @@ pub enum VmmActionError {
     /// The action `SetBalloonDevice` failed because of bad user input.
     #[error("{0}")]
     BalloonConfig(BalloonConfigError),
+    /// The device requested by one of the `GetBlockDevice`, `GetEntropyDevice`,
+    /// `GetNetworkInterface` or `GetVsockDevice` actions does not exist.
+    #[from(ignore)]
+    #[error("The {0} does not exist.")]
+    DeviceNotFound(String),
     /// The action `ConfigureBootSource` failed because of bad user input.
     #[error("{0}")]
     BootSource(BootSourceConfigError),
@@ -153,6 +200,9 @@ pub enum VmmActionError {
     /// `SetEntropyDevice` action failed because of bad user input.
     #[error("{0}")]
     EntropyDevice(EntropyDeviceError),
+    /// The action `SetFullVmConfig` failed because of bad user input.
+    #[error("{0}")]
+    FullVmConfig(ResourcesError),
     /// Internal Vmm error.
     #[error("Internal Vmm error: {0}")]
     InternalVmm(VmmError),
@@ -169,6 +219,12 @@ pub enum VmmActionError {
     /// The action `ConfigureMetrics` failed because of bad user input.
     #[error("{0}")]
     Metrics(MetricsConfigError),
+    /// The action `GetMemoryDumpStatus` failed.
+    #[error("{0}")]
+    MemoryDump(memory_dump::Error),
+    /// One of the actions `SendMigration` or `ReceiveMigration` failed.
+    #[error("Live migration error: {0}")]
+    Migration(MigrationError),
     /// One of the `GetMmds`, `PutMmds` or `PatchMmds` actions failed.
     #[from(ignore)]
     #[error("{0}")]
@@ -208,18 +264,36 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The configuration and live state of a block device.
+    BlockDeviceState(BlockDeviceState),
     /// No data is sent on the channel.
     Empty,
+    /// The configuration and live state of the entropy device.
+    EntropyDeviceState(EntropyDeviceState),
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
+    /// Where the function invocations are forwarded to the guest.
+    InvokeTarget(InvokeTarget),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(MachineConfig),
+    /// Progress of a background memory dump.
+    MemoryDumpStatus(MemoryDumpStatus),
+    /// Summary of a completed outgoing migration.
+    MigrationStats(MigrationStats),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
+    /// The configuration and live state of a network interface.
+    NetworkInterfaceState(NetworkInterfaceState),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
+    /// Time spent in each phase of a snapshot load.
+    RestoreTimings(RestoreTimings),
+    /// The problems which would make the microVM fail to start.
+    ValidationReport(ValidationReport),
     /// The microVM version.
     VmmVersion(String),
+    /// The configuration and live state of the vsock device.
+    VsockDeviceState(VsockDeviceState),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -260,6 +334,36 @@ trait MmdsRequestHandler {
     }
 }
 
+// The device getters are shared by both ApiControllers, which report the state of the same
+// devices before and after boot.
+fn get_block_device(block: &BlockBuilder, drive_id: &str) -> ActionResult {
+    block
+        .state(drive_id)
+        .map(VmmData::BlockDeviceState)
+        .ok_or_else(|| VmmActionError::DeviceNotFound(format!("drive {}", drive_id)))
+}
+
+fn get_entropy_device(entropy: &EntropyDeviceBuilder) -> ActionResult {
+    entropy
+        .state()
+        .map(VmmData::EntropyDeviceState)
+        .ok_or_else(|| VmmActionError::DeviceNotFound("entropy device".to_string()))
+}
+
+fn get_network_interface(net_builder: &NetBuilder, iface_id: &str) -> ActionResult {
+    net_builder
+        .state(iface_id)
+        .map(VmmData::NetworkInterfaceState)
+        .ok_or_else(|| VmmActionError::DeviceNotFound(format!("network interface {}", iface_id)))
+}
+
+fn get_vsock_device(vsock: &VsockBuilder) -> ActionResult {
+    vsock
+        .state()
+        .map(VmmData::VsockDeviceState)
+        .ok_or_else(|| VmmActionError::DeviceNotFound("vsock device".to_string()))
+}
+
 /// Enables pre-boot setup and instantiation of a Firecracker VMM.
 pub struct PrebootApiController<'a> {
     seccomp_filters: &'a BpfThreadMap,
@@ -388,6 +492,9 @@ impl<'a> PrebootApiController<'a> {
     pub fn handle_preboot_request(&mut self, request: VmmAction) -> ActionResult {
         use self::VmmAction::*;
 
+        // The event loop does not run before boot.
+        lifecycle::accept_subscribers();
+
         match request {
             // Supported operations allowed pre-boot.
             ConfigureBootSource(config) => self.set_boot_source(config),
@@ -407,12 +514,18 @@ impl<'a> PrebootApiController<'a> {
                 );
                 Ok(VmmData::FullVmConfig((&*self.vm_resources).into()))
             }
+            GetBlockDevice(drive_id) => get_block_device(&self.vm_resources.block, &drive_id),
+            GetEntropyDevice => get_entropy_device(&self.vm_resources.entropy),
             GetMMDS => self.get_mmds(),
+            GetNetworkInterface(iface_id) => {
+                get_network_interface(&self.vm_resources.net_builder, &iface_id)
+            }
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(self.instance_info.clone())),
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
+            GetVsockDevice => get_vsock_device(&self.vm_resources.vsock),
             InsertBlockDevice(config) => self.insert_block_device(config),
             InsertNetworkDevice(config) => self.insert_net_device(config),
             LoadSnapshot(config) => self
@@ -423,18 +536,29 @@ impl<'a> PrebootApiController<'a> {
                 self.set_custom_cpu_template(custom_cpu_template)
             }
             PutMMDS(value) => self.put_mmds(value),
+            ReceiveMigration(config) => self
+                .receive_migration(&config)
+                .map_err(VmmActionError::Migration),
             SetBalloonDevice(config) => self.set_balloon_device(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
             StartMicroVm => self.start_microvm(),
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             SetEntropyDevice(config) => self.set_entropy_device(config),
+            ValidateMicroVm => Ok(VmmData::ValidationReport(validate_microvm_for_boot(
+                self.vm_resources,
+                self.seccomp_filters,
+            ))),
+            SetFullVmConfig(config) => self.set_full_vm_config(config),
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
             | FlushMetrics
             | Pause
             | Resume
             | GetBalloonStats
+            | GetInvokeTarget
+            | GetMemoryDumpStatus
+            | SendMigration(_)
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
@@ -519,6 +643,14 @@ impl<'a> PrebootApiController<'a> {
         Ok(VmmData::Empty)
     }
 
+    fn set_full_vm_config(&mut self, cfg: VmmConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .swap_config(cfg, &self.instance_info.id)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::FullVmConfig)
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn start_microvm(&mut self) -> ActionResult {
@@ -551,12 +683,13 @@ impl<'a> PrebootApiController<'a> {
             return Err(err);
         }
 
+        let track_dirty_pages = self.vm_resources.track_dirty_pages();
         if load_params.enable_diff_snapshots {
             self.vm_resources.set_track_dirty_pages(true);
         }
         info!("[PASS_debug] restore_from_snapshot API starts for real action ...");
         // Restore VM from snapshot
-        let vmm = restore_from_snapshot(
+        let (vmm, mut timings) = restore_from_snapshot(
             &self.instance_info,
             self.event_manager,
             self.seccomp_filters,
@@ -565,12 +698,19 @@ impl<'a> PrebootApiController<'a> {
             self.vm_resources,
         )
         .map_err(|err| {
-            // If restore fails, we consider the process is too dirty to recover.
-            self.fatal_error = Some(FcExitCode::BadConfiguration);
+            match err {
+                // Cancelled operations stop before building the microVM.
+                RestoreFromSnapshotError::Operation(_) => {
+                    self.vm_resources.set_track_dirty_pages(track_dirty_pages)
+                }
+                // If restore fails, we consider the process is too dirty to recover.
+                _ => self.fatal_error = Some(FcExitCode::BadConfiguration),
+            }
             err
         })?;
         // Resume VM
         if load_params.resume_vm {
+            let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
             vmm.lock()
                 .expect("Poisoned lock")
                 .resume_vm()
@@ -579,21 +719,75 @@ impl<'a> PrebootApiController<'a> {
                     self.fatal_error = Some(FcExitCode::BadConfiguration);
                     err
                 })?;
+            timings.resume_vm_us =
+                utils::time::get_time_us(utils::time::ClockType::Monotonic) - resume_start_us;
         }
         // Set the VM
         self.built_vmm = Some(vmm);
 
+        timings.total_us =
+            update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
+        timings.update_metrics();
         log_dev_preview_warning(
             "Virtual machine snapshots",
             Some(format!(
-                "'load snapshot' VMM action took {} us.",
-                update_metric_with_elapsed_time(
-                    &METRICS.latencies_us.vmm_load_snapshot,
-                    load_start_us
-                )
+                "'load snapshot' VMM action took {} us ({:?}).",
+                timings.total_us, timings
             )),
         );
+        lifecycle::emit(LifecycleEvent::SnapshotLoaded {
+            timings: timings.clone(),
+        });
 
+        Ok(VmmData::RestoreTimings(timings))
+    }
+
+    // On success, this command will end the pre-boot stage and this controller
+    // will be replaced by a runtime controller.
+    fn receive_migration(
+        &mut self,
+        params: &MigrationReceiveParams,
+    ) -> std::result::Result<VmmData, MigrationError> {
+        if self.boot_path {
+            let err = MigrationError::ReceiveNotAllowed;
+            info!("{}", err);
+            return Err(err);
+        }
+
+        if params.enable_diff_snapshots {
+            self.vm_resources.set_track_dirty_pages(true);
+        }
+        let receive_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        let vmm = receive_migration(
+            &self.instance_info,
+            self.event_manager,
+            self.seccomp_filters,
+            params,
+            VERSION_MAP.clone(),
+            self.vm_resources,
+        )
+        .map_err(|err| {
+            // As for snapshot loading, the process is considered too dirty to recover.
+            self.fatal_error = Some(FcExitCode::BadConfiguration);
+            err
+        })?;
+        if params.resume_vm {
+            vmm.lock()
+                .expect("Poisoned lock")
+                .resume_vm()
+                .map_err(|err| {
+                    self.fatal_error = Some(FcExitCode::BadConfiguration);
+                    err
+                })?;
+        }
+        self.built_vmm = Some(vmm);
+
+        let elapsed_time_us =
+            utils::time::get_time_us(utils::time::ClockType::Monotonic) - receive_start_us;
+        info!(
+            "'receive migration' VMM action took {} us.",
+            elapsed_time_us
+        );
         Ok(VmmData::Empty)
     }
 }
@@ -614,6 +808,9 @@ impl RuntimeApiController {
     /// Handles the incoming runtime `VmmAction` request and provides a response for it.
     pub fn handle_request(&mut self, request: VmmAction) -> ActionResult {
         use self::VmmAction::*;
+
+        // The event loop does not run while the microVM is paused.
+        lifecycle::accept_subscribers();
         match request {
             // Supported operations allowed post-boot.
             CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
@@ -632,8 +829,21 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetBlockDevice(drive_id) => get_block_device(&self.vm_resources.block, &drive_id),
+            GetEntropyDevice => get_entropy_device(&self.vm_resources.entropy),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
+            GetInvokeTarget => self.invoke_target(),
             GetMMDS => self.get_mmds(),
+            GetMemoryDumpStatus => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .memory_dump_status()
+                .map(VmmData::MemoryDumpStatus)
+                .map_err(VmmActionError::MemoryDump),
+            GetNetworkInterface(iface_id) => {
+                get_network_interface(&self.vm_resources.net_builder, &iface_id)
+            }
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
@@ -643,12 +853,14 @@ impl RuntimeApiController {
             GetVmmVersion => Ok(VmmData::VmmVersion(
                 self.vmm.lock().expect("Poisoned lock").version(),
             )),
+            GetVsockDevice => get_vsock_device(&self.vm_resources.vsock),
             PatchMMDS(value) => self.patch_mmds(value),
             Pause => self.pause(),
             PutMMDS(value) => self.put_mmds(value),
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            SendMigration(config) => self.send_migration(&config),
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -674,12 +886,15 @@ impl RuntimeApiController {
             | InsertNetworkDevice(_)
             | LoadSnapshot(_)
             | PutCpuConfiguration(_)
+            | ReceiveMigration(_)
             | SetBalloonDevice(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
             | SetEntropyDevice(_)
+            | SetFullVmConfig(_)
             | StartMicroVm
-            | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            | UpdateVmConfiguration(_)
+            | ValidateMicroVm => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
     }
 
@@ -728,6 +943,20 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    // Function invocations are forwarded by the API server, through the Unix socket of the
+    // vsock device, since the VMM thread has to keep moving the data.
+    fn invoke_target(&self) -> ActionResult {
+        let config = self
+            .vm_resources
+            .vsock
+            .config()
+            .ok_or_else(|| VmmActionError::DeviceNotFound("vsock device".to_string()))?;
+        Ok(VmmData::InvokeTarget(InvokeTarget {
+            uds_path: config.uds_path,
+            restore_start_us: self.vmm.lock().expect("Poisoned lock").restore_start_us(),
+        }))
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo to the inner Vmm (if present).
     #[cfg(target_arch = "x86_64")]
     fn send_ctrl_alt_del(&mut self) -> ActionResult {
@@ -762,7 +991,7 @@ impl RuntimeApiController {
             VERSION_MAP.clone(),
         )?;
 
-        match create_params.snapshot_type {
+        let elapsed_time_us = match create_params.snapshot_type {
             SnapshotType::Full => {
                 let elapsed_time_us = update_metric_with_elapsed_time(
                     &METRICS.latencies_us.vmm_full_create_snapshot,
@@ -772,6 +1001,7 @@ impl RuntimeApiController {
                     "'create full snapshot' VMM action took {} us.",
                     elapsed_time_us
                 );
+                elapsed_time_us
             }
             SnapshotType::Diff => {
                 let elapsed_time_us = update_metric_with_elapsed_time(
@@ -782,11 +1012,35 @@ impl RuntimeApiController {
                     "'create diff snapshot' VMM action took {} us.",
                     elapsed_time_us
                 );
+                elapsed_time_us
             }
-        }
+        };
+        lifecycle::emit(LifecycleEvent::SnapshotCreated {
+            snapshot_type: create_params.snapshot_type,
+            duration_us: elapsed_time_us,
+        });
         Ok(VmmData::Empty)
     }
 
+    fn send_migration(&mut self, params: &MigrationSendParams) -> ActionResult {
+        if !self.vm_resources.track_dirty_pages() {
+            return Err(VmmActionError::NotSupported(
+                "Live migration is not allowed on uVMs with dirty page tracking disabled."
+                    .to_string(),
+            ));
+        }
+
+        let mut locked_vmm = self.vmm.lock().expect("Poisoned lock");
+        let vm_info = VmInfo::from(&self.vm_resources);
+        let stats = send_migration(&mut locked_vmm, &vm_info, params, VERSION_MAP.clone())
+            .map_err(VmmActionError::Migration)?;
+        info!(
+            "'send migration' VMM action took {} us, the microVM was paused for {} us.",
+            stats.total_us, stats.downtime_us
+        );
+        Ok(VmmData::MigrationStats(stats))
+    }
+
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device, update the disk image on the
     ///    device and its virtio configuration
@@ -835,6 +1089,7 @@ mod tests {
 
     use mmds::data_store::MmdsVersion;
     use seccompiler::BpfThreadMap;
+    use utils::tempfile::TempFile;
 
     use super::*;
     use crate::cpu_config::templates::test_utils::build_test_template;
@@ -846,7 +1101,10 @@ mod tests {
     use crate::vmm_config::drive::{CacheType, FileEngineType};
     use crate::vmm_config::logger::LoggerLevel;
     use crate::vmm_config::machine_config::VmConfig;
-    use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
+    use crate::vmm_config::migration::MigrationSocket;
+    use crate::vmm_config::snapshot::{
+        DeviceOverrides, MemBackendConfig, MemBackendType, MemoryDumpState, SnapshotFiles,
+    };
     use crate::vmm_config::vsock::VsockBuilder;
     use crate::HTTP_MAX_PAYLOAD_SIZE;
 
@@ -863,7 +1121,9 @@ mod tests {
                     | (LoadSnapshot(_), LoadSnapshot(_))
                     | (Logger(_), Logger(_))
                     | (MachineConfig(_), MachineConfig(_))
+                    | (MemoryDump(_), MemoryDump(_))
                     | (Metrics(_), Metrics(_))
+                    | (Migration(_), Migration(_))
                     | (Mmds(_), Mmds(_))
                     | (MmdsLimitExceeded(_), MmdsLimitExceeded(_))
                     | (MmdsConfig(_), MmdsConfig(_))
@@ -874,6 +1134,8 @@ mod tests {
                     | (StartMicrovm(_), StartMicrovm(_))
                     | (VsockConfig(_), VsockConfig(_))
                     | (EntropyDevice(_), EntropyDevice(_))
+                    | (FullVmConfig(_), FullVmConfig(_))
+                    | (DeviceNotFound(_), DeviceNotFound(_))
             )
         }
     }
@@ -884,6 +1146,9 @@ mod tests {
         pub vm_config: VmConfig,
         pub balloon: BalloonBuilder,
         pub vsock: VsockBuilder,
+        pub block: BlockBuilder,
+        pub net_builder: NetBuilder,
+        pub entropy: EntropyDeviceBuilder,
         balloon_config_called: bool,
         balloon_set: bool,
         boot_src: BootSourceConfig,
@@ -892,6 +1157,7 @@ mod tests {
         vsock_set: bool,
         net_set: bool,
         entropy_set: bool,
+        config_swapped: bool,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
@@ -1000,6 +1266,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn swap_config(&mut self, _: VmmConfig, _: &str) -> Result<(), ResourcesError> {
+            if self.force_errors {
+                return Err(ResourcesError::StartupOnly("logger"));
+            }
+            self.config_swapped = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1057,6 +1331,7 @@ mod tests {
     pub struct MockVmm {
         pub balloon_config_called: bool,
         pub latest_balloon_stats_called: bool,
+        pub memory_dump_status_called: bool,
         pub pause_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
@@ -1065,6 +1340,7 @@ mod tests {
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub restore_start_us: Option<u64>,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1113,6 +1389,19 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
+        pub fn memory_dump_status(&mut self) -> Result<MemoryDumpStatus, memory_dump::Error> {
+            if self.force_errors {
+                return Err(memory_dump::Error::NoDump);
+            }
+            self.memory_dump_status_called = true;
+            Ok(MemoryDumpStatus {
+                state: MemoryDumpState::Done,
+                mem_file_path: PathBuf::new(),
+                bytes_written: 0,
+                total_bytes: 0,
+            })
+        }
+
         pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1172,6 +1461,10 @@ mod tests {
         pub fn version(&self) -> String {
             String::default()
         }
+
+        pub fn restore_start_us(&self) -> Option<u64> {
+            self.restore_start_us
+        }
     }
 
     // Need to redefine this since the non-test one uses real VmResources
@@ -1185,6 +1478,16 @@ mod tests {
         Ok(Arc::new(Mutex::new(MockVmm::default())))
     }
 
+    // Need to redefine this since the non-test one uses real VmResources
+    // instead of our mocks.
+    pub fn validate_microvm_for_boot(vm_res: &VmResources, _: &BpfThreadMap) -> ValidationReport {
+        let mut report = ValidationReport::new();
+        if !vm_res.boot_cfg_set {
+            report.add("boot-source", None, StartMicrovmError::MissingKernelConfig);
+        }
+        report
+    }
+
     // Need to redefine this since the non-test one uses real Vmm
     // instead of our mocks.
     pub fn create_snapshot(
@@ -1205,7 +1508,37 @@ mod tests {
         _: &LoadSnapshotParams,
         _: versionize::VersionMap,
         _: &mut MockVmRes,
-    ) -> Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
+    ) -> Result<(Arc<Mutex<Vmm>>, RestoreTimings), RestoreFromSnapshotError> {
+        Ok((
+            Arc::new(Mutex::new(MockVmm::default())),
+            RestoreTimings::default(),
+        ))
+    }
+
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn send_migration(
+        vmm: &mut Vmm,
+        _: &VmInfo,
+        _: &MigrationSendParams,
+        _: versionize::VersionMap,
+    ) -> Result<MigrationStats, MigrationError> {
+        if vmm.force_errors {
+            return Err(MigrationError::Rejected);
+        }
+        Ok(MigrationStats::default())
+    }
+
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn receive_migration(
+        _: &InstanceInfo,
+        _: &mut EventManager,
+        _: &BpfThreadMap,
+        _: &MigrationReceiveParams,
+        _: versionize::VersionMap,
+        _: &mut MockVmRes,
+    ) -> Result<Arc<Mutex<Vmm>>, MigrationError> {
         Ok(Arc::new(Mutex::new(MockVmm::default())))
     }
 
@@ -1296,6 +1629,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_get_device_state() {
+        check_preboot_request_err(
+            VmmAction::GetBlockDevice(String::from("rootfs")),
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterface(String::from("eth0")),
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+        check_preboot_request_err(
+            VmmAction::GetVsockDevice,
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+        check_preboot_request_err(
+            VmmAction::GetEntropyDevice,
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+    }
+
     #[test]
     fn test_preboot_get_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1447,6 +1800,33 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_validate_microvm() {
+        check_preboot_request(VmmAction::ValidateMicroVm, |result, _| {
+            let report = match result {
+                Ok(VmmData::ValidationReport(report)) => report,
+                other => panic!("Unexpected result: {:?}", other),
+            };
+            assert!(!report.valid);
+            assert_eq!(report.problems[0].resource, "boot-source");
+        });
+    }
+
+    #[test]
+    fn test_preboot_set_full_vm_config() {
+        let req = VmmAction::SetFullVmConfig(VmmConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.config_swapped);
+        });
+
+        let req = VmmAction::SetFullVmConfig(VmmConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::FullVmConfig(ResourcesError::StartupOnly("logger")),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
@@ -1704,12 +2084,20 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                backend_fd: None,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: false,
         });
-        // Request should succeed.
-        preboot.handle_preboot_request(req).unwrap();
+        // Request should succeed and report the restore timings.
+        assert!(matches!(
+            preboot.handle_preboot_request(req),
+            Ok(VmmData::RestoreTimings(_))
+        ));
         // Should have built default mock vmm.
         let vmm = preboot.built_vmm.take().unwrap();
         assert_eq!(*vmm.lock().unwrap(), MockVmm::default());
@@ -1720,8 +2108,13 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                backend_fd: None,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: true,
         });
         // Request should succeed.
@@ -1751,6 +2144,14 @@ mod tests {
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetMemoryDumpStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetInvokeTarget,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1777,8 +2178,25 @@ mod tests {
             VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
+                snapshot_fd: None,
                 mem_file_path: PathBuf::new(),
+                mem_file_fd: None,
                 version: None,
+                encryption: None,
+                background: false,
+                chain_metadata: false,
+                asynchronous: false,
+                files: SnapshotFiles::default(),
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::SendMigration(MigrationSendParams {
+                socket_path: Some(PathBuf::new()),
+                socket_fd: None,
+                max_rounds: 1,
+                dirty_pages_threshold: 0,
+                socket: MigrationSocket::default(),
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1789,6 +2207,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_receive_migration() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let seccomp_filters = BpfThreadMap::new();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr, &seccomp_filters);
+
+        let req = VmmAction::ReceiveMigration(MigrationReceiveParams {
+            socket_path: Some(PathBuf::new()),
+            socket_fd: None,
+            enable_diff_snapshots: true,
+            resume_vm: true,
+            socket: MigrationSocket::default(),
+        });
+        assert_eq!(preboot.handle_preboot_request(req), Ok(VmmData::Empty));
+        // Should have built mock vmm then called resume on it.
+        let vmm = preboot.built_vmm.take().unwrap();
+        assert!(vmm.lock().unwrap().resume_called);
+        assert!(vm_resources.track_dirty_pages());
+    }
+
     #[test]
     fn test_build_microvm_from_requests() {
         // Use atomics to be able to use them non-mutably in closures below.
@@ -1892,6 +2331,64 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_get_device_state() {
+        let mut vm_res = MockVmRes::default();
+        vm_res
+            .entropy
+            .insert(EntropyDeviceConfig::default())
+            .unwrap();
+        let expected_state = vm_res.entropy.state().unwrap();
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm);
+        assert_eq!(
+            runtime.handle_request(VmmAction::GetEntropyDevice),
+            Ok(VmmData::EntropyDeviceState(expected_state))
+        );
+
+        check_runtime_request_err(
+            VmmAction::GetBlockDevice(String::from("rootfs")),
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+        check_runtime_request_err(
+            VmmAction::GetVsockDevice,
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+    }
+
+    #[test]
+    fn test_runtime_get_invoke_target() {
+        check_runtime_request_err(
+            VmmAction::GetInvokeTarget,
+            VmmActionError::DeviceNotFound(String::new()),
+        );
+
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let uds_path = tmp_sock_file.as_path().to_str().unwrap().to_string();
+        let mut vm_res = MockVmRes::default();
+        vm_res
+            .vsock
+            .insert(VsockDeviceConfig {
+                vsock_id: None,
+                guest_cid: 3,
+                uds_path: uds_path.clone(),
+            })
+            .unwrap();
+        let vmm = Arc::new(Mutex::new(MockVmm {
+            restore_start_us: Some(42),
+            ..Default::default()
+        }));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm);
+        assert_eq!(
+            runtime.handle_request(VmmAction::GetInvokeTarget),
+            Ok(VmmData::InvokeTarget(InvokeTarget {
+                uds_path,
+                restore_start_us: Some(42),
+            }))
+        );
+    }
+
     #[test]
     fn test_runtime_pause() {
         let req = VmmAction::Pause;
@@ -1967,6 +2464,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_memory_dump_status() {
+        let req = VmmAction::GetMemoryDumpStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert!(matches!(result, Ok(VmmData::MemoryDumpStatus(_))));
+            assert!(vmm.memory_dump_status_called)
+        });
+
+        let req = VmmAction::GetMemoryDumpStatus;
+        check_runtime_request_err(req, VmmActionError::MemoryDump(memory_dump::Error::NoDump));
+    }
+
     #[test]
     fn test_runtime_update_balloon_config() {
         let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
@@ -2049,6 +2558,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_send_migration() {
+        let params = || MigrationSendParams {
+            socket_path: Some(PathBuf::new()),
+            socket_fd: None,
+            max_rounds: 1,
+            dirty_pages_threshold: 0,
+            socket: MigrationSocket::default(),
+        };
+
+        // Dirty page tracking is required.
+        check_runtime_request_err(
+            VmmAction::SendMigration(params()),
+            VmmActionError::NotSupported(String::new()),
+        );
+
+        let vm_res = MockVmRes {
+            vm_config: VmConfig {
+                track_dirty_pages: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm.clone());
+        assert_eq!(
+            runtime.handle_request(VmmAction::SendMigration(params())),
+            Ok(VmmData::MigrationStats(MigrationStats::default()))
+        );
+
+        vmm.lock().unwrap().force_errors = true;
+        assert_eq!(
+            runtime.handle_request(VmmAction::SendMigration(params())),
+            Err(VmmActionError::Migration(MigrationError::Rejected))
+        );
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
@@ -2131,8 +2677,13 @@ mod tests {
                 mem_backend: MemBackendConfig {
                     backend_type: MemBackendType::File,
                     backend_path: PathBuf::new(),
+                    backend_fd: None,
                 },
+                diff_chain: Vec::new(),
                 enable_diff_snapshots: false,
+                encryption: None,
+                device_overrides: DeviceOverrides::default(),
+                files: SnapshotFiles::default(),
                 resume_vm: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
@@ -2141,6 +2692,24 @@ mod tests {
             VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetFullVmConfig(VmmConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::ValidateMicroVm,
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::ReceiveMigration(MigrationReceiveParams {
+                socket_path: Some(PathBuf::new()),
+                socket_fd: None,
+                enable_diff_snapshots: false,
+                resume_vm: false,
+                socket: MigrationSocket::default(),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
     }
 
     fn verify_load_snap_disallowed_after_boot_resources(res: VmmAction, res_name: &str) {
@@ -2157,8 +2726,13 @@ mod tests {
             mem_backend: MemBackendConfig {
                 backend_type: MemBackendType::File,
                 backend_path: PathBuf::new(),
+                backend_fd: None,
             },
+            diff_chain: Vec::new(),
             enable_diff_snapshots: false,
+            encryption: None,
+            device_overrides: DeviceOverrides::default(),
+            files: SnapshotFiles::default(),
             resume_vm: false,
         });
         let err = preboot.handle_preboot_request(req);
@@ -2219,5 +2793,8 @@ mod tests {
             network_interfaces: Vec::new(),
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
+
+        let req = VmmAction::SetFullVmConfig(VmmConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetFullVmConfig");
     }
 }
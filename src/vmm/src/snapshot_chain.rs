@@ -0,0 +1,617 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Chains of diff snapshots loaded without rebasing them onto their base first.
+//!
+//! Memory files written by Firecracker on request get a metadata file next to them
+//! (`<mem_file>.meta.json`) recording their content identifier and the identifier of the
+//! snapshot they were taken on top of. The identifier names the guest memory contents obtained
+//! once the file is applied on top of its parent, so it is derived from the parent identifier
+//! and from the data stored in the file. It is computed while the file is written.
+//!
+//! A chain is a full snapshot followed by diff snapshots, each one the child of the previous
+//! one. It is loaded by mapping the full snapshot and then, for each diff, loading its data
+//! extents privately over the pages loaded so far.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use aws_lc_rs::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use utils::get_page_size;
+use utils::seek_hole::SeekHole;
+use utils::vm_memory::{GuestMemory, GuestMemoryMmap};
+
+use crate::memory_snapshot::GuestMemoryState;
+use crate::vmm_config::snapshot::SnapshotType;
+
+/// Suffix appended to the path of a memory file to get the path of its metadata file.
+pub const METADATA_SUFFIX: &str = ".meta.json";
+
+/// Size of the buffer used to hash the data extents of a memory file.
+const HASH_CHUNK_SIZE: usize = 1 << 20;
+/// Granularity at which the offsets of the data are hashed along with it.
+const HASH_BLOCK_SIZE: u64 = 4096;
+/// Data extents of a diff snapshot shorter than this are copied into the guest memory rather
+/// than mapped, so that fragmented diffs do not create a mapping per run of dirty pages.
+const MIN_MAPPED_EXTENT: u64 = 2 << 20;
+
+/// Errors associated with diff snapshot chains.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A diff snapshot is not the child of the previous snapshot of the chain.
+    #[error(
+        "{0:?} was not taken on top of the previous snapshot of the chain: expected parent \
+         {1}, found {2:?}"
+    )]
+    BrokenChain(PathBuf, String, Option<String>),
+    /// The chain does not start with a full snapshot.
+    #[error("{0:?} is a diff snapshot and cannot start a chain")]
+    DiffBase(PathBuf),
+    /// Only memory files can be layered.
+    #[error("Diff snapshot chains can only be loaded with the `File` memory backend")]
+    InvalidBackend,
+    /// Failed to map a diff snapshot over the guest memory.
+    #[error("Failed to map {0:?} over the guest memory: {1}")]
+    Map(PathBuf, io::Error),
+    /// The memory files of the chain do not describe the same amount of memory.
+    #[error("{0:?} holds {1:#x} bytes of guest memory, the chain holds {2:#x}")]
+    MemSizeMismatch(PathBuf, u64, u64),
+    /// Cannot fetch the system's page size.
+    #[error("Cannot fetch system's page size: {0}")]
+    PageSize(utils::errno::Error),
+    /// Failed to parse a metadata file.
+    #[error("Failed to parse metadata file {0:?}: {1}")]
+    ParseMetadata(PathBuf, serde_json::Error),
+    /// Failed to read a memory or metadata file.
+    #[error("Failed to read {0:?}: {1}")]
+    Read(PathBuf, io::Error),
+    /// A data extent of a diff snapshot is not page aligned.
+    #[error("Data extent at {1:#x} of {0:?} is not page aligned")]
+    UnalignedExtent(PathBuf, u64),
+    /// Failed to write a metadata file.
+    #[error("Failed to write {0:?}: {1}")]
+    Write(PathBuf, io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Describes where a memory file sits in a chain of snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotMetadata {
+    /// Identifies the guest memory contents obtained by applying the file on top of its
+    /// parent.
+    pub id: String,
+    /// Identifier of the snapshot this diff snapshot was taken on top of. Always empty for
+    /// full snapshots.
+    pub parent_id: Option<String>,
+    /// Whether the file holds the whole guest memory or only the pages dirtied since the
+    /// parent was taken.
+    pub snapshot_type: SnapshotType,
+    /// Length of the memory file, in bytes.
+    pub mem_size: u64,
+    /// Identifiers of the diff snapshots taken on top of this one.
+    #[serde(default)]
+    pub children: Vec<String>,
+}
+
+/// The last snapshot the guest memory of a microVM was saved to or loaded from, i.e. the
+/// parent of the next diff snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainLink {
+    /// Content identifier of the snapshot.
+    pub id: String,
+    /// Path of the memory file of the snapshot.
+    pub mem_file_path: PathBuf,
+}
+
+/// Returns the path of the metadata file of `mem_file_path`.
+pub fn metadata_path(mem_file_path: &Path) -> PathBuf {
+    let mut path = mem_file_path.as_os_str().to_owned();
+    path.push(METADATA_SUFFIX);
+    PathBuf::from(path)
+}
+
+impl SnapshotMetadata {
+    /// Reads the metadata file of `mem_file_path`.
+    pub fn load(mem_file_path: &Path) -> Result<Self> {
+        let path = metadata_path(mem_file_path);
+        let contents = fs::read(&path).map_err(|err| Error::Read(path.clone(), err))?;
+        serde_json::from_slice(&contents).map_err(|err| Error::ParseMetadata(path, err))
+    }
+
+    /// Writes the metadata file of `mem_file_path`.
+    pub fn save(&self, mem_file_path: &Path) -> Result<()> {
+        let path = metadata_path(mem_file_path);
+        // Safe to unwrap because the structure only holds strings and integers.
+        let contents = serde_json::to_vec_pretty(self).unwrap();
+        fs::write(&path, contents).map_err(|err| Error::Write(path, err))
+    }
+}
+
+/// Returns the `[start, end)` ranges of `file` holding data, as opposed to holes.
+pub fn data_extents(file: &mut File) -> io::Result<Vec<(u64, u64)>> {
+    let len = file.metadata()?.len();
+    let mut extents = Vec::new();
+    let mut cursor = 0;
+    while let Some(start) = file.seek_data(cursor)? {
+        let end = file.seek_hole(start)?.unwrap_or(len);
+        extents.push((start, end));
+        cursor = end;
+    }
+    Ok(extents)
+}
+
+/// Computes the content identifier of a memory file taken on top of `parent_id`.
+///
+/// The identifier is a SHA-256 digest of `parent_id` followed by the data stored in the file,
+/// in increasing offset order, with the offset of every 4 KiB block hashed before it. Holes
+/// are skipped, so a diff snapshot is identified by the pages it holds.
+pub struct ContentHasher {
+    context: Context,
+}
+
+impl ContentHasher {
+    /// Starts hashing a memory file taken on top of `parent_id`.
+    pub fn new(parent_id: Option<&str>) -> Self {
+        let mut context = Context::new(&SHA256);
+        context.update(parent_id.unwrap_or_default().as_bytes());
+        ContentHasher { context }
+    }
+
+    /// Hashes `buf`, stored at offset `pos` of the file. Data must be hashed in increasing
+    /// offset order.
+    pub fn update(&mut self, mut pos: u64, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let in_block = pos % HASH_BLOCK_SIZE;
+            if in_block == 0 {
+                self.context.update(&pos.to_le_bytes());
+            }
+            let count = buf.len().min((HASH_BLOCK_SIZE - in_block) as usize);
+            self.context.update(&buf[..count]);
+            pos += count as u64;
+            buf = &buf[count..];
+        }
+    }
+
+    /// Returns the content identifier of the data hashed so far.
+    pub fn finish(self) -> String {
+        self.context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Passes writes through to a memory file, feeding them to a [`ContentHasher`] if any, so that
+/// the file does not have to be read back to compute its content identifier.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<ContentHasher>,
+    pos: u64,
+}
+
+impl<W> HashingWriter<W> {
+    /// Wraps `inner`, positioned at the start of the file.
+    pub fn new(inner: W, hasher: Option<ContentHasher>) -> Self {
+        HashingWriter {
+            inner,
+            hasher,
+            pos: 0,
+        }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the wrapped writer and the hasher fed with the data written.
+    pub fn into_parts(self) -> (W, Option<ContentHasher>) {
+        (self.inner, self.hasher)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(self.pos, &buf[..count]);
+        }
+        self.pos += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for HashingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Computes the content identifier of the memory `file` taken on top of `parent_id` by reading
+/// back its data extents.
+pub fn content_id(file: &mut File, parent_id: Option<&str>) -> io::Result<String> {
+    let mut hasher = ContentHasher::new(parent_id);
+    let mut buf = vec![0u8; HASH_CHUNK_SIZE];
+    for (start, end) in data_extents(file)? {
+        // Seek and read rather than `pread`, which the seccomp filter of the VMM thread does
+        // not allow.
+        file.seek(SeekFrom::Start(start))?;
+        let mut pos = start;
+        while pos < end {
+            let count = HASH_CHUNK_SIZE.min((end - pos) as usize);
+            file.read_exact(&mut buf[..count])?;
+            hasher.update(pos, &buf[..count]);
+            pos += count as u64;
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Writes the metadata of the memory file just saved at `mem_file_path`, whose content
+/// identifier is `id`, and records it as a child of `parent`, if any. Returns the link to the
+/// new snapshot.
+pub fn record_snapshot(
+    mem_file_path: &Path,
+    snapshot_type: SnapshotType,
+    parent: Option<&ChainLink>,
+    id: String,
+) -> Result<ChainLink> {
+    let read_err = |err| Error::Read(mem_file_path.to_path_buf(), err);
+    let parent_id = parent.map(|link| link.id.as_str());
+    let file = File::open(mem_file_path).map_err(read_err)?;
+    let mem_size = file.metadata().map_err(read_err)?.len();
+    let metadata = SnapshotMetadata {
+        id,
+        parent_id: parent_id.map(str::to_string),
+        snapshot_type,
+        mem_size,
+        children: Vec::new(),
+    };
+    metadata.save(mem_file_path)?;
+
+    // The parent files may have been moved since; the child link is informative only.
+    if let Some(parent) = parent {
+        if let Ok(mut parent_metadata) = SnapshotMetadata::load(&parent.mem_file_path) {
+            if parent_metadata.id == parent.id && !parent_metadata.children.contains(&metadata.id) {
+                parent_metadata.children.push(metadata.id.clone());
+                parent_metadata.save(&parent.mem_file_path)?;
+            }
+        }
+    }
+
+    Ok(ChainLink {
+        id: metadata.id,
+        mem_file_path: mem_file_path.to_path_buf(),
+    })
+}
+
+/// Checks that `chain[i]` was taken on top of `chain[i - 1]` for every `i`.
+///
+/// `chain` holds the memory file paths along with their metadata. The first snapshot may be
+/// a diff snapshot, e.g. when compacting the middle of a chain.
+pub fn validate_links(chain: &[(PathBuf, SnapshotMetadata)]) -> Result<()> {
+    for pair in chain.windows(2) {
+        let (_, parent) = &pair[0];
+        let (path, child) = &pair[1];
+        if child.snapshot_type != SnapshotType::Diff
+            || child.parent_id.as_deref() != Some(parent.id.as_str())
+        {
+            return Err(Error::BrokenChain(
+                path.clone(),
+                parent.id.clone(),
+                child.parent_id.clone(),
+            ));
+        }
+        if child.mem_size != parent.mem_size {
+            return Err(Error::MemSizeMismatch(
+                path.clone(),
+                child.mem_size,
+                parent.mem_size,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the metadata of a chain starting with the full snapshot `base_path` and followed by
+/// the diff snapshots `diff_paths`, oldest first, and checks that it is unbroken. Returns the
+/// link to the last snapshot of the chain.
+pub fn validate_chain(base_path: &Path, diff_paths: &[PathBuf]) -> Result<ChainLink> {
+    let chain = std::iter::once(base_path)
+        .chain(diff_paths.iter().map(PathBuf::as_path))
+        .map(|path| Ok((path.to_path_buf(), SnapshotMetadata::load(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    if chain[0].1.snapshot_type != SnapshotType::Full {
+        return Err(Error::DiffBase(base_path.to_path_buf()));
+    }
+    validate_links(&chain)?;
+
+    // Safe to unwrap because the chain holds at least the base.
+    let (mem_file_path, metadata) = chain.into_iter().last().unwrap();
+    Ok(ChainLink {
+        id: metadata.id,
+        mem_file_path,
+    })
+}
+
+/// Loads the data extents of the diff snapshot at `path` over `guest_memory`, replacing the
+/// pages loaded from the previous snapshots of the chain.
+///
+/// Large extents are mapped privately and small ones are copied: either way, guest writes
+/// never reach the file.
+pub fn map_diff(
+    guest_memory: &GuestMemoryMmap,
+    mem_state: &GuestMemoryState,
+    path: &Path,
+) -> Result<()> {
+    let page_size = get_page_size().map_err(Error::PageSize)? as u64;
+    let mut file = File::open(path).map_err(|err| Error::Read(path.to_path_buf(), err))?;
+    let extents = data_extents(&mut file).map_err(|err| Error::Read(path.to_path_buf(), err))?;
+
+    for (start, end) in extents {
+        if start % page_size != 0 || end % page_size != 0 {
+            return Err(Error::UnalignedExtent(path.to_path_buf(), start));
+        }
+        for (region, region_state) in guest_memory.iter().zip(mem_state.regions.iter()) {
+            let region_start = region_state.offset;
+            let region_end = region_start + region_state.size as u64;
+            let map_start = start.max(region_start);
+            let map_end = end.min(region_end);
+            if map_start >= map_end {
+                continue;
+            }
+            // SAFETY: Safe because the range lies within the mapping of the region.
+            let host_addr = unsafe { region.as_ptr().add((map_start - region_start) as usize) };
+            let len = (map_end - map_start) as usize;
+
+            if map_end - map_start < MIN_MAPPED_EXTENT {
+                // SAFETY: Safe because the range lies within the mapping of the region, which
+                // nothing else accesses while the snapshot is loaded.
+                let pages = unsafe { std::slice::from_raw_parts_mut(host_addr, len) };
+                file.seek(SeekFrom::Start(map_start))
+                    .and_then(|_| file.read_exact(pages))
+                    .map_err(|err| Error::Read(path.to_path_buf(), err))?;
+                continue;
+            }
+
+            // SAFETY: Safe because the range lies within the mapping of the region and the
+            // file descriptor is valid. The previous pages of the range are replaced.
+            let addr = unsafe {
+                libc::mmap(
+                    host_addr.cast(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_FIXED | libc::MAP_PRIVATE | libc::MAP_NORESERVE | libc::MAP_POPULATE,
+                    file.as_raw_fd(),
+                    map_start as libc::off_t,
+                )
+            };
+            if addr == libc::MAP_FAILED {
+                return Err(Error::Map(path.to_path_buf(), io::Error::last_os_error()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::FileExt;
+
+    use utils::tempfile::TempFile;
+    use utils::vm_memory::{Bytes, GuestAddress};
+
+    use super::*;
+    use crate::memory_snapshot::{GuestMemoryRegionState, SnapshotMemory};
+
+    fn page_size() -> usize {
+        get_page_size().unwrap()
+    }
+
+    // A sparse file of `pages` pages holding `fill` in the pages listed in `data_pages`.
+    fn memory_file(pages: usize, data_pages: &[usize], fill: u8) -> TempFile {
+        let file = TempFile::new().unwrap();
+        file.as_file()
+            .set_len((pages * page_size()) as u64)
+            .unwrap();
+        for page in data_pages {
+            file.as_file()
+                .write_all_at(&vec![fill; page_size()], (page * page_size()) as u64)
+                .unwrap();
+        }
+        file
+    }
+
+    // Records the metadata of `file`, reading back its contents to identify it.
+    fn record(
+        file: &TempFile,
+        snapshot_type: SnapshotType,
+        parent: Option<&ChainLink>,
+    ) -> ChainLink {
+        let parent_id = parent.map(|link| link.id.as_str());
+        let id = content_id(&mut File::open(file.as_path()).unwrap(), parent_id).unwrap();
+        record_snapshot(file.as_path(), snapshot_type, parent, id).unwrap()
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let file = memory_file(2, &[0, 1], 1);
+        let link = record(&file, SnapshotType::Full, None);
+        let base = SnapshotMetadata::load(file.as_path()).unwrap();
+        assert_eq!(base.id, link.id);
+        assert_eq!(base.parent_id, None);
+        assert_eq!(base.mem_size, 2 * page_size() as u64);
+
+        let diff = memory_file(2, &[1], 2);
+        let diff_link = record(&diff, SnapshotType::Diff, Some(&link));
+        let metadata = SnapshotMetadata::load(diff.as_path()).unwrap();
+        assert_eq!(metadata.parent_id, Some(link.id.clone()));
+        assert_eq!(
+            SnapshotMetadata::load(file.as_path()).unwrap().children,
+            vec![diff_link.id.clone()]
+        );
+
+        std::fs::remove_file(metadata_path(file.as_path())).unwrap();
+        std::fs::remove_file(metadata_path(diff.as_path())).unwrap();
+    }
+
+    #[test]
+    fn test_content_id() {
+        let mut file = File::open(memory_file(4, &[1, 2], 1).as_path()).unwrap();
+        let id = content_id(&mut file, None).unwrap();
+        assert_eq!(id.len(), 64);
+        assert_eq!(content_id(&mut file, None).unwrap(), id);
+        assert_ne!(content_id(&mut file, Some(&id)).unwrap(), id);
+
+        // Same contents at other offsets.
+        let mut other = File::open(memory_file(4, &[0, 1], 1).as_path()).unwrap();
+        assert_ne!(content_id(&mut other, None).unwrap(), id);
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let page_size = page_size();
+        // Data written in pieces, with a hole left by seeking over a page.
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(4 * page_size as u64).unwrap();
+        let mut writer = HashingWriter::new(file.as_file(), Some(ContentHasher::new(Some("p"))));
+        writer.write_all(&vec![1u8; page_size / 2]).unwrap();
+        writer.write_all(&vec![1u8; page_size / 2]).unwrap();
+        writer.seek(SeekFrom::Start(2 * page_size as u64)).unwrap();
+        writer.write_all(&vec![2u8; 2 * page_size]).unwrap();
+        let (_, hasher) = writer.into_parts();
+
+        let mut read_back = File::open(file.as_path()).unwrap();
+        assert_eq!(
+            hasher.unwrap().finish(),
+            content_id(&mut read_back, Some("p")).unwrap()
+        );
+
+        // Without a hasher, writes are only passed through.
+        let mut writer = HashingWriter::new(Vec::new(), None);
+        writer.write_all(&[3u8; 16]).unwrap();
+        let (inner, hasher) = writer.into_parts();
+        assert_eq!(inner, vec![3u8; 16]);
+        assert!(hasher.is_none());
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let base = memory_file(2, &[0, 1], 1);
+        let diff1 = memory_file(2, &[0], 2);
+        let diff2 = memory_file(2, &[1], 3);
+        let base_link = record(&base, SnapshotType::Full, None);
+        let link1 = record(&diff1, SnapshotType::Diff, Some(&base_link));
+        let link2 = record(&diff2, SnapshotType::Diff, Some(&link1));
+        let diffs = vec![diff1.as_path().to_path_buf(), diff2.as_path().to_path_buf()];
+
+        assert_eq!(validate_chain(base.as_path(), &diffs).unwrap(), link2);
+        assert_eq!(validate_chain(base.as_path(), &[]).unwrap(), base_link);
+        assert!(matches!(
+            validate_chain(base.as_path(), &diffs[1..]),
+            Err(Error::BrokenChain(..))
+        ));
+        assert!(matches!(
+            validate_chain(diff1.as_path(), &diffs[1..]),
+            Err(Error::DiffBase(_))
+        ));
+
+        let unknown = memory_file(2, &[0], 4);
+        let diffs = vec![unknown.as_path().to_path_buf()];
+        assert!(matches!(
+            validate_chain(base.as_path(), &diffs),
+            Err(Error::Read(..))
+        ));
+
+        for file in [&base, &diff1, &diff2] {
+            std::fs::remove_file(metadata_path(file.as_path())).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_map_diff() {
+        let page_size = page_size();
+        // Two regions of two pages each, separated by a hole in the guest physical space.
+        let mem_state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: 2 * page_size,
+                    offset: 0,
+                },
+                GuestMemoryRegionState {
+                    base_address: 4 * page_size as u64,
+                    size: 2 * page_size,
+                    offset: 2 * page_size as u64,
+                },
+            ],
+        };
+        let base = memory_file(4, &[0, 1, 2, 3], 1);
+        let diff1 = memory_file(4, &[1, 2], 2);
+        let diff2 = memory_file(4, &[2], 3);
+
+        let guest_memory =
+            GuestMemoryMmap::restore(Some(base.as_file()), &mem_state, false).unwrap();
+        map_diff(&guest_memory, &mem_state, diff1.as_path()).unwrap();
+        map_diff(&guest_memory, &mem_state, diff2.as_path()).unwrap();
+
+        for (addr, expected) in [(0, 1u8), (1, 2), (4, 3), (5, 1)] {
+            let mut page = vec![0u8; page_size];
+            guest_memory
+                .read_slice(&mut page, GuestAddress((addr * page_size) as u64))
+                .unwrap();
+            assert!(page.iter().all(|&byte| byte == expected), "page {}", addr);
+        }
+
+        // Guest writes stay private.
+        guest_memory
+            .write_slice(&[9u8; 8], GuestAddress(page_size as u64))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        diff1
+            .as_file()
+            .read_exact_at(&mut buf, page_size as u64)
+            .unwrap();
+        assert_eq!(buf, [2u8; 8]);
+    }
+    #[test]
+    fn test_map_large_diff() {
+        let page_size = page_size();
+        let pages = 2 * MIN_MAPPED_EXTENT as usize / page_size;
+        let mem_state = GuestMemoryState {
+            regions: vec![GuestMemoryRegionState {
+                base_address: 0,
+                size: pages * page_size,
+                offset: 0,
+            }],
+        };
+        let base = memory_file(pages, &(0..pages).collect::<Vec<_>>(), 1);
+        // One extent large enough to be mapped, and a single page copied.
+        let mapped: Vec<usize> = (0..pages / 2 + 1).collect();
+        let diff = memory_file(pages, &[mapped.as_slice(), &[pages - 1]].concat(), 2);
+
+        let guest_memory =
+            GuestMemoryMmap::restore(Some(base.as_file()), &mem_state, false).unwrap();
+        map_diff(&guest_memory, &mem_state, diff.as_path()).unwrap();
+
+        for (page, expected) in [(0, 2u8), (pages / 2, 2), (pages / 2 + 1, 1), (pages - 1, 2)] {
+            let mut buf = vec![0u8; page_size];
+            guest_memory
+                .read_slice(&mut buf, GuestAddress((page * page_size) as u64))
+                .unwrap();
+            assert!(buf.iter().all(|&byte| byte == expected), "page {}", page);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authenticated encryption of snapshot state and memory files.
+//!
+//! The plaintext is split in fixed size chunks, each sealed with AES-256-GCM under a key
+//! derived from the user key and a random per-file salt:
+//!
+//!  |----------------------------|
+//!  |    64 bit magic (FCSNAPE1) |
+//!  |----------------------------|
+//!  |   chunk size (u32 LE)      |
+//!  |   reserved (u32, zero)     |
+//!  |   plaintext len (u64 LE)   |
+//!  |   128 bit salt             |
+//!  |----------------------------|
+//!  |   chunk 0 || 128 bit tag   |
+//!  |----------------------------|
+//!  |           ...              |
+//!  |----------------------------|
+//!  |   chunk N || 128 bit tag   |
+//!  |----------------------------|
+//!
+//! The last byte of the magic is the version of the format.
+//!
+//! The nonce of a chunk is its index and the header is authenticated along with every chunk,
+//! so reordered, truncated or extended files fail to decrypt just like modified ones. Fixed
+//! size chunks allow decrypting any plaintext range without reading the whole file, which is
+//! what page fault handlers serving guest memory need.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+
+use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use aws_lc_rs::hkdf::{Salt, HKDF_SHA256};
+use aws_lc_rs::rand;
+
+/// Length of the user supplied key, in bytes.
+pub const KEY_LEN: usize = 32;
+/// Plaintext bytes sealed together. A multiple of the page size.
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 << 10;
+
+const MAGIC: &[u8; 8] = b"FCSNAPE1";
+// Length of the part of the magic shared by all the versions of the format.
+const MAGIC_PREFIX_LEN: usize = 7;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = 40;
+const TAG_LEN: usize = 16;
+const KEY_INFO: &[&[u8]] = &[b"firecracker snapshot file"];
+
+/// Errors associated with encrypted snapshot files.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The header describes an invalid layout or does not match the file size.
+    #[error("Invalid encrypted snapshot header")]
+    BadHeader,
+    /// The file does not start with an encrypted snapshot header.
+    #[error("Not an encrypted snapshot file")]
+    BadMagic,
+    /// Key derivation or encryption failed.
+    #[error("Encryption failed")]
+    Crypto,
+    /// An I/O error occurred.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The key has the wrong length.
+    #[error("Encryption key must be {KEY_LEN} bytes, got {0}")]
+    KeyLength(usize),
+    /// The requested range is outside of the plaintext.
+    #[error("Range {0:#x}+{1:#x} is outside of the encrypted file")]
+    OutOfRange(u64, usize),
+    /// A chunk failed authentication.
+    #[error("Authentication failed for chunk {0}: the file was modified or the key is wrong")]
+    Tampered(u64),
+    /// Fewer or more bytes than announced in the header were written.
+    #[error("Expected {0} plaintext bytes, got {1}")]
+    UnexpectedLength(u64, u64),
+    /// The file was encrypted with another version of the format.
+    #[error("Unsupported encrypted snapshot version {:?}", char::from(*.0))]
+    UnsupportedVersion(u8),
+}
+
+/// A key used to encrypt snapshot files.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SnapshotKey([u8; KEY_LEN]);
+
+impl SnapshotKey {
+    /// Creates a key from its raw bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let key = bytes
+            .try_into()
+            .map_err(|_| Error::KeyLength(bytes.len()))?;
+        Ok(SnapshotKey(key))
+    }
+
+    fn derive(&self, salt: &[u8]) -> Result<LessSafeKey, Error> {
+        let okm = Salt::new(HKDF_SHA256, salt)
+            .extract(&self.0)
+            .expand(KEY_INFO, &AES_256_GCM)
+            .map_err(|_| Error::Crypto)?;
+        Ok(LessSafeKey::new(UnboundKey::from(okm)))
+    }
+}
+
+impl fmt::Debug for SnapshotKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SnapshotKey(<redacted>)")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Header {
+    chunk_size: u32,
+    plaintext_len: u64,
+    salt: [u8; SALT_LEN],
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&self.chunk_size.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.plaintext_len.to_le_bytes());
+        bytes[24..40].copy_from_slice(&self.salt);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Result<Self, Error> {
+        if bytes[0..MAGIC_PREFIX_LEN] != MAGIC[0..MAGIC_PREFIX_LEN] {
+            return Err(Error::BadMagic);
+        }
+        if bytes[MAGIC_PREFIX_LEN] != MAGIC[MAGIC_PREFIX_LEN] {
+            return Err(Error::UnsupportedVersion(bytes[MAGIC_PREFIX_LEN]));
+        }
+        // The unwraps are safe because the slices have the right length.
+        let chunk_size = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if chunk_size == 0 || bytes[12..16] != [0; 4] {
+            return Err(Error::BadHeader);
+        }
+        Ok(Header {
+            chunk_size,
+            plaintext_len: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            salt: bytes[24..40].try_into().unwrap(),
+        })
+    }
+
+    fn chunks(&self) -> u64 {
+        let chunk_size = u64::from(self.chunk_size);
+        self.plaintext_len / chunk_size + u64::from(self.plaintext_len % chunk_size != 0)
+    }
+
+    fn encrypted_len(&self) -> u64 {
+        (HEADER_LEN as u64)
+            .saturating_add(self.plaintext_len)
+            .saturating_add(self.chunks().saturating_mul(TAG_LEN as u64))
+    }
+
+    fn chunk_len(&self, index: u64) -> usize {
+        let start = index * u64::from(self.chunk_size);
+        // Fits in a u32 because it is at most `chunk_size`.
+        (self.plaintext_len - start).min(u64::from(self.chunk_size)) as usize
+    }
+
+    fn chunk_offset(&self, index: u64) -> u64 {
+        HEADER_LEN as u64 + index * (u64::from(self.chunk_size) + TAG_LEN as u64)
+    }
+}
+
+fn nonce(index: u64) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[NONCE_LEN - 8..].copy_from_slice(&index.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Returns `true` if `file` starts with an encrypted snapshot header, whatever its version.
+pub fn is_encrypted(file: &File) -> io::Result<bool> {
+    let mut magic = [0u8; MAGIC_PREFIX_LEN];
+    match file.read_exact_at(&mut magic, 0) {
+        Ok(()) => Ok(magic == MAGIC[0..MAGIC_PREFIX_LEN]),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Encrypts a stream of exactly `plaintext_len` bytes, sealing each chunk as it fills up.
+pub struct EncryptingWriter<W: Write> {
+    writer: W,
+    key: LessSafeKey,
+    header: Header,
+    aad: [u8; HEADER_LEN],
+    chunk: Vec<u8>,
+    index: u64,
+    written: u64,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Writes the header of a file holding `plaintext_len` bytes to `writer`.
+    pub fn new(
+        mut writer: W,
+        key: &SnapshotKey,
+        plaintext_len: u64,
+        chunk_size: u32,
+    ) -> Result<Self, Error> {
+        if chunk_size == 0 {
+            return Err(Error::BadHeader);
+        }
+        let mut salt = [0u8; SALT_LEN];
+        rand::fill(&mut salt).map_err(|_| Error::Crypto)?;
+        let header = Header {
+            chunk_size,
+            plaintext_len,
+            salt,
+        };
+        let aad = header.to_bytes();
+        writer.write_all(&aad)?;
+
+        Ok(EncryptingWriter {
+            writer,
+            key: key.derive(&salt)?,
+            header,
+            aad,
+            chunk: Vec::with_capacity(chunk_size as usize),
+            index: 0,
+            written: 0,
+        })
+    }
+
+    fn seal_chunk(&mut self) -> Result<(), Error> {
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(nonce(self.index), Aad::from(&self.aad), &mut self.chunk)
+            .map_err(|_| Error::Crypto)?;
+        self.writer.write_all(&self.chunk)?;
+        self.writer.write_all(tag.as_ref())?;
+        self.chunk.clear();
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Seals the last chunk and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.written != self.header.plaintext_len {
+            return Err(Error::UnexpectedLength(
+                self.header.plaintext_len,
+                self.written,
+            ));
+        }
+        if !self.chunk.is_empty() {
+            self.seal_chunk()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.header.plaintext_len - self.written;
+        if buf.len() as u64 > remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                Error::UnexpectedLength(self.header.plaintext_len, self.written + buf.len() as u64),
+            ));
+        }
+        let count = buf
+            .len()
+            .min(self.header.chunk_size as usize - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..count]);
+        self.written += count as u64;
+        if self.chunk.len() == self.header.chunk_size as usize {
+            self.seal_chunk()
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Encrypts `plaintext` to `writer` in one go.
+pub fn encrypt_to<W: Write>(writer: W, key: &SnapshotKey, plaintext: &[u8]) -> Result<W, Error> {
+    let mut encryptor =
+        EncryptingWriter::new(writer, key, plaintext.len() as u64, DEFAULT_CHUNK_SIZE)?;
+    encryptor.write_all(plaintext)?;
+    encryptor.finish()
+}
+
+/// Random access decryption of an encrypted snapshot file.
+pub struct EncryptedFile {
+    file: File,
+    key: LessSafeKey,
+    header: Header,
+    aad: [u8; HEADER_LEN],
+}
+
+impl EncryptedFile {
+    /// Checks the header of `file` and derives its key.
+    pub fn open(file: File, key: &SnapshotKey) -> Result<Self, Error> {
+        let mut aad = [0u8; HEADER_LEN];
+        file.read_exact_at(&mut aad, 0)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => Error::BadMagic,
+                _ => Error::Io(err),
+            })?;
+        let header = Header::from_bytes(&aad)?;
+        // Catches truncated and extended files before any chunk is read.
+        if file.metadata()?.len() != header.encrypted_len() {
+            return Err(Error::BadHeader);
+        }
+
+        Ok(EncryptedFile {
+            key: key.derive(&header.salt)?,
+            file,
+            header,
+            aad,
+        })
+    }
+
+    /// Number of plaintext bytes in the file.
+    pub fn len(&self) -> u64 {
+        self.header.plaintext_len
+    }
+
+    /// Returns `true` if the file holds no plaintext.
+    pub fn is_empty(&self) -> bool {
+        self.header.plaintext_len == 0
+    }
+
+    fn decrypt_chunk(&self, index: u64, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let len = self.header.chunk_len(index);
+        buf.resize(len + TAG_LEN, 0);
+        self.file
+            .read_exact_at(buf, self.header.chunk_offset(index))?;
+        self.key
+            .open_in_place(nonce(index), Aad::from(&self.aad), buf)
+            .map_err(|_| Error::Tampered(index))?;
+        Ok(len)
+    }
+
+    /// Fills `dst` with the plaintext found at `offset`, decrypting only the chunks covering it.
+    pub fn read_at(&self, offset: u64, dst: &mut [u8]) -> Result<(), Error> {
+        match offset.checked_add(dst.len() as u64) {
+            Some(end) if end <= self.header.plaintext_len => (),
+            _ => return Err(Error::OutOfRange(offset, dst.len())),
+        }
+
+        let chunk_size = u64::from(self.header.chunk_size);
+        // Grown by `decrypt_chunk` to the length of the chunk, which the header does not
+        // bound before the file size is checked against the plaintext length.
+        let mut buf = Vec::new();
+        let mut done = 0;
+        while done < dst.len() {
+            let pos = offset + done as u64;
+            let index = pos / chunk_size;
+            let len = self.decrypt_chunk(index, &mut buf)?;
+            let start = (pos % chunk_size) as usize;
+            let count = (len - start).min(dst.len() - done);
+            dst[done..done + count].copy_from_slice(&buf[start..start + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    /// Decrypts the whole file.
+    pub fn read_all(&self) -> Result<Vec<u8>, Error> {
+        let len = usize::try_from(self.header.plaintext_len).map_err(|_| Error::BadHeader)?;
+        let mut plaintext = vec![0u8; len];
+        self.read_at(0, &mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn key(byte: u8) -> SnapshotKey {
+        SnapshotKey::from_bytes(&[byte; KEY_LEN]).unwrap()
+    }
+
+    fn encrypted_file(plaintext: &[u8], chunk_size: u32) -> TempFile {
+        let file = TempFile::new().unwrap();
+        let mut encryptor =
+            EncryptingWriter::new(file.as_file(), &key(1), plaintext.len() as u64, chunk_size)
+                .unwrap();
+        encryptor.write_all(plaintext).unwrap();
+        encryptor.finish().unwrap();
+        file
+    }
+
+    fn open(file: &TempFile, key: &SnapshotKey) -> Result<EncryptedFile, Error> {
+        EncryptedFile::open(File::open(file.as_path()).unwrap(), key)
+    }
+
+    #[test]
+    fn test_key() {
+        assert!(matches!(
+            SnapshotKey::from_bytes(&[0; 16]),
+            Err(Error::KeyLength(16))
+        ));
+        assert_eq!(format!("{:?}", key(7)), "SnapshotKey(<redacted>)");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let plaintext: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        let file = encrypted_file(&plaintext, 4096);
+        assert!(is_encrypted(file.as_file()).unwrap());
+        assert_eq!(
+            file.as_file().metadata().unwrap().len(),
+            (HEADER_LEN + plaintext.len() + 3 * TAG_LEN) as u64
+        );
+
+        let encrypted = open(&file, &key(1)).unwrap();
+        assert_eq!(encrypted.len(), plaintext.len() as u64);
+        assert_eq!(encrypted.read_all().unwrap(), plaintext);
+
+        // A range spanning the three chunks.
+        let mut dst = vec![0u8; 6000];
+        encrypted.read_at(4000, &mut dst).unwrap();
+        assert_eq!(dst, plaintext[4000..10_000]);
+        assert!(matches!(
+            encrypted.read_at(4001, &mut dst),
+            Err(Error::OutOfRange(4001, 6000))
+        ));
+
+        // Empty files are valid too.
+        let empty = encrypted_file(&[], 4096);
+        assert!(open(&empty, &key(1))
+            .unwrap()
+            .read_all()
+            .unwrap()
+            .is_empty());
+
+        // The same plaintext encrypts differently every time.
+        let other = encrypted_file(&plaintext, 4096);
+        assert_ne!(
+            std::fs::read(file.as_path()).unwrap(),
+            std::fs::read(other.as_path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_plain_file() {
+        let file = TempFile::new().unwrap();
+        assert!(!is_encrypted(file.as_file()).unwrap());
+        assert!(matches!(open(&file, &key(1)), Err(Error::BadMagic)));
+
+        file.as_file().write_all_at(&[0xAB; 64], 0).unwrap();
+        assert!(!is_encrypted(file.as_file()).unwrap());
+        assert!(matches!(open(&file, &key(1)), Err(Error::BadMagic)));
+    }
+
+    #[test]
+    fn test_header() {
+        let file = encrypted_file(&[1u8; 100], 4096);
+        let mut bytes = [0u8; HEADER_LEN];
+        file.as_file().read_exact_at(&mut bytes, 0).unwrap();
+        let header = Header::from_bytes(&bytes).unwrap();
+        assert_eq!(header.chunk_size, 4096);
+        assert_eq!(header.plaintext_len, 100);
+        assert_eq!(header.to_bytes(), bytes);
+
+        // A newer version of the format is still recognized as encrypted.
+        let mut bad = bytes;
+        bad[7] = b'2';
+        assert!(matches!(
+            Header::from_bytes(&bad),
+            Err(Error::UnsupportedVersion(b'2'))
+        ));
+        file.as_file().write_all_at(&bad, 0).unwrap();
+        assert!(is_encrypted(file.as_file()).unwrap());
+        assert!(matches!(
+            open(&file, &key(1)),
+            Err(Error::UnsupportedVersion(b'2'))
+        ));
+
+        // The reserved word is written as zero.
+        let mut bad = bytes;
+        bad[13] = 1;
+        assert!(matches!(Header::from_bytes(&bad), Err(Error::BadHeader)));
+
+        let mut bad = bytes;
+        bad[8..12].copy_from_slice(&[0; 4]);
+        assert!(matches!(Header::from_bytes(&bad), Err(Error::BadHeader)));
+    }
+
+    #[test]
+    fn test_huge_chunk_size() {
+        // The file keeps the same layout, so only decrypting the chunk can tell the header was
+        // modified, and buffers must not be sized from the chunk size before that.
+        let file = encrypted_file(&[1u8; 100], 4096);
+        let mut bytes = [0u8; HEADER_LEN];
+        file.as_file().read_exact_at(&mut bytes, 0).unwrap();
+        let header = Header {
+            chunk_size: u32::MAX,
+            ..Header::from_bytes(&bytes).unwrap()
+        };
+        file.as_file().write_all_at(&header.to_bytes(), 0).unwrap();
+        let encrypted = open(&file, &key(1)).unwrap();
+        let mut dst = [0u8; 10];
+        assert!(matches!(
+            encrypted.read_at(0, &mut dst),
+            Err(Error::Tampered(0))
+        ));
+    }
+
+    #[test]
+    fn test_tamper_detection() {
+        let plaintext = vec![0x5Au8; 3 * 4096];
+        let file = encrypted_file(&plaintext, 4096);
+
+        // Wrong key.
+        let encrypted = open(&file, &key(2)).unwrap();
+        assert!(matches!(encrypted.read_all(), Err(Error::Tampered(0))));
+
+        // Flipped ciphertext bit in the second chunk.
+        let offset = HEADER_LEN as u64 + 4096 + TAG_LEN as u64 + 10;
+        let mut byte = [0u8];
+        file.as_file().read_exact_at(&mut byte, offset).unwrap();
+        file.as_file().write_all_at(&[byte[0] ^ 1], offset).unwrap();
+        let encrypted = open(&file, &key(1)).unwrap();
+        let mut dst = vec![0u8; 4096];
+        encrypted.read_at(0, &mut dst).unwrap();
+        assert!(matches!(
+            encrypted.read_at(4096, &mut dst),
+            Err(Error::Tampered(1))
+        ));
+
+        // Modified header: the plaintext length is authenticated with every chunk.
+        let file = encrypted_file(&plaintext, 4096);
+        let mut bytes = [0u8; HEADER_LEN];
+        file.as_file().read_exact_at(&mut bytes, 0).unwrap();
+        let header = Header {
+            plaintext_len: 3 * 4096 - 1,
+            ..Header::from_bytes(&bytes).unwrap()
+        };
+        file.as_file().write_all_at(&header.to_bytes(), 0).unwrap();
+        file.as_file().set_len(header.encrypted_len()).unwrap();
+        let encrypted = open(&file, &key(1)).unwrap();
+        assert!(matches!(encrypted.read_all(), Err(Error::Tampered(0))));
+
+        // Truncated file.
+        let file = encrypted_file(&plaintext, 4096);
+        let len = file.as_file().metadata().unwrap().len();
+        file.as_file().set_len(len - 1).unwrap();
+        assert!(matches!(open(&file, &key(1)), Err(Error::BadHeader)));
+    }
+
+    #[test]
+    fn test_unexpected_length() {
+        let file = TempFile::new().unwrap();
+        let mut encryptor = EncryptingWriter::new(file.as_file(), &key(1), 10, 4096).unwrap();
+        encryptor.write_all(&[0; 5]).unwrap();
+        assert!(encryptor.write_all(&[0; 6]).is_err());
+        assert!(matches!(
+            encryptor.finish(),
+            Err(Error::UnexpectedLength(10, 5))
+        ));
+    }
+}
@@ -3,6 +3,7 @@
 
 //! Provides the VersionMap that deals with the microvm state versions.
 
+use std::any::TypeId;
 use std::collections::HashMap;
 
 use lazy_static::lazy_static;
@@ -33,6 +34,8 @@ pub const FC_V1_2_SNAP_VERSION: u16 = 6;
 pub const FC_V1_3_SNAP_VERSION: u16 = 7;
 /// Snap version for Firecracker v1.4
 pub const FC_V1_4_SNAP_VERSION: u16 = 8;
+/// Snap version for Firecracker v1.5
+pub const FC_V1_5_SNAP_VERSION: u16 = 9;
 
 lazy_static! {
     // Note: until we have a better design, this needs to be updated when the version changes.
@@ -70,9 +73,28 @@ lazy_static! {
         // v1.4 state change mappings.
         version_map.new_version().set_type_version(DeviceStates::type_id(), 4);
 
+        // v1.5 state change mappings.
+        version_map.new_version().set_type_version(DeviceStates::type_id(), 5);
+
         version_map
     };
 
+    /// Names of the types that have more than one version in `VERSION_MAP`, used to describe
+    /// what changes between two snapshot data versions. Needs to be updated along with it.
+    pub static ref VERSIONED_TYPES: Vec<(&'static str, TypeId)> = {
+        let mut types = vec![
+            ("BlockState", BlockState::type_id()),
+            ("DeviceStates", DeviceStates::type_id()),
+            ("NetConfigSpaceState", NetConfigSpaceState::type_id()),
+            ("QueueState", QueueState::type_id()),
+            ("VmInfo", VmInfo::type_id()),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        types.push(("VcpuState", VcpuState::type_id()));
+
+        types
+    };
+
     /// Static instance used for creating a 1:1 mapping between Firecracker release version
     /// and snapshot data format version.
     /// !CAVEAT!
@@ -96,6 +118,7 @@ lazy_static! {
         mapping.insert(String::from("1.2.0"), FC_V1_2_SNAP_VERSION);
         mapping.insert(String::from("1.3.0"), FC_V1_3_SNAP_VERSION);
         mapping.insert(String::from("1.4.0"), FC_V1_4_SNAP_VERSION);
+        mapping.insert(String::from("1.5.0"), FC_V1_5_SNAP_VERSION);
 
         mapping
     };
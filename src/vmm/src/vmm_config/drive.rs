@@ -10,11 +10,11 @@ use std::{io, result};
 
 use serde::{Deserialize, Serialize};
 
-use super::RateLimiterConfig;
+use super::{RateLimiterBudget, RateLimiterConfig};
 pub use crate::devices::virtio::block::device::FileEngineType;
 use crate::devices::virtio::block::BlockError;
-use crate::devices::virtio::Block;
 pub use crate::devices::virtio::CacheType;
+use crate::devices::virtio::{Block, BlockDeviceStats, VirtioDevice};
 use crate::Error as VmmError;
 
 /// Errors associated with the operations allowed on a drive.
@@ -85,6 +85,32 @@ impl From<&Block> for BlockDeviceConfig {
     }
 }
 
+/// Live state of a block device, along with its configuration.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct BlockDeviceState {
+    /// Configuration of the drive, with the backing file it currently uses.
+    #[serde(flatten)]
+    pub config: BlockDeviceConfig,
+    /// Whether the guest driver activated the device.
+    pub activated: bool,
+    /// Remaining budget of the rate limiter, if the drive is rate limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_budget: Option<RateLimiterBudget>,
+    /// Counters of the requests completed by the drive.
+    pub stats: BlockDeviceStats,
+}
+
+impl From<&Block> for BlockDeviceState {
+    fn from(block: &Block) -> Self {
+        BlockDeviceState {
+            config: BlockDeviceConfig::from(block),
+            activated: block.is_activated(),
+            rate_limiter_budget: RateLimiterBudget::from_rate_limiter(block.rate_limiter()),
+            stats: block.stats(),
+        }
+    }
+}
+
 /// Only provided fields will be updated. I.e. if any optional fields
 /// are missing, they will not be updated.
 #[derive(Debug, Default, PartialEq, Eq, Deserialize)]
@@ -212,6 +238,15 @@ impl BlockBuilder {
         .map_err(DriveError::CreateBlockDevice)
     }
 
+    /// Returns the live state of the drive with the given ID, if there is one.
+    pub fn state(&self, drive_id: &str) -> Option<BlockDeviceState> {
+        self.list
+            .iter()
+            .map(|block| block.lock().expect("Poisoned lock"))
+            .find(|block| block.id() == drive_id)
+            .map(|block| BlockDeviceState::from(block.deref()))
+    }
+
     /// Returns a vec with the structures used to configure the devices.
     pub fn configs(&self) -> Vec<BlockDeviceConfig> {
         let mut ret = vec![];
@@ -612,6 +647,13 @@ mod tests {
         let configs = block_devs.configs();
         assert_eq!(configs.len(), 1);
         assert_eq!(configs.first().unwrap(), &dummy_block_device);
+
+        assert!(block_devs.state("2").is_none());
+        let state = block_devs.state("1").unwrap();
+        assert_eq!(state.config, dummy_block_device);
+        assert!(!state.activated);
+        assert!(state.rate_limiter_budget.is_none());
+        assert_eq!(state.stats, BlockDeviceStats::default());
     }
 
     #[test]
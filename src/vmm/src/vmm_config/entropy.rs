@@ -6,8 +6,9 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use super::RateLimiterConfig;
-use crate::devices::virtio::rng::{Entropy, Error as EntropyError};
+use super::{RateLimiterBudget, RateLimiterConfig};
+use crate::devices::virtio::rng::{Entropy, EntropyDeviceStats, Error as EntropyError};
+use crate::devices::virtio::VirtioDevice;
 
 /// This struct represents the strongly typed equivalent of the json body from entropy device
 /// related requests.
@@ -27,6 +28,32 @@ impl From<&Entropy> for EntropyDeviceConfig {
     }
 }
 
+/// Live state of the entropy device, along with its configuration.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct EntropyDeviceState {
+    /// Configuration of the entropy device.
+    #[serde(flatten)]
+    pub config: EntropyDeviceConfig,
+    /// Whether the guest driver activated the device.
+    pub activated: bool,
+    /// Remaining budget of the rate limiter, if the device is rate limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_budget: Option<RateLimiterBudget>,
+    /// Counters of the requests served by the device.
+    pub stats: EntropyDeviceStats,
+}
+
+impl From<&Entropy> for EntropyDeviceState {
+    fn from(dev: &Entropy) -> Self {
+        EntropyDeviceState {
+            config: EntropyDeviceConfig::from(dev),
+            activated: dev.is_activated(),
+            rate_limiter_budget: RateLimiterBudget::from_rate_limiter(dev.rate_limiter()),
+            stats: dev.stats(),
+        }
+    }
+}
+
 /// Errors that can occur while handling configuration for
 /// an entropy device
 #[derive(Debug, thiserror::Error)]
@@ -81,6 +108,13 @@ impl EntropyDeviceBuilder {
             .map(|dev| EntropyDeviceConfig::from(dev.lock().unwrap().deref()))
     }
 
+    /// Get the live state of the entropy device (if any)
+    pub fn state(&self) -> Option<EntropyDeviceState> {
+        self.0
+            .as_ref()
+            .map(|dev| EntropyDeviceState::from(dev.lock().unwrap().deref()))
+    }
+
     /// Set the entropy device from an already created object
     pub fn set_device(&mut self, device: Arc<Mutex<Entropy>>) {
         self.0 = Some(device);
@@ -102,6 +136,12 @@ mod tests {
         builder.insert(config.clone()).unwrap();
         assert!(builder.get().is_some());
         assert_eq!(builder.config().unwrap(), config);
+
+        let state = builder.state().unwrap();
+        assert_eq!(state.config, config);
+        assert!(!state.activated);
+        assert!(state.rate_limiter_budget.is_none());
+        assert_eq!(state.stats, EntropyDeviceStats::default());
     }
 
     #[test]
@@ -0,0 +1,96 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Default path of the HTTP request sent to the guest.
+pub const DEFAULT_INVOKE_PATH: &str = "/invoke";
+/// Default time to wait for the guest response, in milliseconds.
+pub const DEFAULT_INVOKE_TIMEOUT_MS: u64 = 30000;
+/// Maximum time to wait for the guest response, in milliseconds. The API server serves no
+/// other request meanwhile.
+pub const MAX_INVOKE_TIMEOUT_MS: u64 = 60000;
+
+fn default_path() -> String {
+    DEFAULT_INVOKE_PATH.to_string()
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_INVOKE_TIMEOUT_MS
+}
+
+/// Function invocation to forward to an HTTP server listening on a vsock port of the guest.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InvokeConfig {
+    /// The vsock port the HTTP server of the guest listens on.
+    pub guest_port: u32,
+    /// The path of the HTTP request sent to the guest.
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// The body of the HTTP request sent to the guest.
+    #[serde(default)]
+    pub body: String,
+    /// The content type of the body.
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// How long to wait for the guest to accept the request and to respond, in milliseconds.
+    /// At most `MAX_INVOKE_TIMEOUT_MS`.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// Where the function invocations are forwarded, as known by the VMM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InvokeTarget {
+    /// The Unix socket on which the vsock device accepts host initiated connections.
+    pub uds_path: String,
+    /// When the restore of the microVM from a snapshot started, on the monotonic clock, in
+    /// microseconds. None if the microVM was booted.
+    pub restore_start_us: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoke_config() {
+        let config: InvokeConfig = serde_json::from_str(r#"{ "guest_port": 5000 }"#).unwrap();
+        assert_eq!(
+            config,
+            InvokeConfig {
+                guest_port: 5000,
+                path: DEFAULT_INVOKE_PATH.to_string(),
+                body: String::new(),
+                content_type: "application/json".to_string(),
+                timeout_ms: DEFAULT_INVOKE_TIMEOUT_MS,
+            }
+        );
+
+        let config: InvokeConfig = serde_json::from_str(
+            r#"{
+                "guest_port": 5000,
+                "path": "/run",
+                "body": "hello",
+                "content_type": "text/plain",
+                "timeout_ms": 100
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.path, "/run");
+        assert_eq!(config.body, "hello");
+        assert_eq!(config.content_type, "text/plain");
+        assert_eq!(config.timeout_ms, 100);
+
+        // The guest port is mandatory.
+        assert!(serde_json::from_str::<InvokeConfig>(r#"{ "body": "hello" }"#).is_err());
+        assert!(
+            serde_json::from_str::<InvokeConfig>(r#"{ "guest_port": 5000, "port": 1 }"#).is_err()
+        );
+    }
+}
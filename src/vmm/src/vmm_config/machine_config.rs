@@ -57,6 +57,14 @@ pub struct MachineConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// Attaches a VM generation ID device, which notifies the guest each time it is restored
+    /// from a snapshot. Can be enabled only on aarch64.
+    #[serde(
+        default,
+        skip_serializing_if = "std::ops::Not::not",
+        deserialize_with = "deserialize_vmgenid"
+    )]
+    pub vmgenid: bool,
 }
 
 impl Default for MachineConfig {
@@ -70,8 +78,13 @@ impl fmt::Display for MachineConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"smt\": {:?}, \"cpu_template\": \
-             {:?}, \"track_dirty_pages\": {:?} }}",
-            self.vcpu_count, self.mem_size_mib, self.smt, self.cpu_template, self.track_dirty_pages
+             {:?}, \"track_dirty_pages\": {:?}, \"vmgenid\": {:?} }}",
+            self.vcpu_count,
+            self.mem_size_mib,
+            self.smt,
+            self.cpu_template,
+            self.track_dirty_pages,
+            self.vmgenid
         )
     }
 }
@@ -108,6 +121,14 @@ pub struct MachineConfigUpdate {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_dirty_pages: Option<bool>,
+    /// Attaches a VM generation ID device, which notifies the guest each time it is restored
+    /// from a snapshot. Can be enabled only on aarch64.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_vmgenid"
+    )]
+    pub vmgenid: Option<bool>,
 }
 
 impl MachineConfigUpdate {
@@ -120,6 +141,7 @@ impl MachineConfigUpdate {
             && self.cpu_template.is_none()
             && self.smt.is_none()
             && self.track_dirty_pages.is_none()
+            && self.vmgenid.is_none()
         {
             return true;
         }
@@ -136,6 +158,7 @@ impl From<MachineConfig> for MachineConfigUpdate {
             smt: Some(cfg.smt),
             cpu_template: Some(cfg.cpu_template),
             track_dirty_pages: Some(cfg.track_dirty_pages),
+            vmgenid: Some(cfg.vmgenid),
         }
     }
 }
@@ -153,6 +176,8 @@ pub struct VmConfig {
     pub cpu_template: Option<CpuTemplateType>,
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     pub track_dirty_pages: bool,
+    /// Attaches a VM generation ID device.
+    pub vmgenid: bool,
 }
 
 impl VmConfig {
@@ -202,6 +227,10 @@ impl VmConfig {
             self.track_dirty_pages = track_dirty_pages;
         }
 
+        if let Some(vmgenid) = update.vmgenid {
+            self.vmgenid = vmgenid;
+        }
+
         Ok(())
     }
 }
@@ -214,6 +243,7 @@ impl Default for VmConfig {
             smt: false,
             cpu_template: None,
             track_dirty_pages: false,
+            vmgenid: false,
         }
     }
 }
@@ -226,6 +256,7 @@ impl From<&VmConfig> for MachineConfig {
             smt: value.smt,
             cpu_template: (&value.cpu_template).into(),
             track_dirty_pages: value.track_dirty_pages,
+            vmgenid: value.vmgenid,
         }
     }
 }
@@ -279,3 +310,25 @@ where
 
     Ok(val)
 }
+
+/// Deserialization function for the `vmgenid` field in `MachineConfig` and `MachineConfigUpdate`.
+/// This is called only when `vmgenid` is present in the JSON configuration.
+fn deserialize_vmgenid<'de, D, T>(d: D) -> std::result::Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: Deserialize<'de> + PartialEq + From<bool>,
+{
+    let val = T::deserialize(d)?;
+
+    // The guest finds the device in the device tree. On x86_64 it would have to be described
+    // in ACPI tables, which Firecracker does not provide.
+    #[cfg(target_arch = "x86_64")]
+    if val == T::from(true) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("vmgenid"),
+            &"The VM generation ID device is only supported on aarch64",
+        ));
+    }
+
+    Ok(val)
+}
@@ -0,0 +1,122 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configurations used for live migrating a microVM between two Firecracker processes.
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Default maximum number of pre-copy rounds.
+pub const DEFAULT_MAX_ROUNDS: u32 = 5;
+/// Default number of dirty pages below which the pre-copy stops.
+pub const DEFAULT_DIRTY_PAGES_THRESHOLD: u64 = 256;
+
+fn default_max_rounds() -> u32 {
+    DEFAULT_MAX_ROUNDS
+}
+
+fn default_dirty_pages_threshold() -> u64 {
+    DEFAULT_DIRTY_PAGES_THRESHOLD
+}
+
+/// Connected stream socket received over the API socket, used instead of `socket_path`.
+#[derive(Debug, Default)]
+pub struct MigrationSocket(pub Option<File>);
+
+// Sockets are compared by descriptor, which is enough to check that a request was parsed right.
+impl PartialEq for MigrationSocket {
+    fn eq(&self, other: &Self) -> bool {
+        let fd = |socket: &Self| socket.0.as_ref().map(|file| file.as_raw_fd());
+        fd(self) == fd(other)
+    }
+}
+
+impl Eq for MigrationSocket {}
+
+/// Stores the configuration used for migrating a running microVM to another Firecracker.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationSendParams {
+    /// Path of the Unix socket on which the receiving Firecracker listens.
+    pub socket_path: Option<PathBuf>,
+    /// Index of the file descriptor, among the ones received with the request, of a connected
+    /// stream socket. Replaces `socket_path`.
+    #[serde(default, skip_serializing)]
+    pub socket_fd: Option<usize>,
+    /// Maximum number of pre-copy rounds before the microVM is paused.
+    #[serde(default = "default_max_rounds")]
+    pub max_rounds: u32,
+    /// The microVM is paused as soon as a pre-copy round sends at most this many pages.
+    #[serde(default = "default_dirty_pages_threshold")]
+    pub dirty_pages_threshold: u64,
+    /// The socket designated by `socket_fd`.
+    #[serde(skip)]
+    pub socket: MigrationSocket,
+}
+
+/// Stores the configuration used for receiving a microVM migrated from another Firecracker.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationReceiveParams {
+    /// Path of the Unix socket to listen on for the sending Firecracker.
+    pub socket_path: Option<PathBuf>,
+    /// Index of the file descriptor, among the ones received with the request, of a connected
+    /// stream socket. Replaces `socket_path`.
+    #[serde(default, skip_serializing)]
+    pub socket_fd: Option<usize>,
+    /// Setting this flag will enable KVM dirty page tracking on the received microVM.
+    #[serde(default)]
+    pub enable_diff_snapshots: bool,
+    /// When set to true, the microVM is resumed once it has been received.
+    #[serde(default)]
+    pub resume_vm: bool,
+    /// The socket designated by `socket_fd`.
+    #[serde(skip)]
+    pub socket: MigrationSocket,
+}
+
+/// Summary of a completed outgoing migration.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationStats {
+    /// Number of pre-copy rounds run after the initial copy of the guest memory.
+    pub rounds: u32,
+    /// Number of pages sent, counting pages sent more than once.
+    pub pages_sent: u64,
+    /// Number of pages sent while the microVM was paused.
+    pub stop_copy_pages: u64,
+    /// Time during which the microVM was paused, in microseconds.
+    pub downtime_us: u64,
+    /// Duration of the whole migration, in microseconds.
+    pub total_us: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_params_defaults() {
+        let params: MigrationSendParams =
+            serde_json::from_str(r#"{"socket_path": "/tmp/migration.sock"}"#).unwrap();
+        assert_eq!(
+            params,
+            MigrationSendParams {
+                socket_path: Some(PathBuf::from("/tmp/migration.sock")),
+                socket_fd: None,
+                max_rounds: DEFAULT_MAX_ROUNDS,
+                dirty_pages_threshold: DEFAULT_DIRTY_PAGES_THRESHOLD,
+                socket: MigrationSocket::default(),
+            }
+        );
+
+        let params: MigrationReceiveParams = serde_json::from_str(r#"{"socket_fd": 0}"#).unwrap();
+        assert_eq!(params.socket_fd, Some(0));
+        assert_eq!(params.socket, MigrationSocket::default());
+        assert!(!params.enable_diff_snapshots);
+        assert!(!params.resume_vm);
+
+        assert!(serde_json::from_str::<MigrationReceiveParams>(r#"{"max_rounds": 3}"#).is_err());
+    }
+}
@@ -21,20 +21,28 @@ pub mod drive;
 pub mod entropy;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
+/// Wrapper for the function invocations forwarded to the guest over vsock.
+pub mod invoke;
 /// Wrapper for configuring the logger.
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
+/// Wrapper for configuring live migration between Firecracker processes.
+pub mod migration;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for the problems found by a dry run of the microVM start.
+pub mod validation;
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
+/// Wrapper for the requests handled by a Firecracker process running in zygote mode.
+pub mod zygote;
 
 // TODO: Migrate the VMM public-facing code (i.e. interface) to use stateless structures,
 // for receiving data/args, such as the below `RateLimiterConfig` and `TokenBucketConfig`.
@@ -163,6 +171,52 @@ impl RateLimiterConfig {
     }
 }
 
+/// Remaining budget of a live TokenBucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenBucketBudget {
+    /// See TokenBucket::size.
+    pub size: u64,
+    /// Tokens left in the bucket, as of the last time tokens were taken from it.
+    pub budget: u64,
+    /// Tokens left in the one time burst.
+    pub one_time_burst: u64,
+}
+
+impl From<&TokenBucket> for TokenBucketBudget {
+    fn from(tb: &TokenBucket) -> Self {
+        TokenBucketBudget {
+            size: tb.capacity(),
+            budget: tb.budget(),
+            one_time_burst: tb.one_time_burst(),
+        }
+    }
+}
+
+/// Remaining budgets of a live RateLimiter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct RateLimiterBudget {
+    /// Budget of the RateLimiter::bandwidth bucket.
+    pub bandwidth: Option<TokenBucketBudget>,
+    /// Budget of the RateLimiter::ops bucket.
+    pub ops: Option<TokenBucketBudget>,
+    /// Whether the rate limiter is blocked until its buckets refill.
+    pub blocked: bool,
+}
+
+impl RateLimiterBudget {
+    /// Returns the budgets of `rl`, unless it has no bucket.
+    fn from_rate_limiter(rl: &RateLimiter) -> Option<RateLimiterBudget> {
+        if rl.bandwidth().is_none() && rl.ops().is_none() {
+            return None;
+        }
+        Some(RateLimiterBudget {
+            bandwidth: rl.bandwidth().map(TokenBucketBudget::from),
+            ops: rl.ops().map(TokenBucketBudget::from),
+            blocked: rl.is_blocked(),
+        })
+    }
+}
+
 type Result<T> = std::result::Result<T, std::io::Error>;
 
 /// Create and opens a File for writing to it.
@@ -235,6 +289,25 @@ mod tests {
         assert_eq!(generated_rl_conf.into_option(), Some(rl_conf));
     }
 
+    #[test]
+    fn test_rate_limiter_budget() {
+        assert!(RateLimiterBudget::from_rate_limiter(&RateLimiter::default()).is_none());
+
+        let mut rl = RateLimiter::new(SIZE, ONE_TIME_BURST, REFILL_TIME, 0, 0, 0).unwrap();
+        assert!(rl.consume(ONE_TIME_BURST + 1, rate_limiter::TokenType::Bytes));
+        let budget = RateLimiterBudget::from_rate_limiter(&rl).unwrap();
+        assert_eq!(
+            budget.bandwidth,
+            Some(TokenBucketBudget {
+                size: SIZE,
+                budget: SIZE - 1,
+                one_time_burst: 0,
+            })
+        );
+        assert!(budget.ops.is_none());
+        assert!(!budget.blocked);
+    }
+
     #[test]
     fn test_fifo_line_writer() {
         let log_file_temp =
@@ -9,9 +9,9 @@ use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
-use super::RateLimiterConfig;
+use super::{RateLimiterBudget, RateLimiterConfig};
 use crate::devices::virtio::net::TapError;
-use crate::devices::virtio::Net;
+use crate::devices::virtio::{Net, NetDeviceStats, VirtioDevice};
 use crate::Error as VmmError;
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
@@ -45,6 +45,36 @@ impl From<&Net> for NetworkInterfaceConfig {
     }
 }
 
+/// Live state of a network interface, along with its configuration.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct NetworkInterfaceState {
+    /// Configuration of the network interface.
+    #[serde(flatten)]
+    pub config: NetworkInterfaceConfig,
+    /// Whether the guest driver activated the device.
+    pub activated: bool,
+    /// Remaining budget of the RX rate limiter, if received frames are rate limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter_budget: Option<RateLimiterBudget>,
+    /// Remaining budget of the TX rate limiter, if transmitted frames are rate limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter_budget: Option<RateLimiterBudget>,
+    /// Counters of the frames exchanged with the guest.
+    pub stats: NetDeviceStats,
+}
+
+impl From<&Net> for NetworkInterfaceState {
+    fn from(net: &Net) -> Self {
+        NetworkInterfaceState {
+            config: NetworkInterfaceConfig::from(net),
+            activated: net.is_activated(),
+            rx_rate_limiter_budget: RateLimiterBudget::from_rate_limiter(net.rx_rate_limiter()),
+            tx_rate_limiter_budget: RateLimiterBudget::from_rate_limiter(net.tx_rate_limiter()),
+            stats: net.stats(),
+        }
+    }
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
 /// can be updated.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -115,6 +145,28 @@ impl NetBuilder {
     /// Builds a network device based on a network interface config. Keeps a device reference
     /// in the builder's internal list.
     pub fn build(&mut self, netif_config: NetworkInterfaceConfig) -> Result<Arc<Mutex<Net>>> {
+        self.make_room(&netif_config)?;
+
+        // Add new device.
+        let net = Arc::new(Mutex::new(Self::create_net(netif_config)?));
+        self.net_devices.push(net.clone());
+
+        Ok(net)
+    }
+
+    /// Adds a network device built for another builder, keeping its tap device open. Fails
+    /// if its MAC address is used by another device of this builder.
+    pub fn reuse_device(&mut self, device: Arc<Mutex<Net>>) -> Result<()> {
+        let netif_config =
+            NetworkInterfaceConfig::from(device.lock().expect("Poisoned lock").deref());
+        self.make_room(&netif_config)?;
+        self.net_devices.push(device);
+        Ok(())
+    }
+
+    // Validates that a device configured as `netif_config` can be added, and removes the device
+    // it replaces, if any.
+    fn make_room(&mut self, netif_config: &NetworkInterfaceConfig) -> Result<()> {
         let mac_conflict = |net: &Arc<Mutex<Net>>| {
             let net = net.lock().expect("Poisoned lock");
             // Check if another net dev has same MAC.
@@ -140,11 +192,7 @@ impl NetBuilder {
             self.net_devices.swap_remove(index);
         }
 
-        // Add new device.
-        let net = Arc::new(Mutex::new(Self::create_net(netif_config)?));
-        self.net_devices.push(net.clone());
-
-        Ok(net)
+        Ok(())
     }
 
     /// Creates a Net device from a NetworkInterfaceConfig.
@@ -171,6 +219,15 @@ impl NetBuilder {
         .map_err(NetworkInterfaceError::CreateNetworkDevice)
     }
 
+    /// Returns the live state of the network interface with the given ID, if there is one.
+    pub fn state(&self, iface_id: &str) -> Option<NetworkInterfaceState> {
+        self.net_devices
+            .iter()
+            .map(|net| net.lock().expect("Poisoned lock"))
+            .find(|net| net.id() == iface_id)
+            .map(|net| NetworkInterfaceState::from(net.deref()))
+    }
+
     /// Returns a vec with the structures used to configure the net devices.
     pub fn configs(&self) -> Vec<NetworkInterfaceConfig> {
         let mut ret = vec![];
@@ -334,6 +391,14 @@ mod tests {
         let configs = net_builder.configs();
         assert_eq!(configs.len(), 1);
         assert_eq!(configs.first().unwrap(), &net_if_cfg);
+
+        assert!(net_builder.state("other").is_none());
+        let state = net_builder.state(net_id).unwrap();
+        assert_eq!(state.config, net_if_cfg);
+        assert!(!state.activated);
+        assert!(state.rx_rate_limiter_budget.is_none());
+        assert!(state.tx_rate_limiter_budget.is_none());
+        assert_eq!(state.stats, NetDeviceStats::default());
     }
 
     #[test]
@@ -3,13 +3,18 @@
 
 //! Configurations used in the snapshotting context.
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use crate::snapshot_crypto::{self, SnapshotKey, KEY_LEN};
+
 /// The snapshot type options that are available when
 /// creating a new snapshot.
-#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum SnapshotType {
     /// Diff snapshot.
     Diff,
@@ -40,12 +45,145 @@ pub struct CreateSnapshotParams {
     #[serde(default = "SnapshotType::default")]
     pub snapshot_type: SnapshotType,
     /// Path to the file that will contain the microVM state.
+    #[serde(default)]
     pub snapshot_path: PathBuf,
+    /// Index of the file descriptor, among the ones received with the request, of the file
+    /// that will contain the microVM state. Replaces `snapshot_path`.
+    #[serde(default, skip_serializing)]
+    pub snapshot_fd: Option<usize>,
     /// Path to the file that will contain the guest memory.
+    #[serde(default)]
     pub mem_file_path: PathBuf,
+    /// Index of the file descriptor, among the ones received with the request, of the file
+    /// that will contain the guest memory. Replaces `mem_file_path`.
+    #[serde(default, skip_serializing)]
+    pub mem_file_fd: Option<usize>,
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
+    /// Encrypts the snapshot state and memory files when present.
+    #[serde(default, skip_serializing)]
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Writes the guest memory from a child process, so that the microVM can be resumed as soon
+    /// as the request returns. Only full snapshots support it.
+    #[serde(default)]
+    pub background: bool,
+    /// Writes the lineage of the memory file next to it (`<mem_file>.meta.json`), so that the
+    /// diff snapshots taken on top of it can be loaded as a chain.
+    #[serde(default)]
+    pub chain_metadata: bool,
+    /// Returns as soon as the snapshot creation starts, with an operation tracking it. Handled by
+    /// the API server.
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
+    /// The files designated by `snapshot_fd` and `mem_file_fd`.
+    #[serde(skip)]
+    pub files: SnapshotFiles,
+}
+
+/// Snapshot files received over the API socket, used instead of the corresponding paths.
+#[derive(Debug, Default)]
+pub struct SnapshotFiles {
+    /// The microVM state file.
+    pub snapshot: Option<File>,
+    /// The guest memory file, or the socket connected to the page fault handler when the
+    /// guest memory is backed by userfaultfd.
+    pub mem: Option<File>,
+}
+
+// Files are compared by descriptor, which is enough to check that a request was parsed right.
+impl PartialEq for SnapshotFiles {
+    fn eq(&self, other: &Self) -> bool {
+        let fd = |file: &Option<File>| file.as_ref().map(|file| file.as_raw_fd());
+        fd(&self.snapshot) == fd(&other.snapshot) && fd(&self.mem) == fd(&other.mem)
+    }
+}
+
+impl Eq for SnapshotFiles {}
+
+/// State of a background memory dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MemoryDumpState {
+    /// The memory file is being written.
+    InProgress,
+    /// The memory file and its metadata are written.
+    Done,
+    /// The dump failed; the memory file is unusable.
+    Failed,
+}
+
+/// Progress of the background memory dump started by the last snapshot creation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryDumpStatus {
+    /// State of the dump.
+    pub state: MemoryDumpState,
+    /// Path to the memory file being written.
+    pub mem_file_path: PathBuf,
+    /// Number of guest memory bytes written so far.
+    pub bytes_written: u64,
+    /// Size of the guest memory.
+    pub total_bytes: u64,
+}
+
+/// Errors associated with loading a snapshot encryption key.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotEncryptionConfigError {
+    /// The inline key is not valid hex.
+    #[error("The encryption key must be {} hexadecimal digits", 2 * KEY_LEN)]
+    InvalidHexKey,
+    /// The key does not have the right length.
+    #[error("{0}")]
+    Key(snapshot_crypto::Error),
+    /// Failed to read the key file.
+    #[error("Cannot read the encryption key file: {0}")]
+    KeyFile(std::io::Error),
+    /// Exactly one of `key` and `key_path` must be given.
+    #[error("Exactly one of `key` and `key_path` must be specified")]
+    KeySource,
+}
+
+/// Key used to encrypt or decrypt the snapshot files, given inline or through a key file.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotEncryptionConfig {
+    /// The AES-256 key as hexadecimal digits.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Path to a file holding the raw key bytes.
+    #[serde(default)]
+    pub key_path: Option<PathBuf>,
+}
+
+impl fmt::Debug for SnapshotEncryptionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnapshotEncryptionConfig")
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+impl SnapshotEncryptionConfig {
+    /// Reads the key from the configuration or from the key file.
+    pub fn load_key(&self) -> Result<SnapshotKey, SnapshotEncryptionConfigError> {
+        use self::SnapshotEncryptionConfigError::*;
+        let bytes = match (&self.key, &self.key_path) {
+            (Some(key), None) => parse_hex(key).ok_or(InvalidHexKey)?,
+            (None, Some(key_path)) => std::fs::read(key_path).map_err(KeyFile)?,
+            _ => return Err(KeySource),
+        };
+        SnapshotKey::from_bytes(&bytes).map_err(Key)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -55,9 +193,18 @@ pub struct LoadSnapshotParams {
     pub snapshot_path: PathBuf,
     /// Specifies guest memory backend configuration.
     pub mem_backend: MemBackendConfig,
+    /// Diff snapshot memory files mapped on top of the `mem_backend` file, oldest first.
+    pub diff_chain: Vec<PathBuf>,
     /// Setting this flag will enable KVM dirty page tracking and will
     /// allow taking subsequent incremental snapshots.
     pub enable_diff_snapshots: bool,
+    /// Decrypts the snapshot files when present.
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Host resources to use instead of the ones saved in the snapshot.
+    pub device_overrides: DeviceOverrides,
+    /// Files received with the request, used instead of `snapshot_path` and
+    /// `mem_backend.backend_path`.
+    pub files: SnapshotFiles,
     /// Setting this flag enables user page faults handling by a different process.
     pub enable_user_page_faults: bool,
     /// Path to the passfd socket.
@@ -85,7 +232,12 @@ pub struct LoadSnapshotParams {
 #[serde(deny_unknown_fields)]
 pub struct LoadSnapshotConfig {
     /// Path to the file that contains the microVM state to be loaded.
+    #[serde(default)]
     pub snapshot_path: PathBuf,
+    /// Index of the file descriptor, among the ones received with the request, of the file
+    /// that contains the microVM state. Replaces `snapshot_path`.
+    #[serde(default)]
+    pub snapshot_fd: Option<usize>,
     /// Path to the file that contains the guest memory to be loaded. To be used only if
     /// `mem_backend` is not specified.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -94,20 +246,171 @@ pub struct LoadSnapshotConfig {
     /// None value is allowed only if `mem_file_path` is present.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_backend: Option<MemBackendConfig>,
+    /// Diff snapshot memory files to map on top of the guest memory file, oldest first.
+    #[serde(default)]
+    pub diff_chain: Vec<PathBuf>,
+    /// Whether or not to enable KVM dirty page tracking.
+    #[serde(default)]
+    pub enable_diff_snapshots: bool,
+    /// Key needed to decrypt encrypted snapshot files.
+    #[serde(default)]
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Host devices to attach the restored network interfaces to.
+    #[serde(default)]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Host files backing the restored block devices.
+    #[serde(default)]
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Host socket backing the restored vsock device.
+    #[serde(default)]
+    pub vsock_override: Option<VsockOverride>,
+    /// Whether or not to resume the vm post snapshot load.
+    #[serde(default)]
+    pub resume_vm: bool,
+    /// Returns as soon as the snapshot load starts, with an operation tracking it. Handled by the
+    /// API server.
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
+}
+
+/// Snapshot to restore the microVM from when Firecracker starts, given by the `snapshot-load`
+/// section of the configuration file or by the command line.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StartupSnapshotConfig {
+    /// Path to the file that contains the microVM state to be loaded.
+    pub snapshot_path: PathBuf,
+    /// Guest memory backend configuration. File descriptors cannot be given at startup.
+    pub mem_backend: MemBackendConfig,
+    /// Diff snapshot memory files to map on top of the guest memory file, oldest first.
+    #[serde(default)]
+    pub diff_chain: Vec<PathBuf>,
     /// Whether or not to enable KVM dirty page tracking.
     #[serde(default)]
     pub enable_diff_snapshots: bool,
+    /// Key needed to decrypt encrypted snapshot files.
+    #[serde(default)]
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Host devices to attach the restored network interfaces to.
+    #[serde(default)]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Host files backing the restored block devices.
+    #[serde(default)]
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Host socket backing the restored vsock device.
+    #[serde(default)]
+    pub vsock_override: Option<VsockOverride>,
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
 }
 
+impl From<StartupSnapshotConfig> for LoadSnapshotParams {
+    fn from(config: StartupSnapshotConfig) -> Self {
+        LoadSnapshotParams {
+            snapshot_path: config.snapshot_path,
+            mem_backend: config.mem_backend,
+            diff_chain: config.diff_chain,
+            enable_diff_snapshots: config.enable_diff_snapshots,
+            encryption: config.encryption,
+            device_overrides: DeviceOverrides {
+                network_overrides: config.network_overrides,
+                drive_overrides: config.drive_overrides,
+                vsock_override: config.vsock_override,
+            },
+            files: SnapshotFiles::default(),
+            enable_user_page_faults: false,
+            sock_file_path: PathBuf::from("/tmp/PASS.socket"),
+            overlay_file_path: PathBuf::from("/tmp/overlay_file"),
+            overlay_regions: HashMap::new(),
+            ws_file_path: PathBuf::from("/tmp/ws_file"),
+            ws_regions: Vec::new(),
+            load_ws: false,
+            fadvise: String::new(),
+            resume_vm: config.resume_vm,
+        }
+    }
+}
+
+/// Host device to attach a restored network interface to, instead of the saved one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkOverride {
+    /// ID of the network interface, as saved in the snapshot.
+    pub iface_id: String,
+    /// Name of the host TAP device to use.
+    pub host_dev_name: String,
+}
+
+/// Host file backing a restored block device, instead of the saved one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveOverride {
+    /// ID of the block device, as saved in the snapshot.
+    pub drive_id: String,
+    /// Path of the host file to use.
+    pub path_on_host: String,
+}
+
+/// Host socket backing the restored vsock device, instead of the saved one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockOverride {
+    /// ID of the vsock device, as saved in the snapshot.
+    pub vsock_id: String,
+    /// Path of the host Unix socket to use.
+    pub uds_path: String,
+}
+
+/// Host resources replacing the saved ones while the devices of a snapshot are restored, so
+/// that several clones of a snapshot can run side by side.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceOverrides {
+    /// Overrides for the network interfaces.
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Overrides for the block devices.
+    pub drive_overrides: Vec<DriveOverride>,
+    /// Override for the vsock device.
+    pub vsock_override: Option<VsockOverride>,
+}
+
+impl DeviceOverrides {
+    /// Returns the TAP device to use for the network interface `iface_id`, if overridden.
+    pub fn host_dev_name(&self, iface_id: &str) -> Option<&str> {
+        self.network_overrides
+            .iter()
+            .find(|entry| entry.iface_id == iface_id)
+            .map(|entry| entry.host_dev_name.as_str())
+    }
+
+    /// Returns the host file to use for the block device `drive_id`, if overridden.
+    pub fn path_on_host(&self, drive_id: &str) -> Option<&str> {
+        self.drive_overrides
+            .iter()
+            .find(|entry| entry.drive_id == drive_id)
+            .map(|entry| entry.path_on_host.as_str())
+    }
+
+    /// Returns the host socket to use for the vsock device `vsock_id`, if overridden.
+    pub fn uds_path(&self, vsock_id: &str) -> Option<&str> {
+        self.vsock_override
+            .as_ref()
+            .filter(|entry| entry.vsock_id == vsock_id)
+            .map(|entry| entry.uds_path.as_str())
+    }
+}
+
 /// Stores the configuration used for managing snapshot memory.
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MemBackendConfig {
     /// Path to the backend used to handle the guest memory.
+    #[serde(default)]
     pub backend_path: PathBuf,
+    /// Index of the file descriptor, among the ones received with the request, of the backend
+    /// used to handle the guest memory. Replaces `backend_path`.
+    #[serde(default)]
+    pub backend_fd: Option<usize>,
     /// Specifies the guest memory backend type.
     pub backend_type: MemBackendType,
 }
@@ -128,3 +431,121 @@ pub struct Vm {
     /// The microVM state, which can be `paused` or `resumed`.
     pub state: VmState,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_startup_snapshot_config() {
+        let config: StartupSnapshotConfig = serde_json::from_str(
+            r#"{
+                "snapshot_path": "vm.snap",
+                "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" },
+                "drive_overrides": [{ "drive_id": "rootfs", "path_on_host": "clone.ext4" }],
+                "resume_vm": true
+            }"#,
+        )
+        .unwrap();
+        let params = LoadSnapshotParams::from(config);
+        assert_eq!(params.snapshot_path, PathBuf::from("vm.snap"));
+        assert_eq!(params.mem_backend.backend_path, PathBuf::from("vm.mem"));
+        assert_eq!(params.mem_backend.backend_type, MemBackendType::File);
+        assert_eq!(
+            params.device_overrides.path_on_host("rootfs"),
+            Some("clone.ext4")
+        );
+        assert!(params.files.snapshot.is_none() && params.files.mem.is_none());
+        assert!(params.resume_vm);
+        assert!(!params.enable_diff_snapshots);
+
+        // The request-only fields are not accepted.
+        assert!(serde_json::from_str::<StartupSnapshotConfig>(
+            r#"{
+                "snapshot_path": "vm.snap",
+                "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" },
+                "async": true
+            }"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_device_overrides() {
+        let overrides = DeviceOverrides {
+            network_overrides: vec![NetworkOverride {
+                iface_id: "eth0".to_string(),
+                host_dev_name: "tap7".to_string(),
+            }],
+            drive_overrides: vec![DriveOverride {
+                drive_id: "rootfs".to_string(),
+                path_on_host: "/clones/7/rootfs.ext4".to_string(),
+            }],
+            vsock_override: Some(VsockOverride {
+                vsock_id: "vsock0".to_string(),
+                uds_path: "/clones/7/v.sock".to_string(),
+            }),
+        };
+        assert_eq!(overrides.host_dev_name("eth0"), Some("tap7"));
+        assert_eq!(overrides.host_dev_name("eth1"), None);
+        assert_eq!(
+            overrides.path_on_host("rootfs"),
+            Some("/clones/7/rootfs.ext4")
+        );
+        assert_eq!(overrides.path_on_host("scratch"), None);
+        assert_eq!(overrides.uds_path("vsock0"), Some("/clones/7/v.sock"));
+        assert_eq!(overrides.uds_path("vsock1"), None);
+        assert_eq!(DeviceOverrides::default().uds_path("vsock0"), None);
+    }
+
+    #[test]
+    fn test_load_key() {
+        let hex_key = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+        let config = SnapshotEncryptionConfig {
+            key: Some(hex_key.to_string()),
+            key_path: None,
+        };
+        let key = config.load_key().unwrap();
+        assert!(!format!("{:?}", config).contains(hex_key));
+
+        let key_file = TempFile::new().unwrap();
+        key_file
+            .as_file()
+            .write_all(&parse_hex(hex_key).unwrap())
+            .unwrap();
+        let config = SnapshotEncryptionConfig {
+            key: None,
+            key_path: Some(key_file.as_path().to_path_buf()),
+        };
+        assert_eq!(config.load_key().unwrap(), key);
+
+        let config = SnapshotEncryptionConfig {
+            key: Some("0011".to_string()),
+            key_path: None,
+        };
+        assert!(matches!(
+            config.load_key(),
+            Err(SnapshotEncryptionConfigError::Key(_))
+        ));
+        let config = SnapshotEncryptionConfig {
+            key: Some("zz".repeat(KEY_LEN)),
+            key_path: None,
+        };
+        assert!(matches!(
+            config.load_key(),
+            Err(SnapshotEncryptionConfigError::InvalidHexKey)
+        ));
+        let config = SnapshotEncryptionConfig {
+            key: None,
+            key_path: None,
+        };
+        assert!(matches!(
+            config.load_key(),
+            Err(SnapshotEncryptionConfigError::KeySource)
+        ));
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Serialize;
+
+/// A problem in the configuration of a resource which would make the microVM fail to start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationProblem {
+    /// The resource with the problem, named as in the full microVM configuration.
+    pub resource: &'static str,
+    /// The ID of the device with the problem, for resources holding several devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Description of the problem.
+    pub message: String,
+}
+
+/// The problems found by a dry run of the microVM start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationReport {
+    /// Whether the microVM can be started as configured.
+    pub valid: bool,
+    /// The problems found, in the order the resources are set up when starting the microVM.
+    pub problems: Vec<ValidationProblem>,
+}
+
+impl ValidationReport {
+    /// Creates a report without problems.
+    pub fn new() -> Self {
+        ValidationReport {
+            valid: true,
+            problems: Vec::new(),
+        }
+    }
+
+    /// Records a problem of `resource`, or of its device with the given ID.
+    pub fn add(&mut self, resource: &'static str, id: Option<&str>, message: impl ToString) {
+        self.valid = false;
+        self.problems.push(ValidationProblem {
+            resource,
+            id: id.map(str::to_string),
+            message: message.to_string(),
+        });
+    }
+}
+
+impl Default for ValidationReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_report() {
+        let mut report = ValidationReport::new();
+        assert!(report.valid);
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"valid":true,"problems":[]}"#
+        );
+
+        report.add("boot-source", None, "Missing kernel");
+        report.add("drives", Some("rootfs"), "Missing file");
+        assert!(!report.valid);
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"valid":false,"problems":[{"resource":"boot-source","message":"Missing kernel"},{"resource":"drives","id":"rootfs","message":"Missing file"}]}"#
+        );
+    }
+}
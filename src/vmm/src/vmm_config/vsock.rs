@@ -7,7 +7,9 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use crate::devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use crate::devices::virtio::{
+    VirtioDevice, Vsock, VsockDeviceStats, VsockError, VsockUnixBackend, VsockUnixBackendError,
+};
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
@@ -65,6 +67,30 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
     }
 }
 
+/// Live state of the vsock device, along with its configuration.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct VsockDeviceState {
+    /// Configuration of the vsock device.
+    #[serde(flatten)]
+    pub config: VsockDeviceConfig,
+    /// Whether the guest driver activated the device.
+    pub activated: bool,
+    /// Counters of the packets exchanged with the guest.
+    pub stats: VsockDeviceStats,
+}
+
+impl From<&VsockAndUnixPath> for VsockDeviceState {
+    fn from(vsock: &VsockAndUnixPath) -> Self {
+        let config = VsockDeviceConfig::from(vsock);
+        let vsock_lock = vsock.vsock.lock().unwrap();
+        VsockDeviceState {
+            config,
+            activated: vsock_lock.is_activated(),
+            stats: vsock_lock.stats(),
+        }
+    }
+}
+
 /// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
 #[derive(Default)]
 pub struct VsockBuilder {
@@ -120,6 +146,11 @@ impl VsockBuilder {
     pub fn config(&self) -> Option<VsockDeviceConfig> {
         self.inner.as_ref().map(VsockDeviceConfig::from)
     }
+
+    /// Returns the live state of the vsock device.
+    pub fn state(&self) -> Option<VsockDeviceState> {
+        self.inner.as_ref().map(VsockDeviceState::from)
+    }
 }
 
 #[cfg(test)]
@@ -174,6 +205,11 @@ pub(crate) mod tests {
         let config = vsock_builder.config();
         assert!(config.is_some());
         assert_eq!(config.unwrap(), vsock_config);
+
+        let state = vsock_builder.state().unwrap();
+        assert_eq!(state.config, vsock_config);
+        assert!(!state.activated);
+        assert_eq!(state.stats, VsockDeviceStats::default());
     }
 
     #[test]
@@ -0,0 +1,130 @@
+// Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+use crate::persist::RestoreTimings;
+use crate::resources::RestoreVmmConfig;
+use crate::vmm_config::logger::LoggerConfig;
+use crate::vmm_config::metrics::MetricsConfig;
+use crate::vmm_config::snapshot::StartupSnapshotConfig;
+
+/// Request to restore a microVM in a new process, received on the control socket of a
+/// Firecracker process running in zygote mode.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZygoteRequest {
+    /// The instance ID of the restored microVM.
+    pub id: String,
+    /// The snapshot to restore the microVM from.
+    #[serde(rename = "snapshot-load")]
+    pub snapshot_load: StartupSnapshotConfig,
+    /// The logger configuration of the new process.
+    #[serde(rename = "logger")]
+    pub logger: Option<LoggerConfig>,
+    /// The metrics configuration of the new process.
+    #[serde(rename = "metrics")]
+    pub metrics: Option<MetricsConfig>,
+}
+
+impl ZygoteRequest {
+    /// Splits the request into the instance ID and the restore configuration.
+    pub fn into_parts(self) -> (String, RestoreVmmConfig) {
+        (
+            self.id,
+            RestoreVmmConfig {
+                snapshot_load: self.snapshot_load,
+                logger: self.logger,
+                metrics: self.metrics,
+            },
+        )
+    }
+}
+
+/// Response to a `ZygoteRequest`, sent by the new process once the microVM is restored, or by
+/// the zygote if the process could not be created.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ZygoteResponse {
+    /// The PID of the process running the microVM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    /// Time spent in each phase of the snapshot load.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<RestoreTimings>,
+    /// Why the microVM could not be restored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault_message: Option<String>,
+}
+
+impl ZygoteResponse {
+    /// Creates the response to a request which failed.
+    pub fn fault(pid: Option<i32>, fault_message: String) -> Self {
+        ZygoteResponse {
+            pid,
+            fault_message: Some(fault_message),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_zygote_request() {
+        let request: ZygoteRequest = serde_json::from_str(
+            r#"{
+                "id": "vm-1",
+                "snapshot-load": {
+                    "snapshot_path": "vm.snap",
+                    "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" },
+                    "resume_vm": true
+                },
+                "metrics": { "metrics_path": "vm-1.metrics" }
+            }"#,
+        )
+        .unwrap();
+        let (id, config) = request.into_parts();
+        assert_eq!(id, "vm-1");
+        assert_eq!(config.snapshot_load.snapshot_path, PathBuf::from("vm.snap"));
+        assert!(config.snapshot_load.resume_vm);
+        assert!(config.logger.is_none());
+        assert_eq!(
+            config.metrics.unwrap().metrics_path,
+            PathBuf::from("vm-1.metrics")
+        );
+
+        // The instance ID is mandatory.
+        assert!(serde_json::from_str::<ZygoteRequest>(
+            r#"{
+                "snapshot-load": {
+                    "snapshot_path": "vm.snap",
+                    "mem_backend": { "backend_path": "vm.mem", "backend_type": "File" }
+                }
+            }"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_zygote_response() {
+        let response = ZygoteResponse {
+            pid: Some(42),
+            timings: Some(RestoreTimings::default()),
+            fault_message: None,
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["timings"]["total_us"], 0);
+        assert!(json.get("fault_message").is_none());
+
+        let response = ZygoteResponse::fault(None, "Could not fork".to_string());
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"fault_message":"Could not fork"}"#
+        );
+    }
+}
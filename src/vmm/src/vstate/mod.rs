@@ -4,3 +4,24 @@
 pub(crate) mod system;
 pub(crate) mod vcpu;
 pub(crate) mod vm;
+
+use serde::ser::Error as _;
+use serde::Serializer;
+use versionize::{VersionMap, Versionize};
+
+/// Serializes a KVM structure as the hex encoding of its snapshot representation.
+///
+/// Used to describe snapshot state that has no meaningful structure outside of KVM.
+pub(crate) fn serialize_kvm_state<T: Versionize, S: Serializer>(
+    state: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut bytes = Vec::new();
+    Versionize::serialize(state, &mut bytes, &VersionMap::new(), 1).map_err(S::Error::custom)?;
+    serializer.serialize_str(
+        &bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>(),
+    )
+}
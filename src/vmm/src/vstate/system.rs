@@ -6,6 +6,7 @@
 // found in the THIRD-PARTY file.
 
 use std::result;
+use std::sync::Mutex;
 
 use kvm_bindings::KVM_API_VERSION;
 use kvm_ioctls::{Error as KvmIoctlsError, Kvm};
@@ -36,6 +37,9 @@ pub enum Error {
 
 type Result<T> = result::Result<T, Error>;
 
+// KVM context opened ahead of the microVM, see `KvmContext::preopen`.
+static PREOPENED: Mutex<Option<KvmContext>> = Mutex::new(None);
+
 /// Describes a KVM context that gets attached to the microVM.
 /// It gives access to the functionality of the KVM wrapper as
 /// long as every required KVM capability is present on the host.
@@ -92,6 +96,30 @@ impl KvmContext {
         }
     }
 
+    /// Opens KVM and checks its capabilities ahead of time, for the next microVM built by this
+    /// process or by a process forked from it.
+    pub fn preopen() -> Result<()> {
+        *PREOPENED.lock().expect("Poisoned lock") = Some(KvmContext::new()?);
+        Ok(())
+    }
+
+    /// Takes the context opened ahead of time, if any, or opens a new one.
+    pub fn preopened_or_new() -> Result<Self> {
+        match PREOPENED.lock().expect("Poisoned lock").take() {
+            Some(kvm) => Ok(kvm),
+            None => KvmContext::new(),
+        }
+    }
+
+    /// Runs `f` on the context opened ahead of time, if any, or on a new one, and leaves the
+    /// preopened context to the next microVM built.
+    pub fn with_preopened_or_new<T>(f: impl FnOnce(&KvmContext) -> T) -> Result<T> {
+        match PREOPENED.lock().expect("Poisoned lock").as_ref() {
+            Some(kvm) => Ok(f(kvm)),
+            None => Ok(f(&KvmContext::new()?)),
+        }
+    }
+
     pub fn fd(&self) -> &Kvm {
         &self.kvm
     }
@@ -126,4 +154,16 @@ mod tests {
         assert_eq!(m1.dev(), m2.dev());
         assert_eq!(m1.ino(), m2.ino());
     }
+
+    #[test]
+    fn test_preopened_kvm_context() {
+        KvmContext::preopen().unwrap();
+        assert!(KvmContext::with_preopened_or_new(|kvm| kvm.max_memslots()).is_ok());
+        assert!(KvmContext::preopened_or_new().is_ok());
+
+        // The preopened context is only used once. Other tests building microVMs may take it
+        // first, so only check that it is gone.
+        assert!(PREOPENED.lock().unwrap().is_none());
+        assert!(KvmContext::preopened_or_new().is_ok());
+    }
 }
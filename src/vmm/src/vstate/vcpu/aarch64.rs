@@ -10,6 +10,7 @@ use std::result;
 use kvm_bindings::{RegList, KVM_REG_ARM_COPROC_MASK, KVM_REG_ARM_CORE};
 use kvm_ioctls::*;
 use logger::{error, IncMetric, METRICS};
+use serde::Serialize;
 use utils::vm_memory::{Address, GuestAddress, GuestMemoryMmap};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -21,6 +22,7 @@ use crate::arch::regs::{
 };
 use crate::cpu_config::templates::CpuConfiguration;
 use crate::vcpu::VcpuConfig;
+use crate::vstate::serialize_kvm_state;
 use crate::vstate::vcpu::VcpuEmulation;
 use crate::vstate::vm::Vm;
 
@@ -240,8 +242,9 @@ impl KvmVcpu {
 }
 
 /// Structure holding VCPU kvm state.
-#[derive(Clone, Default, Versionize)]
+#[derive(Clone, Default, Versionize, Serialize)]
 pub struct VcpuState {
+    #[serde(serialize_with = "serialize_kvm_state")]
     pub mp_state: kvm_bindings::kvm_mp_state,
     pub regs: Vec<Aarch64Register>,
     // We will be using the mpidr for passing it to the VmState.
@@ -24,6 +24,7 @@ use utils::signal::{register_signal_handler, sigrtmin, Killable};
 use utils::sm::StateMachine;
 
 use crate::cpu_config::templates::{CpuConfiguration, GuestConfigError};
+use crate::lifecycle::{self, LifecycleEvent};
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
 
@@ -291,7 +292,10 @@ impl Vcpu {
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
                 Ok(VcpuEmulation::Stopped) => return self.exit(FcExitCode::Ok),
                 // Emulation errors lead to vCPU exit.
-                Err(_) => return self.exit(FcExitCode::GenericError),
+                Err(err) => {
+                    self.emit_exit(err.to_string());
+                    return self.exit(FcExitCode::GenericError);
+                }
             }
         }
 
@@ -469,6 +473,14 @@ impl Vcpu {
         self.kvm_vcpu.fd.run()
     }
 
+    /// Tells the lifecycle events subscribers why this vCPU stopped running the guest.
+    fn emit_exit(&self, reason: String) {
+        lifecycle::emit(LifecycleEvent::VcpuExited {
+            vcpu: self.kvm_vcpu.index,
+            reason,
+        });
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
@@ -491,10 +503,12 @@ impl Vcpu {
                 }
                 VcpuExit::Hlt => {
                     info!("Received KVM_EXIT_HLT signal");
+                    self.emit_exit("KVM_EXIT_HLT".to_string());
                     Ok(VcpuEmulation::Stopped)
                 }
                 VcpuExit::Shutdown => {
                     info!("Received KVM_EXIT_SHUTDOWN signal");
+                    self.emit_exit("KVM_EXIT_SHUTDOWN".to_string());
                     Ok(VcpuEmulation::Stopped)
                 }
                 // Documentation specifies that below kvm exits are considered
@@ -526,6 +540,7 @@ impl Vcpu {
                             "Received KVM_SYSTEM_EVENT: type: {}, event: {}",
                             event_type, event_flags
                         );
+                        self.emit_exit(format!("KVM_SYSTEM_EVENT: type: {}", event_type));
                         Ok(VcpuEmulation::Stopped)
                     }
                     _ => {
@@ -13,6 +13,7 @@ use kvm_bindings::{
 };
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use logger::{error, warn, IncMetric, METRICS};
+use serde::{Serialize, Serializer};
 use utils::vm_memory::{Address, GuestAddress, GuestMemoryMmap};
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
@@ -21,6 +22,7 @@ use crate::arch::x86_64::interrupts;
 use crate::arch::x86_64::msr::{create_boot_msr_entries, Error as MsrError};
 use crate::arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecialRegistersError};
 use crate::cpu_config::x86_64::{cpuid, CpuConfiguration};
+use crate::vstate::serialize_kvm_state;
 use crate::vstate::vcpu::{VcpuConfig, VcpuEmulation};
 use crate::vstate::vm::Vm;
 
@@ -558,27 +560,86 @@ impl KvmVcpu {
     }
 }
 
-#[derive(Clone, Versionize)]
+#[derive(Clone, Versionize, Serialize)]
 /// Structure holding VCPU kvm state.
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct VcpuState {
+    #[serde(serialize_with = "serialize_cpuid")]
     pub cpuid: CpuId,
     #[version(end = 3, default_fn = "default_msrs")]
+    #[serde(skip)]
     msrs: Msrs,
     #[version(start = 3, de_fn = "de_saved_msrs", ser_fn = "ser_saved_msrs")]
+    #[serde(serialize_with = "serialize_msrs")]
     saved_msrs: Vec<Msrs>,
+    #[serde(serialize_with = "serialize_kvm_state")]
     debug_regs: kvm_debugregs,
+    #[serde(serialize_with = "serialize_kvm_state")]
     lapic: kvm_lapic_state,
+    #[serde(serialize_with = "serialize_kvm_state")]
     mp_state: kvm_mp_state,
+    #[serde(serialize_with = "serialize_kvm_state")]
     regs: kvm_regs,
+    #[serde(serialize_with = "serialize_kvm_state")]
     sregs: kvm_sregs,
+    #[serde(serialize_with = "serialize_kvm_state")]
     vcpu_events: kvm_vcpu_events,
+    #[serde(serialize_with = "serialize_kvm_state")]
     xcrs: kvm_xcrs,
+    #[serde(serialize_with = "serialize_kvm_state")]
     xsave: kvm_xsave,
     #[version(start = 2, default_fn = "default_tsc_khz", ser_fn = "ser_tsc")]
     pub tsc_khz: Option<u32>,
 }
 
+/// A CPUID leaf as shown in snapshot state dumps.
+#[derive(Serialize)]
+struct CpuidEntryState {
+    function: u32,
+    index: u32,
+    flags: u32,
+    eax: u32,
+    ebx: u32,
+    ecx: u32,
+    edx: u32,
+}
+
+fn serialize_cpuid<S: Serializer>(
+    cpuid: &CpuId,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(cpuid.as_slice().iter().map(|entry| CpuidEntryState {
+        function: entry.function,
+        index: entry.index,
+        flags: entry.flags,
+        eax: entry.eax,
+        ebx: entry.ebx,
+        ecx: entry.ecx,
+        edx: entry.edx,
+    }))
+}
+
+/// An MSR as shown in snapshot state dumps.
+#[derive(Serialize)]
+struct MsrEntryState {
+    index: u32,
+    data: u64,
+}
+
+fn serialize_msrs<S: Serializer>(
+    msrs: &[Msrs],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        msrs.iter()
+            .flat_map(|chunk| chunk.as_slice().iter())
+            .map(|entry| MsrEntryState {
+                index: entry.index,
+                data: entry.data,
+            }),
+    )
+}
+
 impl VcpuState {
     fn default_tsc_khz(_: u16) -> Option<u32> {
         warn!("CPU TSC freq not found in snapshot");
@@ -15,6 +15,7 @@ use kvm_bindings::{
 };
 use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::{Kvm, VmFd};
+use serde::Serialize;
 use utils::vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -23,6 +24,8 @@ use versionize_derive::Versionize;
 use crate::arch::aarch64::gic::GICDevice;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::gic::GicState;
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::serialize_kvm_state;
 
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug, thiserror::Error)]
@@ -350,22 +353,27 @@ impl Vm {
 }
 
 #[cfg(target_arch = "x86_64")]
-#[derive(Versionize)]
+#[derive(Versionize, Serialize)]
 /// Structure holding VM kvm state.
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct VmState {
+    #[serde(serialize_with = "serialize_kvm_state")]
     pitstate: kvm_pit_state2,
+    #[serde(serialize_with = "serialize_kvm_state")]
     clock: kvm_clock_data,
     // TODO: rename this field to adopt inclusive language once Linux updates it, too.
+    #[serde(serialize_with = "serialize_kvm_state")]
     pic_master: kvm_irqchip,
     // TODO: rename this field to adopt inclusive language once Linux updates it, too.
+    #[serde(serialize_with = "serialize_kvm_state")]
     pic_slave: kvm_irqchip,
+    #[serde(serialize_with = "serialize_kvm_state")]
     ioapic: kvm_irqchip,
 }
 
 /// Structure holding an general specific VM state.
 #[cfg(target_arch = "aarch64")]
-#[derive(Default, Versionize)]
+#[derive(Default, Versionize, Serialize)]
 pub struct VmState {
     gic: GicState,
 }
@@ -8,7 +8,9 @@ use std::{io, thread};
 use snapshot::Snapshot;
 use utils::tempfile::TempFile;
 use vmm::builder::{build_and_boot_microvm, build_microvm_from_snapshot, setup_serial_device};
-use vmm::persist::{self, snapshot_state_sanity_check, MicrovmState, MicrovmStateError, VmInfo};
+use vmm::persist::{
+    self, snapshot_state_sanity_check, MicrovmState, MicrovmStateError, RestoreTimings, VmInfo,
+};
 use vmm::resources::VmResources;
 use vmm::seccomp_filters::{get_filters, SeccompConfig};
 use vmm::utilities::mock_devices::MockSerialInput;
@@ -18,7 +20,9 @@ use vmm::utilities::test_utils::dirty_tracking_vmm;
 use vmm::utilities::test_utils::{create_vmm, default_vmm, default_vmm_no_boot};
 use vmm::version_map::VERSION_MAP;
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
-use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
+use vmm::vmm_config::snapshot::{
+    CreateSnapshotParams, DeviceOverrides, SnapshotFiles, SnapshotType,
+};
 use vmm::{DumpCpuConfigError, EventManager, FcExitCode};
 
 #[test]
@@ -203,8 +207,15 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
     let snapshot_params = CreateSnapshotParams {
         snapshot_type,
         snapshot_path: snapshot_file.as_path().to_path_buf(),
+        snapshot_fd: None,
         mem_file_path: memory_file.as_path().to_path_buf(),
+        mem_file_fd: None,
         version: Some(String::from("0.24.0")),
+        encryption: None,
+        background: false,
+        chain_metadata: false,
+        asynchronous: false,
+        files: SnapshotFiles::default(),
     };
     let vm_info = VmInfo {
         mem_size_mib: 1u64,
@@ -283,6 +294,8 @@ fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
         false,
         &empty_seccomp_filters,
         vm_resources,
+        &DeviceOverrides::default(),
+        &mut RestoreTimings::default(),
     )
     .unwrap();
     // For now we're happy we got this far, we don't test what the guest is actually doing.
@@ -276,7 +276,7 @@ class MicrovmBuilder:
                 resume=resume,
                 timeout=timeout,
             )
-        status_ok = vm.api_session.is_status_no_content(response.status_code)
+        status_ok = vm.api_session.is_status_ok(response.status_code)
 
         # Verify response status and cleanup if needed before assert.
         if not status_ok: